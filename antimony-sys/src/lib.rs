@@ -106,13 +106,15 @@
 
 use std::os::raw::*;
 
-pub const LIBANTIMONY_VERSION_STRING: &'static [u8; 7usize] = b"v2.7.0\0";
+pub const LIBANTIMONY_VERSION_STRING: &[u8; 7usize] = b"v2.7.0\0";
 
 /// The different types of reactions and interactions.
 ///
 /// Corresponds to `rd_type` in the C API.
 #[repr(u32)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Interaction {
     /// A reversible reaction: `->` or `<=>`
     ///
@@ -141,6 +143,8 @@ pub enum Interaction {
 ///
 /// Corresponds to `return_type` in the C API.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 #[repr(u32)]
 pub enum SymbolKind {
     /// Every symbol of every type in Antimony
@@ -252,6 +256,8 @@ pub enum SymbolKind {
 /// Corresponds to `formula_type` in the C API.
 #[repr(u32)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum FormulaKind {
     /// Corresponds to `formulaINITIAL` in the C API.
     Initial = 0,
@@ -0,0 +1,493 @@
+//! Assembling models programmatically.
+//!
+//! libAntimony can only read models, so `ModelBuilder` writes each call out as one line of
+//! Antimony and loads the result with `loadAntimonyString`. Because every call owns exactly one
+//! line, errors reported by libAntimony (which name a line number) are traced back to the call
+//! that caused them.
+//!
+//! ```no_run
+//! use antimony::builder::ModelBuilder;
+//!
+//! let module = ModelBuilder::new("decay")
+//!     .compartment("cell", 1.0)
+//!     .species("S1", Some("cell"), 10.0)
+//!     .species("S2", Some("cell"), 0.0)
+//!     .parameter("k1", 0.1)
+//!     .reaction("J0", &[(1.0, "S1")], &[(1.0, "S2")], "k1*S1")
+//!     .build()?;
+//! assert_eq!(module.reactions.len(), 1);
+//! # Ok::<(), antimony::builder::BuildError>(())
+//! ```
+
+use std::collections::HashSet;
+use std::error;
+use std::fmt;
+
+use crate::model::{Event, Participant};
+use crate::{writer, Document, Error, Module};
+
+/// Words that cannot be used as symbol names.
+const KEYWORDS: &[&str] = &[
+    "after",
+    "at",
+    "compartment",
+    "const",
+    "delete",
+    "DNA",
+    "end",
+    "formula",
+    "fromTrigger",
+    "function",
+    "gene",
+    "has",
+    "import",
+    "in",
+    "is",
+    "model",
+    "module",
+    "operator",
+    "persistent",
+    "priority",
+    "reaction",
+    "species",
+    "substanceOnly",
+    "t0",
+    "time",
+    "unit",
+    "var",
+];
+
+/// A builder call, identifying where a problem originated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    /// The position of the call among all calls on the builder, starting at zero.
+    pub index: usize,
+    /// The builder method, e.g., `"reaction"`.
+    pub method: &'static str,
+    /// The name passed to the method.
+    pub id: String,
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "call #{} `{}(\"{}\", ..)`",
+            self.index, self.method, self.id
+        )
+    }
+}
+
+/// Errors reported by `ModelBuilder::build`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// A name is not a valid Antimony identifier, or is a keyword.
+    InvalidName(Call, String),
+    /// A name was declared by an earlier call.
+    DuplicateName(Call),
+    /// A formula spans several lines or statements.
+    InvalidFormula(Call, String),
+    /// A value (a size, initial value, or stoichiometry) is not a finite number.
+    InvalidValue(Call, String),
+    /// libAntimony rejected the model; `call` is the builder call on the offending line, if
+    /// the error could be traced to one.
+    Load {
+        /// The builder call responsible, if known.
+        call: Option<Call>,
+        /// libAntimony's error message.
+        message: String,
+    },
+    /// Any other failure while loading the model.
+    Antimony(Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::InvalidName(call, name) => {
+                write!(f, "{}: `{}` is not a valid name", call, name)
+            }
+            BuildError::DuplicateName(call) => {
+                write!(f, "{}: `{}` is already declared", call, call.id)
+            }
+            BuildError::InvalidFormula(call, formula) => {
+                write!(f, "{}: invalid formula {:?}", call, formula)
+            }
+            BuildError::InvalidValue(call, value) => {
+                write!(f, "{}: `{}` is not a finite number", call, value)
+            }
+            BuildError::Load {
+                call: Some(call),
+                message,
+            } => write!(f, "{}: {}", call, message.trim()),
+            BuildError::Load {
+                call: None,
+                message,
+            } => write!(f, "{}", message.trim()),
+            BuildError::Antimony(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for BuildError {}
+
+/// A builder for a single Antimony model.
+#[derive(Debug, Clone)]
+pub struct ModelBuilder {
+    name: String,
+    definitions: Vec<Document>,
+    lines: Vec<(Call, String)>,
+    declared: HashSet<String>,
+    errors: Vec<BuildError>,
+}
+
+impl ModelBuilder {
+    /// Start a model with the given name.
+    pub fn new(name: &str) -> ModelBuilder {
+        let mut builder = ModelBuilder {
+            name: name.to_owned(),
+            definitions: Vec::new(),
+            lines: Vec::new(),
+            declared: HashSet::new(),
+            errors: Vec::new(),
+        };
+        if let Err(err) = check_name(name) {
+            builder.errors.push(BuildError::InvalidName(
+                Call {
+                    index: 0,
+                    method: "new",
+                    id: name.to_owned(),
+                },
+                err,
+            ));
+        }
+        builder
+    }
+
+    /// Declare a compartment with a constant size.
+    pub fn compartment(&mut self, id: &str, size: f64) -> &mut Self {
+        let call = self.declare("compartment", id);
+        self.check_value(&call, size);
+        self.push(call, format!("compartment {} = {};", id, size))
+    }
+
+    /// Declare a variable species with an initial amount, optionally inside a compartment.
+    pub fn species(&mut self, id: &str, compartment: Option<&str>, initial: f64) -> &mut Self {
+        let call = self.declare("species", id);
+        self.push_species(call, "", id, compartment, initial)
+    }
+
+    /// Declare a boundary species, whose value reactions never change.
+    pub fn boundary_species(
+        &mut self,
+        id: &str,
+        compartment: Option<&str>,
+        initial: f64,
+    ) -> &mut Self {
+        let call = self.declare("boundary_species", id);
+        self.push_species(call, "$", id, compartment, initial)
+    }
+
+    /// Declare a parameter with an initial value. Parameters stay constant unless a rule or
+    /// event changes them.
+    pub fn parameter(&mut self, id: &str, value: f64) -> &mut Self {
+        let call = self.declare("parameter", id);
+        self.check_value(&call, value);
+        self.push(call, format!("{} = {};", id, value))
+    }
+
+    /// Add a reversible reaction (`->`) with the given reactants and products, each paired
+    /// with its stoichiometry.
+    pub fn reaction(
+        &mut self,
+        id: &str,
+        reactants: &[(f64, &str)],
+        products: &[(f64, &str)],
+        rate: &str,
+    ) -> &mut Self {
+        let call = self.declare("reaction", id);
+        self.push_reaction(call, "->", reactants, products, rate)
+    }
+
+    /// Add an irreversible reaction (`=>`).
+    pub fn irreversible_reaction(
+        &mut self,
+        id: &str,
+        reactants: &[(f64, &str)],
+        products: &[(f64, &str)],
+        rate: &str,
+    ) -> &mut Self {
+        let call = self.declare("irreversible_reaction", id);
+        self.push_reaction(call, "=>", reactants, products, rate)
+    }
+
+    /// Add an event.
+    pub fn event(&mut self, event: &Event) -> &mut Self {
+        let call = self.declare("event", &event.id);
+        let mut formulas = vec![event.trigger.as_str()];
+        formulas.extend(event.delay.as_deref());
+        formulas.extend(event.priority.as_deref());
+        for assignment in &event.assignments {
+            self.check_reference(&call, &assignment.variable);
+            formulas.push(&assignment.formula);
+        }
+        for formula in formulas {
+            self.check_formula(&call, formula);
+        }
+        self.push(call, writer::event(event))
+    }
+
+    /// Govern a symbol by a rate rule, `id' = formula`. The symbol still needs an initial
+    /// value, e.g., from `parameter`.
+    pub fn rate_rule(&mut self, id: &str, formula: &str) -> &mut Self {
+        let call = self.call("rate_rule", id);
+        self.check_reference(&call, id);
+        self.check_formula(&call, formula);
+        self.push(call, format!("{}' = {};", id, formula))
+    }
+
+    /// Govern a symbol by an assignment rule, `id := formula`.
+    pub fn assignment_rule(&mut self, id: &str, formula: &str) -> &mut Self {
+        let call = self.call("assignment_rule", id);
+        self.check_reference(&call, id);
+        self.check_formula(&call, formula);
+        self.push(call, format!("{} := {};", id, formula))
+    }
+
+    /// Instantiate the main module of `definition` as a submodule, passing `args` to its
+    /// interface. The definitions of all modules in the document are included in the output.
+    pub fn submodule(&mut self, id: &str, definition: &Document, args: &[&str]) -> &mut Self {
+        let call = self.declare("submodule", id);
+        for arg in args {
+            self.check_reference(&call, arg);
+        }
+        if !self.definitions.contains(definition) {
+            self.definitions.push(definition.clone());
+        }
+        let line = format!("{}: {}({});", id, definition.main, args.join(", "));
+        self.push(call, line)
+    }
+
+    /// The Antimony text for the model, including the definitions of its submodules.
+    pub fn to_antimony(&self) -> String {
+        self.render().0
+    }
+
+    /// Load the model through libAntimony.
+    pub fn build(&self) -> Result<Module, BuildError> {
+        if let Some(err) = self.errors.first() {
+            return Err(err.clone());
+        }
+        let (text, first) = self.render();
+        let doc = Document::load_antimony_str(&text).map_err(|err| match err {
+            Error::Load(message) => {
+                let call = error_line(&message)
+                    .and_then(|line| line.checked_sub(first))
+                    .and_then(|i| self.lines.get(i))
+                    .map(|(call, _)| call.clone());
+                BuildError::Load { call, message }
+            }
+            err => BuildError::Antimony(err),
+        })?;
+        doc.modules
+            .into_iter()
+            .find(|m| m.name == self.name)
+            .ok_or_else(|| BuildError::Antimony(Error::NoSuchModule(self.name.clone())))
+    }
+
+    /// The text of the model and the line number of the first builder call.
+    fn render(&self) -> (String, usize) {
+        let mut text = String::new();
+        for doc in &self.definitions {
+            text.push_str(&writer::definitions(doc));
+        }
+        text.push_str(&format!("model *{}()\n", self.name));
+        let first = text.lines().count() + 1;
+        for (_, line) in &self.lines {
+            text.push_str("  ");
+            text.push_str(line);
+            text.push('\n');
+        }
+        text.push_str("end\n");
+        (text, first)
+    }
+
+    fn call(&self, method: &'static str, id: &str) -> Call {
+        Call {
+            index: self.lines.len(),
+            method,
+            id: id.to_owned(),
+        }
+    }
+
+    fn declare(&mut self, method: &'static str, id: &str) -> Call {
+        let call = self.call(method, id);
+        if let Err(err) = check_name(id) {
+            self.errors.push(BuildError::InvalidName(call.clone(), err));
+        } else if !self.declared.insert(id.to_owned()) {
+            self.errors.push(BuildError::DuplicateName(call.clone()));
+        }
+        call
+    }
+
+    fn check_reference(&mut self, call: &Call, id: &str) {
+        if let Err(err) = id.split('.').try_for_each(check_name) {
+            self.errors.push(BuildError::InvalidName(call.clone(), err));
+        }
+    }
+
+    fn check_formula(&mut self, call: &Call, formula: &str) {
+        if formula.trim().is_empty() || formula.contains([';', '\n']) {
+            self.errors
+                .push(BuildError::InvalidFormula(call.clone(), formula.to_owned()));
+        }
+    }
+
+    fn check_value(&mut self, call: &Call, value: f64) {
+        // Antimony would read `NaN` or `inf` as the name of a new, undefined symbol.
+        if !value.is_finite() {
+            self.errors
+                .push(BuildError::InvalidValue(call.clone(), value.to_string()));
+        }
+    }
+
+    fn push(&mut self, call: Call, line: String) -> &mut Self {
+        self.lines.push((call, line));
+        self
+    }
+
+    fn push_species(
+        &mut self,
+        call: Call,
+        boundary: &str,
+        id: &str,
+        compartment: Option<&str>,
+        initial: f64,
+    ) -> &mut Self {
+        self.check_value(&call, initial);
+        let within = match compartment {
+            Some(c) => {
+                self.check_reference(&call, c);
+                format!(" in {}", c)
+            }
+            None => String::new(),
+        };
+        let line = format!("species {}{}{} = {};", boundary, id, within, initial);
+        self.push(call, line)
+    }
+
+    fn push_reaction(
+        &mut self,
+        call: Call,
+        arrow: &str,
+        reactants: &[(f64, &str)],
+        products: &[(f64, &str)],
+        rate: &str,
+    ) -> &mut Self {
+        let mut participants = |side: &[(f64, &str)]| -> Vec<Participant> {
+            side.iter()
+                .map(|&(stoichiometry, species)| {
+                    self.check_reference(&call, species);
+                    self.check_value(&call, stoichiometry);
+                    Participant {
+                        species: species.to_owned(),
+                        stoichiometry,
+                    }
+                })
+                .collect()
+        };
+        let reactants = participants(reactants);
+        let products = participants(products);
+        self.check_formula(&call, rate);
+        let line = format!(
+            "{}: {} {} {}; {};",
+            call.id,
+            writer::side(&reactants),
+            arrow,
+            writer::side(&products),
+            rate
+        );
+        self.push(call, line)
+    }
+}
+
+/// Check that a name is a plain Antimony identifier and not a keyword.
+fn check_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    };
+    if valid && !KEYWORDS.contains(&name) {
+        Ok(())
+    } else {
+        Err(name.to_owned())
+    }
+}
+
+/// The line number libAntimony reports in an error message, as in `... line 7: syntax error`.
+fn error_line(message: &str) -> Option<usize> {
+    let rest = &message[message.find("line ")? + "line ".len()..];
+    let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_one_line_per_call() {
+        let text = ModelBuilder::new("m")
+            .species("S1", None, 10.0)
+            .boundary_species("S2", None, 0.0)
+            .parameter("k1", 0.5)
+            .irreversible_reaction("J0", &[(2.0, "S1")], &[(1.0, "S2")], "k1*S1")
+            .assignment_rule("S3", "S1 + S2")
+            .to_antimony();
+        let expected = "model *m()\n  species S1 = 10;\n  species $S2 = 0;\n  k1 = 0.5;\n  \
+                        J0: 2 S1 => S2; k1*S1;\n  S3 := S1 + S2;\nend\n";
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn rejects_bad_names_before_loading() {
+        let mut builder = ModelBuilder::new("m");
+        builder.parameter("k1", 1.0).parameter("k1", 2.0);
+        match builder.build() {
+            Err(BuildError::DuplicateName(call)) => assert_eq!(call.index, 1),
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut builder = ModelBuilder::new("m");
+        builder.species("species", None, 1.0);
+        assert!(matches!(builder.build(), Err(BuildError::InvalidName(..))));
+        let mut builder = ModelBuilder::new("m");
+        builder.rate_rule("x", "1; y = 2");
+        assert!(matches!(
+            builder.build(),
+            Err(BuildError::InvalidFormula(..))
+        ));
+        let mut builder = ModelBuilder::new("m");
+        builder.parameter("k1", 1.0).species("S1", None, f64::NAN);
+        match builder.build() {
+            Err(BuildError::InvalidValue(call, value)) => {
+                assert_eq!((call.index, value.as_str()), (1, "NaN"))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut builder = ModelBuilder::new("m");
+        builder.compartment("cell", f64::INFINITY);
+        assert!(matches!(builder.build(), Err(BuildError::InvalidValue(..))));
+    }
+
+    #[test]
+    fn finds_error_lines() {
+        assert_eq!(
+            error_line("Error in model string, line 12:  syntax error"),
+            Some(12)
+        );
+        assert_eq!(error_line("no such module"), None);
+    }
+}
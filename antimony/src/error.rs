@@ -0,0 +1,28 @@
+use std::error;
+use std::fmt;
+
+/// Errors reported while loading or extracting models through libAntimony.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// libAntimony rejected the input; carries its error message.
+    Load(String),
+    /// A query about a loaded module failed; carries libAntimony's error message.
+    Query(String),
+    /// No module with the given name exists in the loaded set.
+    NoSuchModule(String),
+    /// A string passed to libAntimony contained an interior NUL byte.
+    InvalidString(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Load(msg) => write!(f, "failed to load model: {}", msg.trim()),
+            Error::Query(msg) => write!(f, "failed to query model: {}", msg.trim()),
+            Error::NoSuchModule(name) => write!(f, "no module named `{}`", name),
+            Error::InvalidString(s) => write!(f, "string contains a NUL byte: {:?}", s),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,406 @@
+//! Copying loaded modules out of libAntimony.
+//!
+//! The C API has no query for the divider of a reaction (`->` vs. `=>`), for the module a
+//! submodule instantiates, or for function definitions, so these are recovered from the
+//! Antimony text libAntimony generates for the loaded modules.
+
+use std::collections::HashMap;
+use std::os::raw::{c_char, c_long, c_ulong, c_void};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use antimony_sys as sys;
+
+use crate::ffi::{self, collect, free, take, take_nonempty, take_required};
+use crate::model::*;
+use crate::{Error, FormulaKind, Interaction, SymbolKind};
+
+pub(crate) type Loader = unsafe extern "C" fn(*const c_char) -> c_long;
+
+pub(crate) fn load_path(loader: Loader, path: &Path) -> Result<Document, Error> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| Error::InvalidString(path.to_string_lossy().into_owned()))?;
+    load_str(loader, path)
+}
+
+pub(crate) fn load_str(loader: Loader, arg: &str) -> Result<Document, Error> {
+    let arg = ffi::cstring(arg)?;
+    let _guard = ffi::lock();
+    if unsafe { loader(arg.as_ptr()) } < 0 {
+        return Err(Error::Load(ffi::last_error()));
+    }
+    document()
+}
+
+/// Extract every module of the active set. The caller must hold the libAntimony lock.
+fn document() -> Result<Document, Error> {
+    let main = unsafe { take_required(sys::getMainModuleName())? };
+    let names = unsafe { collect(sys::getNumModules(), |n| sys::getNthModuleName(n))? };
+    let text = unsafe { take(sys::getAntimonyString(ptr::null())) }.unwrap_or_default();
+    let scan = Scan::new(&text);
+    let modules = names
+        .into_iter()
+        .map(|name| module(name, &scan))
+        .collect::<Result<_, _>>()?;
+    Ok(Document { main, modules })
+}
+
+fn module(name: String, scan: &Scan) -> Result<Module, Error> {
+    let m = ffi::cstring(&name)?;
+    let m = m.as_ptr();
+    unsafe {
+        let interface = collect(sys::getNumSymbolsInInterfaceOf(m), |n| {
+            sys::getNthSymbolNameInInterfaceOf(m, n)
+        })?;
+        let symbols = symbols(m)?;
+        let reactions = reactions(m, &name, scan)?;
+        let interactions = interactions(m)?;
+        let events = events(m)?;
+        let strands = strands(m)?;
+        let submodules = collect(sys::getNumSymbolsOfType(m, SymbolKind::Module), |n| {
+            sys::getNthSymbolNameOfType(m, SymbolKind::Module, n)
+        })?
+        .into_iter()
+        .map(|id| Submodule {
+            module: scan.submodule(&name, &id).map(str::to_owned),
+            id,
+        })
+        .collect();
+        let n = sys::getNumReplacedSymbolNames(m);
+        let former = collect(n, |i| sys::getNthFormerSymbolName(m, i))?;
+        let replacement = collect(n, |i| sys::getNthReplacementSymbolName(m, i))?;
+        let replacements = former
+            .into_iter()
+            .zip(replacement)
+            .map(|(former, replacement)| Replacement {
+                former,
+                replacement,
+            })
+            .collect();
+        let stoichiometry = stoichiometry(m)?;
+        Ok(Module {
+            functions: scan.functions.clone(),
+            name,
+            interface,
+            symbols,
+            reactions,
+            interactions,
+            events,
+            strands,
+            submodules,
+            replacements,
+            stoichiometry,
+        })
+    }
+}
+
+unsafe fn symbols(m: *const c_char) -> Result<Vec<Symbol>, Error> {
+    let all = SymbolKind::Any;
+    let mut symbols = Vec::new();
+    for n in 0..sys::getNumSymbolsOfType(m, all) {
+        let id = take_required(sys::getNthSymbolNameOfType(m, all, n))?;
+        let name = take_nonempty(sys::getNthSymbolDisplayNameOfType(m, all, n));
+        let cid = ffi::cstring(&id)?;
+        let kind = sys::getTypeOfSymbol(m, cid.as_ptr());
+        let compartment = take_nonempty(sys::getNthSymbolCompartmentOfType(m, all, n))
+            .filter(|c| c != "default_compartment");
+        let (initial, rule) = match sys::getTypeOfEquationForSymbol(m, cid.as_ptr()) {
+            FormulaKind::Initial => (
+                take_nonempty(sys::getNthSymbolInitialAssignmentOfType(m, all, n)),
+                None,
+            ),
+            FormulaKind::Assignment => (
+                None,
+                take_nonempty(sys::getNthSymbolAssignmentRuleOfType(m, all, n))
+                    .map(Rule::Assignment),
+            ),
+            FormulaKind::Rate => (
+                take_nonempty(sys::getNthSymbolInitialAssignmentOfType(m, all, n)),
+                take_nonempty(sys::getNthSymbolRateRuleOfType(m, all, n)).map(Rule::Rate),
+            ),
+            FormulaKind::Kinetic | FormulaKind::Trigger => (None, None),
+        };
+        symbols.push(Symbol {
+            id,
+            name,
+            kind,
+            compartment,
+            initial,
+            rule,
+        });
+    }
+    Ok(symbols)
+}
+
+unsafe fn reactions(m: *const c_char, module: &str, scan: &Scan) -> Result<Vec<Reaction>, Error> {
+    let mut reactions = Vec::new();
+    for n in 0..sys::getNumReactions(m) {
+        let id = take_required(sys::getNthSymbolNameOfType(m, SymbolKind::Reaction, n))?;
+        let name = take_nonempty(sys::getNthSymbolDisplayNameOfType(
+            m,
+            SymbolKind::Reaction,
+            n,
+        ));
+        let reactants = collect(sys::getNumReactants(m, n), |i| {
+            sys::getNthReactionMthReactantName(m, n, i)
+        })?
+        .into_iter()
+        .enumerate()
+        .map(|(i, species)| Participant {
+            species,
+            stoichiometry: sys::getNthReactionMthReactantStoichiometries(m, n, i as c_ulong),
+        })
+        .collect();
+        let products = collect(sys::getNumProducts(m, n), |i| {
+            sys::getNthReactionMthProductName(m, n, i)
+        })?
+        .into_iter()
+        .enumerate()
+        .map(|(i, species)| Participant {
+            species,
+            stoichiometry: sys::getNthReactionMthProductStoichiometries(m, n, i as c_ulong),
+        })
+        .collect();
+        let rate = take_nonempty(sys::getNthReactionRate(m, n));
+        reactions.push(Reaction {
+            kind: scan.divider(module, &id),
+            id,
+            name,
+            reactants,
+            products,
+            rate,
+        });
+    }
+    Ok(reactions)
+}
+
+unsafe fn interactions(m: *const c_char) -> Result<Vec<Regulation>, Error> {
+    let mut interactions = Vec::new();
+    for n in 0..sys::getNumInteractions(m) {
+        let id = take_required(sys::getNthSymbolNameOfType(m, SymbolKind::Interaction, n))?;
+        let interactors = collect(sys::getNumInteractors(m, n), |i| {
+            sys::getNthInteractionMthInteractorName(m, n, i)
+        })?;
+        let interactees = collect(sys::getNumInteractees(m, n), |i| {
+            sys::getNthInteractionMthInteracteeName(m, n, i)
+        })?;
+        interactions.push(Regulation {
+            id,
+            kind: sys::getNthInteractionDivider(m, n),
+            interactors,
+            interactees,
+        });
+    }
+    Ok(interactions)
+}
+
+unsafe fn events(m: *const c_char) -> Result<Vec<Event>, Error> {
+    let mut events = Vec::new();
+    for n in 0..sys::getNumEvents(m) {
+        let id = take_required(sys::getNthEventName(m, n))?;
+        let name = take_nonempty(sys::getNthSymbolDisplayNameOfType(m, SymbolKind::Event, n));
+        let trigger = take_required(sys::getTriggerForEvent(m, n))?;
+        let delay = if sys::getEventHasDelay(m, n) {
+            take_nonempty(sys::getDelayForEvent(m, n))
+        } else {
+            None
+        };
+        let priority = if sys::getEventHasPriority(m, n) {
+            take_nonempty(sys::getPriorityForEvent(m, n))
+        } else {
+            None
+        };
+        let count = sys::getNumAssignmentsForEvent(m, n);
+        let variables = collect(count, |i| sys::getNthAssignmentVariableForEvent(m, n, i))?;
+        let formulas = collect(count, |i| sys::getNthAssignmentEquationForEvent(m, n, i))?;
+        let assignments = variables
+            .into_iter()
+            .zip(formulas)
+            .map(|(variable, formula)| Assignment { variable, formula })
+            .collect();
+        events.push(Event {
+            id,
+            name,
+            trigger,
+            delay,
+            priority,
+            persistent: sys::getPersistenceForEvent(m, n),
+            initial_value: sys::getT0ForEvent(m, n),
+            from_trigger: sys::getFromTriggerForEvent(m, n),
+            assignments,
+        });
+    }
+    Ok(events)
+}
+
+unsafe fn strands(m: *const c_char) -> Result<Vec<Strand>, Error> {
+    let mut strands = Vec::new();
+    for n in 0..sys::getNumDNAStrands(m) {
+        let size = sys::getSizeOfNthDNAStrand(m, n) as usize;
+        let parts = take_array(sys::getNthDNAStrand(m, n), size)
+            .ok_or_else(|| Error::Query(ffi::last_error()))?;
+        strands.push(Strand {
+            parts,
+            open_upstream: sys::getIsNthDNAStrandOpen(m, n, true),
+            open_downstream: sys::getIsNthDNAStrandOpen(m, n, false),
+        });
+    }
+    Ok(strands)
+}
+
+unsafe fn stoichiometry(m: *const c_char) -> Result<Stoichiometry, Error> {
+    let rows = sys::getStoichiometryMatrixNumRows(m) as usize;
+    let cols = sys::getStoichiometryMatrixNumColumns(m) as usize;
+    let species = take_array(sys::getStoichiometryMatrixRowLabels(m), rows).unwrap_or_default();
+    let reactions =
+        take_array(sys::getStoichiometryMatrixColumnLabels(m), cols).unwrap_or_default();
+    let mut matrix = vec![vec![0.0; cols]; rows];
+    if rows > 0 && cols > 0 {
+        let raw = sys::getStoichiometryMatrix(m);
+        if raw.is_null() {
+            return Err(Error::Query(ffi::last_error()));
+        }
+        for (r, row) in matrix.iter_mut().enumerate() {
+            let ptr = *raw.add(r);
+            row.copy_from_slice(slice::from_raw_parts(ptr, cols));
+            free(ptr as *mut c_void);
+        }
+        free(raw as *mut c_void);
+    }
+    Ok(Stoichiometry {
+        species,
+        reactions,
+        matrix,
+    })
+}
+
+/// Take ownership of an array of `len` strings allocated by libAntimony.
+unsafe fn take_array(raw: *mut *mut c_char, len: usize) -> Option<Vec<String>> {
+    if raw.is_null() {
+        return None;
+    }
+    let strings = (0..len)
+        .map(|i| take(*raw.add(i)).unwrap_or_default())
+        .collect();
+    free(raw as *mut c_void);
+    Some(strings)
+}
+
+/// Facts recovered from the Antimony rendering of the loaded modules.
+#[derive(Default)]
+struct Scan<'a> {
+    modules: HashMap<&'a str, ScannedModule<'a>>,
+    functions: Vec<Function>,
+}
+
+#[derive(Default)]
+struct ScannedModule<'a> {
+    dividers: HashMap<&'a str, Interaction>,
+    submodules: HashMap<&'a str, &'a str>,
+}
+
+/// The definition being scanned.
+enum Block<'a> {
+    Module(&'a str),
+    Function(Function),
+}
+
+impl<'a> Scan<'a> {
+    fn new(text: &'a str) -> Scan<'a> {
+        let mut scan = Scan::default();
+        let mut current = None;
+        for line in text.lines().map(str::trim) {
+            if let Some(header) = line
+                .strip_prefix("model ")
+                .or_else(|| line.strip_prefix("module "))
+            {
+                let name = header
+                    .trim_start_matches('*')
+                    .split('(')
+                    .next()
+                    .unwrap_or("");
+                current = Some(Block::Module(name.trim()));
+                continue;
+            }
+            if let Some(header) = line.strip_prefix("function ") {
+                let (id, rest) = header.split_at(header.find('(').unwrap_or(header.len()));
+                let parameters = rest
+                    .trim_matches(|c| c == '(' || c == ')')
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_owned)
+                    .collect();
+                current = Some(Block::Function(Function {
+                    id: id.trim().to_owned(),
+                    parameters,
+                    body: String::new(),
+                }));
+                continue;
+            }
+            if line == "end" {
+                if let Some(Block::Function(mut function)) = current.take() {
+                    function.body = function.body.trim().trim_end_matches(';').to_owned();
+                    scan.functions.push(function);
+                }
+                continue;
+            }
+            let module = match &mut current {
+                Some(Block::Module(name)) => scan.modules.entry(name).or_default(),
+                Some(Block::Function(function)) => {
+                    if !line.starts_with("//") {
+                        function.body.push(' ');
+                        function.body.push_str(line);
+                    }
+                    continue;
+                }
+                None => continue,
+            };
+            let (label, body) = match line.find(':') {
+                Some(i) if !line[i + 1..].starts_with('=') => (&line[..i], &line[i + 1..]),
+                _ => continue,
+            };
+            let id = match label.split_whitespace().next() {
+                Some(id) => id,
+                None => continue,
+            };
+            let body = body.split(';').next().unwrap_or("").trim();
+            if body.contains("=>") {
+                module.dividers.insert(id, Interaction::Transforms);
+            } else if body.contains("->") {
+                module.dividers.insert(id, Interaction::Becomes);
+            } else if let Some(open) = body.find('(') {
+                let callee = &body[..open];
+                if !callee.is_empty() && callee.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    module.submodules.insert(id, callee);
+                }
+            }
+        }
+        scan
+    }
+
+    /// The module instantiated by a submodule.
+    fn submodule(&self, module: &str, id: &str) -> Option<&'a str> {
+        self.modules.get(module)?.submodules.get(id).copied()
+    }
+
+    /// The divider of a reaction, following dotted names into submodules.
+    fn divider(&self, module: &str, id: &str) -> Interaction {
+        let scanned = match self.modules.get(module) {
+            Some(scanned) => scanned,
+            None => return Interaction::Becomes,
+        };
+        if let Some(&kind) = scanned.dividers.get(id) {
+            return kind;
+        }
+        match id.find('.') {
+            Some(dot) => match self.submodule(module, &id[..dot]) {
+                Some(inner) => self.divider(inner, &id[dot + 1..]),
+                None => Interaction::Becomes,
+            },
+            None => Interaction::Becomes,
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Flux balance analysis.
+//!
+//! Flux balance analysis predicts the reaction rates (fluxes) of a network at steady state
+//! from its stoichiometry alone: it maximizes an objective, such as the flux of a biomass
+//! reaction, over the fluxes `v` with `N v = 0` for the stoichiometry matrix `N` and within
+//! bounds on each flux. Rate laws, and so the initial values of the model, play no part.
+//!
+//! ```no_run
+//! use antimony::fba::FluxBalance;
+//! use antimony::Document;
+//!
+//! let doc = Document::load_sbml_file("e_coli_core.xml")?;
+//! let mut fba = FluxBalance::new(doc.main());
+//! fba.set_bounds("EX_glc", -10.0, 0.0)?.set_objective("BIOMASS", 1.0)?;
+//! let growth = fba.optimize()?.objective;
+//! fba.knock_out("PGI")?;
+//! println!("growth {} without PGI {}", growth, fba.optimize()?.objective);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The linear programs are solved by a simplex method implemented here, so that no external
+//! solver is needed.
+
+mod simplex;
+
+use std::error;
+use std::fmt;
+
+use self::simplex::Simplex;
+use crate::Module;
+
+/// Errors from setting up or solving a flux balance problem.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A reaction id is not a reaction of the stoichiometry matrix.
+    UnknownReaction(String),
+    /// Bounds of a reaction are not numbers, or the lower exceeds the upper bound.
+    InvalidBounds(String),
+    /// No fluxes satisfy the steady state and the bounds.
+    Infeasible,
+    /// The objective can grow without limit.
+    Unbounded,
+    /// The simplex method did not converge.
+    IterationLimit,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownReaction(id) => write!(f, "`{}` is not a reaction of the model", id),
+            Error::InvalidBounds(id) => write!(f, "the bounds of `{}` are invalid", id),
+            Error::Infeasible => write!(f, "no fluxes satisfy the constraints"),
+            Error::Unbounded => write!(f, "the objective is unbounded"),
+            Error::IterationLimit => write!(f, "the simplex method did not converge"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A flux balance problem: the stoichiometry of a module, with bounds and an objective.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FluxBalance {
+    /// The reactions, in the order of the stoichiometry matrix.
+    reactions: Vec<String>,
+    /// The rows of the stoichiometry matrix.
+    matrix: Vec<Vec<f64>>,
+    lower: Vec<f64>,
+    upper: Vec<f64>,
+    objective: Vec<f64>,
+}
+
+/// The fluxes maximizing the objective.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+    /// The maximum of the objective.
+    pub objective: f64,
+    /// The reactions, labeling `fluxes`.
+    pub reactions: Vec<String>,
+    /// One optimal flux of each reaction; other optima may exist (see
+    /// `FluxBalance::variability`).
+    pub fluxes: Vec<f64>,
+}
+
+impl Solution {
+    /// The flux of the reaction with the given id, if any.
+    pub fn flux(&self, reaction: &str) -> Option<f64> {
+        let j = self.reactions.iter().position(|r| r == reaction)?;
+        Some(self.fluxes[j])
+    }
+}
+
+/// The range of each flux over the optimal (or nearly optimal) solutions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variability {
+    /// The reactions, labeling the ranges.
+    pub reactions: Vec<String>,
+    /// The smallest flux of each reaction.
+    pub minimum: Vec<f64>,
+    /// The largest flux of each reaction.
+    pub maximum: Vec<f64>,
+}
+
+impl Variability {
+    /// The smallest and largest flux of the reaction with the given id, if any.
+    pub fn range(&self, reaction: &str) -> Option<(f64, f64)> {
+        let j = self.reactions.iter().position(|r| r == reaction)?;
+        Some((self.minimum[j], self.maximum[j]))
+    }
+}
+
+impl FluxBalance {
+    /// The flux balance problem of a module's stoichiometry matrix, without an objective.
+    /// Irreversible reactions have fluxes from zero up, and reversible ones are unbounded.
+    pub fn new(module: &Module) -> FluxBalance {
+        let stoichiometry = &module.stoichiometry;
+        let reactions = stoichiometry.reactions.clone();
+        let lower = reactions
+            .iter()
+            .map(|id| match module.reaction(id) {
+                Some(r) if !r.is_reversible() => 0.0,
+                _ => f64::NEG_INFINITY,
+            })
+            .collect();
+        FluxBalance {
+            matrix: stoichiometry.matrix.clone(),
+            lower,
+            upper: vec![f64::INFINITY; reactions.len()],
+            objective: vec![0.0; reactions.len()],
+            reactions,
+        }
+    }
+
+    /// The reactions, in the order of fluxes in the results.
+    pub fn reactions(&self) -> &[String] {
+        &self.reactions
+    }
+
+    /// The lower and upper bounds of a reaction's flux.
+    pub fn bounds(&self, reaction: &str) -> Result<(f64, f64), Error> {
+        let j = self.column(reaction)?;
+        Ok((self.lower[j], self.upper[j]))
+    }
+
+    /// Bound the flux of a reaction; infinite bounds are allowed.
+    pub fn set_bounds(
+        &mut self,
+        reaction: &str,
+        lower: f64,
+        upper: f64,
+    ) -> Result<&mut Self, Error> {
+        let j = self.column(reaction)?;
+        if lower.is_nan() || upper.is_nan() || lower > upper {
+            return Err(Error::InvalidBounds(reaction.to_owned()));
+        }
+        self.lower[j] = lower;
+        self.upper[j] = upper;
+        Ok(self)
+    }
+
+    /// Set the weight of a reaction's flux in the objective, which is maximized. Every weight
+    /// starts at zero; a negative weight minimizes the flux.
+    pub fn set_objective(&mut self, reaction: &str, weight: f64) -> Result<&mut Self, Error> {
+        let j = self.column(reaction)?;
+        self.objective[j] = weight;
+        Ok(self)
+    }
+
+    /// Knock a reaction out by fixing its flux at zero.
+    pub fn knock_out(&mut self, reaction: &str) -> Result<&mut Self, Error> {
+        self.set_bounds(reaction, 0.0, 0.0)
+    }
+
+    /// Maximize the objective.
+    pub fn optimize(&self) -> Result<Solution, Error> {
+        let mut simplex = self.simplex()?;
+        let cost: Vec<f64> = self.objective.iter().map(|w| -w).collect();
+        let objective = -simplex.minimize_structural(&cost)?;
+        Ok(Solution {
+            objective,
+            reactions: self.reactions.clone(),
+            fluxes: simplex.solution().to_vec(),
+        })
+    }
+
+    /// Flux variability analysis: the range of each flux over the solutions whose objective
+    /// is at least `fraction` of the maximum (1 for the optimal solutions only).
+    pub fn variability(&self, fraction: f64) -> Result<Variability, Error> {
+        let optimum = self.optimize()?.objective;
+        // Require the objective to reach the fraction of the optimum through a slack
+        // variable s: Σ w v - s = bound with s ≥ 0.
+        let bound = optimum - (1.0 - fraction) * optimum.abs();
+        let mut matrix = self.matrix.clone();
+        matrix.iter_mut().for_each(|row| row.push(0.0));
+        let mut row = self.objective.clone();
+        row.push(-1.0);
+        matrix.push(row);
+        let mut b = vec![0.0; self.matrix.len()];
+        b.push(bound);
+        let lower: Vec<f64> = self.lower.iter().copied().chain(Some(0.0)).collect();
+        let upper: Vec<f64> = self
+            .upper
+            .iter()
+            .copied()
+            .chain(Some(f64::INFINITY))
+            .collect();
+        let mut simplex = Simplex::new(&matrix, &b, &lower, &upper)?;
+
+        let n = self.reactions.len();
+        let mut minimum = Vec::with_capacity(n);
+        let mut maximum = Vec::with_capacity(n);
+        let mut cost = vec![0.0; n + 1];
+        for j in 0..n {
+            cost[j] = 1.0;
+            minimum.push(match simplex.minimize_structural(&cost) {
+                Ok(value) => value,
+                Err(Error::Unbounded) => f64::NEG_INFINITY,
+                Err(err) => return Err(err),
+            });
+            cost[j] = -1.0;
+            maximum.push(match simplex.minimize_structural(&cost) {
+                Ok(value) => -value,
+                Err(Error::Unbounded) => f64::INFINITY,
+                Err(err) => return Err(err),
+            });
+            cost[j] = 0.0;
+        }
+        Ok(Variability {
+            reactions: self.reactions.clone(),
+            minimum,
+            maximum,
+        })
+    }
+
+    /// The optimum with each reaction knocked out in turn, `None` where no fluxes remain
+    /// feasible.
+    pub fn single_knockouts(&self) -> Result<Vec<(String, Option<f64>)>, Error> {
+        let mut results = Vec::with_capacity(self.reactions.len());
+        let mut knocked = self.clone();
+        for (j, id) in self.reactions.iter().enumerate() {
+            knocked.lower[j] = 0.0;
+            knocked.upper[j] = 0.0;
+            let objective = match knocked.optimize() {
+                Ok(solution) => Some(solution.objective),
+                Err(Error::Infeasible) => None,
+                Err(err) => return Err(err),
+            };
+            results.push((id.clone(), objective));
+            knocked.lower[j] = self.lower[j];
+            knocked.upper[j] = self.upper[j];
+        }
+        Ok(results)
+    }
+
+    fn column(&self, reaction: &str) -> Result<usize, Error> {
+        self.reactions
+            .iter()
+            .position(|r| r == reaction)
+            .ok_or_else(|| Error::UnknownReaction(reaction.to_owned()))
+    }
+
+    /// A feasible basis of the steady state within the bounds.
+    fn simplex(&self) -> Result<Simplex, Error> {
+        let b = vec![0.0; self.matrix.len()];
+        Simplex::new(&self.matrix, &b, &self.lower, &self.upper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    /// Uptake of A, which is converted to the biomass precursor B directly or through C, and
+    /// a byproduct D.
+    fn network() -> Module {
+        Fixture::new()
+            .species("A", 0.0)
+            .species("B", 0.0)
+            .species("C", 0.0)
+            .species("D", 0.0)
+            .irreversible("uptake", &[], &[(1.0, "A")], "1")
+            .irreversible("direct", &[(1.0, "A")], &[(1.0, "B")], "1")
+            .irreversible("detour", &[(1.0, "A")], &[(1.0, "C")], "1")
+            .reaction("convert", &[(1.0, "C")], &[(1.0, "B"), (1.0, "D")], "1")
+            .irreversible("secrete", &[(1.0, "D")], &[], "1")
+            .irreversible("growth", &[(2.0, "B")], &[], "1")
+            .build()
+    }
+
+    #[test]
+    fn maximizes_growth_within_bounds() {
+        let mut fba = FluxBalance::new(&network());
+        assert_eq!(
+            fba.bounds("convert"),
+            Ok((f64::NEG_INFINITY, f64::INFINITY))
+        );
+        fba.set_objective("growth", 1.0).unwrap();
+        assert_eq!(fba.optimize().err(), Some(Error::Unbounded));
+        fba.set_bounds("uptake", 0.0, 10.0).unwrap();
+        let solution = fba.optimize().unwrap();
+        assert!((solution.objective - 5.0).abs() < 1e-9);
+        assert!((solution.flux("uptake").unwrap() - 10.0).abs() < 1e-9);
+
+        let variability = fba.variability(1.0).unwrap();
+        let (lo, hi) = variability.range("direct").unwrap();
+        assert!(lo.abs() < 1e-9 && (hi - 10.0).abs() < 1e-9);
+        let (lo, hi) = variability.range("growth").unwrap();
+        assert!((lo - 5.0).abs() < 1e-9 && (hi - 5.0).abs() < 1e-9);
+        let (lo, _) = fba.variability(0.5).unwrap().range("growth").unwrap();
+        assert!((lo - 2.5).abs() < 1e-9);
+
+        assert_eq!(
+            fba.set_bounds("nope", 0.0, 1.0).err(),
+            Some(Error::UnknownReaction("nope".into()))
+        );
+        assert_eq!(
+            fba.set_bounds("direct", 1.0, 0.0).err(),
+            Some(Error::InvalidBounds("direct".into()))
+        );
+    }
+
+    #[test]
+    fn knocks_out_reactions() {
+        let mut fba = FluxBalance::new(&network());
+        fba.set_bounds("uptake", 0.0, 10.0)
+            .unwrap()
+            .set_objective("growth", 1.0)
+            .unwrap();
+        let knockouts = fba.single_knockouts().unwrap();
+        let growth = |id: &str| knockouts.iter().find(|k| k.0 == id).unwrap().1;
+        assert!((growth("direct").unwrap() - 5.0).abs() < 1e-9);
+        assert!(growth("uptake").unwrap().abs() < 1e-9);
+        // Without secretion, the detour stops; the direct route remains.
+        assert!((growth("secrete").unwrap() - 5.0).abs() < 1e-9);
+        // Forcing flux through a knocked-out route leaves nothing feasible.
+        fba.set_bounds("detour", 1.0, 10.0).unwrap();
+        fba.knock_out("secrete").unwrap();
+        assert_eq!(fba.optimize().err(), Some(Error::Infeasible));
+    }
+}
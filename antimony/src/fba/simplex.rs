@@ -0,0 +1,307 @@
+//! The bounded-variable primal simplex method on a dense tableau.
+//!
+//! Problems have the form `min c·x` subject to `A x = b` and `l ≤ x ≤ u`, where bounds may be
+//! infinite. Nonbasic variables rest at one of their bounds (free ones at zero) instead of
+//! being shifted and split, so that flux bounds cost nothing. Phase one starts from one
+//! artificial variable per row and minimizes their sum; the feasible basis it finds is then
+//! kept, so that the same constraints can be optimized for many objectives in turn.
+//!
+//! Entering variables are chosen by the largest reduced cost, falling back to Bland's rule
+//! after a run of degenerate pivots so that the method cannot cycle.
+
+use super::Error;
+
+/// Reduced costs and pivots smaller than this are taken to be zero.
+const EPSILON: f64 = 1e-9;
+/// Bounds are taken to hold within this tolerance.
+const FEASIBILITY: f64 = 1e-7;
+/// The number of degenerate pivots after which Bland's rule takes over.
+const DEGENERATE: usize = 50;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Simplex {
+    /// `B^-1 A`, one row per constraint and one column per variable, artificial ones last.
+    tableau: Vec<Vec<f64>>,
+    /// The basic variable of each row.
+    basis: Vec<usize>,
+    /// Whether each variable is basic.
+    basic: Vec<bool>,
+    /// The value of every variable.
+    x: Vec<f64>,
+    lower: Vec<f64>,
+    upper: Vec<f64>,
+    /// The number of variables of the problem, before the artificial ones.
+    structural: usize,
+}
+
+impl Simplex {
+    /// Find a feasible point of `A x = b` within the bounds, where `a` holds the rows of `A`.
+    pub fn new(a: &[Vec<f64>], b: &[f64], lower: &[f64], upper: &[f64]) -> Result<Simplex, Error> {
+        let (m, n) = (a.len(), lower.len());
+        let mut x: Vec<f64> = lower
+            .iter()
+            .zip(upper)
+            .map(|(&l, &u)| {
+                if l.is_finite() {
+                    l
+                } else if u.is_finite() {
+                    u
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        // One artificial variable per row absorbs the residual of the starting point.
+        let mut tableau = Vec::with_capacity(m);
+        for (i, (row, &b)) in a.iter().zip(b).enumerate() {
+            let residual = b - row.iter().zip(&x).map(|(a, x)| a * x).sum::<f64>();
+            let sign = if residual < 0.0 { -1.0 } else { 1.0 };
+            let mut t: Vec<f64> = row.iter().map(|a| a * sign).collect();
+            t.resize(n + m, 0.0);
+            t[n + i] = 1.0;
+            tableau.push(t);
+            x.push(residual.abs());
+        }
+        let mut simplex = Simplex {
+            tableau,
+            basis: (n..n + m).collect(),
+            basic: (0..n + m).map(|j| j >= n).collect(),
+            x,
+            lower: lower.iter().copied().chain(vec![0.0; m]).collect(),
+            upper: upper
+                .iter()
+                .copied()
+                .chain(vec![f64::INFINITY; m])
+                .collect(),
+            structural: n,
+        };
+        let mut cost = vec![0.0; n];
+        cost.resize(n + m, 1.0);
+        let infeasibility = simplex.minimize(&cost)?;
+        let scale = b.iter().fold(1.0f64, |s, b| s.max(b.abs()));
+        if infeasibility > FEASIBILITY * scale {
+            return Err(Error::Infeasible);
+        }
+        // Fix the artificial variables at zero, and move those still basic out of the basis
+        // where another variable can take their place.
+        for j in n..n + m {
+            simplex.upper[j] = 0.0;
+            simplex.x[j] = 0.0;
+        }
+        for r in 0..m {
+            if simplex.basis[r] < n {
+                continue;
+            }
+            let entering =
+                (0..n).find(|&j| !simplex.basic[j] && simplex.tableau[r][j].abs() > 1e-7);
+            if let Some(j) = entering {
+                simplex.pivot(r, j, &mut []);
+            }
+        }
+        Ok(simplex)
+    }
+
+    /// The values of the problem's variables.
+    pub fn solution(&self) -> &[f64] {
+        &self.x[..self.structural]
+    }
+
+    /// Minimize `cost · x` over the problem's variables, from the current feasible basis.
+    pub fn minimize_structural(&mut self, cost: &[f64]) -> Result<f64, Error> {
+        let mut full = cost.to_vec();
+        full.resize(self.x.len(), 0.0);
+        self.minimize(&full)
+    }
+
+    /// Minimize `cost · x` over all variables, returning the minimum.
+    fn minimize(&mut self, cost: &[f64]) -> Result<f64, Error> {
+        let (m, total) = (self.basis.len(), self.x.len());
+        // The reduced costs of every variable.
+        let mut reduced = cost.to_vec();
+        for (row, &b) in self.tableau.iter().zip(&self.basis) {
+            if cost[b] != 0.0 {
+                for (d, t) in reduced.iter_mut().zip(row) {
+                    *d -= cost[b] * t;
+                }
+            }
+        }
+        let limit = 50 * (m + total) + 1000;
+        let mut degenerate = 0;
+        for _ in 0..limit {
+            // Choose the entering variable and the direction it moves in.
+            let mut entering: Option<(usize, f64)> = None;
+            let mut best = 0.0;
+            for (j, &d) in reduced.iter().enumerate() {
+                if self.basic[j] || self.upper[j] - self.lower[j] <= 0.0 {
+                    continue;
+                }
+                let direction = if d < -EPSILON && self.x[j] < self.upper[j] {
+                    1.0
+                } else if d > EPSILON && self.x[j] > self.lower[j] {
+                    -1.0
+                } else {
+                    continue;
+                };
+                if degenerate >= DEGENERATE {
+                    entering = Some((j, direction));
+                    break;
+                }
+                if d.abs() > best {
+                    best = d.abs();
+                    entering = Some((j, direction));
+                }
+            }
+            let (j, direction) = match entering {
+                Some(e) => e,
+                None => {
+                    let value = cost.iter().zip(&self.x).map(|(c, x)| c * x).sum();
+                    return Ok(value);
+                }
+            };
+
+            // The ratio test: how far the entering variable can move before it or a basic
+            // variable reaches a bound. Ties go to the largest pivot, or under Bland's rule to
+            // the basic variable with the smallest index.
+            let mut step = self.upper[j] - self.lower[j];
+            let mut leaving: Option<usize> = None;
+            let mut pivot = 0.0;
+            for r in 0..m {
+                let alpha = direction * self.tableau[r][j];
+                if alpha.abs() <= EPSILON {
+                    continue;
+                }
+                let b = self.basis[r];
+                let room = if alpha > 0.0 {
+                    self.x[b] - self.lower[b]
+                } else {
+                    self.upper[b] - self.x[b]
+                };
+                let ratio = room.max(0.0) / alpha.abs();
+                let better = match leaving {
+                    Some(l) if degenerate >= DEGENERATE => b < self.basis[l],
+                    _ => alpha.abs() > pivot,
+                };
+                if ratio < step - EPSILON || (ratio <= step + EPSILON && better) {
+                    step = ratio;
+                    leaving = Some(r);
+                    pivot = alpha.abs();
+                }
+            }
+            if step == f64::INFINITY {
+                return Err(Error::Unbounded);
+            }
+            if step <= EPSILON {
+                degenerate += 1;
+            } else {
+                degenerate = 0;
+            }
+
+            self.x[j] += direction * step;
+            for r in 0..m {
+                let b = self.basis[r];
+                self.x[b] -= direction * step * self.tableau[r][j];
+            }
+            match leaving {
+                Some(r) => {
+                    let b = self.basis[r];
+                    self.x[b] = if direction * self.tableau[r][j] > 0.0 {
+                        self.lower[b]
+                    } else {
+                        self.upper[b]
+                    };
+                    self.pivot(r, j, &mut reduced);
+                }
+                // The entering variable moved from one bound to the other.
+                None => {
+                    self.x[j] = if direction > 0.0 {
+                        self.upper[j]
+                    } else {
+                        self.lower[j]
+                    };
+                }
+            }
+        }
+        Err(Error::IterationLimit)
+    }
+
+    /// Make variable `j` basic in row `r`, updating the reduced costs `reduced` if given.
+    fn pivot(&mut self, r: usize, j: usize, reduced: &mut [f64]) {
+        let pivot = self.tableau[r][j];
+        self.tableau[r].iter_mut().for_each(|t| *t /= pivot);
+        let row = self.tableau[r].clone();
+        for (i, other) in self.tableau.iter_mut().enumerate() {
+            let factor = other[j];
+            if i != r && factor != 0.0 {
+                for (t, p) in other.iter_mut().zip(&row) {
+                    *t -= factor * p;
+                }
+                other[j] = 0.0;
+            }
+        }
+        if let Some(&factor) = reduced.get(j) {
+            for (d, p) in reduced.iter_mut().zip(&row) {
+                *d -= factor * p;
+            }
+            reduced[j] = 0.0;
+        }
+        let leaving = self.basis[r];
+        self.basic[leaving] = false;
+        self.basic[j] = true;
+        self.basis[r] = j;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn optimizes_with_bounds() {
+        // max x + y s.t. x + 2y = 4 (as x + 2y - s = 0 with s = 4), 0 ≤ x ≤ 3, y free.
+        let a = vec![vec![1.0, 2.0, -1.0]];
+        let inf = f64::INFINITY;
+        let mut simplex = Simplex::new(&a, &[0.0], &[0.0, -inf, 4.0], &[3.0, inf, 4.0]).unwrap();
+        let value = simplex.minimize_structural(&[-1.0, -1.0, 0.0]).unwrap();
+        assert!((value + 3.5).abs() < 1e-9);
+        assert!((simplex.solution()[0] - 3.0).abs() < 1e-9);
+        assert!((simplex.solution()[1] - 0.5).abs() < 1e-9);
+        // The same constraints, minimizing instead.
+        let value = simplex.minimize_structural(&[1.0, 1.0, 0.0]).unwrap();
+        assert!((value - 2.0).abs() < 1e-9);
+        assert!(simplex.solution()[0].abs() < 1e-9);
+    }
+
+    #[test]
+    fn terminates_on_degenerate_problems() {
+        // Beale's example, which cycles under the largest-coefficient rule, with slacks last.
+        let a = vec![
+            vec![0.25, -8.0, -1.0, 9.0, 1.0, 0.0, 0.0],
+            vec![0.5, -12.0, -0.5, 3.0, 0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+        ];
+        let inf = f64::INFINITY;
+        let mut simplex = Simplex::new(&a, &[0.0, 0.0, 1.0], &[0.0; 7], &[inf; 7]).unwrap();
+        let value = simplex
+            .minimize_structural(&[-0.75, 20.0, -0.5, 6.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        assert!((value + 1.25).abs() < 1e-9);
+        assert!((simplex.solution()[0] - 1.0).abs() < 1e-9);
+        assert!((simplex.solution()[2] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn detects_infeasible_and_unbounded_problems() {
+        let a = vec![vec![1.0, 1.0]];
+        assert_eq!(
+            Simplex::new(&a, &[0.0], &[1.0, 1.0], &[2.0, 2.0]).err(),
+            Some(Error::Infeasible)
+        );
+        let a = vec![vec![1.0, -1.0]];
+        let inf = f64::INFINITY;
+        let mut simplex = Simplex::new(&a, &[0.0], &[0.0, 0.0], &[inf, inf]).unwrap();
+        assert_eq!(
+            simplex.minimize_structural(&[-1.0, 0.0]),
+            Err(Error::Unbounded)
+        );
+    }
+}
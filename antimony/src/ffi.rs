@@ -0,0 +1,78 @@
+//! Helpers for calling into libAntimony.
+//!
+//! libAntimony keeps every loaded file in process-global state, so each call sequence that
+//! loads or queries modules must hold the lock returned by `lock` for its whole duration.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_ulong, c_void};
+use std::sync::{Mutex, MutexGuard};
+
+use antimony_sys as sys;
+
+use crate::Error;
+
+extern "C" {
+    pub(crate) fn free(ptr: *mut c_void);
+}
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Acquire exclusive access to the global libAntimony state.
+pub(crate) fn lock() -> MutexGuard<'static, ()> {
+    LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Convert a Rust string into a C string, rejecting interior NUL bytes.
+pub(crate) fn cstring(s: &str) -> Result<CString, Error> {
+    CString::new(s).map_err(|_| Error::InvalidString(s.to_owned()))
+}
+
+/// Take ownership of a string allocated by libAntimony, returning `None` for `NULL`.
+///
+/// # Safety
+///
+/// `ptr` must be `NULL` or a `malloc`ed, NUL-terminated string that nothing else will free.
+pub(crate) unsafe fn take(ptr: *mut c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let s = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+    free(ptr as *mut c_void);
+    Some(s)
+}
+
+/// Take ownership of an optional equation, mapping both `NULL` and `""` to `None`.
+///
+/// # Safety
+///
+/// See `take`.
+pub(crate) unsafe fn take_nonempty(ptr: *mut c_char) -> Option<String> {
+    take(ptr).filter(|s| !s.trim().is_empty())
+}
+
+/// Take ownership of a string that libAntimony should always return, reporting its last error
+/// otherwise.
+///
+/// # Safety
+///
+/// See `take`.
+pub(crate) unsafe fn take_required(ptr: *mut c_char) -> Result<String, Error> {
+    take(ptr).ok_or_else(|| Error::Query(last_error()))
+}
+
+/// The last error message recorded by libAntimony.
+pub(crate) fn last_error() -> String {
+    unsafe { take(sys::getLastError()) }.unwrap_or_default()
+}
+
+/// Collect the strings `f(0)`, ..., `f(n - 1)`.
+///
+/// # Safety
+///
+/// Every pointer returned by `f` must satisfy the requirements of `take`.
+pub(crate) unsafe fn collect<F>(n: c_ulong, mut f: F) -> Result<Vec<String>, Error>
+where
+    F: FnMut(c_ulong) -> *mut c_char,
+{
+    (0..n).map(|i| take_required(f(i))).collect()
+}
@@ -0,0 +1,141 @@
+//! Measured time courses, read from CSV.
+
+use std::fs;
+use std::path::Path;
+
+use super::Error;
+
+/// Measurements over time: one row per time, one column per measured quantity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Data {
+    /// The measured quantities, each the id of a symbol of the model (see `Data::rename`).
+    pub columns: Vec<String>,
+    /// The measurement times, in increasing order.
+    pub times: Vec<f64>,
+    /// The values, one row per time; `NaN` where a quantity was not measured.
+    pub values: Vec<Vec<f64>>,
+}
+
+impl Data {
+    /// Parse comma-separated values with a header row. The time column is the one labeled
+    /// `time` (in any case), or else the first; the others are measured quantities. Empty
+    /// cells, `NA`, and `NaN` are missing values. Rows must be in increasing order of time.
+    pub fn from_csv(text: &str) -> Result<Data, Error> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let header: Vec<String> = match lines.next() {
+            Some((_, line)) => fields(line)
+                .map(|f| f.trim_matches('"').to_owned())
+                .collect(),
+            None => return Err(Error::Data("no header row".to_owned())),
+        };
+        let time = header
+            .iter()
+            .position(|h| h.eq_ignore_ascii_case("time"))
+            .unwrap_or(0);
+        let mut data = Data {
+            columns: header
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != time)
+                .map(|(_, h)| h.clone())
+                .collect(),
+            times: Vec::new(),
+            values: Vec::new(),
+        };
+        for (number, line) in lines {
+            let invalid =
+                |message: String| Err(Error::Data(format!("line {}: {}", number, message)));
+            let cells: Vec<&str> = fields(line).collect();
+            if cells.len() != header.len() {
+                let message = format!(
+                    "{} fields where the header has {}",
+                    cells.len(),
+                    header.len()
+                );
+                return invalid(message);
+            }
+            let mut row = Vec::with_capacity(data.columns.len());
+            for (j, cell) in cells.iter().enumerate() {
+                let value = match *cell {
+                    "" | "NA" | "NaN" | "nan" => f64::NAN,
+                    cell => match cell.parse::<f64>() {
+                        Ok(value) => value,
+                        Err(_) => return invalid(format!("`{}` is not a number", cell)),
+                    },
+                };
+                if j == time {
+                    if !value.is_finite() || data.times.last().is_some_and(|&t| t >= value) {
+                        return invalid("times must be numbers in increasing order".to_owned());
+                    }
+                    data.times.push(value);
+                } else {
+                    row.push(value);
+                }
+            }
+            data.values.push(row);
+        }
+        Ok(data)
+    }
+
+    /// Read a CSV file; see `Data::from_csv`.
+    pub fn load_csv<P: AsRef<Path>>(path: P) -> Result<Data, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|err| Error::Data(format!("cannot read {}: {}", path.display(), err)))?;
+        Data::from_csv(&text)
+    }
+
+    /// Map a column to the symbol it measures, when the labels of the file are not ids.
+    pub fn rename(&mut self, column: &str, id: &str) -> Result<&mut Self, Error> {
+        match self.columns.iter_mut().find(|c| *c == column) {
+            Some(c) => {
+                *c = id.to_owned();
+                Ok(self)
+            }
+            None => Err(Error::Data(format!("no column `{}`", column))),
+        }
+    }
+
+    /// The number of values present.
+    pub(crate) fn count(&self) -> usize {
+        self.values.iter().flatten().filter(|v| !v.is_nan()).count()
+    }
+}
+
+/// The trimmed fields of a line.
+fn fields(line: &str) -> impl Iterator<Item = &str> {
+    line.split(',').map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_csv_with_missing_values() {
+        let text = "# measured\nS1, \"Time\" ,S2\n1.5, 0, NA\n1.0,1,2\n\n0.5,2.5,\n";
+        let mut data = Data::from_csv(text).unwrap();
+        assert_eq!(data.columns, ["S1", "S2"]);
+        assert_eq!(data.times, [0.0, 1.0, 2.5]);
+        assert_eq!(data.values[1], [1.0, 2.0]);
+        assert!(data.values[0][1].is_nan() && data.values[2][1].is_nan());
+        assert_eq!(data.count(), 4);
+        data.rename("S2", "P").unwrap();
+        assert_eq!(data.columns, ["S1", "P"]);
+
+        assert_eq!(
+            Data::from_csv("time,S\n0,1\n1,x").err(),
+            Some(Error::Data("line 3: `x` is not a number".into()))
+        );
+        assert_eq!(
+            Data::from_csv("time,S\n1,1\n0,1").err(),
+            Some(Error::Data(
+                "line 3: times must be numbers in increasing order".into()
+            ))
+        );
+    }
+}
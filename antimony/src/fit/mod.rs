@@ -0,0 +1,517 @@
+//! Parameter estimation from measured time courses.
+//!
+//! `fit` adjusts free parameters of a module, within bounds, to minimize the sum of squared
+//! differences between simulated and measured values at the measurement times. The model is
+//! simulated from `FitOptions::simulation.start` with the values of the module, apart from the
+//! parameters being fitted.
+//!
+//! ```no_run
+//! use antimony::fit::{fit, Data, FitOptions, Parameter};
+//! use antimony::Document;
+//!
+//! let doc = Document::load_antimony_str("J0: S1 => S2; k1*S1; S1 = 10; k1 = 1")?;
+//! let data = Data::load_csv("measurements.csv")?;
+//! let parameters = [Parameter::new("k1", 0.0, 10.0)];
+//! let result = fit(doc.main(), &parameters, &data, &FitOptions::default())?;
+//! let (low, high) = result.confidence_intervals[0];
+//! println!("k1 = {} ({} to {})", result.values[0], low, high);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Levenberg–Marquardt converges quickly from a good guess, and differential evolution
+//! searches the whole box of bounds for models with several minima, finishing with a
+//! Levenberg–Marquardt step. Where the parameters are constant formulas and the measured
+//! quantities are state variables, the Jacobian comes from the forward sensitivity equations
+//! (see `Simulator::sensitivities`); otherwise from finite differences.
+//!
+//! Confidence intervals are the asymptotic ones of nonlinear least squares: they assume
+//! independent errors of equal variance, and are only as good as the linearization of the
+//! model about the fitted values.
+
+mod data;
+mod optimize;
+
+use std::error;
+use std::fmt;
+
+use self::optimize::{normal_equations, sum_of_squares, Evolution, Minimum, Objective};
+use crate::linalg::Lu;
+use crate::sim::{self, normal_quantile, Method, Random, Simulator, TimeCourse};
+use crate::{Module, SymbolKind};
+
+pub use self::data::Data;
+
+/// Errors from setting up or running a fit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The model could not be simulated at the starting values.
+    Simulation(sim::Error),
+    /// The measurements could not be read; carries a description.
+    Data(String),
+    /// The bounds of a parameter are not numbers, or the lower exceeds the upper bound, or
+    /// they are infinite for differential evolution.
+    InvalidBounds(String),
+    /// The options are inconsistent; carries a description.
+    InvalidOptions(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Simulation(err) => err.fmt(f),
+            Error::Data(message) => write!(f, "invalid data: {}", message),
+            Error::InvalidBounds(id) => write!(f, "the bounds of `{}` are invalid", id),
+            Error::InvalidOptions(message) => write!(f, "invalid options: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<sim::Error> for Error {
+    fn from(err: sim::Error) -> Error {
+        Error::Simulation(err)
+    }
+}
+
+/// A parameter to fit: any symbol that `Simulator::set` accepts, such as a rate constant or
+/// an initial concentration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    /// The symbol id.
+    pub id: String,
+    /// The lowest value allowed, possibly `-∞`.
+    pub lower: f64,
+    /// The highest value allowed, possibly `∞`.
+    pub upper: f64,
+    /// The starting value for Levenberg–Marquardt, or `None` to start from the model's value.
+    pub initial: Option<f64>,
+}
+
+impl Parameter {
+    pub fn new(id: &str, lower: f64, upper: f64) -> Parameter {
+        Parameter {
+            id: id.to_owned(),
+            lower,
+            upper,
+            initial: None,
+        }
+    }
+
+    /// Start from the given value instead of the model's.
+    pub fn starting_at(mut self, value: f64) -> Parameter {
+        self.initial = Some(value);
+        self
+    }
+}
+
+/// The optimizer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Algorithm {
+    /// The Levenberg–Marquardt method, from the starting values.
+    LevenbergMarquardt,
+    /// Differential evolution over the bounds, followed by Levenberg–Marquardt from the best
+    /// point found.
+    DifferentialEvolution,
+}
+
+/// How to fit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitOptions {
+    pub algorithm: Algorithm,
+    /// The largest number of Levenberg–Marquardt iterations.
+    pub max_iterations: usize,
+    /// The relative reduction of the sum of squares below which the optimizers stop.
+    pub tolerance: f64,
+    /// The number of points in each generation of differential evolution.
+    pub population: usize,
+    /// The largest number of generations of differential evolution.
+    pub generations: usize,
+    /// The confidence level of the intervals.
+    pub confidence: f64,
+    /// How to simulate: the start time, method, and tolerances are used, and `seed` seeds
+    /// differential evolution. The output times and selections are those of the data.
+    pub simulation: sim::Options,
+}
+
+impl Default for FitOptions {
+    fn default() -> FitOptions {
+        FitOptions {
+            algorithm: Algorithm::LevenbergMarquardt,
+            max_iterations: 100,
+            tolerance: 1e-10,
+            population: 30,
+            generations: 200,
+            confidence: 0.95,
+            simulation: sim::Options {
+                relative_tolerance: 1e-8,
+                absolute_tolerance: 1e-10,
+                ..sim::Options::default()
+            },
+        }
+    }
+}
+
+/// The result of a fit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fit {
+    /// The fitted parameters.
+    pub parameters: Vec<String>,
+    /// The fitted values.
+    pub values: Vec<f64>,
+    /// The standard error of each value.
+    pub standard_errors: Vec<f64>,
+    /// The confidence interval of each value, at the level of `FitOptions::confidence`. They
+    /// are not numbers if there are no more measurements than parameters, or if the
+    /// parameters cannot be identified from the data.
+    pub confidence_intervals: Vec<(f64, f64)>,
+    /// The simulated values at the measurement times, with the columns of the data.
+    pub simulated: TimeCourse,
+    /// The simulated minus the measured values; `NaN` where nothing was measured.
+    pub residuals: TimeCourse,
+    /// The sum of squared residuals.
+    pub sum_of_squares: f64,
+    /// The number of iterations (or generations) of the optimizers.
+    pub iterations: usize,
+    /// The number of simulations run.
+    pub simulations: usize,
+}
+
+impl Fit {
+    /// The fitted value of a parameter, if it was fitted.
+    pub fn value(&self, id: &str) -> Option<f64> {
+        let k = self.parameters.iter().position(|p| p == id)?;
+        Some(self.values[k])
+    }
+}
+
+/// Fit parameters of a module to measurements.
+pub fn fit(
+    module: &Module,
+    parameters: &[Parameter],
+    data: &Data,
+    options: &FitOptions,
+) -> Result<Fit, Error> {
+    if !(options.confidence > 0.0 && options.confidence < 1.0) {
+        let message = "`confidence` must be between 0 and 1".to_owned();
+        return Err(Error::InvalidOptions(message));
+    }
+    if parameters.is_empty() {
+        return Err(Error::InvalidOptions(
+            "there are no parameters to fit".to_owned(),
+        ));
+    }
+    if data.count() == 0 {
+        return Err(Error::Data("there are no measurements".to_owned()));
+    }
+    let simulator = Simulator::new(module)?;
+    let start = options.simulation.start;
+    let lower: Vec<f64> = parameters.iter().map(|p| p.lower).collect();
+    let upper: Vec<f64> = parameters.iter().map(|p| p.upper).collect();
+    let mut x = Vec::with_capacity(parameters.len());
+    for p in parameters {
+        if p.lower.is_nan() || p.upper.is_nan() || p.lower > p.upper {
+            return Err(Error::InvalidBounds(p.id.clone()));
+        }
+        let initial = match p.initial {
+            Some(value) => value,
+            None => simulator.initial_value(&p.id, start)?,
+        };
+        x.push(initial.clamp(p.lower, p.upper));
+    }
+    let state = simulator.state_variables();
+    let sensitivities = matches!(options.simulation.method, Method::Rk45 | Method::Rosenbrock)
+        && parameters.iter().all(|p| {
+            let symbol = module.symbol(&p.id);
+            symbol.is_some_and(|s| s.kind == SymbolKind::FormulaConstant)
+        })
+        && data.columns.iter().all(|c| state.contains(c));
+    let mut problem = Problem {
+        simulator,
+        parameters,
+        data,
+        options: sim::Options {
+            end: data.times.last().copied().unwrap_or(start),
+            times: data.times.clone(),
+            selections: data.columns.clone(),
+            ..options.simulation.clone()
+        },
+        sensitivities,
+        simulations: 0,
+    };
+
+    let bounds = (&lower[..], &upper[..]);
+    let mut iterations = 0;
+    if options.algorithm == Algorithm::DifferentialEvolution {
+        if let Some(p) = parameters.iter().find(|p| !(p.upper - p.lower).is_finite()) {
+            return Err(Error::InvalidBounds(p.id.clone()));
+        }
+        let seed = options.simulation.seed;
+        let mut random = seed.map_or_else(Random::from_entropy, Random::new);
+        let settings = Evolution {
+            population: options.population,
+            generations: options.generations,
+            tolerance: options.tolerance,
+        };
+        let best = optimize::differential_evolution(&mut problem, bounds, &settings, &mut random)?;
+        iterations += best.iterations;
+        x = best.x;
+    }
+    let Minimum {
+        x,
+        residuals,
+        iterations: steps,
+    } = optimize::levenberg_marquardt(
+        &mut problem,
+        x,
+        bounds,
+        options.max_iterations,
+        options.tolerance,
+    )?;
+    iterations += steps;
+
+    // The covariance `s^2 (J^T J)^-1` of the estimates, from the residual variance `s^2`.
+    let p = x.len();
+    let sum = sum_of_squares(&residuals);
+    let dof = residuals.len().saturating_sub(p);
+    let j = problem.jacobian(&x, &residuals)?;
+    let (a, _) = normal_equations(&j, &residuals, p);
+    let variance = if dof > 0 { sum / dof as f64 } else { f64::NAN };
+    let lu = Lu::new(&a);
+    let standard_errors: Vec<f64> = (0..p)
+        .map(|k| match &lu {
+            Some(lu) => {
+                let mut column = vec![0.0; p];
+                column[k] = 1.0;
+                lu.solve(&mut column);
+                (variance * column[k]).sqrt()
+            }
+            None => f64::NAN,
+        })
+        .collect();
+    let t = t_quantile(0.5 + 0.5 * options.confidence, dof as f64);
+    let confidence_intervals = x
+        .iter()
+        .zip(&standard_errors)
+        .map(|(x, se)| (x - t * se, x + t * se))
+        .collect();
+
+    let simulated = problem.simulate(&x)?;
+    let mut differences = simulated.clone();
+    for (row, measured) in differences.rows.iter_mut().zip(&data.values) {
+        for (value, m) in row[1..].iter_mut().zip(measured) {
+            *value -= m;
+        }
+    }
+    Ok(Fit {
+        parameters: parameters.iter().map(|p| p.id.clone()).collect(),
+        values: x,
+        standard_errors,
+        confidence_intervals,
+        simulated,
+        residuals: differences,
+        sum_of_squares: sum,
+        iterations,
+        simulations: problem.simulations,
+    })
+}
+
+/// The residuals of the simulations against the data.
+struct Problem<'a> {
+    simulator: Simulator,
+    parameters: &'a [Parameter],
+    data: &'a Data,
+    /// The simulation options, with the times and columns of the data.
+    options: sim::Options,
+    /// Whether the Jacobian comes from the forward sensitivity equations.
+    sensitivities: bool,
+    simulations: usize,
+}
+
+impl Problem<'_> {
+    fn set(&mut self, x: &[f64]) -> Result<(), Error> {
+        for (p, &value) in self.parameters.iter().zip(x) {
+            self.simulator.set(&p.id, value)?;
+        }
+        Ok(())
+    }
+
+    /// Simulate at the measurement times, with a column of times first.
+    fn simulate(&mut self, x: &[f64]) -> Result<TimeCourse, Error> {
+        self.set(x)?;
+        self.simulations += 1;
+        let mut options = self.options.clone();
+        options.selections.insert(0, "time".to_owned());
+        Ok(self.simulator.simulate(&options)?)
+    }
+}
+
+impl Objective for Problem<'_> {
+    fn residuals(&mut self, x: &[f64]) -> Result<Vec<f64>, Error> {
+        let course = self.simulate(x)?;
+        let mut r = Vec::with_capacity(self.data.count());
+        for (row, measured) in course.rows.iter().zip(&self.data.values) {
+            for (value, m) in row[1..].iter().zip(measured) {
+                if !m.is_nan() {
+                    r.push(value - m);
+                }
+            }
+        }
+        Ok(r)
+    }
+
+    fn jacobian(&mut self, x: &[f64], r: &[f64]) -> Result<Vec<Vec<f64>>, Error> {
+        if self.sensitivities {
+            self.set(x)?;
+            self.simulations += 1;
+            let ids: Vec<&str> = self.parameters.iter().map(|p| p.id.as_str()).collect();
+            match self.simulator.sensitivities(&ids, &self.options) {
+                Ok(result) => {
+                    let s = &result.sensitivities;
+                    let columns: Vec<usize> = (self.data.columns.iter())
+                        .map(|c| {
+                            s.outputs
+                                .iter()
+                                .position(|o| o == c)
+                                .expect("a state variable")
+                        })
+                        .collect();
+                    let mut j = Vec::with_capacity(r.len());
+                    for (at, measured) in s.values.iter().zip(&self.data.values) {
+                        for (&i, m) in columns.iter().zip(measured) {
+                            if !m.is_nan() {
+                                j.push(at[i].clone());
+                            }
+                        }
+                    }
+                    return Ok(j);
+                }
+                // Events or delays rule the sensitivities out; differences still work.
+                Err(sim::Error::InvalidOptions(_)) => self.sensitivities = false,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        // Forward differences, stepping back from an upper bound.
+        let mut j = vec![vec![0.0; x.len()]; r.len()];
+        let mut shifted = x.to_vec();
+        for (k, p) in self.parameters.iter().enumerate() {
+            let mut h = f64::EPSILON.sqrt() * x[k].abs().max(1e-6);
+            if x[k] + h > p.upper {
+                h = -h;
+            }
+            shifted[k] = x[k] + h;
+            let trial = self.residuals(&shifted)?;
+            shifted[k] = x[k];
+            for (row, (a, b)) in j.iter_mut().zip(trial.iter().zip(r)) {
+                row[k] = (a - b) / h;
+            }
+        }
+        Ok(j)
+    }
+}
+
+/// The `p` quantile of Student's t distribution with `dof` degrees of freedom, by the
+/// Cornish–Fisher expansion about the normal quantile (Abramowitz and Stegun, 26.7.5), which
+/// is accurate to three digits from three degrees of freedom on.
+fn t_quantile(p: f64, dof: f64) -> f64 {
+    if dof.is_nan() || dof <= 0.0 {
+        return f64::NAN;
+    }
+    let z = normal_quantile(p);
+    let z2 = z * z;
+    let g1 = z * (z2 + 1.0) / 4.0;
+    let g2 = z * ((5.0 * z2 + 16.0) * z2 + 3.0) / 96.0;
+    let g3 = z * (((3.0 * z2 + 19.0) * z2 + 17.0) * z2 - 15.0) / 384.0;
+    let g4 = z * ((((79.0 * z2 + 776.0) * z2 + 1482.0) * z2 - 1920.0) * z2 - 945.0) / 92160.0;
+    z + g1 / dof + g2 / dof.powi(2) + g3 / dof.powi(3) + g4 / dof.powi(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    /// `S` decays at rate `k` into `P`; measurements of both at k = 0.3, starting from S = 10,
+    /// slightly perturbed.
+    fn problem() -> (Module, Data) {
+        let module = Fixture::new()
+            .species("S", 10.0)
+            .species("P", 0.0)
+            .parameter("k", 1.0)
+            .irreversible("J0", &[(1.0, "S")], &[(1.0, "P")], "k*S")
+            .build();
+        let mut text = String::from("time,S,P\n");
+        for i in 0..11 {
+            let t = 0.5 * i as f64;
+            let s = 10.0 * (-0.3 * t).exp();
+            let noise = if i % 2 == 0 { 0.01 } else { -0.01 };
+            let p = if i == 4 {
+                "NA".to_owned()
+            } else {
+                (10.0 - s - noise).to_string()
+            };
+            text.push_str(&format!("{},{},{}\n", t, s + noise, p));
+        }
+        (module, Data::from_csv(&text).unwrap())
+    }
+
+    #[test]
+    fn fits_rate_constants_and_initial_values() {
+        let (module, data) = problem();
+        let options = FitOptions::default();
+        let result = fit(&module, &[Parameter::new("k", 0.0, 5.0)], &data, &options).unwrap();
+        let k = result.value("k").unwrap();
+        assert!((k - 0.3).abs() < 1e-3, "{}", k);
+        let (low, high) = result.confidence_intervals[0];
+        assert!(
+            low < 0.3 && 0.3 < high && high - low < 0.05,
+            "{} {}",
+            low,
+            high
+        );
+        assert!(result.residuals.rows[4][2].is_nan());
+        assert!(result.residuals.rows[0][1].abs() < 0.1);
+
+        // S0 is a species, so its column of the Jacobian comes from finite differences.
+        let options = FitOptions {
+            algorithm: Algorithm::DifferentialEvolution,
+            population: 15,
+            generations: 30,
+            simulation: sim::Options {
+                seed: Some(3),
+                ..options.simulation
+            },
+            ..options
+        };
+        let parameters = [
+            Parameter::new("k", 0.01, 2.0),
+            Parameter::new("S", 1.0, 20.0),
+        ];
+        let result = fit(&module, &parameters, &data, &options).unwrap();
+        assert!((result.value("k").unwrap() - 0.3).abs() < 1e-3);
+        assert!((result.value("S").unwrap() - 10.0).abs() < 0.02);
+        assert!(result.sum_of_squares < 2.5e-3, "{}", result.sum_of_squares);
+    }
+
+    #[test]
+    fn fits_data_that_starts_after_the_simulation() {
+        let (module, _) = problem();
+        let mut text = String::from("time,S\n");
+        for i in 1..7 {
+            let t = 0.5 * i as f64;
+            text.push_str(&format!("{},{}\n", t, 10.0 * (-0.3 * t).exp()));
+        }
+        let data = Data::from_csv(&text).unwrap();
+        let parameters = [Parameter::new("k", 0.0, 5.0)];
+        let result = fit(&module, &parameters, &data, &FitOptions::default()).unwrap();
+        assert!((result.value("k").unwrap() - 0.3).abs() < 1e-4);
+        assert!(result.sum_of_squares < 1e-8, "{}", result.sum_of_squares);
+    }
+
+    #[test]
+    fn approximates_t_quantiles() {
+        assert!((normal_quantile(0.975) - 1.959964).abs() < 1e-6);
+        assert!((t_quantile(0.975, 10.0) - 2.228139).abs() < 1e-3);
+        assert!((t_quantile(0.995, 30.0) - 2.749996).abs() < 1e-3);
+    }
+}
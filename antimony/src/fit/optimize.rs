@@ -0,0 +1,233 @@
+//! Least-squares optimizers: Levenberg–Marquardt, locally, and differential evolution,
+//! globally.
+
+use super::Error;
+use crate::linalg::Lu;
+use crate::sim::Random;
+
+/// A least-squares problem: the residuals as a function of the parameters.
+pub(crate) trait Objective {
+    /// The residuals at `x`.
+    fn residuals(&mut self, x: &[f64]) -> Result<Vec<f64>, Error>;
+
+    /// The Jacobian of the residuals at `x`, where they are `r`, one row per residual.
+    fn jacobian(&mut self, x: &[f64], r: &[f64]) -> Result<Vec<Vec<f64>>, Error>;
+}
+
+/// The sum of squares of the residuals.
+pub(crate) fn sum_of_squares(r: &[f64]) -> f64 {
+    r.iter().map(|r| r * r).sum()
+}
+
+/// `J^T J` and `J^T r`.
+pub(crate) fn normal_equations(j: &[Vec<f64>], r: &[f64], p: usize) -> (Vec<Vec<f64>>, Vec<f64>) {
+    let mut a = vec![vec![0.0; p]; p];
+    let mut g = vec![0.0; p];
+    for (row, r) in j.iter().zip(r) {
+        for k in 0..p {
+            g[k] += row[k] * r;
+            for (a, x) in a[k].iter_mut().zip(row) {
+                *a += row[k] * x;
+            }
+        }
+    }
+    (a, g)
+}
+
+/// The result of an optimization.
+pub(crate) struct Minimum {
+    pub x: Vec<f64>,
+    pub residuals: Vec<f64>,
+    pub iterations: usize,
+}
+
+/// Minimize the sum of squares from `x` within the bounds by the Levenberg–Marquardt method,
+/// with Marquardt's scaling of the damping by the diagonal of `J^T J`. Steps are clipped to
+/// the bounds. Iteration stops when a step reduces the sum of squares by less than the
+/// relative `tolerance`, or no damping gives a reduction.
+pub(crate) fn levenberg_marquardt(
+    objective: &mut dyn Objective,
+    mut x: Vec<f64>,
+    (lower, upper): (&[f64], &[f64]),
+    max_iterations: usize,
+    tolerance: f64,
+) -> Result<Minimum, Error> {
+    let p = x.len();
+    let mut r = objective.residuals(&x)?;
+    let mut cost = sum_of_squares(&r);
+    let mut lambda = 1e-3;
+    let mut iterations = 0;
+    while iterations < max_iterations && cost > 0.0 {
+        iterations += 1;
+        let j = objective.jacobian(&x, &r)?;
+        let (a, g) = normal_equations(&j, &r, p);
+        let mut reduction = None;
+        for _ in 0..30 {
+            let mut damped = a.clone();
+            for (k, row) in damped.iter_mut().enumerate() {
+                row[k] += lambda * a[k][k].max(1e-12);
+            }
+            let mut step: Vec<f64> = g.iter().map(|g| -g).collect();
+            match Lu::new(&damped) {
+                Some(lu) => lu.solve(&mut step),
+                None => {
+                    lambda *= 10.0;
+                    continue;
+                }
+            }
+            let trial: Vec<f64> = (0..p)
+                .map(|k| (x[k] + step[k]).clamp(lower[k], upper[k]))
+                .collect();
+            if trial == x {
+                break;
+            }
+            // A point where the model cannot be simulated counts as a worse one.
+            if let Ok(residuals) = objective.residuals(&trial) {
+                let trial_cost = sum_of_squares(&residuals);
+                if trial_cost < cost {
+                    reduction = Some((cost - trial_cost) / cost);
+                    x = trial;
+                    r = residuals;
+                    cost = trial_cost;
+                    lambda = (lambda / 3.0).max(1e-12);
+                    break;
+                }
+            }
+            lambda *= 4.0;
+        }
+        match reduction {
+            Some(reduction) if reduction >= tolerance => {}
+            _ => break,
+        }
+    }
+    Ok(Minimum {
+        x,
+        residuals: r,
+        iterations,
+    })
+}
+
+/// Settings of differential evolution.
+pub(crate) struct Evolution {
+    pub population: usize,
+    pub generations: usize,
+    pub tolerance: f64,
+}
+
+/// Minimize the sum of squares within finite bounds by differential evolution (Storn and
+/// Price, 1997), with the rand/1/bin strategy. Iteration stops after the given number of
+/// generations, or when the sums of squares of the population agree within the relative
+/// tolerance.
+pub(crate) fn differential_evolution(
+    objective: &mut dyn Objective,
+    (lower, upper): (&[f64], &[f64]),
+    settings: &Evolution,
+    random: &mut Random,
+) -> Result<Minimum, Error> {
+    const WEIGHT: f64 = 0.8;
+    const CROSSOVER: f64 = 0.9;
+    let p = lower.len();
+    let size = settings.population.max(4);
+    let pick = |random: &mut Random, count: usize| {
+        ((random.uniform() * count as f64) as usize).min(count - 1)
+    };
+    let cost = |objective: &mut dyn Objective, x: &[f64]| {
+        objective
+            .residuals(x)
+            .map_or(f64::INFINITY, |r| sum_of_squares(&r))
+    };
+    let mut population: Vec<Vec<f64>> = (0..size)
+        .map(|_| {
+            (0..p)
+                .map(|k| lower[k] + random.uniform() * (upper[k] - lower[k]))
+                .collect()
+        })
+        .collect();
+    let mut costs: Vec<f64> = population.iter().map(|x| cost(objective, x)).collect();
+    let mut iterations = 0;
+    while iterations < settings.generations {
+        iterations += 1;
+        for i in 0..size {
+            let mut others = [i; 3];
+            for n in 0..3 {
+                while others[..n].contains(&others[n]) || others[n] == i {
+                    others[n] = pick(random, size);
+                }
+            }
+            let [a, b, c] = others;
+            let always = pick(random, p);
+            let trial: Vec<f64> = (0..p)
+                .map(|k| {
+                    if k != always && random.uniform() >= CROSSOVER {
+                        return population[i][k];
+                    }
+                    let mutant = population[a][k] + WEIGHT * (population[b][k] - population[c][k]);
+                    // Out of bounds, move halfway from the parent to the bound instead.
+                    if mutant < lower[k] {
+                        0.5 * (lower[k] + population[i][k])
+                    } else if mutant > upper[k] {
+                        0.5 * (upper[k] + population[i][k])
+                    } else {
+                        mutant
+                    }
+                })
+                .collect();
+            let trial_cost = cost(objective, &trial);
+            if trial_cost <= costs[i] {
+                population[i] = trial;
+                costs[i] = trial_cost;
+            }
+        }
+        let best = costs.iter().copied().fold(f64::INFINITY, f64::min);
+        let worst = costs.iter().copied().fold(0.0, f64::max);
+        if worst - best <= settings.tolerance * best.max(f64::MIN_POSITIVE) {
+            break;
+        }
+    }
+    let best = (0..size)
+        .min_by(|&i, &j| costs[i].total_cmp(&costs[j]))
+        .expect("the population is not empty");
+    let x = population.swap_remove(best);
+    Ok(Minimum {
+        residuals: objective.residuals(&x)?,
+        x,
+        iterations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rosenbrock's function as residuals `10 (y - x^2)` and `1 - x`.
+    struct Banana;
+
+    impl Objective for Banana {
+        fn residuals(&mut self, x: &[f64]) -> Result<Vec<f64>, Error> {
+            Ok(vec![10.0 * (x[1] - x[0] * x[0]), 1.0 - x[0]])
+        }
+
+        fn jacobian(&mut self, x: &[f64], _: &[f64]) -> Result<Vec<Vec<f64>>, Error> {
+            Ok(vec![vec![-20.0 * x[0], 10.0], vec![-1.0, 0.0]])
+        }
+    }
+
+    #[test]
+    fn minimizes_the_rosenbrock_function() {
+        let inf = f64::INFINITY;
+        let bounds: (&[f64], &[f64]) = (&[-inf, -inf], &[inf, inf]);
+        let local = levenberg_marquardt(&mut Banana, vec![-1.2, 1.0], bounds, 100, 1e-15).unwrap();
+        assert!((local.x[0] - 1.0).abs() < 1e-6 && (local.x[1] - 1.0).abs() < 1e-6);
+
+        // Within bounds that exclude the minimum, the best point is on the boundary.
+        let bounds: (&[f64], &[f64]) = (&[-2.0, -2.0], &[0.5, 2.0]);
+        let settings = Evolution {
+            population: 20,
+            generations: 300,
+            tolerance: 1e-10,
+        };
+        let global =
+            differential_evolution(&mut Banana, bounds, &settings, &mut Random::new(1)).unwrap();
+        assert!((global.x[0] - 0.5).abs() < 1e-3 && (global.x[1] - 0.25).abs() < 1e-3);
+    }
+}
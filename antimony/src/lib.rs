@@ -1,3 +1,54 @@
+//! High-level bindings for the Antimony biochemical-modeling library
+//!
+//! libAntimony reads models written in Antimony, SBML, or CellML into process-global state and
+//! answers queries about them through a plain C API (see the `antimony-sys` crate). This crate
+//! loads a file or string once, copies every module out of libAntimony into an owned
+//! `Document`, and releases the library again, so the rest of a program works with ordinary
+//! Rust values:
+//!
+//! ```no_run
+//! let doc = antimony::Document::load_antimony_str("S1 -> S2; k1*S1; S1 = 10; k1 = 0.1")?;
+//! for species in doc.main().species() {
+//!     println!("{} = {:?}", species.id, species.initial);
+//! }
+//! # Ok::<(), antimony::Error>(())
+//! ```
+//!
+//! Models can also be edited or assembled in Rust and written back out as Antimony text with
+//! `Module::to_antimony` and `Document::to_antimony`, which libAntimony itself cannot do, or built
+//! from scratch with `builder::ModelBuilder`. Their formulas can be parsed, evaluated, and
+//! differentiated with `math`, the models themselves simulated with `sim` and fitted to data with
+//! `fit`, and their reaction networks analyzed with `structural` and `fba`.
+//!
+//! # Features
+//!
+//! - `serde`: implement `Serialize` and `Deserialize` for the model types; see `schema`.
+//! - `rayon`: run the simulations of a parameter scan in parallel; see `sim::Scan`.
+//! - `arrow`: convert results to Arrow record batches; see `sim::TimeCourse::to_record_batch`.
+//! - `parquet`: write results as Parquet files, with `sim::TimeCourse::write_parquet`.
+
+pub mod builder;
+mod error;
+mod extract;
+pub mod fba;
+mod ffi;
+pub mod fit;
+mod linalg;
+pub mod math;
+pub mod model;
+#[cfg(feature = "serde")]
+pub mod schema;
+pub mod sim;
+pub mod structural;
+#[cfg(test)]
+mod testing;
+mod writer;
+
+pub use antimony_sys::{FormulaKind, Interaction, SymbolKind};
+
+pub use crate::error::Error;
+pub use crate::model::{Document, Module};
+
 #[cfg(test)]
 mod tests {
     #[test]
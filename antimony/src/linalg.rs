@@ -0,0 +1,321 @@
+//! Dense linear algebra for the numerical methods.
+
+/// An LU factorization with partial pivoting of a square matrix.
+#[derive(Debug, Clone)]
+pub(crate) struct Lu {
+    n: usize,
+    /// `L` (below the diagonal, with an implicit unit diagonal) and `U`, row-major.
+    lu: Vec<f64>,
+    pivots: Vec<usize>,
+}
+
+impl Lu {
+    /// Factor `a`, or return `None` if it is singular to working precision.
+    pub fn new(a: &[Vec<f64>]) -> Option<Lu> {
+        let n = a.len();
+        let mut lu: Vec<f64> = a.iter().flat_map(|row| row.iter().copied()).collect();
+        let mut pivots: Vec<usize> = (0..n).collect();
+        let scale = lu.iter().fold(0.0f64, |m, x| m.max(x.abs()));
+        for k in 0..n {
+            let p = (k..n)
+                .max_by(|&i, &j| lu[i * n + k].abs().total_cmp(&lu[j * n + k].abs()))
+                .expect("k < n");
+            let pivot = lu[p * n + k].abs();
+            if pivot.is_nan() || pivot <= scale * f64::EPSILON * n as f64 {
+                return None;
+            }
+            if p != k {
+                for j in 0..n {
+                    lu.swap(p * n + j, k * n + j);
+                }
+                pivots.swap(p, k);
+            }
+            let pivot = lu[k * n + k];
+            for i in k + 1..n {
+                let factor = lu[i * n + k] / pivot;
+                lu[i * n + k] = factor;
+                if factor != 0.0 {
+                    for j in k + 1..n {
+                        lu[i * n + j] -= factor * lu[k * n + j];
+                    }
+                }
+            }
+        }
+        Some(Lu { n, lu, pivots })
+    }
+
+    /// Solve `A x = b`, overwriting `b` with `x`.
+    pub fn solve(&self, b: &mut [f64]) {
+        let n = self.n;
+        let mut x: Vec<f64> = self.pivots.iter().map(|&p| b[p]).collect();
+        for i in 0..n {
+            let sum: f64 = (0..i).map(|j| self.lu[i * n + j] * x[j]).sum();
+            x[i] -= sum;
+        }
+        for i in (0..n).rev() {
+            let sum: f64 = (i + 1..n).map(|j| self.lu[i * n + j] * x[j]).sum();
+            x[i] = (x[i] - sum) / self.lu[i * n + i];
+        }
+        b.copy_from_slice(&x);
+    }
+}
+
+/// The eigenvalues of a square matrix, as `(real, imaginary)` pairs, or `None` if the QR
+/// iteration does not converge.
+///
+/// The matrix is reduced to upper Hessenberg form by elimination with pivoting, whose
+/// eigenvalues are then found by the shifted QR algorithm with Francis double steps (`elmhes`
+/// and `hqr` of Numerical Recipes). Complex eigenvalues come in conjugate pairs.
+pub(crate) fn eigenvalues(a: &[Vec<f64>]) -> Option<Vec<(f64, f64)>> {
+    let n = a.len();
+    let mut a = a.to_vec();
+    hessenberg(&mut a);
+    let mut roots = Vec::with_capacity(n);
+    if n == 0 {
+        return Some(roots);
+    }
+    let mut norm = 0.0;
+    for (i, row) in a.iter().enumerate() {
+        norm += row[i.saturating_sub(1)..]
+            .iter()
+            .map(|x| x.abs())
+            .sum::<f64>();
+    }
+    let mut nn = n - 1;
+    // The accumulated exceptional shifts.
+    let mut shift = 0.0;
+    'deflate: loop {
+        let mut iterations = 0;
+        loop {
+            // Look for a negligible subdiagonal element, splitting off the block below it.
+            let mut l = nn;
+            while l > 0 {
+                let mut s = a[l - 1][l - 1].abs() + a[l][l].abs();
+                if s == 0.0 {
+                    s = norm;
+                }
+                if a[l][l - 1].abs() + s == s {
+                    a[l][l - 1] = 0.0;
+                    break;
+                }
+                l -= 1;
+            }
+            let mut x = a[nn][nn];
+            if l == nn {
+                roots.push((x + shift, 0.0));
+                if nn == 0 {
+                    break 'deflate;
+                }
+                nn -= 1;
+                continue 'deflate;
+            }
+            let mut y = a[nn - 1][nn - 1];
+            let mut w = a[nn][nn - 1] * a[nn - 1][nn];
+            if l == nn - 1 {
+                let p = 0.5 * (y - x);
+                let q = p * p + w;
+                let z = q.abs().sqrt();
+                x += shift;
+                if q >= 0.0 {
+                    let z = p + z.copysign(p);
+                    roots.push((x + z, 0.0));
+                    roots.push((if z != 0.0 { x - w / z } else { x + z }, 0.0));
+                } else {
+                    roots.push((x + p, z));
+                    roots.push((x + p, -z));
+                }
+                if nn < 2 {
+                    break 'deflate;
+                }
+                nn -= 2;
+                continue 'deflate;
+            }
+            if iterations == 30 {
+                return None;
+            }
+            if iterations == 10 || iterations == 20 {
+                shift += x;
+                for (i, row) in a.iter_mut().enumerate().take(nn + 1) {
+                    row[i] -= x;
+                }
+                let s = a[nn][nn - 1].abs() + a[nn - 1][nn - 2].abs();
+                x = 0.75 * s;
+                y = x;
+                w = -0.4375 * s * s;
+            }
+            iterations += 1;
+
+            // Find two consecutive small subdiagonal elements to start the double step at.
+            let (mut p, mut q, mut r, mut z);
+            let mut m = nn - 2;
+            loop {
+                z = a[m][m];
+                r = x - z;
+                let s = y - z;
+                p = (r * s - w) / a[m + 1][m] + a[m][m + 1];
+                q = a[m + 1][m + 1] - z - r - s;
+                r = a[m + 2][m + 1];
+                let s = p.abs() + q.abs() + r.abs();
+                p /= s;
+                q /= s;
+                r /= s;
+                if m == l {
+                    break;
+                }
+                let u = a[m][m - 1].abs() * (q.abs() + r.abs());
+                let v = p.abs() * (a[m - 1][m - 1].abs() + z.abs() + a[m + 1][m + 1].abs());
+                if u + v == v {
+                    break;
+                }
+                m -= 1;
+            }
+            for i in m + 2..=nn {
+                a[i][i - 2] = 0.0;
+                if i != m + 2 {
+                    a[i][i - 3] = 0.0;
+                }
+            }
+            // The double step, by Householder reflections on rows and columns `l..=nn`.
+            for k in m..nn {
+                if k != m {
+                    p = a[k][k - 1];
+                    q = a[k + 1][k - 1];
+                    r = if k != nn - 1 { a[k + 2][k - 1] } else { 0.0 };
+                    x = p.abs() + q.abs() + r.abs();
+                    if x != 0.0 {
+                        p /= x;
+                        q /= x;
+                        r /= x;
+                    }
+                }
+                let s = (p * p + q * q + r * r).sqrt().copysign(p);
+                if s == 0.0 {
+                    continue;
+                }
+                if k == m {
+                    if l != m {
+                        a[k][k - 1] = -a[k][k - 1];
+                    }
+                } else {
+                    a[k][k - 1] = -s * x;
+                }
+                p += s;
+                x = p / s;
+                y = q / s;
+                z = r / s;
+                q /= p;
+                r /= p;
+                let (upper, lower) = a.split_at_mut(k + 1);
+                let (next, rest) = lower.split_at_mut(1);
+                let (row, next) = (&mut upper[k], &mut next[0]);
+                let mut last = rest.first_mut().filter(|_| k != nn - 1);
+                for j in k..=nn {
+                    let mut p = row[j] + q * next[j];
+                    if let Some(last) = &mut last {
+                        p += r * last[j];
+                        last[j] -= p * z;
+                    }
+                    next[j] -= p * y;
+                    row[j] -= p * x;
+                }
+                for row in &mut a[l..=nn.min(k + 3)] {
+                    let mut p = x * row[k] + y * row[k + 1];
+                    if k != nn - 1 {
+                        p += z * row[k + 2];
+                        row[k + 2] -= p * r;
+                    }
+                    row[k + 1] -= p * q;
+                    row[k] -= p;
+                }
+            }
+        }
+    }
+    Some(roots)
+}
+
+/// Reduce `a` to upper Hessenberg form with the same eigenvalues, by Gaussian elimination
+/// with pivoting.
+fn hessenberg(a: &mut [Vec<f64>]) {
+    let n = a.len();
+    for m in 1..n.saturating_sub(1) {
+        let i = (m..n)
+            .max_by(|&i, &j| a[i][m - 1].abs().total_cmp(&a[j][m - 1].abs()))
+            .expect("m < n");
+        let x = a[i][m - 1];
+        if i != m {
+            a.swap(i, m);
+            for row in a.iter_mut() {
+                row.swap(i, m);
+            }
+        }
+        if x == 0.0 {
+            continue;
+        }
+        for i in m + 1..n {
+            let y = a[i][m - 1] / x;
+            if y == 0.0 {
+                continue;
+            }
+            a[i][m - 1] = 0.0;
+            let (upper, lower) = a.split_at_mut(i);
+            for (x, pivot) in lower[0][m..].iter_mut().zip(&upper[m][m..]) {
+                *x -= y * pivot;
+            }
+            for row in a.iter_mut() {
+                row[m] += y * row[i];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solves_with_pivoting() {
+        let a = vec![
+            vec![0.0, 2.0, 1.0],
+            vec![1.0, 1.0, 0.0],
+            vec![2.0, 0.0, 3.0],
+        ];
+        let lu = Lu::new(&a).unwrap();
+        let mut b = vec![7.0, 3.0, 11.0];
+        lu.solve(&mut b);
+        for (x, expected) in b.iter().zip(&[1.0, 2.0, 3.0]) {
+            assert!((x - expected).abs() < 1e-12);
+        }
+        assert!(Lu::new(&[vec![1.0, 2.0], vec![2.0, 4.0]]).is_none());
+    }
+
+    #[test]
+    fn finds_real_and_complex_eigenvalues() {
+        let sorted = |a: &[Vec<f64>]| {
+            let mut roots = eigenvalues(a).unwrap();
+            roots.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+            roots
+        };
+        let close = |roots: &[(f64, f64)], expected: &[(f64, f64)]| {
+            roots.len() == expected.len()
+                && roots
+                    .iter()
+                    .zip(expected)
+                    .all(|(a, b)| (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9)
+        };
+        let a = vec![vec![0.0, 1.0], vec![-2.0, -3.0]];
+        assert!(close(&sorted(&a), &[(-2.0, 0.0), (-1.0, 0.0)]));
+        let a = vec![vec![1.0, -2.0], vec![2.0, 1.0]];
+        assert!(close(&sorted(&a), &[(1.0, -2.0), (1.0, 2.0)]));
+        // The companion matrix of (x - 1)(x - 2)(x - 3)(x^2 + 1).
+        let a = vec![
+            vec![6.0, -12.0, 12.0, -11.0, 6.0],
+            vec![1.0, 0.0, 0.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0, 0.0, 0.0],
+            vec![0.0, 0.0, 1.0, 0.0, 0.0],
+            vec![0.0, 0.0, 0.0, 1.0, 0.0],
+        ];
+        let expected = [(0.0, -1.0), (0.0, 1.0), (1.0, 0.0), (2.0, 0.0), (3.0, 0.0)];
+        assert!(close(&sorted(&a), &expected), "{:?}", sorted(&a));
+        assert_eq!(eigenvalues(&[]), Some(Vec::new()));
+    }
+}
@@ -0,0 +1,343 @@
+//! Symbolic differentiation and Jacobian matrices.
+
+use super::{BinaryOp, Builtin, Env, Error, EvalError, Expr, Function, UnaryOp};
+use crate::Module;
+
+fn num(n: f64) -> Expr {
+    // Adding zero turns `-0` into `0`, which prints without a sign.
+    Expr::Number(n + 0.0)
+}
+
+fn is_num(e: &Expr, n: f64) -> bool {
+    matches!(e, Expr::Number(m) if *m == n)
+}
+
+// Constructors that drop the zeros and ones the differentiation rules produce, so that
+// derivatives stay readable without a full simplification pass.
+
+fn add(a: Expr, b: Expr) -> Expr {
+    if let (Expr::Number(x), Expr::Number(y)) = (&a, &b) {
+        num(x + y)
+    } else if is_num(&a, 0.0) {
+        b
+    } else if is_num(&b, 0.0) {
+        a
+    } else {
+        Expr::binary(BinaryOp::Add, a, b)
+    }
+}
+
+fn sub(a: Expr, b: Expr) -> Expr {
+    if let (Expr::Number(x), Expr::Number(y)) = (&a, &b) {
+        num(x - y)
+    } else if is_num(&b, 0.0) {
+        a
+    } else if is_num(&a, 0.0) {
+        neg(b)
+    } else {
+        Expr::binary(BinaryOp::Sub, a, b)
+    }
+}
+
+fn mul(a: Expr, b: Expr) -> Expr {
+    if let (Expr::Number(x), Expr::Number(y)) = (&a, &b) {
+        num(x * y)
+    } else if is_num(&a, 0.0) || is_num(&b, 0.0) {
+        num(0.0)
+    } else if is_num(&a, 1.0) {
+        b
+    } else if is_num(&b, 1.0) {
+        a
+    } else {
+        Expr::binary(BinaryOp::Mul, a, b)
+    }
+}
+
+fn div(a: Expr, b: Expr) -> Expr {
+    if is_num(&a, 0.0) {
+        num(0.0)
+    } else if is_num(&b, 1.0) {
+        a
+    } else {
+        Expr::binary(BinaryOp::Div, a, b)
+    }
+}
+
+fn pow(a: Expr, b: Expr) -> Expr {
+    if is_num(&b, 1.0) {
+        a
+    } else {
+        Expr::binary(BinaryOp::Pow, a, b)
+    }
+}
+
+fn neg(a: Expr) -> Expr {
+    match a {
+        Expr::Number(n) => num(-n),
+        Expr::Unary(UnaryOp::Neg, e) => *e,
+        a => Expr::unary(UnaryOp::Neg, a),
+    }
+}
+
+fn call(b: Builtin, a: &Expr) -> Expr {
+    Expr::builtin(b, vec![a.clone()])
+}
+
+fn sq(a: &Expr) -> Expr {
+    pow(a.clone(), num(2.0))
+}
+
+impl Expr {
+    /// The partial derivative with respect to a symbol.
+    ///
+    /// Step functions (`floor`, `ceil`, `quotient`, comparisons, and logical operators) have a
+    /// derivative of zero, and piecewise expressions are differentiated piece by piece. Calls of
+    /// user-defined functions must be inlined first (see `Expr::inline`); they, `delay`, and
+    /// `factorial` yield `Error::NotDifferentiable`.
+    pub fn derivative(&self, var: &str) -> Result<Expr, Error> {
+        Ok(match self {
+            Expr::Number(_) | Expr::Boolean(_) | Expr::Constant(_) | Expr::Time => num(0.0),
+            Expr::Symbol(id) => num(if id == var { 1.0 } else { 0.0 }),
+            Expr::Unary(UnaryOp::Neg, e) => neg(e.derivative(var)?),
+            Expr::Unary(UnaryOp::Not, _) => num(0.0),
+            Expr::Binary(op, _, _) if op.is_logical() => num(0.0),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (&**a, &**b);
+                let (da, db) = (a.derivative(var)?, b.derivative(var)?);
+                match op {
+                    BinaryOp::Add => add(da, db),
+                    BinaryOp::Sub => sub(da, db),
+                    BinaryOp::Mul => add(mul(da, b.clone()), mul(a.clone(), db)),
+                    BinaryOp::Div => {
+                        if is_num(&db, 0.0) {
+                            div(da, b.clone())
+                        } else {
+                            div(sub(mul(da, b.clone()), mul(a.clone(), db)), sq(b))
+                        }
+                    }
+                    BinaryOp::Pow if is_num(&db, 0.0) => {
+                        mul(mul(b.clone(), pow(a.clone(), sub(b.clone(), num(1.0)))), da)
+                    }
+                    BinaryOp::Pow if is_num(&da, 0.0) => {
+                        mul(mul(self.clone(), call(Builtin::Ln, a)), db)
+                    }
+                    BinaryOp::Pow => mul(
+                        self.clone(),
+                        add(
+                            mul(db, call(Builtin::Ln, a)),
+                            div(mul(b.clone(), da), a.clone()),
+                        ),
+                    ),
+                    // a % b == a - b*quotient(a, b), and quotient is a step function.
+                    BinaryOp::Rem => sub(
+                        da,
+                        mul(
+                            db,
+                            Expr::builtin(Builtin::Quotient, vec![a.clone(), b.clone()]),
+                        ),
+                    ),
+                    _ => unreachable!("logical operators are handled above"),
+                }
+            }
+            Expr::Piecewise(pieces, otherwise) => {
+                let mut dpieces = Vec::with_capacity(pieces.len());
+                for (value, condition) in pieces {
+                    dpieces.push((value.derivative(var)?, condition.clone()));
+                }
+                let dotherwise = otherwise.derivative(var)?;
+                if dpieces.iter().all(|(d, _)| *d == dotherwise) {
+                    dotherwise
+                } else {
+                    Expr::Piecewise(dpieces, Box::new(dotherwise))
+                }
+            }
+            Expr::Call(Function::User(id), _) => return Err(Error::NotDifferentiable(id.clone())),
+            Expr::Call(Function::Builtin(b), args) => builtin(*b, args, var)?,
+        })
+    }
+}
+
+fn builtin(b: Builtin, args: &[Expr], var: &str) -> Result<Expr, Error> {
+    use self::Builtin::*;
+
+    if !b.accepts(args.len()) {
+        return Err(Error::Arity {
+            function: b.name().to_owned(),
+            found: args.len(),
+        });
+    }
+    let x = &args[0];
+    let dx = x.derivative(var)?;
+    if is_num(&dx, 0.0) && args[1..].iter().all(|a| !a.depends_on(var)) {
+        return Ok(num(0.0));
+    }
+    let one = || num(1.0);
+    // The derivative of the function with respect to its (single) argument.
+    let outer = match b {
+        Ceil | Floor | Quotient => return Ok(num(0.0)),
+        Factorial | Delay => return Err(Error::NotDifferentiable(b.name().to_owned())),
+        Log => {
+            // log(base, x) == ln(x)/ln(base)
+            let ratio = div(call(Ln, &args[1]), call(Ln, x));
+            return ratio.derivative(var);
+        }
+        Root => return pow(args[1].clone(), div(one(), x.clone())).derivative(var),
+        Min | Max => {
+            if args.len() == 1 {
+                return Ok(dx);
+            }
+            let extreme = Expr::builtin(b, args.to_vec());
+            let mut pieces = Vec::with_capacity(args.len() - 1);
+            for a in &args[..args.len() - 1] {
+                let is_extreme = Expr::binary(BinaryOp::Eq, a.clone(), extreme.clone());
+                pieces.push((a.derivative(var)?, is_extreme));
+            }
+            let last = args[args.len() - 1].derivative(var)?;
+            return Ok(Expr::Piecewise(pieces, Box::new(last)));
+        }
+        Abs => Expr::Piecewise(
+            vec![(num(-1.0), Expr::binary(BinaryOp::Lt, x.clone(), num(0.0)))],
+            Box::new(one()),
+        ),
+        Exp => call(Exp, x),
+        Ln => div(one(), x.clone()),
+        Log10 => div(one(), mul(x.clone(), call(Ln, &num(10.0)))),
+        Sqrt => div(one(), mul(num(2.0), call(Sqrt, x))),
+        Sin => call(Cos, x),
+        Cos => neg(call(Sin, x)),
+        Tan => sq(&call(Sec, x)),
+        Sec => mul(call(Sec, x), call(Tan, x)),
+        Csc => neg(mul(call(Csc, x), call(Cot, x))),
+        Cot => neg(sq(&call(Csc, x))),
+        Sinh => call(Cosh, x),
+        Cosh => call(Sinh, x),
+        Tanh => sq(&call(Sech, x)),
+        Sech => neg(mul(call(Sech, x), call(Tanh, x))),
+        Csch => neg(mul(call(Csch, x), call(Coth, x))),
+        Coth => neg(sq(&call(Csch, x))),
+        Asin => div(one(), call(Sqrt, &sub(one(), sq(x)))),
+        Acos => neg(div(one(), call(Sqrt, &sub(one(), sq(x))))),
+        Atan => div(one(), add(one(), sq(x))),
+        Asec => div(one(), mul(call(Abs, x), call(Sqrt, &sub(sq(x), one())))),
+        Acsc => neg(div(
+            one(),
+            mul(call(Abs, x), call(Sqrt, &sub(sq(x), one()))),
+        )),
+        Acot => neg(div(one(), add(one(), sq(x)))),
+        Asinh => div(one(), call(Sqrt, &add(sq(x), one()))),
+        Acosh => div(one(), call(Sqrt, &sub(sq(x), one()))),
+        Atanh | Acoth => div(one(), sub(one(), sq(x))),
+        Asech => neg(div(one(), mul(x.clone(), call(Sqrt, &sub(one(), sq(x)))))),
+        Acsch => neg(div(
+            one(),
+            mul(call(Abs, x), call(Sqrt, &add(one(), sq(x)))),
+        )),
+    };
+    Ok(mul(outer, dx))
+}
+
+/// The Jacobian matrix of a module's differential equations: entry `(i, j)` is the partial
+/// derivative of `d(x_i)/dt` with respect to `x_j`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jacobian {
+    /// The state variables, labeling both rows and columns; see `Module::rate_equations`.
+    pub variables: Vec<String>,
+    /// The entries, row-major.
+    pub entries: Vec<Vec<Expr>>,
+}
+
+impl Jacobian {
+    /// The entry for the given row and column variables, if both are state variables.
+    pub fn get(&self, row: &str, column: &str) -> Option<&Expr> {
+        let i = self.variables.iter().position(|v| v == row)?;
+        let j = self.variables.iter().position(|v| v == column)?;
+        Some(&self.entries[i][j])
+    }
+
+    /// Evaluate every entry, giving the numeric Jacobian at the state described by `env`.
+    pub fn eval(&self, env: &Env) -> Result<Vec<Vec<f64>>, EvalError> {
+        self.entries
+            .iter()
+            .map(|row| row.iter().map(|e| e.eval(env)).collect())
+            .collect()
+    }
+}
+
+impl Module {
+    /// The symbolic Jacobian of the module's differential equations.
+    ///
+    /// The rate equations are first expanded (see `Module::expand`), so that derivatives
+    /// propagate through assignment rules and user-defined functions, and each entry is
+    /// simplified.
+    pub fn jacobian(&self) -> Result<Jacobian, Error> {
+        let equations = self.rate_equations()?;
+        let variables: Vec<String> = equations.iter().map(|(id, _)| id.clone()).collect();
+        let mut entries = Vec::with_capacity(equations.len());
+        for (_, rhs) in &equations {
+            let rhs = self.expand(rhs)?;
+            let row = variables
+                .iter()
+                .map(|v| rhs.derivative(v).map(|d| d.simplify()))
+                .collect::<Result<_, _>>()?;
+            entries.push(row);
+        }
+        Ok(Jacobian { variables, entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::parse;
+    use crate::testing::Fixture;
+
+    fn d(formula: &str, var: &str) -> String {
+        parse(formula).unwrap().derivative(var).unwrap().to_string()
+    }
+
+    #[test]
+    fn differentiates_rate_laws() {
+        assert_eq!(d("k1*S1", "S1"), "k1");
+        assert_eq!(d("k1*S1", "S2"), "0");
+        assert_eq!(d("S1^2", "S1"), "2*S1");
+        assert_eq!(d("Vm*S/(Km + S)", "S"), "(Vm*(Km + S) - Vm*S)/(Km + S)^2");
+        assert_eq!(d("exp(-k*t)", "t"), "exp(-k*t)*-k");
+        assert_eq!(
+            parse("factorial(n)").unwrap().derivative("n"),
+            Err(Error::NotDifferentiable("factorial".into()))
+        );
+    }
+
+    #[test]
+    fn derivatives_agree_with_finite_differences() {
+        let mut env = Env::new();
+        env.set("a", 1.3).set("b", 0.4);
+        for formula in &["a^b", "arctan(a*b)", "log(b + 2, a)", "sqrt(a)/cosh(b)"] {
+            let e = parse(formula).unwrap();
+            let de = e.derivative("a").unwrap().eval(&env).unwrap();
+            let h = 1e-6;
+            let mut hi = env.clone();
+            hi.set("a", 1.3 + h);
+            let mut lo = env.clone();
+            lo.set("a", 1.3 - h);
+            let fd = (e.eval(&hi).unwrap() - e.eval(&lo).unwrap()) / (2.0 * h);
+            assert!((de - fd).abs() < 1e-6, "{}: {} vs {}", formula, de, fd);
+        }
+    }
+
+    #[test]
+    fn builds_jacobian_from_stoichiometry() {
+        let module = Fixture::new()
+            .species("S1", 3.0)
+            .species("S2", 5.0)
+            .parameter("k1", 2.0)
+            .reaction("J0", &[(1.0, "S1")], &[(1.0, "S2")], "k1*S1*S2")
+            .build();
+        let jacobian = module.jacobian().unwrap();
+        assert_eq!(jacobian.variables, ["S1", "S2"]);
+        assert_eq!(jacobian.get("S1", "S1").unwrap().to_string(), "-k1*S2");
+        let env = Env::from_module(&module).unwrap();
+        let numeric = jacobian.eval(&env).unwrap();
+        assert_eq!(numeric, [[-10.0, -6.0], [10.0, 6.0]]);
+    }
+}
@@ -0,0 +1,489 @@
+//! Numeric evaluation of expressions.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{BinaryOp, Builtin, Expr, Function, UnaryOp};
+use crate::model::Rule;
+use crate::Module;
+
+/// User-defined functions may call each other, but not this deeply.
+const MAX_DEPTH: usize = 64;
+
+/// Errors from evaluating an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// A symbol has no value in the environment.
+    UndefinedSymbol(String),
+    /// A function is neither built in nor defined in the environment.
+    UndefinedFunction(String),
+    /// A function was called with the wrong number of arguments.
+    Arity {
+        /// The function name.
+        function: String,
+        /// The number of arguments given.
+        found: usize,
+    },
+    /// A function was applied outside its domain, e.g., the logarithm of a negative number.
+    Domain {
+        /// The function or operator.
+        function: &'static str,
+        /// The offending argument.
+        argument: f64,
+    },
+    /// A division, remainder, or negative power of zero.
+    DivisionByZero,
+    /// `delay(x, tau)` with nonzero `tau`, which needs the history of `x`.
+    NoHistory,
+    /// User-defined functions call each other too deeply (most likely recursively).
+    Recursion(String),
+    /// Initial values depend on each other in a cycle; carries the symbols involved.
+    Cycle(Vec<String>),
+    /// A formula of the model failed to parse.
+    Parse(super::Error),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::UndefinedSymbol(id) => write!(f, "symbol `{}` has no value", id),
+            EvalError::UndefinedFunction(id) => write!(f, "function `{}` is not defined", id),
+            EvalError::Arity { function, found } => write!(
+                f,
+                "wrong number of arguments to `{}`: found {}",
+                function, found
+            ),
+            EvalError::Domain { function, argument } => {
+                write!(f, "`{}` is undefined for {}", function, argument)
+            }
+            EvalError::DivisionByZero => f.write_str("division by zero"),
+            EvalError::NoHistory => f.write_str("`delay` needs the history of its argument"),
+            EvalError::Recursion(id) => write!(f, "function `{}` recurses too deeply", id),
+            EvalError::Cycle(ids) => {
+                write!(f, "initial values depend on each other: {}", ids.join(", "))
+            }
+            EvalError::Parse(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for EvalError {}
+
+impl From<super::Error> for EvalError {
+    fn from(err: super::Error) -> EvalError {
+        EvalError::Parse(err)
+    }
+}
+
+/// Values of symbols, the current time, and user-defined functions.
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+    values: HashMap<String, f64>,
+    time: f64,
+    functions: HashMap<String, Arc<(Vec<String>, Expr)>>,
+}
+
+impl Env {
+    /// An empty environment at time zero.
+    pub fn new() -> Env {
+        Env::default()
+    }
+
+    /// The environment at time zero of a module: every function it defines, and the initial
+    /// value of every symbol with an initial value or assignment rule, evaluated in dependency
+    /// order.
+    pub fn from_module(module: &Module) -> Result<Env, EvalError> {
+        let mut env = Env::new();
+        for f in &module.functions {
+            env.define(&f.id, f.parameters.clone(), f.parse_body(module)?);
+        }
+        let mut pending = Vec::new();
+        for s in &module.symbols {
+            let formula = match &s.rule {
+                Some(Rule::Assignment(f)) => f,
+                _ => match &s.initial {
+                    Some(f) => f,
+                    None => continue,
+                },
+            };
+            pending.push((s.id.as_str(), module.formula(formula)?));
+        }
+        while !pending.is_empty() {
+            let before = pending.len();
+            let mut blocked = Vec::new();
+            for (id, expr) in pending {
+                match expr.eval(&env) {
+                    Ok(value) => {
+                        env.set(id, value);
+                    }
+                    Err(EvalError::UndefinedSymbol(_)) => blocked.push((id, expr)),
+                    Err(err) => return Err(err),
+                }
+            }
+            if blocked.len() == before {
+                // Nothing could be evaluated, so either a cycle or a missing value blocks it.
+                let (_, expr) = &blocked[0];
+                if let Err(EvalError::UndefinedSymbol(missing)) = expr.eval(&env) {
+                    if !blocked.iter().any(|(id, _)| *id == missing) {
+                        return Err(EvalError::UndefinedSymbol(missing));
+                    }
+                }
+                let ids = blocked.iter().map(|(id, _)| id.to_string()).collect();
+                return Err(EvalError::Cycle(ids));
+            }
+            pending = blocked;
+        }
+        Ok(env)
+    }
+
+    /// Set the value of a symbol.
+    pub fn set(&mut self, id: &str, value: f64) -> &mut Self {
+        match self.values.get_mut(id) {
+            Some(slot) => *slot = value,
+            None => {
+                self.values.insert(id.to_owned(), value);
+            }
+        }
+        self
+    }
+
+    /// The value of a symbol, if set.
+    pub fn get(&self, id: &str) -> Option<f64> {
+        self.values.get(id).copied()
+    }
+
+    /// Every symbol with a value.
+    pub fn values(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.values.iter().map(|(id, v)| (id.as_str(), *v))
+    }
+
+    /// Set the current time.
+    pub fn set_time(&mut self, time: f64) -> &mut Self {
+        self.time = time;
+        self
+    }
+
+    /// The current time.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Define a function with the given parameters and body.
+    pub fn define(&mut self, id: &str, parameters: Vec<String>, body: Expr) -> &mut Self {
+        self.functions
+            .insert(id.to_owned(), Arc::new((parameters, body)));
+        self
+    }
+}
+
+/// Values bound while evaluating the body of a user-defined function.
+type Locals<'a> = &'a [(&'a str, f64)];
+
+impl Expr {
+    /// Evaluate the expression. Booleans evaluate to `1.0` (true) and `0.0` (false), and any
+    /// nonzero number counts as true.
+    pub fn eval(&self, env: &Env) -> Result<f64, EvalError> {
+        eval(self, env, &[], 0)
+    }
+}
+
+pub(crate) fn truth(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn domain(function: &'static str, argument: f64) -> EvalError {
+    EvalError::Domain { function, argument }
+}
+
+fn eval(e: &Expr, env: &Env, locals: Locals, depth: usize) -> Result<f64, EvalError> {
+    let eval = |e: &Expr| eval(e, env, locals, depth);
+    Ok(match e {
+        Expr::Number(n) => *n,
+        Expr::Boolean(b) => truth(*b),
+        Expr::Constant(c) => c.value(),
+        Expr::Time => env.time,
+        Expr::Symbol(id) => match locals.iter().find(|(name, _)| name == id) {
+            Some((_, v)) => *v,
+            None => env
+                .get(id)
+                .ok_or_else(|| EvalError::UndefinedSymbol(id.clone()))?,
+        },
+        Expr::Unary(UnaryOp::Neg, e) => -eval(e)?,
+        Expr::Unary(UnaryOp::Not, e) => truth(eval(e)? == 0.0),
+        Expr::Binary(BinaryOp::And, a, b) => truth(eval(a)? != 0.0 && eval(b)? != 0.0),
+        Expr::Binary(BinaryOp::Or, a, b) => truth(eval(a)? != 0.0 || eval(b)? != 0.0),
+        Expr::Binary(op, a, b) => binary(*op, eval(a)?, eval(b)?)?,
+        Expr::Piecewise(pieces, otherwise) => {
+            for (value, condition) in pieces {
+                if eval(condition)? != 0.0 {
+                    return eval(value);
+                }
+            }
+            eval(otherwise)?
+        }
+        Expr::Call(Function::Builtin(b), args) if !b.accepts(args.len()) => {
+            return Err(arity(*b, args.len()))
+        }
+        Expr::Call(Function::Builtin(Builtin::Delay), args) => {
+            if eval(&args[1])? != 0.0 {
+                return Err(EvalError::NoHistory);
+            }
+            eval(&args[0])?
+        }
+        Expr::Call(Function::Builtin(b), args) => {
+            let args = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+            builtin(*b, &args)?
+        }
+        Expr::Call(Function::User(id), args) => {
+            let f = env
+                .functions
+                .get(id)
+                .ok_or_else(|| EvalError::UndefinedFunction(id.clone()))?;
+            let (parameters, body) = &**f;
+            if parameters.len() != args.len() {
+                return Err(EvalError::Arity {
+                    function: id.clone(),
+                    found: args.len(),
+                });
+            }
+            if depth >= MAX_DEPTH {
+                return Err(EvalError::Recursion(id.clone()));
+            }
+            let mut bound = Vec::with_capacity(args.len());
+            for (p, arg) in parameters.iter().zip(args) {
+                bound.push((p.as_str(), eval(arg)?));
+            }
+            self::eval(body, env, &bound, depth + 1)?
+        }
+    })
+}
+
+pub(crate) fn binary(op: BinaryOp, a: f64, b: f64) -> Result<f64, EvalError> {
+    Ok(match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div => {
+            if b == 0.0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            a / b
+        }
+        BinaryOp::Rem => {
+            if b == 0.0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            a % b
+        }
+        BinaryOp::Pow => {
+            if a == 0.0 && b < 0.0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            if a < 0.0 && b.fract() != 0.0 {
+                return Err(domain("pow", a));
+            }
+            a.powf(b)
+        }
+        BinaryOp::Lt => truth(a < b),
+        BinaryOp::Le => truth(a <= b),
+        BinaryOp::Gt => truth(a > b),
+        BinaryOp::Ge => truth(a >= b),
+        BinaryOp::Eq => truth(a == b),
+        BinaryOp::Ne => truth(a != b),
+        BinaryOp::And => truth(a != 0.0 && b != 0.0),
+        BinaryOp::Or => truth(a != 0.0 || b != 0.0),
+        BinaryOp::Xor => truth((a != 0.0) != (b != 0.0)),
+    })
+}
+
+fn arity(b: Builtin, found: usize) -> EvalError {
+    EvalError::Arity {
+        function: b.name().to_owned(),
+        found,
+    }
+}
+
+pub(crate) fn builtin(b: Builtin, args: &[f64]) -> Result<f64, EvalError> {
+    if !b.accepts(args.len()) {
+        return Err(arity(b, args.len()));
+    }
+    let x = args[0];
+    let checked = |name, ok: bool, value: f64| if ok { Ok(value) } else { Err(domain(name, x)) };
+    let reciprocal = |value: f64| {
+        if value == 0.0 {
+            Err(EvalError::DivisionByZero)
+        } else {
+            Ok(1.0 / value)
+        }
+    };
+    match b {
+        Builtin::Abs => Ok(x.abs()),
+        Builtin::Ceil => Ok(x.ceil()),
+        Builtin::Floor => Ok(x.floor()),
+        Builtin::Exp => Ok(x.exp()),
+        Builtin::Ln => checked("ln", x > 0.0, x.ln()),
+        Builtin::Log10 => checked("log10", x > 0.0, x.log10()),
+        Builtin::Log => {
+            let y = args[1];
+            if x <= 0.0 || x == 1.0 {
+                return Err(domain("log", x));
+            }
+            if y <= 0.0 {
+                return Err(domain("log", y));
+            }
+            Ok(y.ln() / x.ln())
+        }
+        Builtin::Sqrt => checked("sqrt", x >= 0.0, x.sqrt()),
+        Builtin::Root => {
+            let y = args[1];
+            if x == 0.0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            if y < 0.0 {
+                // Odd integer roots of negative numbers are real.
+                if x.fract() == 0.0 && x % 2.0 != 0.0 {
+                    return Ok(-(-y).powf(1.0 / x));
+                }
+                return Err(domain("root", y));
+            }
+            Ok(y.powf(1.0 / x))
+        }
+        Builtin::Factorial => {
+            if x < 0.0 || x.fract() != 0.0 {
+                return Err(domain("factorial", x));
+            }
+            // 171! is already beyond the range of `f64`, so larger arguments need no loop.
+            if x > 170.0 {
+                return Ok(f64::INFINITY);
+            }
+            Ok((1..=x as u64).fold(1.0, |acc, k| acc * k as f64))
+        }
+        Builtin::Sin => Ok(x.sin()),
+        Builtin::Cos => Ok(x.cos()),
+        Builtin::Tan => Ok(x.tan()),
+        Builtin::Sec => reciprocal(x.cos()),
+        Builtin::Csc => reciprocal(x.sin()),
+        Builtin::Cot => reciprocal(x.tan()),
+        Builtin::Sinh => Ok(x.sinh()),
+        Builtin::Cosh => Ok(x.cosh()),
+        Builtin::Tanh => Ok(x.tanh()),
+        Builtin::Sech => reciprocal(x.cosh()),
+        Builtin::Csch => reciprocal(x.sinh()),
+        Builtin::Coth => reciprocal(x.tanh()),
+        Builtin::Asin => checked("arcsin", x.abs() <= 1.0, x.asin()),
+        Builtin::Acos => checked("arccos", x.abs() <= 1.0, x.acos()),
+        Builtin::Atan => Ok(x.atan()),
+        Builtin::Asec => checked("arcsec", x.abs() >= 1.0, (1.0 / x).acos()),
+        Builtin::Acsc => checked("arccsc", x.abs() >= 1.0, (1.0 / x).asin()),
+        Builtin::Acot => reciprocal(x).map(f64::atan),
+        Builtin::Asinh => Ok(x.asinh()),
+        Builtin::Acosh => checked("arccosh", x >= 1.0, x.acosh()),
+        Builtin::Atanh => checked("arctanh", x.abs() < 1.0, x.atanh()),
+        Builtin::Asech => checked("arcsech", x > 0.0 && x <= 1.0, (1.0 / x).acosh()),
+        Builtin::Acsch => reciprocal(x).map(f64::asinh),
+        Builtin::Acoth => checked("arccoth", x.abs() > 1.0, (1.0 / x).atanh()),
+        Builtin::Quotient => {
+            if args[1] == 0.0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            Ok((x / args[1]).trunc())
+        }
+        Builtin::Min => Ok(args.iter().copied().fold(f64::INFINITY, f64::min)),
+        Builtin::Max => Ok(args.iter().copied().fold(f64::NEG_INFINITY, f64::max)),
+        Builtin::Delay => Ok(x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::parse;
+    use crate::model::{Function as FunctionDef, Symbol};
+    use crate::SymbolKind;
+
+    fn eval_str(formula: &str, env: &Env) -> Result<f64, EvalError> {
+        parse(formula).unwrap().eval(env)
+    }
+
+    #[test]
+    fn evaluates_with_symbols_and_time() {
+        let mut env = Env::new();
+        env.set("S1", 4.0).set("k1", 0.5).set_time(2.0);
+        assert_eq!(eval_str("k1*S1^2 + time", &env), Ok(10.0));
+        assert_eq!(eval_str("piecewise(1, S1 > 5, 2)", &env), Ok(2.0));
+        assert_eq!(eval_str("max(S1, k1, 7)", &env), Ok(7.0));
+        assert_eq!(
+            eval_str("S2 + 1", &env),
+            Err(EvalError::UndefinedSymbol("S2".into()))
+        );
+    }
+
+    #[test]
+    fn reports_domain_errors() {
+        let env = Env::new();
+        assert_eq!(eval_str("1/0", &env), Err(EvalError::DivisionByZero));
+        assert!(matches!(
+            eval_str("ln(-1)", &env),
+            Err(EvalError::Domain { function: "ln", .. })
+        ));
+        assert!(matches!(
+            eval_str("sqrt(-4)", &env),
+            Err(EvalError::Domain { .. })
+        ));
+        assert_eq!(eval_str("root(3, -8)", &env), Ok(-2.0));
+        assert_eq!(eval_str("factorial(5)", &env), Ok(120.0));
+        assert!(eval_str("factorial(170)", &env).unwrap().is_finite());
+        // Huge arguments overflow at once rather than looping.
+        let started = std::time::Instant::now();
+        assert_eq!(eval_str("factorial(1e18)", &env), Ok(f64::INFINITY));
+        assert!(started.elapsed().as_secs() < 1);
+        // Calls built directly, without the parser's checks, report a wrong arity.
+        let x = Expr::symbol("x");
+        for call in [
+            Expr::builtin(Builtin::Log, vec![Expr::Number(2.0)]),
+            Expr::builtin(Builtin::Delay, vec![x.clone()]),
+            Expr::builtin(Builtin::Max, Vec::new()),
+        ] {
+            assert!(matches!(call.eval(&env), Err(EvalError::Arity { .. })));
+        }
+        let short = Expr::builtin(Builtin::Root, vec![x]);
+        assert!(matches!(
+            short.derivative("x"),
+            Err(crate::math::Error::Arity { .. })
+        ));
+    }
+
+    #[test]
+    fn initial_values_follow_dependencies() {
+        let symbol = |id: &str, initial: &str| Symbol {
+            id: id.into(),
+            name: None,
+            kind: SymbolKind::FormulaConstant,
+            compartment: None,
+            initial: Some(initial.into()),
+            rule: None,
+        };
+        let mut module = Module {
+            symbols: vec![symbol("a", "sq(b) + 1"), symbol("b", "3")],
+            functions: vec![FunctionDef {
+                id: "sq".into(),
+                parameters: vec!["x".into()],
+                body: "x*x".into(),
+            }],
+            ..Module::default()
+        };
+        let env = Env::from_module(&module).unwrap();
+        assert_eq!(env.get("a"), Some(10.0));
+        module.symbols[1].initial = Some("a".into());
+        assert!(matches!(
+            Env::from_module(&module),
+            Err(EvalError::Cycle(_))
+        ));
+    }
+}
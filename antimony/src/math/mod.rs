@@ -0,0 +1,652 @@
+//! Formulas as typed expression trees.
+//!
+//! libAntimony returns every formula (rates, rules, triggers, assignments) as an infix string in
+//! the libSBML Level 3 syntax. `parse` turns such a string into an `Expr`, and
+//! `Module::formula` additionally checks that every symbol and function it mentions is defined
+//! by the module. `Expr::eval` computes the value of an expression in an `Env` of symbol
+//! values, `Expr::derivative` differentiates it symbolically, and `Module::jacobian` combines
+//! both with the stoichiometry matrix. `Expr::simplify` rewrites an expression into a canonical
+//! form, so that equivalent formulas compare equal and print the same way.
+//!
+//! ```
+//! use antimony::math::{self, BinaryOp, Expr};
+//!
+//! let expr = math::parse("k1*S1 - k2*S2").unwrap();
+//! assert!(matches!(expr, Expr::Binary(BinaryOp::Sub, _, _)));
+//! assert_eq!(expr.to_string(), "k1*S1 - k2*S2");
+//! ```
+
+mod diff;
+pub(crate) mod eval;
+mod ode;
+mod parse;
+mod simplify;
+
+use std::collections::BTreeSet;
+use std::error;
+use std::fmt;
+
+use crate::Module;
+
+pub use self::diff::Jacobian;
+pub use self::eval::{Env, EvalError};
+pub use self::parse::parse;
+
+/// A mathematical expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// A numeric literal.
+    Number(f64),
+    /// `true` or `false`.
+    Boolean(bool),
+    /// A named mathematical constant.
+    Constant(Constant),
+    /// The simulation time, `time`.
+    Time,
+    /// A reference to a symbol (or, within a function body, to a parameter).
+    Symbol(String),
+    /// A unary operation.
+    Unary(UnaryOp, Box<Expr>),
+    /// A binary operation.
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// A call of a built-in or user-defined function.
+    Call(Function, Vec<Expr>),
+    /// `piecewise(v1, c1, v2, c2, ..., otherwise)`: the value of the first piece whose
+    /// condition holds, or `otherwise`.
+    Piecewise(Vec<(Expr, Expr)>, Box<Expr>),
+}
+
+/// Named mathematical constants.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Constant {
+    /// `pi`
+    Pi,
+    /// `exponentiale`
+    E,
+    /// `avogadro`
+    Avogadro,
+    /// `INF`
+    Infinity,
+    /// `NaN`
+    NaN,
+}
+
+impl Constant {
+    /// The numeric value of the constant.
+    pub fn value(self) -> f64 {
+        match self {
+            Constant::Pi => std::f64::consts::PI,
+            Constant::E => std::f64::consts::E,
+            Constant::Avogadro => 6.022_140_76e23,
+            Constant::Infinity => f64::INFINITY,
+            Constant::NaN => f64::NAN,
+        }
+    }
+}
+
+/// Unary operators.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    /// `-x`
+    Neg,
+    /// `!x`
+    Not,
+}
+
+/// Binary operators.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    /// `a + b`
+    Add,
+    /// `a - b`
+    Sub,
+    /// `a * b`
+    Mul,
+    /// `a / b`
+    Div,
+    /// `a ^ b`
+    Pow,
+    /// `a % b`
+    Rem,
+    /// `a < b`
+    Lt,
+    /// `a <= b`
+    Le,
+    /// `a > b`
+    Gt,
+    /// `a >= b`
+    Ge,
+    /// `a == b`
+    Eq,
+    /// `a != b`
+    Ne,
+    /// `a && b`
+    And,
+    /// `a || b`
+    Or,
+    /// `xor(a, b)`
+    Xor,
+}
+
+impl BinaryOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or | BinaryOp::Xor => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq | BinaryOp::Ne => 3,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 4,
+            BinaryOp::Add | BinaryOp::Sub => 5,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 6,
+            BinaryOp::Pow => 8,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => " + ",
+            BinaryOp::Sub => " - ",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Pow => "^",
+            BinaryOp::Rem => " % ",
+            BinaryOp::Lt => " < ",
+            BinaryOp::Le => " <= ",
+            BinaryOp::Gt => " > ",
+            BinaryOp::Ge => " >= ",
+            BinaryOp::Eq => " == ",
+            BinaryOp::Ne => " != ",
+            BinaryOp::And => " && ",
+            BinaryOp::Or => " || ",
+            BinaryOp::Xor => ", ",
+        }
+    }
+
+    /// Whether the operator yields a boolean.
+    pub fn is_logical(self) -> bool {
+        !matches!(
+            self,
+            BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::Pow
+                | BinaryOp::Rem
+        )
+    }
+}
+
+/// The function called by `Expr::Call`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Function {
+    /// A function built into the formula syntax.
+    Builtin(Builtin),
+    /// A function defined by the model with `function name(...) ... end`.
+    User(String),
+}
+
+/// Functions built into the formula syntax.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    Abs,
+    Ceil,
+    Floor,
+    Exp,
+    /// Natural logarithm, `ln(x)`.
+    Ln,
+    /// Base-10 logarithm, `log10(x)` or `log(x)`.
+    Log10,
+    /// Logarithm with explicit base, `log(base, x)`.
+    Log,
+    Sqrt,
+    /// `root(n, x)`: the `n`th root of `x`.
+    Root,
+    Factorial,
+    Sin,
+    Cos,
+    Tan,
+    Sec,
+    Csc,
+    Cot,
+    Sinh,
+    Cosh,
+    Tanh,
+    Sech,
+    Csch,
+    Coth,
+    Asin,
+    Acos,
+    Atan,
+    Asec,
+    Acsc,
+    Acot,
+    Asinh,
+    Acosh,
+    Atanh,
+    Asech,
+    Acsch,
+    Acoth,
+    /// `quotient(a, b)`: `a / b` rounded toward zero.
+    Quotient,
+    /// The smallest of any number of arguments.
+    Min,
+    /// The largest of any number of arguments.
+    Max,
+    /// `delay(x, tau)`: the value of `x` at time `time - tau`.
+    Delay,
+}
+
+impl Builtin {
+    /// Every built-in function, with its name and the minimum and maximum number of arguments.
+    const TABLE: &'static [(Builtin, &'static str, usize, usize)] = &[
+        (Builtin::Abs, "abs", 1, 1),
+        (Builtin::Ceil, "ceil", 1, 1),
+        (Builtin::Floor, "floor", 1, 1),
+        (Builtin::Exp, "exp", 1, 1),
+        (Builtin::Ln, "ln", 1, 1),
+        (Builtin::Log10, "log10", 1, 1),
+        (Builtin::Log, "log", 2, 2),
+        (Builtin::Sqrt, "sqrt", 1, 1),
+        (Builtin::Root, "root", 2, 2),
+        (Builtin::Factorial, "factorial", 1, 1),
+        (Builtin::Sin, "sin", 1, 1),
+        (Builtin::Cos, "cos", 1, 1),
+        (Builtin::Tan, "tan", 1, 1),
+        (Builtin::Sec, "sec", 1, 1),
+        (Builtin::Csc, "csc", 1, 1),
+        (Builtin::Cot, "cot", 1, 1),
+        (Builtin::Sinh, "sinh", 1, 1),
+        (Builtin::Cosh, "cosh", 1, 1),
+        (Builtin::Tanh, "tanh", 1, 1),
+        (Builtin::Sech, "sech", 1, 1),
+        (Builtin::Csch, "csch", 1, 1),
+        (Builtin::Coth, "coth", 1, 1),
+        (Builtin::Asin, "arcsin", 1, 1),
+        (Builtin::Acos, "arccos", 1, 1),
+        (Builtin::Atan, "arctan", 1, 1),
+        (Builtin::Asec, "arcsec", 1, 1),
+        (Builtin::Acsc, "arccsc", 1, 1),
+        (Builtin::Acot, "arccot", 1, 1),
+        (Builtin::Asinh, "arcsinh", 1, 1),
+        (Builtin::Acosh, "arccosh", 1, 1),
+        (Builtin::Atanh, "arctanh", 1, 1),
+        (Builtin::Asech, "arcsech", 1, 1),
+        (Builtin::Acsch, "arccsch", 1, 1),
+        (Builtin::Acoth, "arccoth", 1, 1),
+        (Builtin::Quotient, "quotient", 2, 2),
+        (Builtin::Min, "min", 1, usize::MAX),
+        (Builtin::Max, "max", 1, usize::MAX),
+        (Builtin::Delay, "delay", 2, 2),
+    ];
+
+    /// The name of the function in formulas.
+    pub fn name(self) -> &'static str {
+        Builtin::TABLE
+            .iter()
+            .find(|entry| entry.0 == self)
+            .map(|entry| entry.1)
+            .expect("every builtin is in the table")
+    }
+
+    /// The minimum and maximum number of arguments.
+    pub fn arity(self) -> (usize, usize) {
+        Builtin::TABLE
+            .iter()
+            .find(|entry| entry.0 == self)
+            .map(|entry| (entry.2, entry.3))
+            .expect("every builtin is in the table")
+    }
+
+    /// Whether the function takes `count` arguments.
+    pub(crate) fn accepts(self, count: usize) -> bool {
+        let (min, max) = self.arity();
+        (min..=max).contains(&count)
+    }
+}
+
+/// Errors from parsing or resolving a formula.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The formula is not well-formed; `position` is a byte offset into it.
+    Syntax {
+        /// The byte offset of the problem.
+        position: usize,
+        /// A description of the problem.
+        message: String,
+    },
+    /// A function was called with the wrong number of arguments.
+    Arity {
+        /// The function name.
+        function: String,
+        /// The number of arguments given.
+        found: usize,
+    },
+    /// A symbol is not defined by the module.
+    UnknownSymbol(String),
+    /// A function is neither built in nor defined by the module.
+    UnknownFunction(String),
+    /// The derivative of an expression calling this function cannot be expressed.
+    NotDifferentiable(String),
+    /// A user-defined function calls itself, directly or through others, so it cannot be
+    /// inlined.
+    Recursion(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Syntax { position, message } => {
+                write!(f, "syntax error at offset {}: {}", position, message)
+            }
+            Error::Arity { function, found } => write!(
+                f,
+                "wrong number of arguments to `{}`: found {}",
+                function, found
+            ),
+            Error::UnknownSymbol(id) => write!(f, "unknown symbol `{}`", id),
+            Error::UnknownFunction(id) => write!(f, "unknown function `{}`", id),
+            Error::NotDifferentiable(id) => write!(f, "`{}` cannot be differentiated", id),
+            Error::Recursion(id) => write!(f, "function `{}` calls itself", id),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl Expr {
+    /// Shorthand for `Expr::Binary`.
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    /// Shorthand for `Expr::Unary`.
+    pub fn unary(op: UnaryOp, operand: Expr) -> Expr {
+        Expr::Unary(op, Box::new(operand))
+    }
+
+    /// Shorthand for a call of a built-in function.
+    pub fn builtin(function: Builtin, args: Vec<Expr>) -> Expr {
+        Expr::Call(Function::Builtin(function), args)
+    }
+
+    /// Shorthand for `Expr::Symbol`.
+    pub fn symbol(id: &str) -> Expr {
+        Expr::Symbol(id.to_owned())
+    }
+
+    /// Visit every subexpression, parents before children.
+    pub fn visit<'a, F: FnMut(&'a Expr)>(&'a self, f: &mut F) {
+        f(self);
+        match self {
+            Expr::Unary(_, e) => e.visit(f),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.visit(f);
+                rhs.visit(f);
+            }
+            Expr::Call(_, args) => args.iter().for_each(|e| e.visit(f)),
+            Expr::Piecewise(pieces, otherwise) => {
+                for (value, condition) in pieces {
+                    value.visit(f);
+                    condition.visit(f);
+                }
+                otherwise.visit(f);
+            }
+            _ => {}
+        }
+    }
+
+    /// The ids of every symbol referenced, in sorted order.
+    pub fn symbols(&self) -> BTreeSet<&str> {
+        let mut symbols = BTreeSet::new();
+        self.visit(&mut |e| {
+            if let Expr::Symbol(id) = e {
+                symbols.insert(id.as_str());
+            }
+        });
+        symbols
+    }
+
+    /// Whether the expression refers to the given symbol.
+    pub fn depends_on(&self, id: &str) -> bool {
+        let mut found = false;
+        self.visit(&mut |e| found |= matches!(e, Expr::Symbol(s) if s == id));
+        found
+    }
+
+    /// Replace every symbol for which `f` returns an expression.
+    pub fn substitute<F: FnMut(&str) -> Option<Expr>>(&self, f: &mut F) -> Expr {
+        match self {
+            Expr::Symbol(id) => f(id).unwrap_or_else(|| self.clone()),
+            Expr::Unary(op, e) => Expr::unary(*op, e.substitute(f)),
+            Expr::Binary(op, lhs, rhs) => Expr::binary(*op, lhs.substitute(f), rhs.substitute(f)),
+            Expr::Call(function, args) => Expr::Call(
+                function.clone(),
+                args.iter().map(|e| e.substitute(f)).collect(),
+            ),
+            Expr::Piecewise(pieces, otherwise) => Expr::Piecewise(
+                pieces
+                    .iter()
+                    .map(|(value, condition)| (value.substitute(f), condition.substitute(f)))
+                    .collect(),
+                Box::new(otherwise.substitute(f)),
+            ),
+            _ => self.clone(),
+        }
+    }
+
+    /// Replace every call of a user-defined function by the function's body, with the
+    /// arguments in place of its parameters.
+    pub fn inline(&self, module: &Module) -> Result<Expr, Error> {
+        self.inline_within(module, &mut Vec::new())
+    }
+
+    /// Inline the calls of an expression within the bodies of the functions in `stack`.
+    fn inline_within(&self, module: &Module, stack: &mut Vec<String>) -> Result<Expr, Error> {
+        let mut result = Ok(());
+        let expr = self.map_calls(&mut |id, args| {
+            if result.is_err() {
+                return None;
+            }
+            if stack.iter().any(|f| f == id) {
+                result = Err(Error::Recursion(id.to_owned()));
+                return None;
+            }
+            let f = match module.function(id) {
+                Some(f) if f.parameters.len() == args.len() => f,
+                Some(_) => {
+                    result = Err(Error::Arity {
+                        function: id.to_owned(),
+                        found: args.len(),
+                    });
+                    return None;
+                }
+                None => {
+                    result = Err(Error::UnknownFunction(id.to_owned()));
+                    return None;
+                }
+            };
+            stack.push(id.to_owned());
+            let body = f
+                .parse_body(module)
+                .and_then(|body| body.inline_within(module, stack));
+            stack.pop();
+            match body {
+                Ok(body) => Some(body.substitute(&mut |p| {
+                    let i = f.parameters.iter().position(|q| q == p)?;
+                    Some(args[i].clone())
+                })),
+                Err(err) => {
+                    result = Err(err);
+                    None
+                }
+            }
+        });
+        result.map(|()| expr)
+    }
+
+    /// Rebuild the expression bottom-up, replacing each call of a user-defined function for
+    /// which `f` returns an expression.
+    fn map_calls<F: FnMut(&str, &[Expr]) -> Option<Expr>>(&self, f: &mut F) -> Expr {
+        match self {
+            Expr::Unary(op, e) => Expr::unary(*op, e.map_calls(f)),
+            Expr::Binary(op, lhs, rhs) => Expr::binary(*op, lhs.map_calls(f), rhs.map_calls(f)),
+            Expr::Call(function, args) => {
+                let args: Vec<_> = args.iter().map(|e| e.map_calls(f)).collect();
+                match function {
+                    Function::User(id) => f(id, &args),
+                    Function::Builtin(_) => None,
+                }
+                .unwrap_or_else(|| Expr::Call(function.clone(), args))
+            }
+            Expr::Piecewise(pieces, otherwise) => Expr::Piecewise(
+                pieces
+                    .iter()
+                    .map(|(value, condition)| (value.map_calls(f), condition.map_calls(f)))
+                    .collect(),
+                Box::new(otherwise.map_calls(f)),
+            ),
+            _ => self.clone(),
+        }
+    }
+
+    /// Check that every symbol and function referenced is defined by the module.
+    pub fn resolve(&self, module: &Module) -> Result<(), Error> {
+        self.resolve_with(module, &[])
+    }
+
+    fn resolve_with(&self, module: &Module, locals: &[String]) -> Result<(), Error> {
+        let mut result = Ok(());
+        self.visit(&mut |e| {
+            if result.is_err() {
+                return;
+            }
+            match e {
+                Expr::Symbol(id) if !locals.contains(id) && module.symbol(id).is_none() => {
+                    result = Err(Error::UnknownSymbol(id.clone()));
+                }
+                Expr::Call(Function::User(id), args) => match module.function(id) {
+                    Some(f) if f.parameters.len() == args.len() => {}
+                    Some(_) => {
+                        result = Err(Error::Arity {
+                            function: id.clone(),
+                            found: args.len(),
+                        })
+                    }
+                    None => result = Err(Error::UnknownFunction(id.clone())),
+                },
+                _ => {}
+            }
+        });
+        result
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(op, _, _) if *op != BinaryOp::Xor => op.precedence(),
+            Expr::Unary(_, _) => 7,
+            Expr::Number(n) if n.is_sign_negative() => 7,
+            _ => 9,
+        }
+    }
+}
+
+impl Module {
+    /// Parse one of the module's formulas, checking that every symbol and function it refers
+    /// to is defined.
+    pub fn formula(&self, formula: &str) -> Result<Expr, Error> {
+        let expr = parse(formula)?;
+        expr.resolve(self)?;
+        Ok(expr)
+    }
+}
+
+impl crate::model::Function {
+    /// Parse the body of the function, checking that it only refers to its parameters, to
+    /// symbols of the module, and to functions the module defines.
+    pub fn parse_body(&self, module: &Module) -> Result<Expr, Error> {
+        let expr = parse(&self.body)?;
+        expr.resolve_with(module, &self.parameters)?;
+        Ok(expr)
+    }
+}
+
+/// Write `e`, parenthesized if it binds more loosely than `min`.
+fn write_operand(f: &mut fmt::Formatter, e: &Expr, min: u8) -> fmt::Result {
+    if e.precedence() < min {
+        write!(f, "({})", e)
+    } else {
+        write!(f, "{}", e)
+    }
+}
+
+fn write_number(f: &mut fmt::Formatter, n: f64) -> fmt::Result {
+    if n.is_nan() {
+        f.write_str("NaN")
+    } else if n.is_infinite() {
+        f.write_str(if n > 0.0 { "INF" } else { "-INF" })
+    } else if n != 0.0 && (n.abs() < 1e-4 || n.abs() >= 1e15) {
+        write!(f, "{:e}", n)
+    } else {
+        write!(f, "{}", n)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write_number(f, *n),
+            Expr::Boolean(b) => write!(f, "{}", b),
+            Expr::Constant(c) => f.write_str(match c {
+                Constant::Pi => "pi",
+                Constant::E => "exponentiale",
+                Constant::Avogadro => "avogadro",
+                Constant::Infinity => "INF",
+                Constant::NaN => "NaN",
+            }),
+            Expr::Time => f.write_str("time"),
+            Expr::Symbol(id) => f.write_str(id),
+            Expr::Unary(op, e) => {
+                f.write_str(match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                })?;
+                // Unary minus binds more loosely than `^`, so `-x^2` needs no parentheses.
+                write_operand(f, e, 7)
+            }
+            Expr::Binary(BinaryOp::Xor, lhs, rhs) => write!(f, "xor({}, {})", lhs, rhs),
+            Expr::Binary(op, lhs, rhs) => {
+                let p = op.precedence();
+                if *op == BinaryOp::Pow {
+                    // Right-associative: `a^b^c` is `a^(b^c)`.
+                    write_operand(f, lhs, p + 1)?;
+                    f.write_str(op.symbol())?;
+                    write_operand(f, rhs, p)
+                } else {
+                    write_operand(f, lhs, p)?;
+                    f.write_str(op.symbol())?;
+                    write_operand(f, rhs, p + 1)
+                }
+            }
+            Expr::Call(function, args) => {
+                match function {
+                    Function::Builtin(b) => f.write_str(b.name())?,
+                    Function::User(id) => f.write_str(id)?,
+                }
+                f.write_str("(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                f.write_str(")")
+            }
+            Expr::Piecewise(pieces, otherwise) => {
+                f.write_str("piecewise(")?;
+                for (value, condition) in pieces {
+                    write!(f, "{}, {}, ", value, condition)?;
+                }
+                write!(f, "{})", otherwise)
+            }
+        }
+    }
+}
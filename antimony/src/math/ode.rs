@@ -0,0 +1,110 @@
+//! The differential equations defined by a module.
+
+use super::{BinaryOp, Error, Expr};
+use crate::model::Rule;
+use crate::Module;
+
+impl Module {
+    /// The right-hand sides of the module's differential equations, as `(id, d(id)/dt)` pairs.
+    ///
+    /// The state variables are the variable species of the stoichiometry matrix that are not
+    /// governed by a rule, followed by every symbol with a rate rule. A species changes by the
+    /// sum of the rates of the reactions it takes part in, weighted by its stoichiometry and
+    /// divided by the size of its compartment (reaction rates are amounts per time, while
+    /// species values are concentrations). Reactions without a rate do not contribute.
+    pub fn rate_equations(&self) -> Result<Vec<(String, Expr)>, Error> {
+        let mut rates = Vec::with_capacity(self.stoichiometry.reactions.len());
+        for id in &self.stoichiometry.reactions {
+            rates.push(match self.reaction(id).and_then(|r| r.rate.as_ref()) {
+                Some(rate) => Some(self.formula(rate)?),
+                None => None,
+            });
+        }
+        let mut equations = Vec::new();
+        for (id, row) in self
+            .stoichiometry
+            .species
+            .iter()
+            .zip(&self.stoichiometry.matrix)
+        {
+            let symbol = match self.symbol(id) {
+                Some(s) if s.rule.is_none() && !s.is_constant() => s,
+                _ => continue,
+            };
+            let mut sum: Option<Expr> = None;
+            for (&n, rate) in row.iter().zip(&rates) {
+                let rate = match rate {
+                    Some(rate) if n != 0.0 => rate.clone(),
+                    _ => continue,
+                };
+                let (op, term) = if n.abs() == 1.0 {
+                    (
+                        if n > 0.0 {
+                            BinaryOp::Add
+                        } else {
+                            BinaryOp::Sub
+                        },
+                        rate,
+                    )
+                } else if n < 0.0 {
+                    let term = Expr::binary(BinaryOp::Mul, Expr::Number(-n), rate);
+                    (BinaryOp::Sub, term)
+                } else {
+                    (
+                        BinaryOp::Add,
+                        Expr::binary(BinaryOp::Mul, Expr::Number(n), rate),
+                    )
+                };
+                sum = Some(match sum {
+                    Some(sum) => Expr::binary(op, sum, term),
+                    None if op == BinaryOp::Sub => Expr::unary(super::UnaryOp::Neg, term),
+                    None => term,
+                });
+            }
+            let mut rhs = sum.unwrap_or(Expr::Number(0.0));
+            if let Some(compartment) = &symbol.compartment {
+                rhs = Expr::binary(BinaryOp::Div, rhs, Expr::symbol(compartment));
+            }
+            equations.push((id.clone(), rhs));
+        }
+        for s in &self.symbols {
+            if let Some(Rule::Rate(formula)) = &s.rule {
+                equations.push((s.id.clone(), self.formula(formula)?));
+            }
+        }
+        Ok(equations)
+    }
+
+    /// Rewrite an expression in terms of independent quantities: inline user-defined
+    /// functions, and replace every symbol governed by an assignment rule by its formula and
+    /// every reaction id by the reaction's rate, recursively.
+    pub fn expand(&self, expr: &Expr) -> Result<Expr, Error> {
+        self.expand_with(expr, &mut Vec::new())
+    }
+
+    fn expand_with(&self, expr: &Expr, stack: &mut Vec<String>) -> Result<Expr, Error> {
+        let mut result = Ok(());
+        let expr = expr.inline(self)?.substitute(&mut |id| {
+            if result.is_err() || stack.iter().any(|s| s == id) {
+                return None;
+            }
+            let formula = match self.symbol(id).and_then(|s| s.rule.as_ref()) {
+                Some(Rule::Assignment(f)) => f,
+                _ => self.reaction(id)?.rate.as_ref()?,
+            };
+            stack.push(id.to_owned());
+            let expanded = self
+                .formula(formula)
+                .and_then(|e| self.expand_with(&e, stack));
+            stack.pop();
+            match expanded {
+                Ok(e) => Some(e),
+                Err(err) => {
+                    result = Err(err);
+                    None
+                }
+            }
+        });
+        result.map(|()| expr)
+    }
+}
@@ -0,0 +1,459 @@
+//! A parser for libSBML's Level 3 infix formula syntax.
+//!
+//! Precedence, from loosest to tightest: `||`, `&&`, `==`/`!=`, `<`/`<=`/`>`/`>=`, `+`/`-`,
+//! `*`/`/`/`%`, unary `-`/`!`, and finally `^` (right-associative), so that `-x^2` is
+//! `-(x^2)`.
+
+use super::{BinaryOp, Builtin, Constant, Error, Expr, Function, UnaryOp};
+
+/// Parse a formula into an expression.
+pub fn parse(formula: &str) -> Result<Expr, Error> {
+    let mut parser = Parser {
+        tokens: lex(formula)?,
+        pos: 0,
+        end: formula.len(),
+    };
+    let expr = parser.expr(0)?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(expr),
+        Some((at, token)) => Err(syntax(*at, format!("unexpected {}", token.describe()))),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Number(n) => format!("number {}", n),
+            Token::Ident(id) => format!("`{}`", id),
+            Token::Op(op) => format!("`{}`", op),
+        }
+    }
+}
+
+const OPERATORS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "+", "-", "*", "/", "^", "%", "<", ">", "!", "(", ")", ",",
+];
+
+fn syntax(position: usize, message: String) -> Error {
+    Error::Syntax { position, message }
+}
+
+fn lex(s: &str) -> Result<Vec<(usize, Token)>, Error> {
+    let bytes = s.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+                let mut j = i + 1;
+                if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+                    j += 1;
+                }
+                if j < bytes.len() && bytes[j].is_ascii_digit() {
+                    i = j;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let n = s[start..i]
+                .parse()
+                .map_err(|_| syntax(start, format!("invalid number `{}`", &s[start..i])))?;
+            tokens.push((start, Token::Number(n)));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'.')
+            {
+                i += 1;
+            }
+            tokens.push((start, Token::Ident(s[start..i].to_owned())));
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| s[i..].starts_with(*op))
+                .ok_or_else(|| {
+                    let c = s[i..].chars().next().unwrap_or_default();
+                    syntax(i, format!("unexpected character `{}`", c))
+                })?;
+            tokens.push((i, Token::Op(op)));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+/// Binary operators with their precedence, loosest first.
+fn binary(op: &str) -> Option<(BinaryOp, u8)> {
+    let op = match op {
+        "||" => BinaryOp::Or,
+        "&&" => BinaryOp::And,
+        "==" => BinaryOp::Eq,
+        "!=" => BinaryOp::Ne,
+        "<" => BinaryOp::Lt,
+        "<=" => BinaryOp::Le,
+        ">" => BinaryOp::Gt,
+        ">=" => BinaryOp::Ge,
+        "+" => BinaryOp::Add,
+        "-" => BinaryOp::Sub,
+        "*" => BinaryOp::Mul,
+        "/" => BinaryOp::Div,
+        "%" => BinaryOp::Rem,
+        _ => return None,
+    };
+    Some((op, op.precedence()))
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(at, _)| *at)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        match self.peek() {
+            Some(Token::Op(found)) if *found == op => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), Error> {
+        match self.peek() {
+            Some(Token::Op(found)) if *found == op => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(token) => Err(syntax(
+                self.position(),
+                format!("expected `{}`, found {}", op, token.describe()),
+            )),
+            None => Err(syntax(self.end, format!("expected `{}`", op))),
+        }
+    }
+
+    /// Parse binary operations binding at least as tightly as `min`.
+    fn expr(&mut self, min: u8) -> Result<Expr, Error> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let (op, precedence) = match binary(op) {
+                Some(found) if found.1 >= min => found,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.expr(precedence + 1)?;
+            lhs = Expr::binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        match self.peek() {
+            Some(Token::Op("-")) => {
+                self.pos += 1;
+                Ok(match self.unary()? {
+                    Expr::Number(n) => Expr::Number(-n),
+                    e => Expr::unary(UnaryOp::Neg, e),
+                })
+            }
+            Some(Token::Op("+")) => {
+                self.pos += 1;
+                self.unary()
+            }
+            Some(Token::Op("!")) => {
+                self.pos += 1;
+                Ok(Expr::unary(UnaryOp::Not, self.unary()?))
+            }
+            _ => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, Error> {
+        let base = self.atom()?;
+        if let Some(Token::Op("^")) = self.peek() {
+            self.pos += 1;
+            // The exponent may itself carry a sign, as in `x^-1`.
+            let exponent = self.unary()?;
+            return Ok(Expr::binary(BinaryOp::Pow, base, exponent));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, Error> {
+        let at = self.position();
+        let token = match self.tokens.get(self.pos) {
+            Some((_, token)) => token.clone(),
+            None => return Err(syntax(self.end, "unexpected end of formula".into())),
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Op("(") => {
+                let e = self.expr(0)?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Token::Op(op) => Err(syntax(at, format!("unexpected `{}`", op))),
+            Token::Ident(id) => {
+                if let Some(Token::Op("(")) = self.peek() {
+                    self.pos += 1;
+                    let mut args = Vec::new();
+                    if !self.eat(")") {
+                        loop {
+                            args.push(self.expr(0)?);
+                            if self.eat(")") {
+                                break;
+                            }
+                            self.expect(",")?;
+                        }
+                    }
+                    call(&id, args)
+                } else {
+                    Ok(name(&id))
+                }
+            }
+        }
+    }
+}
+
+/// The expression for a bare name.
+fn name(id: &str) -> Expr {
+    match id {
+        "time" => Expr::Time,
+        "true" | "True" => Expr::Boolean(true),
+        "false" | "False" => Expr::Boolean(false),
+        "pi" => Expr::Constant(Constant::Pi),
+        "exponentiale" => Expr::Constant(Constant::E),
+        "avogadro" => Expr::Constant(Constant::Avogadro),
+        "INF" | "inf" | "infinity" | "Infinity" => Expr::Constant(Constant::Infinity),
+        "NaN" | "nan" | "notanumber" => Expr::Constant(Constant::NaN),
+        _ => Expr::Symbol(id.to_owned()),
+    }
+}
+
+/// Fold the arguments of an n-ary operator written as a function, e.g., `and(a, b, c)`.
+fn fold(id: &str, op: BinaryOp, args: Vec<Expr>) -> Result<Expr, Error> {
+    let mut args = args.into_iter();
+    let first = args.next().ok_or_else(|| arity(id, 0))?;
+    Ok(args.fold(first, |lhs, rhs| Expr::binary(op, lhs, rhs)))
+}
+
+fn arity(id: &str, found: usize) -> Error {
+    Error::Arity {
+        function: id.to_owned(),
+        found,
+    }
+}
+
+/// The expression for a call, mapping operators written as functions onto operators.
+fn call(id: &str, mut args: Vec<Expr>) -> Result<Expr, Error> {
+    let n = args.len();
+    let op = match id {
+        "pow" | "power" => Some(BinaryOp::Pow),
+        "rem" => Some(BinaryOp::Rem),
+        "eq" => Some(BinaryOp::Eq),
+        "neq" => Some(BinaryOp::Ne),
+        "lt" => Some(BinaryOp::Lt),
+        "leq" => Some(BinaryOp::Le),
+        "gt" => Some(BinaryOp::Gt),
+        "geq" => Some(BinaryOp::Ge),
+        "minus" if n == 2 => Some(BinaryOp::Sub),
+        "divide" => Some(BinaryOp::Div),
+        _ => None,
+    };
+    if let Some(op) = op {
+        if n != 2 {
+            return Err(arity(id, n));
+        }
+        let rhs = args.pop().unwrap();
+        let lhs = args.pop().unwrap();
+        return Ok(Expr::binary(op, lhs, rhs));
+    }
+    match id {
+        "plus" => return fold(id, BinaryOp::Add, args),
+        "times" => return fold(id, BinaryOp::Mul, args),
+        "and" => return fold(id, BinaryOp::And, args),
+        "or" => return fold(id, BinaryOp::Or, args),
+        "xor" => return fold(id, BinaryOp::Xor, args),
+        "minus" | "not" => {
+            if n != 1 {
+                return Err(arity(id, n));
+            }
+            let op = if id == "not" {
+                UnaryOp::Not
+            } else {
+                UnaryOp::Neg
+            };
+            return Ok(Expr::unary(op, args.pop().unwrap()));
+        }
+        "piecewise" => {
+            if n == 0 {
+                return Err(arity(id, n));
+            }
+            let otherwise = if n % 2 == 1 {
+                args.pop().unwrap()
+            } else {
+                Expr::Constant(Constant::NaN)
+            };
+            let mut pieces = Vec::new();
+            let mut args = args.into_iter();
+            while let (Some(value), Some(condition)) = (args.next(), args.next()) {
+                pieces.push((value, condition));
+            }
+            return Ok(Expr::Piecewise(pieces, Box::new(otherwise)));
+        }
+        "log" if n == 1 => return Ok(Expr::builtin(Builtin::Log10, args)),
+        _ => {}
+    }
+    let builtin = match id {
+        "ceiling" => Some(Builtin::Ceil),
+        "asin" => Some(Builtin::Asin),
+        "acos" => Some(Builtin::Acos),
+        "atan" => Some(Builtin::Atan),
+        "asinh" => Some(Builtin::Asinh),
+        "acosh" => Some(Builtin::Acosh),
+        "atanh" => Some(Builtin::Atanh),
+        _ => Builtin::TABLE
+            .iter()
+            .find(|entry| entry.1 == id)
+            .map(|entry| entry.0),
+    };
+    match builtin {
+        Some(builtin) => {
+            let (min, max) = builtin.arity();
+            if n < min || n > max {
+                return Err(arity(id, n));
+            }
+            Ok(Expr::builtin(builtin, args))
+        }
+        None => Ok(Expr::Call(Function::User(id.to_owned()), args)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sym(id: &str) -> Expr {
+        Expr::symbol(id)
+    }
+
+    #[test]
+    fn respects_precedence() {
+        let e = parse("-x^2 + a*b/c").unwrap();
+        let expected = Expr::binary(
+            BinaryOp::Add,
+            Expr::unary(
+                UnaryOp::Neg,
+                Expr::binary(BinaryOp::Pow, sym("x"), Expr::Number(2.0)),
+            ),
+            Expr::binary(
+                BinaryOp::Div,
+                Expr::binary(BinaryOp::Mul, sym("a"), sym("b")),
+                sym("c"),
+            ),
+        );
+        assert_eq!(e, expected);
+        assert_eq!(
+            parse("a - b - c").unwrap(),
+            Expr::binary(
+                BinaryOp::Sub,
+                Expr::binary(BinaryOp::Sub, sym("a"), sym("b")),
+                sym("c")
+            )
+        );
+        assert_eq!(
+            parse("2^3^2").unwrap(),
+            Expr::binary(
+                BinaryOp::Pow,
+                Expr::Number(2.0),
+                Expr::binary(BinaryOp::Pow, Expr::Number(3.0), Expr::Number(2.0))
+            )
+        );
+    }
+
+    #[test]
+    fn parses_functions_and_logic() {
+        let e = parse("piecewise(k1, time > 5 && !off, 0)").unwrap();
+        match e {
+            Expr::Piecewise(pieces, otherwise) => {
+                assert_eq!(pieces.len(), 1);
+                assert_eq!(*otherwise, Expr::Number(0.0));
+                assert!(matches!(pieces[0].1, Expr::Binary(BinaryOp::And, _, _)));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            parse("pow(S1, 2)").unwrap(),
+            Expr::binary(BinaryOp::Pow, sym("S1"), Expr::Number(2.0))
+        );
+        assert_eq!(
+            parse("delay(A.x, 1.5e-1)").unwrap(),
+            Expr::builtin(Builtin::Delay, vec![sym("A.x"), Expr::Number(0.15)])
+        );
+        assert_eq!(
+            parse("f(x)").unwrap(),
+            Expr::Call(Function::User("f".into()), vec![sym("x")])
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert!(matches!(
+            parse("k1 * (S1"),
+            Err(Error::Syntax { position: 8, .. })
+        ));
+        assert!(matches!(parse("exp(1, 2)"), Err(Error::Arity { .. })));
+        assert!(matches!(
+            parse("a $ b"),
+            Err(Error::Syntax { position: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn displays_round_trip() {
+        for formula in &[
+            "k1*S1 - k2*S2",
+            "-x^2",
+            "(-x)^2",
+            "a/(b*c)",
+            "a - (b - c)",
+            "2^(-1)",
+            "piecewise(1, time > 5, 0)",
+            "!(a && b) || c",
+            "xor(a, b)",
+            "Vm*S/(Km + S)",
+        ] {
+            let e = parse(formula).unwrap();
+            assert_eq!(e.to_string(), *formula);
+            assert_eq!(parse(&e.to_string()).unwrap(), e);
+        }
+    }
+}
@@ -0,0 +1,435 @@
+//! Algebraic simplification into a canonical form.
+//!
+//! Sums are collected into a constant plus a coefficient for each distinct term, and products
+//! into a coefficient times each distinct base raised to its total exponent. Rebuilding the
+//! expression from these collections folds constants, drops identities (`x + 0`, `1*x`, `x^1`),
+//! merges like terms (`x + x` becomes `2*x`, `x*x` becomes `x^2`), sorts the operands of `+`
+//! and `*`, and finally pulls factors common to every term of a sum out in front of it.
+
+use std::cmp::Ordering;
+
+use super::{BinaryOp, Env, Expr, Function, UnaryOp};
+
+impl Expr {
+    /// An equivalent expression in canonical form.
+    ///
+    /// Simplification assumes every subexpression is defined: `x/x` becomes `1` even though
+    /// it is undefined for `x = 0`. Calls of built-in functions with constant arguments are
+    /// folded unless they would fail to evaluate.
+    pub fn simplify(&self) -> Expr {
+        simplify(self)
+    }
+}
+
+fn num(n: f64) -> Expr {
+    // Adding zero turns `-0` into `0`, which prints without a sign.
+    Expr::Number(n + 0.0)
+}
+
+fn is_integer(n: f64) -> bool {
+    n.fract() == 0.0 && n.is_finite()
+}
+
+/// The order of terms in sums and of factors in products: numbers first, then simple terms
+/// alphabetically (ignoring case), then sums and piecewise expressions.
+fn order(a: &Expr, b: &Expr) -> Ordering {
+    fn rank(e: &Expr) -> u8 {
+        match e {
+            Expr::Number(_) | Expr::Boolean(_) | Expr::Constant(_) | Expr::Time => 0,
+            Expr::Symbol(_)
+            | Expr::Call(_, _)
+            | Expr::Binary(BinaryOp::Pow, _, _)
+            | Expr::Binary(BinaryOp::Mul, _, _)
+            | Expr::Binary(BinaryOp::Div, _, _) => 1,
+            _ => 2,
+        }
+    }
+    let (x, y) = (a.to_string(), b.to_string());
+    rank(a)
+        .cmp(&rank(b))
+        .then_with(|| x.to_lowercase().cmp(&y.to_lowercase()))
+        .then_with(|| x.cmp(&y))
+}
+
+/// `base^exponent`, or just `base` for an exponent of one.
+fn raise(base: Expr, exponent: Expr) -> Expr {
+    if exponent == num(1.0) {
+        base
+    } else {
+        Expr::binary(BinaryOp::Pow, base, exponent)
+    }
+}
+
+fn simplify(e: &Expr) -> Expr {
+    match e {
+        Expr::Binary(BinaryOp::Add, _, _)
+        | Expr::Binary(BinaryOp::Sub, _, _)
+        | Expr::Unary(UnaryOp::Neg, _) => {
+            let mut sum = Sum::default();
+            sum.add(e, 1.0);
+            sum.build()
+        }
+        Expr::Binary(BinaryOp::Mul, _, _) | Expr::Binary(BinaryOp::Div, _, _) => {
+            let mut product = Product::new();
+            product.mul(e, 1.0);
+            product.build()
+        }
+        Expr::Number(n) => num(*n),
+        Expr::Binary(BinaryOp::Pow, a, b) => power(simplify(a), simplify(b)),
+        Expr::Binary(op, a, b) => fold(Expr::binary(*op, simplify(a), simplify(b))),
+        Expr::Unary(UnaryOp::Not, a) => fold(Expr::unary(UnaryOp::Not, simplify(a))),
+        Expr::Call(f, args) => fold(Expr::Call(f.clone(), args.iter().map(simplify).collect())),
+        Expr::Piecewise(pieces, otherwise) => {
+            let mut kept = Vec::with_capacity(pieces.len());
+            for (value, condition) in pieces {
+                let (value, condition) = (simplify(value), simplify(condition));
+                match truth(&condition) {
+                    Some(false) => {}
+                    Some(true) => return finish_piecewise(kept, value),
+                    None => kept.push((value, condition)),
+                }
+            }
+            finish_piecewise(kept, simplify(otherwise))
+        }
+        _ => e.clone(),
+    }
+}
+
+fn finish_piecewise(pieces: Vec<(Expr, Expr)>, otherwise: Expr) -> Expr {
+    if pieces.iter().all(|(value, _)| *value == otherwise) {
+        otherwise
+    } else {
+        Expr::Piecewise(pieces, Box::new(otherwise))
+    }
+}
+
+/// The value of a constant condition.
+fn truth(e: &Expr) -> Option<bool> {
+    match e {
+        Expr::Boolean(b) => Some(*b),
+        Expr::Number(n) => Some(*n != 0.0),
+        _ => None,
+    }
+}
+
+fn is_constant(e: &Expr) -> bool {
+    matches!(e, Expr::Number(_) | Expr::Boolean(_))
+}
+
+/// Evaluate a node whose operands are already simplified, if they are all constant.
+fn fold(e: Expr) -> Expr {
+    let constant = match &e {
+        Expr::Binary(_, a, b) => is_constant(a) && is_constant(b),
+        Expr::Unary(_, a) => is_constant(a),
+        Expr::Call(Function::Builtin(_), args) => args.iter().all(is_constant),
+        _ => false,
+    };
+    if !constant {
+        return e;
+    }
+    let logical = match &e {
+        Expr::Binary(op, _, _) => op.is_logical(),
+        Expr::Unary(op, _) => *op == UnaryOp::Not,
+        _ => false,
+    };
+    match e.eval(&Env::new()) {
+        Ok(v) if logical => Expr::Boolean(v != 0.0),
+        Ok(v) if v.is_finite() => num(v),
+        _ => e,
+    }
+}
+
+/// `a^b` for simplified `a` and `b`.
+fn power(a: Expr, b: Expr) -> Expr {
+    match (&a, &b) {
+        (_, Expr::Number(k)) if *k == 0.0 => num(1.0),
+        (_, Expr::Number(k)) if *k == 1.0 => a,
+        (Expr::Number(x), _) if *x == 1.0 => num(1.0),
+        (Expr::Number(_), Expr::Number(_)) => fold(Expr::binary(BinaryOp::Pow, a, b)),
+        (_, Expr::Number(k)) if is_integer(*k) => {
+            let mut product = Product::new();
+            product.mul(&a, *k);
+            product.build()
+        }
+        _ => Expr::binary(BinaryOp::Pow, a, b),
+    }
+}
+
+/// A constant plus a weighted sum of terms, none of which has a numeric coefficient.
+#[derive(Default)]
+struct Sum {
+    constant: f64,
+    terms: Vec<(f64, Expr)>,
+}
+
+impl Sum {
+    /// Add `weight` times `e`.
+    fn add(&mut self, e: &Expr, weight: f64) {
+        match e {
+            Expr::Binary(BinaryOp::Add, a, b) => {
+                self.add(a, weight);
+                self.add(b, weight);
+            }
+            Expr::Binary(BinaryOp::Sub, a, b) => {
+                self.add(a, weight);
+                self.add(b, -weight);
+            }
+            Expr::Unary(UnaryOp::Neg, a) => self.add(a, -weight),
+            _ => match simplify(e) {
+                Expr::Number(n) => self.constant += weight * n,
+                s @ Expr::Binary(BinaryOp::Add, _, _)
+                | s @ Expr::Binary(BinaryOp::Sub, _, _)
+                | s @ Expr::Unary(UnaryOp::Neg, _) => self.add(&s, weight),
+                s => {
+                    let mut product = Product::new();
+                    product.mul(&s, 1.0);
+                    let coefficient = product.coefficient;
+                    product.coefficient = 1.0;
+                    self.term(weight * coefficient, product.build());
+                }
+            },
+        }
+    }
+
+    fn term(&mut self, coefficient: f64, term: Expr) {
+        match self.terms.iter_mut().find(|(_, t)| *t == term) {
+            Some((c, _)) => *c += coefficient,
+            None => self.terms.push((coefficient, term)),
+        }
+    }
+
+    fn build(mut self) -> Expr {
+        self.terms.retain(|(c, _)| *c != 0.0);
+        if self.terms.is_empty() {
+            return num(self.constant);
+        }
+        if self.constant == 0.0 && self.terms.len() > 1 {
+            if let Some(factored) = self.factor() {
+                return factored;
+            }
+        }
+        self.terms.sort_by(|(_, a), (_, b)| order(a, b));
+        let mut parts = self
+            .terms
+            .into_iter()
+            .map(|(c, t)| (c, t, false))
+            .collect::<Vec<_>>();
+        if self.constant != 0.0 {
+            parts.push((self.constant, num(1.0), true));
+        }
+        let mut result: Option<Expr> = None;
+        for (c, term, constant) in parts {
+            let magnitude = |c: f64| {
+                if constant {
+                    num(c)
+                } else {
+                    scale(c, term.clone())
+                }
+            };
+            result = Some(match result {
+                None => magnitude(c),
+                Some(sum) if c < 0.0 => Expr::binary(BinaryOp::Sub, sum, magnitude(-c)),
+                Some(sum) => Expr::binary(BinaryOp::Add, sum, magnitude(c)),
+            });
+        }
+        result.expect("at least one term")
+    }
+
+    /// Pull the factors shared by every term out of the sum, as in `k*a + k*b = k*(a + b)`.
+    fn factor(&self) -> Option<Expr> {
+        let products: Vec<Product> = self
+            .terms
+            .iter()
+            .map(|(c, t)| {
+                let mut p = Product::new();
+                p.mul(t, 1.0);
+                p.coefficient *= c;
+                p
+            })
+            .collect();
+        let mut common = Product::new();
+        for (base, exponent) in &products[0].factors {
+            let mut least = match exponent {
+                Expr::Number(k) if *k > 0.0 => *k,
+                _ => continue,
+            };
+            for p in &products[1..] {
+                match p.exponent(base) {
+                    Some(Expr::Number(k)) if *k > 0.0 => least = least.min(*k),
+                    _ => {
+                        least = 0.0;
+                        break;
+                    }
+                }
+            }
+            if least > 0.0 {
+                common.factor(base.clone(), num(least));
+            }
+        }
+        if common.factors.is_empty() {
+            return None;
+        }
+        let mut rest = Sum::default();
+        for mut p in products {
+            for (base, exponent) in &common.factors {
+                p.factor(
+                    base.clone(),
+                    simplify(&Expr::unary(UnaryOp::Neg, exponent.clone())),
+                );
+            }
+            let coefficient = p.coefficient;
+            p.coefficient = 1.0;
+            rest.add(&p.build(), coefficient);
+        }
+        common.factor(rest.build(), num(1.0));
+        Some(common.build())
+    }
+}
+
+/// `c*term` for a coefficient `c` and a term without one.
+fn scale(c: f64, term: Expr) -> Expr {
+    let mut product = Product::new();
+    product.mul(&term, 1.0);
+    product.coefficient *= c;
+    product.build()
+}
+
+/// A coefficient times powers of distinct bases.
+struct Product {
+    coefficient: f64,
+    factors: Vec<(Expr, Expr)>,
+}
+
+impl Product {
+    fn new() -> Product {
+        Product {
+            coefficient: 1.0,
+            factors: Vec::new(),
+        }
+    }
+
+    /// Multiply by `e` raised to the integer `exponent`.
+    fn mul(&mut self, e: &Expr, exponent: f64) {
+        match e {
+            Expr::Binary(BinaryOp::Mul, a, b) => {
+                self.mul(a, exponent);
+                self.mul(b, exponent);
+            }
+            Expr::Binary(BinaryOp::Div, a, b) => {
+                self.mul(a, exponent);
+                self.mul(b, -exponent);
+            }
+            Expr::Unary(UnaryOp::Neg, a) => {
+                self.coefficient *= (-1f64).powf(exponent);
+                self.mul(a, exponent);
+            }
+            Expr::Binary(BinaryOp::Pow, a, b) => match simplify(b) {
+                Expr::Number(k) if is_integer(k) => self.mul(a, k * exponent),
+                k => {
+                    let exponent = simplify(&Expr::binary(BinaryOp::Mul, num(exponent), k));
+                    self.factor(simplify(a), exponent);
+                }
+            },
+            _ => match simplify(e) {
+                Expr::Number(n) if n != 0.0 || exponent > 0.0 => {
+                    self.coefficient *= n.powf(exponent)
+                }
+                s @ Expr::Binary(BinaryOp::Mul, _, _)
+                | s @ Expr::Binary(BinaryOp::Div, _, _)
+                | s @ Expr::Binary(BinaryOp::Pow, _, _)
+                | s @ Expr::Unary(UnaryOp::Neg, _) => self.mul(&s, exponent),
+                s => self.factor(s, num(exponent)),
+            },
+        }
+    }
+
+    /// Multiply by `base^exponent` for simplified `base` and `exponent`.
+    fn factor(&mut self, base: Expr, exponent: Expr) {
+        match self.factors.iter_mut().find(|(b, _)| *b == base) {
+            Some((_, e)) => {
+                *e = simplify(&Expr::binary(BinaryOp::Add, e.clone(), exponent));
+            }
+            None => self.factors.push((base, exponent)),
+        }
+    }
+
+    fn exponent(&self, base: &Expr) -> Option<&Expr> {
+        self.factors.iter().find(|(b, _)| b == base).map(|(_, e)| e)
+    }
+
+    fn build(mut self) -> Expr {
+        if self.coefficient == 0.0 {
+            return num(0.0);
+        }
+        self.factors
+            .retain(|(_, e)| !matches!(e, Expr::Number(k) if *k == 0.0));
+        self.factors.sort_by(|(a, _), (b, _)| order(a, b));
+        let mut numerator = Vec::new();
+        let mut denominator = Vec::new();
+        for (base, exponent) in self.factors {
+            match exponent {
+                Expr::Number(k) if k < 0.0 => denominator.push(raise(base, num(-k))),
+                Expr::Unary(UnaryOp::Neg, k) => denominator.push(raise(base, *k)),
+                k => numerator.push(raise(base, k)),
+            }
+        }
+        let c = self.coefficient;
+        let mut factors = numerator.into_iter();
+        let numerator = match factors.next() {
+            None => num(c),
+            Some(first) => {
+                let first = if c == 1.0 {
+                    first
+                } else if c == -1.0 {
+                    Expr::unary(UnaryOp::Neg, first)
+                } else {
+                    Expr::binary(BinaryOp::Mul, num(c), first)
+                };
+                factors.fold(first, |acc, f| Expr::binary(BinaryOp::Mul, acc, f))
+            }
+        };
+        let mut factors = denominator.into_iter();
+        match factors.next() {
+            None => numerator,
+            Some(first) => {
+                let denominator = factors.fold(first, |acc, f| Expr::binary(BinaryOp::Mul, acc, f));
+                Expr::binary(BinaryOp::Div, numerator, denominator)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::parse;
+
+    fn simplified(formula: &str) -> String {
+        parse(formula).unwrap().simplify().to_string()
+    }
+
+    #[test]
+    fn removes_identities_and_folds_constants() {
+        assert_eq!(simplified("1*k1*(S1^1)"), "k1*S1");
+        assert_eq!(simplified("x + 0 - 2*3"), "x - 6");
+        assert_eq!(simplified("x*x/x^3"), "1/x");
+        assert_eq!(simplified("-(-y)"), "y");
+        assert_eq!(simplified("sqrt(4) + ln(x)*0"), "2");
+        assert_eq!(simplified("piecewise(a, 1 > 2, b)"), "b");
+    }
+
+    #[test]
+    fn orders_terms_and_extracts_common_factors() {
+        assert_eq!(simplified("b + a + b"), "a + 2*b");
+        assert_eq!(simplified("k1*S1 - k1*S2"), "k1*(S1 - S2)");
+        assert_eq!(simplified("S*Vm/(Km + S)"), simplified("Vm*S/(S + Km)"));
+        assert_eq!(simplified("-k*x + y"), "-k*x + y");
+    }
+
+    #[test]
+    fn is_idempotent() {
+        for formula in &["Vm*S/(Km + S) - k2*P^2/P", "2^x*2^y", "a*(b + c) + a*d"] {
+            let once = parse(formula).unwrap().simplify();
+            assert_eq!(once.simplify(), once, "{}", formula);
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//! Owned snapshots of the modules held by libAntimony.
+//!
+//! Every type here is plain data: once a `Document` has been loaded, nothing in it refers back
+//! to the global libAntimony state, so modules may be cloned, sent across threads, and edited
+//! freely.
+
+use std::path::Path;
+
+use antimony_sys as sys;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{extract, writer, Error, FormulaKind, Interaction, SymbolKind};
+
+/// The modules read from a single file or string.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        into = "crate::schema::DocumentRepr",
+        try_from = "crate::schema::DocumentRepr"
+    )
+)]
+pub struct Document {
+    /// The name of the main module: the one marked with an asterisk, or else the last one
+    /// defined.
+    pub main: String,
+    /// Every module in the file, in definition order.
+    pub modules: Vec<Module>,
+}
+
+impl Document {
+    /// Load a file of any format libAntimony knows about (Antimony, SBML, or CellML).
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Document, Error> {
+        extract::load_path(sys::loadFile, path.as_ref())
+    }
+
+    /// Load a string of any format libAntimony knows about (Antimony, SBML, or CellML).
+    pub fn load_str(model: &str) -> Result<Document, Error> {
+        extract::load_str(sys::loadString, model)
+    }
+
+    /// Load a file known to be Antimony.
+    pub fn load_antimony_file<P: AsRef<Path>>(path: P) -> Result<Document, Error> {
+        extract::load_path(sys::loadAntimonyFile, path.as_ref())
+    }
+
+    /// Load a string known to be Antimony.
+    pub fn load_antimony_str(model: &str) -> Result<Document, Error> {
+        extract::load_str(sys::loadAntimonyString, model)
+    }
+
+    /// Load a file known to be SBML.
+    pub fn load_sbml_file<P: AsRef<Path>>(path: P) -> Result<Document, Error> {
+        extract::load_path(sys::loadSBMLFile, path.as_ref())
+    }
+
+    /// Load a string known to be SBML.
+    pub fn load_sbml_str(model: &str) -> Result<Document, Error> {
+        extract::load_str(sys::loadSBMLString, model)
+    }
+
+    /// Load a file known to be CellML.
+    pub fn load_cellml_file<P: AsRef<Path>>(path: P) -> Result<Document, Error> {
+        extract::load_path(sys::loadCellMLFile, path.as_ref())
+    }
+
+    /// Load a string known to be CellML.
+    pub fn load_cellml_str(model: &str) -> Result<Document, Error> {
+        extract::load_str(sys::loadCellMLString, model)
+    }
+
+    /// The main module.
+    pub fn main(&self) -> &Module {
+        self.module(&self.main)
+            .expect("the main module is always part of its document")
+    }
+
+    /// Consume the document, keeping only its main module.
+    pub fn into_main(self) -> Module {
+        let main = self.main;
+        self.modules
+            .into_iter()
+            .find(|m| m.name == main)
+            .expect("the main module is always part of its document")
+    }
+
+    /// The module with the given name, if any.
+    pub fn module(&self, name: &str) -> Option<&Module> {
+        self.modules.iter().find(|m| m.name == name)
+    }
+
+    /// Render every module as Antimony text that libAntimony can load again.
+    pub fn to_antimony(&self) -> String {
+        writer::document(self)
+    }
+}
+
+impl From<Module> for Document {
+    fn from(module: Module) -> Document {
+        Document {
+            main: module.name.clone(),
+            modules: vec![module],
+        }
+    }
+}
+
+/// A single model, flattened so that the symbols of its submodules appear under dotted names
+/// (e.g., `A.x` for the symbol `x` of submodule `A`).
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Module {
+    /// The module name.
+    pub name: String,
+    /// The symbols in the interface of the module, i.e., `x, y` in `model M(x, y)`.
+    pub interface: Vec<String>,
+    /// Every symbol in the module, in libAntimony's order.
+    pub symbols: Vec<Symbol>,
+    /// All reactions, including genes.
+    pub reactions: Vec<Reaction>,
+    /// All interactions (activation, inhibition, and generic influence).
+    pub interactions: Vec<Regulation>,
+    /// All events.
+    pub events: Vec<Event>,
+    /// All DNA strands, expanded to their operators and genes.
+    pub strands: Vec<Strand>,
+    /// The submodules used within the module.
+    pub submodules: Vec<Submodule>,
+    /// The symbols synchronized with `is` or through a submodule's interface.
+    pub replacements: Vec<Replacement>,
+    /// The stoichiometry matrix of the variable species against all reactions.
+    pub stoichiometry: Stoichiometry,
+    /// The user-defined functions available to the module's formulas.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub functions: Vec<Function>,
+}
+
+impl Module {
+    /// The symbol with the given id, if any.
+    pub fn symbol(&self, id: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.id == id)
+    }
+
+    /// The reaction with the given id, if any.
+    pub fn reaction(&self, id: &str) -> Option<&Reaction> {
+        self.reactions.iter().find(|r| r.id == id)
+    }
+
+    /// The event with the given id, if any.
+    pub fn event(&self, id: &str) -> Option<&Event> {
+        self.events.iter().find(|e| e.id == id)
+    }
+
+    /// The user-defined function with the given id, if any.
+    pub fn function(&self, id: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.id == id)
+    }
+
+    /// All species, both constant (boundary) and variable.
+    pub fn species(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(|s| s.is_species())
+    }
+
+    /// All compartments, both constant and variable.
+    pub fn compartments(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(|s| s.is_compartment())
+    }
+
+    /// All formulas (parameters and other values defined by an equation).
+    pub fn parameters(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(|s| s.is_formula())
+    }
+
+    /// Render the module as Antimony text.
+    ///
+    /// The modules instantiated by submodules are not included; use `Document::to_antimony`
+    /// to write them along with their parent.
+    pub fn to_antimony(&self) -> String {
+        writer::module(self)
+    }
+}
+
+/// A named value of the model: a species, compartment, formula, or any other kind of symbol.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Symbol {
+    /// The identifier (in SBML, the `id`).
+    pub id: String,
+    /// The display name (in SBML, the `name`), if one was given.
+    pub name: Option<String>,
+    /// The most specific kind of the symbol, e.g., `SymbolKind::SpeciesConstant`.
+    pub kind: SymbolKind,
+    /// The enclosing compartment, or `None` for the default compartment.
+    pub compartment: Option<String>,
+    /// The initial value or initial assignment.
+    pub initial: Option<String>,
+    /// The assignment or rate rule governing the symbol over time.
+    pub rule: Option<Rule>,
+}
+
+impl Symbol {
+    /// Whether the symbol is a species.
+    pub fn is_species(&self) -> bool {
+        matches!(
+            self.kind,
+            SymbolKind::SpeciesVariable | SymbolKind::SpeciesConstant | SymbolKind::Species
+        )
+    }
+
+    /// Whether the symbol is a compartment.
+    pub fn is_compartment(&self) -> bool {
+        matches!(
+            self.kind,
+            SymbolKind::CompartmentVariable
+                | SymbolKind::CompartmentConstant
+                | SymbolKind::Compartment
+        )
+    }
+
+    /// Whether the symbol is a formula (a parameter or other value defined by an equation).
+    pub fn is_formula(&self) -> bool {
+        matches!(
+            self.kind,
+            SymbolKind::FormulaVariable | SymbolKind::FormulaConstant | SymbolKind::Formula
+        )
+    }
+
+    /// Whether the symbol is constant (a boundary species, constant formula, etc.).
+    pub fn is_constant(&self) -> bool {
+        matches!(
+            self.kind,
+            SymbolKind::SpeciesConstant
+                | SymbolKind::FormulaConstant
+                | SymbolKind::CompartmentConstant
+                | SymbolKind::OperatorConstant
+        )
+    }
+
+    /// The kind of the symbol's main equation.
+    pub fn formula_kind(&self) -> FormulaKind {
+        match (&self.rule, self.kind) {
+            (Some(Rule::Assignment(_)), _) => FormulaKind::Assignment,
+            (Some(Rule::Rate(_)), _) => FormulaKind::Rate,
+            (None, SymbolKind::Reaction) | (None, SymbolKind::Gene) => FormulaKind::Kinetic,
+            (None, SymbolKind::Event) => FormulaKind::Trigger,
+            (None, _) => FormulaKind::Initial,
+        }
+    }
+}
+
+/// A rule determining how a symbol changes with time.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "type", content = "formula", rename_all = "snake_case")
+)]
+pub enum Rule {
+    /// `x := formula`: the symbol always equals the formula.
+    Assignment(String),
+    /// `x' = formula`: the formula is the symbol's time derivative.
+    Rate(String),
+}
+
+/// A reaction or gene.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Reaction {
+    /// The identifier.
+    pub id: String,
+    /// The display name, if one was given.
+    pub name: Option<String>,
+    /// `Interaction::Becomes` for reversible (`->`) and `Interaction::Transforms` for
+    /// irreversible (`=>`) reactions.
+    pub kind: Interaction,
+    /// The species consumed, with their stoichiometries.
+    pub reactants: Vec<Participant>,
+    /// The species produced, with their stoichiometries.
+    pub products: Vec<Participant>,
+    /// The reaction rate, or `None` if it was never set.
+    pub rate: Option<String>,
+}
+
+impl Reaction {
+    /// Whether the reaction may run backwards.
+    pub fn is_reversible(&self) -> bool {
+        self.kind != Interaction::Transforms
+    }
+}
+
+/// A species taking part in a reaction.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Participant {
+    /// The species id.
+    pub species: String,
+    /// The stoichiometric coefficient.
+    pub stoichiometry: f64,
+}
+
+/// An interaction: species influencing the rates of reactions without being consumed.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Regulation {
+    /// The identifier.
+    pub id: String,
+    /// `Interaction::Activates`, `Interaction::Inhibits`, or `Interaction::Influences`.
+    pub kind: Interaction,
+    /// The influencing species.
+    pub interactors: Vec<String>,
+    /// The influenced reactions.
+    pub interactees: Vec<String>,
+}
+
+/// An event: assignments performed when a trigger becomes true.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Event {
+    /// The identifier.
+    pub id: String,
+    /// The display name, if one was given.
+    pub name: Option<String>,
+    /// The trigger, a formula interpreted in a boolean context.
+    pub trigger: String,
+    /// The delay between triggering and executing the assignments.
+    pub delay: Option<String>,
+    /// The priority among simultaneously executing events.
+    pub priority: Option<String>,
+    /// Whether the event still executes if its trigger turns false during the delay.
+    pub persistent: bool,
+    /// The value of the trigger at time zero.
+    pub initial_value: bool,
+    /// Whether assignments use values from trigger time rather than from execution time.
+    pub from_trigger: bool,
+    /// The assignments, in order.
+    pub assignments: Vec<Assignment>,
+}
+
+/// An assignment of an event.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Assignment {
+    /// The symbol assigned to.
+    pub variable: String,
+    /// The value assigned.
+    pub formula: String,
+}
+
+/// A DNA strand of operators and genes.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Strand {
+    /// The operators and genes, upstream first.
+    pub parts: Vec<String>,
+    /// Whether the strand is attachable upstream (`--X--Y`).
+    pub open_upstream: bool,
+    /// Whether the strand is attachable downstream (`X--Y--`).
+    pub open_downstream: bool,
+}
+
+/// A submodule used within a module, as in `A: M()`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Submodule {
+    /// The name of the submodule within its parent (`A`).
+    pub id: String,
+    /// The name of the module it instantiates (`M`), if libAntimony reports one.
+    pub module: Option<String>,
+}
+
+/// A user-defined function, as in `function f(x, y) x*y end`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Function {
+    /// The function name.
+    pub id: String,
+    /// The names of the parameters, in order.
+    pub parameters: Vec<String>,
+    /// The formula computed from the parameters.
+    pub body: String,
+}
+
+/// A synchronization of two symbols, as in `A.x is y`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Replacement {
+    /// The symbol that was replaced (`A.x`).
+    pub former: String,
+    /// The symbol used in its place (`y`).
+    pub replacement: String,
+}
+
+/// A stoichiometry matrix with one row per variable species and one column per reaction.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Stoichiometry {
+    /// The row labels: variable species ids.
+    pub species: Vec<String>,
+    /// The column labels: reaction ids.
+    pub reactions: Vec<String>,
+    /// The coefficients, row-major.
+    pub matrix: Vec<Vec<f64>>,
+}
+
+impl Stoichiometry {
+    /// The net stoichiometry of a species in a reaction, or `None` if either is unknown.
+    pub fn get(&self, species: &str, reaction: &str) -> Option<f64> {
+        let row = self.species.iter().position(|s| s == species)?;
+        let col = self.reactions.iter().position(|r| r == reaction)?;
+        Some(self.matrix[row][col])
+    }
+}
@@ -0,0 +1,148 @@
+//! The serialized form of extracted models (requires the `serde` feature).
+//!
+//! Every type in `antimony::model` implements `Serialize` and `Deserialize`, so documents and
+//! modules can be written with any serde format: JSON (`serde_json`), YAML (`serde_yaml`),
+//! MessagePack (`rmp-serde`), and so on. A serialized `Document` carries the schema `VERSION`
+//! it was written with; documents written by any earlier version of this crate can be read
+//! back, and documents from a newer schema are rejected rather than misread.
+//!
+//! # JSON Schema (version 1)
+//!
+//! Field names are written as below; optional fields are `null` when absent. `functions` may be
+//! omitted, in which case it is read as empty.
+//!
+//! ```text
+//! Document     = { "version": 1, "main": string, "modules": [Module] }
+//! Module       = { "name": string, "interface": [string], "symbols": [Symbol],
+//!                  "reactions": [Reaction], "interactions": [Regulation],
+//!                  "events": [Event], "strands": [Strand], "submodules": [Submodule],
+//!                  "replacements": [Replacement], "stoichiometry": Stoichiometry,
+//!                  "functions": [Function] }
+//! Symbol       = { "id": string, "name": string?, "kind": SymbolKind,
+//!                  "compartment": string?, "initial": string?, "rule": Rule? }
+//! Rule         = { "type": "assignment" | "rate", "formula": string }
+//! Reaction     = { "id": string, "name": string?, "kind": "becomes" | "transforms",
+//!                  "reactants": [Participant], "products": [Participant], "rate": string? }
+//! Participant  = { "species": string, "stoichiometry": number }
+//! Regulation   = { "id": string, "kind": "activates" | "inhibits" | "influences",
+//!                  "interactors": [string], "interactees": [string] }
+//! Event        = { "id": string, "name": string?, "trigger": string, "delay": string?,
+//!                  "priority": string?, "persistent": bool, "initial_value": bool,
+//!                  "from_trigger": bool, "assignments": [Assignment] }
+//! Assignment   = { "variable": string, "formula": string }
+//! Strand       = { "parts": [string], "open_upstream": bool, "open_downstream": bool }
+//! Submodule    = { "id": string, "module": string? }
+//! Function     = { "id": string, "parameters": [string], "body": string }
+//! Replacement  = { "former": string, "replacement": string }
+//! Stoichiometry = { "species": [string], "reactions": [string], "matrix": [[number]] }
+//! ```
+//!
+//! `SymbolKind` and `FormulaKind` values are the snake-case names of their variants, e.g.,
+//! `"species_variable"` or `"formula_constant"`.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::{Document, Module};
+
+/// The current schema version, written into every serialized `Document`.
+pub const VERSION: u32 = 1;
+
+/// A serialized document was written with a newer, unknown schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedVersion(pub u32);
+
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "schema version {} is newer than the supported version {}",
+            self.0, VERSION
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct DocumentRepr {
+    version: u32,
+    main: String,
+    modules: Vec<Module>,
+}
+
+impl From<Document> for DocumentRepr {
+    fn from(doc: Document) -> DocumentRepr {
+        DocumentRepr {
+            version: VERSION,
+            main: doc.main,
+            modules: doc.modules,
+        }
+    }
+}
+
+impl TryFrom<DocumentRepr> for Document {
+    type Error = UnsupportedVersion;
+
+    fn try_from(repr: DocumentRepr) -> Result<Document, UnsupportedVersion> {
+        if repr.version > VERSION {
+            return Err(UnsupportedVersion(repr.version));
+        }
+        Ok(Document {
+            main: repr.main,
+            modules: repr.modules,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::*;
+    use crate::{Interaction, SymbolKind};
+
+    fn document() -> Document {
+        Document::from(Module {
+            name: "M".into(),
+            symbols: vec![Symbol {
+                id: "S1".into(),
+                name: None,
+                kind: SymbolKind::SpeciesVariable,
+                compartment: None,
+                initial: Some("10".into()),
+                rule: None,
+            }],
+            reactions: vec![Reaction {
+                id: "J0".into(),
+                name: None,
+                kind: Interaction::Transforms,
+                reactants: vec![Participant {
+                    species: "S1".into(),
+                    stoichiometry: 1.0,
+                }],
+                products: vec![],
+                rate: Some("k1*S1".into()),
+            }],
+            ..Module::default()
+        })
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let doc = document();
+        let json = serde_json::to_value(&doc).unwrap();
+        assert_eq!(json["version"], VERSION);
+        assert_eq!(json["modules"][0]["symbols"][0]["kind"], "species_variable");
+        assert_eq!(json["modules"][0]["reactions"][0]["kind"], "transforms");
+        assert_eq!(serde_json::from_value::<Document>(json).unwrap(), doc);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut json = serde_json::to_value(document()).unwrap();
+        json["version"] = (VERSION + 1).into();
+        assert!(serde_json::from_value::<Document>(json).is_err());
+    }
+}
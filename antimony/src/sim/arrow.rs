@@ -0,0 +1,74 @@
+//! Tables of results as Arrow record batches and Parquet files.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+
+use super::TimeCourse;
+
+impl TimeCourse {
+    /// The table as an Arrow record batch, with a non-nullable `Float64` column per column of
+    /// the table. Each field has the label as its name and the display name in its metadata,
+    /// under `name`.
+    pub fn to_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let fields: Vec<Field> = self
+            .columns
+            .iter()
+            .zip(&self.names)
+            .map(|(label, name)| {
+                let metadata = HashMap::from([("name".to_owned(), name.clone())]);
+                Field::new(label, DataType::Float64, false).with_metadata(metadata)
+            })
+            .collect();
+        let arrays: Vec<ArrayRef> = (0..self.columns.len())
+            .map(|j| {
+                let values: Vec<f64> = self.rows.iter().map(|row| row[j]).collect();
+                Arc::new(Float64Array::from(values)) as ArrayRef
+            })
+            .collect();
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)
+    }
+
+    /// Write the table as a Parquet file, with the columns of `TimeCourse::to_record_batch`.
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet<W>(&self, writer: W) -> Result<(), parquet::errors::ParquetError>
+    where
+        W: std::io::Write + Send,
+    {
+        let batch = self.to_record_batch()?;
+        let mut writer = parquet::arrow::ArrowWriter::try_new(writer, batch.schema(), None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_record_batches() {
+        let mut course = TimeCourse::new(
+            vec!["time".into(), "S1".into()],
+            vec![vec![0.0, 10.0], vec![1.0, 5.0]],
+        );
+        course.names[1] = "substrate".into();
+        let batch = course.to_record_batch().unwrap();
+        assert_eq!((batch.num_rows(), batch.num_columns()), (2, 2));
+        let field = batch.schema().field(1).clone();
+        assert_eq!(field.name(), "S1");
+        assert_eq!(field.metadata()["name"], "substrate");
+        let column = batch.column(1).as_any().downcast_ref::<Float64Array>();
+        assert_eq!(column.unwrap().values(), &[10.0, 5.0]);
+
+        #[cfg(feature = "parquet")]
+        {
+            let mut file = Vec::new();
+            course.write_parquet(&mut file).unwrap();
+            assert!(file.starts_with(b"PAR1") && file.ends_with(b"PAR1"));
+        }
+    }
+}
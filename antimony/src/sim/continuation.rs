@@ -0,0 +1,588 @@
+//! Numerical continuation: how steady states move, and where they change, as a parameter
+//! varies.
+//!
+//! The branch of steady states through the starting point is followed by pseudo-arclength
+//! continuation (Keller, 1977), which also passes around folds, where the branch turns back.
+//! Each step predicts the next point along the tangent of the branch, and corrects it with
+//! Newton's method on the reduced system of `Simulator::steady_state`, augmented by the
+//! condition that the point lie on the hyperplane through the prediction normal to the
+//! tangent. Steps grow while the corrector converges quickly and shrink when it fails.
+//!
+//! Bifurcations are detected by the sign changes of test functions between consecutive points,
+//! and located by the Illinois method on the length of the step:
+//!
+//! - a fold (saddle-node bifurcation), where the parameter component of the tangent vanishes
+//!   and a real eigenvalue crosses zero;
+//! - a Hopf bifurcation, where the largest real part of the complex eigenvalues crosses zero
+//!   and oscillations are born or die, with a frequency given by the imaginary part there.
+
+use super::solver::Rhs;
+use super::steady::Reduced;
+use super::{Eigenvalue, Error, Simulator, SteadyStateOptions, SystemRhs, TimeCourse};
+use crate::linalg::{eigenvalues, Lu};
+use crate::{Module, SymbolKind};
+
+/// How to follow a branch of steady states.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContinuationOptions {
+    /// The lowest value of the parameter; the branch ends there.
+    pub lower: f64,
+    /// The highest value of the parameter; the branch ends there.
+    pub upper: f64,
+    /// The length of the first step along the branch. If positive, the parameter first
+    /// increases; if negative, it first decreases.
+    pub step: f64,
+    /// The shortest step, below which the continuation stops.
+    pub min_step: f64,
+    /// The longest step.
+    pub max_step: f64,
+    /// The number of points after which to stop, not counting the bifurcations.
+    pub max_points: usize,
+    /// The largest rate of change accepted at a steady state; see
+    /// `SteadyStateOptions::tolerance`.
+    pub tolerance: f64,
+    /// The number of Newton iterations of the corrector after which a step is shortened.
+    pub max_iterations: usize,
+    /// Whether to use the symbolic Jacobian of the model; see `Options::analytic_jacobian`.
+    pub analytic_jacobian: bool,
+}
+
+impl Default for ContinuationOptions {
+    fn default() -> ContinuationOptions {
+        ContinuationOptions {
+            lower: 0.0,
+            upper: f64::INFINITY,
+            step: 0.01,
+            min_step: 1e-8,
+            max_step: 0.5,
+            max_points: 1000,
+            tolerance: 1e-9,
+            max_iterations: 10,
+            analytic_jacobian: true,
+        }
+    }
+}
+
+/// A branch of steady states, in the order followed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    /// The parameter varied.
+    pub parameter: String,
+    /// The state variables (see `Simulator::state_variables`).
+    pub variables: Vec<String>,
+    /// The points of the branch, including those of the bifurcations.
+    pub points: Vec<BranchPoint>,
+    /// The bifurcations, in order along the branch.
+    pub bifurcations: Vec<Bifurcation>,
+}
+
+/// A steady state on a branch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchPoint {
+    /// The value of the parameter.
+    pub parameter: f64,
+    /// The steady value of each state variable.
+    pub values: Vec<f64>,
+    /// The eigenvalues of the Jacobian of the reduced system.
+    pub eigenvalues: Vec<Eigenvalue>,
+}
+
+impl BranchPoint {
+    /// Whether small perturbations decay: every eigenvalue has a negative real part.
+    pub fn is_stable(&self) -> bool {
+        self.eigenvalues.iter().all(|e| e.re < 0.0)
+    }
+}
+
+/// A bifurcation located on a branch.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bifurcation {
+    /// What changes there.
+    pub kind: BifurcationKind,
+    /// The index of its point in `Branch::points`.
+    pub point: usize,
+}
+
+/// The kind of a bifurcation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BifurcationKind {
+    /// The branch turns back, and a stable and an unstable steady state meet.
+    Fold,
+    /// A pair of complex eigenvalues crosses the imaginary axis, with the given angular
+    /// frequency.
+    Hopf { frequency: f64 },
+}
+
+impl Branch {
+    /// The values of the parameter or of a state variable along the branch, if it is one.
+    pub fn column(&self, id: &str) -> Option<Vec<f64>> {
+        if id == self.parameter {
+            return Some(self.points.iter().map(|p| p.parameter).collect());
+        }
+        let i = self.variables.iter().position(|v| v == id)?;
+        Some(self.points.iter().map(|p| p.values[i]).collect())
+    }
+
+    /// The branch as a table to plot: a column for the parameter, one per state variable, and
+    /// `stable`, one where the steady state is stable and zero where it is not.
+    pub fn table(&self) -> TimeCourse {
+        let mut columns = vec![self.parameter.clone()];
+        columns.extend_from_slice(&self.variables);
+        columns.push("stable".to_owned());
+        let rows = self
+            .points
+            .iter()
+            .map(|p| {
+                let mut row = vec![p.parameter];
+                row.extend_from_slice(&p.values);
+                row.push(if p.is_stable() { 1.0 } else { 0.0 });
+                row
+            })
+            .collect();
+        TimeCourse::new(columns, rows)
+    }
+}
+
+/// Follow the branch of steady states of a module through its initial values as a parameter
+/// varies; see `Simulator::continuation`.
+pub fn continuation(
+    module: &Module,
+    parameter: &str,
+    options: &ContinuationOptions,
+) -> Result<Branch, Error> {
+    Simulator::new(module)?.continuation(parameter, options)
+}
+
+impl Simulator {
+    /// Follow the branch of steady states through the steady state found from the initial
+    /// values (see `Simulator::steady_state`) as a constant parameter varies, until the
+    /// parameter leaves its bounds, the number of points reaches `max_points`, or the
+    /// corrector fails even on the shortest step. Conserved totals keep their values of the
+    /// starting point, and constants whose initial values depend on the parameter follow it.
+    pub fn continuation(
+        &self,
+        parameter: &str,
+        options: &ContinuationOptions,
+    ) -> Result<Branch, Error> {
+        let invalid = |message: String| Err(Error::InvalidOptions(message));
+        let symbol = self.module.symbol(parameter);
+        let symbol = symbol.ok_or_else(|| Error::UnknownSymbol(parameter.to_owned()))?;
+        if symbol.kind != SymbolKind::FormulaConstant {
+            return invalid(format!("`{}` is not a constant parameter", parameter));
+        }
+        if options.lower.is_nan() || options.upper.is_nan() || options.upper < options.lower {
+            return invalid("`upper` must not be below `lower`".to_owned());
+        }
+        if !(options.step.is_finite() && options.step != 0.0) {
+            return invalid("`step` must be finite and not zero".to_owned());
+        }
+        if !(options.min_step > 0.0 && options.max_step >= options.min_step) {
+            return invalid("`min_step` must be positive and at most `max_step`".to_owned());
+        }
+        let p = self.initial_value(parameter, 0.0)?;
+        if p < options.lower || p > options.upper {
+            return invalid(format!("`{}` starts outside its bounds", parameter));
+        }
+        let start = self.steady_state(&SteadyStateOptions {
+            tolerance: options.tolerance,
+            max_iterations: options.max_iterations.max(100),
+            analytic_jacobian: options.analytic_jacobian,
+            ..SteadyStateOptions::default()
+        })?;
+
+        let system = &self.system;
+        let mut values = system.initial_values(0.0, &self.overrides)?;
+        values[..system.n_state].copy_from_slice(&start.values);
+        let jacobian = if options.analytic_jacobian {
+            self.jacobian()
+        } else {
+            None
+        };
+        let mut reduced = Reduced::new(
+            &self.module,
+            SystemRhs {
+                system,
+                values: values.clone(),
+                jacobian,
+                history: None,
+            },
+        );
+        reduced.conserve(&values);
+        let mut z: Vec<f64> = reduced.independent.iter().map(|&i| values[i]).collect();
+        z.push(p);
+        let mut curve = Curve {
+            simulator: self,
+            reduced,
+            slot: self.slot(parameter)?,
+            overrides: self.overrides.clone(),
+            options,
+        };
+        let m = z.len() - 1;
+        let mut direction = vec![0.0; m + 1];
+        direction[m] = options.step.signum();
+        let first = curve.solution(z, &direction)?;
+
+        let mut branch = Branch {
+            parameter: parameter.to_owned(),
+            variables: start.variables,
+            points: vec![curve.point(&first)],
+            bifurcations: Vec::new(),
+        };
+        let tests: [(Option<BifurcationKind>, Test); 4] = [
+            (Some(BifurcationKind::Fold), &|s: &Solution| {
+                Some(s.tangent[m])
+            }),
+            (
+                Some(BifurcationKind::Hopf { frequency: 0.0 }),
+                &|s: &Solution| s.hopf(),
+            ),
+            (None, &|s: &Solution| Some(s.z[m] - options.lower)),
+            (None, &|s: &Solution| Some(s.z[m] - options.upper)),
+        ];
+        let mut current = first;
+        let mut ds = options.step.abs().clamp(options.min_step, options.max_step);
+        let mut count = 1;
+        while count < options.max_points {
+            let (next, iterations) = match curve.advance(&current, ds) {
+                Some(next) => next,
+                None if ds > options.min_step => {
+                    ds = (ds / 2.0).max(options.min_step);
+                    continue;
+                }
+                None => break,
+            };
+            // The zeros of the test functions within the step, in order along it.
+            let mut zeros: Vec<(f64, Option<BifurcationKind>, Solution)> = Vec::new();
+            for (kind, test) in &tests {
+                let (a, b) = match (test(&current), test(&next)) {
+                    (Some(a), Some(b)) if a.signum() != b.signum() && a != 0.0 => (a, b),
+                    _ => continue,
+                };
+                let (s, at) = curve
+                    .locate(&current, ds, (a, b), test)
+                    .unwrap_or_else(|| (ds, next.clone()));
+                zeros.push((s, *kind, at));
+            }
+            zeros.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let mut bounded = false;
+            for (_, kind, at) in zeros {
+                let kind = match kind {
+                    Some(BifurcationKind::Hopf { .. }) => BifurcationKind::Hopf {
+                        frequency: at.frequency(),
+                    },
+                    Some(kind) => kind,
+                    None => {
+                        branch.points.push(curve.point(&at));
+                        bounded = true;
+                        break;
+                    }
+                };
+                branch.bifurcations.push(Bifurcation {
+                    kind,
+                    point: branch.points.len(),
+                });
+                branch.points.push(curve.point(&at));
+            }
+            // A start on a bound has no sign change to stop at when the step leaves it.
+            if bounded || !(options.lower..=options.upper).contains(&next.z[m]) {
+                break;
+            }
+            branch.points.push(curve.point(&next));
+            count += 1;
+            current = next;
+            if iterations <= 3 {
+                ds = (ds * 1.5).min(options.max_step);
+            }
+        }
+        Ok(branch)
+    }
+}
+
+/// A test function, whose sign changes at a bifurcation, or `None` where it is undefined.
+type Test<'t> = &'t dyn Fn(&Solution) -> Option<f64>;
+
+/// A point of the branch as the corrector finds it: the reduced state with the parameter
+/// last, the unit tangent there, and the eigenvalues of the reduced Jacobian.
+#[derive(Clone)]
+struct Solution {
+    z: Vec<f64>,
+    tangent: Vec<f64>,
+    eigenvalues: Vec<(f64, f64)>,
+}
+
+impl Solution {
+    /// The largest real part of the complex eigenvalues, if there are any.
+    fn hopf(&self) -> Option<f64> {
+        self.complex().map(|&(re, _)| re).reduce(f64::max)
+    }
+
+    /// The imaginary part of the complex eigenvalue closest to the imaginary axis.
+    fn frequency(&self) -> f64 {
+        self.complex()
+            .min_by(|a, b| a.0.abs().total_cmp(&b.0.abs()))
+            .map_or(0.0, |&(_, im)| im.abs())
+    }
+
+    fn complex(&self) -> impl Iterator<Item = &(f64, f64)> {
+        self.eigenvalues
+            .iter()
+            .filter(|(re, im)| im.abs() > 1e-8 * re.abs().max(1.0))
+    }
+}
+
+/// The steady states of the reduced system as a curve in the space of its state and the
+/// parameter.
+struct Curve<'a> {
+    simulator: &'a Simulator,
+    reduced: Reduced<'a>,
+    /// The slot of the parameter.
+    slot: usize,
+    overrides: Vec<Option<f64>>,
+    options: &'a ContinuationOptions,
+}
+
+impl Curve<'_> {
+    /// The rates of the reduced system at `z`.
+    fn rates(&mut self, z: &[f64], dx: &mut [f64]) -> Result<(), Error> {
+        let (x, p) = z.split_at(z.len() - 1);
+        if self.overrides[self.slot] != Some(p[0]) {
+            self.overrides[self.slot] = Some(p[0]);
+            let values = self.simulator.system.initial_values(0.0, &self.overrides)?;
+            self.reduced.full.values.copy_from_slice(&values);
+        }
+        self.reduced.eval(0.0, x, dx)
+    }
+
+    /// The rates and their Jacobian with respect to the state and, in the last column, the
+    /// parameter, by central differences.
+    fn jacobian(&mut self, z: &[f64]) -> Result<(Vec<f64>, Vec<Vec<f64>>), Error> {
+        let m = z.len() - 1;
+        let mut f = vec![0.0; m];
+        self.rates(z, &mut f)?;
+        let mut jac = vec![vec![0.0; m + 1]; m];
+        let mut square = vec![vec![0.0; m]; m];
+        self.reduced.jacobian(0.0, &z[..m], &f, &mut square)?;
+        let h = f64::EPSILON.cbrt() * z[m].abs().max(1.0);
+        let (mut up, mut down) = (vec![0.0; m], vec![0.0; m]);
+        let mut shifted = z.to_vec();
+        shifted[m] = z[m] + h;
+        self.rates(&shifted, &mut up)?;
+        shifted[m] = z[m] - h;
+        self.rates(&shifted, &mut down)?;
+        for (i, row) in jac.iter_mut().enumerate() {
+            row[..m].copy_from_slice(&square[i]);
+            row[m] = (up[i] - down[i]) / (2.0 * h);
+        }
+        Ok((f, jac))
+    }
+
+    /// The branch point at `z`, with the tangent pointing the way of `previous`.
+    fn solution(&mut self, z: Vec<f64>, previous: &[f64]) -> Result<Solution, Error> {
+        let m = z.len() - 1;
+        let (_, mut jac) = self.jacobian(&z)?;
+        let square: Vec<Vec<f64>> = jac.iter().map(|row| row[..m].to_vec()).collect();
+        let eigenvalues = eigenvalues(&square).unwrap_or_default();
+        jac.push(previous.to_vec());
+        let mut tangent = vec![0.0; m + 1];
+        tangent[m] = 1.0;
+        let lu = Lu::new(&jac).ok_or(Error::Singular)?;
+        lu.solve(&mut tangent);
+        let norm = tangent.iter().map(|t| t * t).sum::<f64>().sqrt();
+        let sign = if dot(&tangent, previous) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        tangent.iter_mut().for_each(|t| *t *= sign / norm);
+        Ok(Solution {
+            z,
+            tangent,
+            eigenvalues,
+        })
+    }
+
+    /// The point a step of length `ds` along the tangent from `from`, and the number of
+    /// corrector iterations it took, or `None` if the corrector fails.
+    fn advance(&mut self, from: &Solution, ds: f64) -> Option<(Solution, usize)> {
+        let predicted: Vec<f64> = from
+            .z
+            .iter()
+            .zip(&from.tangent)
+            .map(|(z, t)| z + ds * t)
+            .collect();
+        let mut z = predicted.clone();
+        let m = z.len() - 1;
+        for iteration in 1..=self.options.max_iterations {
+            let (f, mut jac) = self.jacobian(&z).ok()?;
+            let mut step: Vec<f64> = f.iter().map(|f| -f).collect();
+            let offset: Vec<f64> = z.iter().zip(&predicted).map(|(z, p)| z - p).collect();
+            step.push(-dot(&from.tangent, &offset));
+            jac.push(from.tangent.clone());
+            Lu::new(&jac)?.solve(&mut step);
+            z.iter_mut().zip(&step).for_each(|(z, s)| *z += s);
+            let mut dx = vec![0.0; m];
+            self.rates(&z, &mut dx).ok()?;
+            let small = step
+                .iter()
+                .zip(&z)
+                .all(|(s, z)| s.abs() <= 1e-6 * z.abs().max(1.0));
+            if !z.iter().all(|z| z.is_finite()) {
+                return None;
+            }
+            if small && self.reduced.residual(&z[..m], &dx) <= self.options.tolerance {
+                let solution = self.solution(z, &from.tangent).ok()?;
+                return Some((solution, iteration));
+            }
+        }
+        None
+    }
+
+    /// Locate the zero of a test function, which is `a` at `from` and `b` a step of length
+    /// `ds` further, by the Illinois method on the length of the step. Returns that length
+    /// and the point there.
+    fn locate(
+        &mut self,
+        from: &Solution,
+        ds: f64,
+        (mut a, mut b): (f64, f64),
+        test: Test,
+    ) -> Option<(f64, Solution)> {
+        let (mut lo, mut hi) = (0.0, ds);
+        let mut best = None;
+        let mut side = 0;
+        for _ in 0..40 {
+            let s = (lo * b - hi * a) / (b - a);
+            let (point, _) = self.advance(from, s)?;
+            let g = test(&point)?;
+            best = Some((s, point));
+            if g == 0.0 || (hi - lo) <= 1e-12 * ds.max(1.0) {
+                break;
+            }
+            if g.signum() == a.signum() {
+                lo = s;
+                a = g;
+                if side == -1 {
+                    b /= 2.0;
+                }
+                side = -1;
+            } else {
+                hi = s;
+                b = g;
+                if side == 1 {
+                    a /= 2.0;
+                }
+                side = 1;
+            }
+            if g.abs() <= 1e-10 {
+                break;
+            }
+        }
+        best
+    }
+
+    /// The steady state of the full system at a point.
+    fn point(&mut self, solution: &Solution) -> BranchPoint {
+        let m = solution.z.len() - 1;
+        self.reduced.expand(&solution.z[..m]);
+        BranchPoint {
+            parameter: solution.z[m],
+            values: self.reduced.y.clone(),
+            eigenvalues: solution
+                .eigenvalues
+                .iter()
+                .map(|&(re, im)| Eigenvalue { re, im })
+                .collect(),
+        }
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn follows_a_hysteresis_loop() {
+        // The steady states of x' = p + x - x^3 are p = x^3 - x, with folds at x = ±1/√3.
+        let module = Fixture::new()
+            .parameter("p", -1.0)
+            .rate_rule("x", -1.3, "p + x - x^3")
+            .build();
+        let options = ContinuationOptions {
+            lower: -1.0,
+            upper: 1.0,
+            step: 0.05,
+            max_step: 0.1,
+            ..ContinuationOptions::default()
+        };
+        let branch = continuation(&module, "p", &options).unwrap();
+        let fold = 2.0 / (3.0 * 3f64.sqrt());
+        let folds: Vec<f64> = branch
+            .bifurcations
+            .iter()
+            .map(|b| {
+                assert_eq!(b.kind, BifurcationKind::Fold);
+                branch.points[b.point].parameter
+            })
+            .collect();
+        assert_eq!(folds.len(), 2, "{:?}", folds);
+        assert!((folds[0] - fold).abs() < 1e-6 && (folds[1] + fold).abs() < 1e-6);
+        let last = branch.points.last().unwrap();
+        assert!((last.parameter - 1.0).abs() < 1e-12);
+        assert!((last.values[0].powi(3) - last.values[0] - 1.0).abs() < 1e-8);
+        // Stable, unstable between the folds, and stable again.
+        let table = branch.table();
+        assert_eq!(table.columns, ["p", "x", "stable"]);
+        let stable = table.column("stable").unwrap();
+        let first = branch.bifurcations[0].point;
+        let second = branch.bifurcations[1].point;
+        assert!(stable[..first].iter().all(|&s| s == 1.0));
+        assert!(stable[first + 1..second].iter().all(|&s| s == 0.0));
+        assert!(stable[second + 1..].iter().all(|&s| s == 1.0));
+
+        // Stepping outward from a bound stops at once.
+        let options = ContinuationOptions {
+            step: -0.05,
+            ..options
+        };
+        let branch = continuation(&module, "p", &options).unwrap();
+        assert_eq!(branch.points.len(), 1);
+        assert_eq!(branch.points[0].parameter, -1.0);
+    }
+
+    #[test]
+    fn locates_a_hopf_bifurcation() {
+        // The normal form of a Hopf bifurcation at p = 0, with eigenvalues p ± 2i.
+        let module = Fixture::new()
+            .parameter("p", -0.5)
+            .rate_rule("x", 0.0, "p*x - 2*y - x*(x^2 + y^2)")
+            .rate_rule("y", 0.0, "2*x + p*y - y*(x^2 + y^2)")
+            .build();
+        let options = ContinuationOptions {
+            lower: -1.0,
+            upper: 0.5,
+            step: 0.1,
+            ..ContinuationOptions::default()
+        };
+        let branch = continuation(&module, "p", &options).unwrap();
+        assert_eq!(branch.bifurcations.len(), 1);
+        let hopf = branch.bifurcations[0];
+        match hopf.kind {
+            BifurcationKind::Hopf { frequency } => assert!((frequency - 2.0).abs() < 1e-9),
+            kind => panic!("{:?}", kind),
+        }
+        assert!(branch.points[hopf.point].parameter.abs() < 1e-9);
+        assert!(branch.points[0].is_stable() && !branch.points.last().unwrap().is_stable());
+
+        assert_eq!(
+            continuation(&module, "x", &options).err(),
+            Some(Error::InvalidOptions(
+                "`x` is not a constant parameter".into()
+            ))
+        );
+    }
+}
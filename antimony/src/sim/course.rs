@@ -0,0 +1,231 @@
+//! Tables of results, with their selection, interpolation, and output as delimited text.
+
+use std::io::{self, Write};
+
+use crate::Module;
+
+/// Simulation results: one row per output time, one column per selection.
+///
+/// The same table holds other results that come one row at a time, such as the points of a
+/// scan (see `Simulator::scan`) or of a branch of steady states (see `Branch::table`).
+///
+/// ```no_run
+/// use antimony::sim::{simulate, Options};
+/// use antimony::Document;
+///
+/// let doc = Document::load_antimony_str("J0: S1 => ; k1*S1; S1 = 10; k1 = 0.5")?;
+/// let result = simulate(doc.main(), &Options::default())?;
+/// let halfway = result.at(2.5).unwrap();
+/// result.select(&["time", "S1"]).unwrap().write_csv(std::fs::File::create("S1.csv")?)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeCourse {
+    /// The column labels: `time` or symbol ids.
+    pub columns: Vec<String>,
+    /// The display name of each column: that of its symbol if it has one, or else its label.
+    pub names: Vec<String>,
+    /// The values, one row per output time.
+    pub rows: Vec<Vec<f64>>,
+}
+
+impl TimeCourse {
+    /// A table with the given labels, which also serve as display names.
+    pub fn new(columns: Vec<String>, rows: Vec<Vec<f64>>) -> TimeCourse {
+        TimeCourse {
+            names: columns.clone(),
+            columns,
+            rows,
+        }
+    }
+
+    /// Take the display names of the columns from the symbols and reactions of a module.
+    pub(crate) fn name(mut self, module: &Module) -> TimeCourse {
+        for (name, id) in self.names.iter_mut().zip(&self.columns) {
+            let display = match module.symbol(id) {
+                Some(symbol) => symbol.name.as_ref(),
+                None => module.reaction(id).and_then(|r| r.name.as_ref()),
+            };
+            if let Some(display) = display.filter(|d| !d.is_empty()) {
+                name.clone_from(display);
+            }
+        }
+        self
+    }
+
+    /// The values of the column with the given label, if any.
+    pub fn column(&self, label: &str) -> Option<Vec<f64>> {
+        let j = self.columns.iter().position(|c| c == label)?;
+        Some(self.rows.iter().map(|row| row[j]).collect())
+    }
+
+    /// The output times, if there is a `time` column.
+    pub fn times(&self) -> Option<Vec<f64>> {
+        self.column("time")
+    }
+
+    /// The table with only the given columns, in the given order, or `None` if one of them is
+    /// missing.
+    pub fn select(&self, labels: &[&str]) -> Option<TimeCourse> {
+        let indices = labels
+            .iter()
+            .map(|label| self.columns.iter().position(|c| c == label))
+            .collect::<Option<Vec<usize>>>()?;
+        Some(TimeCourse {
+            columns: indices.iter().map(|&j| self.columns[j].clone()).collect(),
+            names: indices.iter().map(|&j| self.names[j].clone()).collect(),
+            rows: self
+                .rows
+                .iter()
+                .map(|row| indices.iter().map(|&j| row[j]).collect())
+                .collect(),
+        })
+    }
+
+    /// The row at time `time`, interpolated linearly between the output times around it, or
+    /// `None` if there is no `time` column or the time is outside the table. The times must
+    /// increase, as they do in the results of a simulation.
+    pub fn at(&self, time: f64) -> Option<Vec<f64>> {
+        let j = self.columns.iter().position(|c| c == "time")?;
+        let (first, last) = (self.rows.first()?, self.rows.last()?);
+        if !(time >= first[j] && time <= last[j]) {
+            return None;
+        }
+        let k = self.rows.partition_point(|row| row[j] < time);
+        let after = &self.rows[k];
+        if k == 0 || after[j] == time {
+            return Some(after.clone());
+        }
+        let before = &self.rows[k - 1];
+        let w = (time - before[j]) / (after[j] - before[j]);
+        Some(
+            before
+                .iter()
+                .zip(after)
+                .map(|(a, b)| a + w * (b - a))
+                .collect(),
+        )
+    }
+
+    /// The table at other times, interpolated as by `TimeCourse::at`, or `None` if any of the
+    /// times is outside the table.
+    pub fn interpolate(&self, times: &[f64]) -> Option<TimeCourse> {
+        Some(TimeCourse {
+            columns: self.columns.clone(),
+            names: self.names.clone(),
+            rows: times.iter().map(|&t| self.at(t)).collect::<Option<_>>()?,
+        })
+    }
+
+    /// The table with the display names as column labels, e.g., to write it for readers who
+    /// do not know the ids.
+    pub fn named(self) -> TimeCourse {
+        TimeCourse {
+            columns: self.names.clone(),
+            ..self
+        }
+    }
+
+    /// Write the table as comma-separated values, with a header row of the labels.
+    pub fn write_csv<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.write_delimited(writer, ',')
+    }
+
+    /// Write the table as tab-separated values, with a header row of the labels.
+    pub fn write_tsv<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.write_delimited(writer, '\t')
+    }
+
+    /// The table as comma-separated values; see `TimeCourse::write_csv`.
+    pub fn to_csv(&self) -> String {
+        let mut out = Vec::new();
+        self.write_csv(&mut out)
+            .expect("writing to memory succeeds");
+        String::from_utf8(out).expect("the table is UTF-8")
+    }
+
+    /// The table as tab-separated values; see `TimeCourse::write_tsv`.
+    pub fn to_tsv(&self) -> String {
+        let mut out = Vec::new();
+        self.write_tsv(&mut out)
+            .expect("writing to memory succeeds");
+        String::from_utf8(out).expect("the table is UTF-8")
+    }
+
+    /// Write the labels, quoted where they contain the delimiter, a quote, or a line break,
+    /// and then the rows, with numbers in the shortest form that reads back exactly, in
+    /// scientific notation if they are very small or large.
+    fn write_delimited<W: Write>(&self, writer: W, delimiter: char) -> io::Result<()> {
+        let mut out = io::BufWriter::new(writer);
+        for (j, label) in self.columns.iter().enumerate() {
+            if j > 0 {
+                write!(out, "{}", delimiter)?;
+            }
+            if label.contains([delimiter, '"', '\n', '\r']) {
+                write!(out, "\"{}\"", label.replace('"', "\"\""))?;
+            } else {
+                out.write_all(label.as_bytes())?;
+            }
+        }
+        writeln!(out)?;
+        for row in &self.rows {
+            for (j, value) in row.iter().enumerate() {
+                if j > 0 {
+                    write!(out, "{}", delimiter)?;
+                }
+                let magnitude = value.abs();
+                if magnitude != 0.0 && !(1e-5..1e16).contains(&magnitude) {
+                    write!(out, "{:e}", value)?;
+                } else {
+                    write!(out, "{}", value)?;
+                }
+            }
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn selects_interpolates_and_writes() {
+        let mut module = Fixture::new().species("S", 1.0).build();
+        module.symbols[0].name = Some("substrate".into());
+        let course = TimeCourse::new(
+            vec!["time".into(), "S".into(), "a,b".into()],
+            vec![
+                vec![0.0, 1.0, 0.5],
+                vec![1.0, 3.0, f64::NAN],
+                vec![3.0, 4.0, 1e-12],
+            ],
+        )
+        .name(&module);
+        assert_eq!(course.names, ["time", "substrate", "a,b"]);
+        assert_eq!(course.times(), Some(vec![0.0, 1.0, 3.0]));
+        let halfway = course.at(0.5).unwrap();
+        assert!(halfway[..2] == [0.5, 2.0] && halfway[2].is_nan());
+        assert_eq!(course.at(2.0).unwrap()[..2], [2.0, 3.5]);
+        assert_eq!(course.at(1.0).unwrap()[..2], [1.0, 3.0]);
+        assert_eq!(course.at(3.5), None);
+
+        let selected = course.select(&["S", "time"]).unwrap();
+        assert_eq!(selected.columns, ["S", "time"]);
+        let resampled = selected.interpolate(&[0.0, 2.0]).unwrap();
+        assert_eq!(resampled.rows, [[1.0, 0.0], [3.5, 2.0]]);
+        assert_eq!(resampled.named().columns, ["substrate", "time"]);
+        assert_eq!(course.select(&["S", "P"]), None);
+
+        assert_eq!(
+            course.to_csv(),
+            "time,S,\"a,b\"\n0,1,0.5\n1,3,NaN\n3,4,1e-12\n"
+        );
+        assert_eq!(
+            course.select(&["time", "a,b"]).unwrap().to_tsv(),
+            "time\ta,b\n0\t0.5\n1\tNaN\n3\t1e-12\n"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Delay differential equations: the history that `delay(x, tau)` reads.
+//!
+//! The integrator records every accepted step, and a delayed value is found by cubic Hermite
+//! interpolation of the state at the delayed time, from which the assignment rules and then
+//! `x` are evaluated. Before the start of the simulation the history is constant. Delayed
+//! times within the current step, which only lags shorter than the step can reach, are
+//! extrapolated from the last recorded point; steps are therefore kept no longer than the
+//! shortest lag.
+//!
+//! The derivatives of a delay differential equation are discontinuous where a discontinuity
+//! (the start, or an event) is seen through a lag, then through a lag again in the next
+//! derivative, and so on. Integrators assume smoothness, so they stop at those breakpoints and
+//! restart.
+
+use super::solver::hermite;
+use super::system::System;
+use crate::math::EvalError;
+
+/// The number of times a discontinuity is followed through the lags: beyond the order of the
+/// integrators, it no longer matters.
+const ORDER: usize = 5;
+/// The most breakpoints followed from one discontinuity.
+const MAX_BREAKPOINTS: usize = 1000;
+
+/// The recorded states and derivatives of a simulation, oldest first.
+#[derive(Debug, Clone)]
+pub(crate) struct History {
+    points: Points,
+    scratch: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+struct Points {
+    times: Vec<f64>,
+    states: Vec<Vec<f64>>,
+    slopes: Vec<Vec<f64>>,
+}
+
+impl History {
+    /// A constant history of the state `y` up to time `t`, for a system with `slots` slots.
+    pub fn new(t: f64, y: &[f64], slots: usize) -> History {
+        History {
+            points: Points {
+                times: vec![t],
+                states: vec![y.to_vec()],
+                slopes: vec![vec![0.0; y.len()]],
+            },
+            scratch: vec![0.0; slots],
+        }
+    }
+
+    /// Record the state `y` and its derivative `f` at time `t`, which must not precede the
+    /// last time recorded. Recording the same time again gives the derivative after a
+    /// discontinuity.
+    pub fn push(&mut self, t: f64, y: &[f64], f: &[f64]) {
+        self.points.times.push(t);
+        self.points.states.push(y.to_vec());
+        self.points.slopes.push(f.to_vec());
+    }
+
+    /// Store the delayed values at time `t` into their slots of `values`.
+    pub fn fill(&mut self, system: &System, t: f64, values: &mut [f64]) -> Result<(), EvalError> {
+        let n = system.n_state;
+        for d in &system.delays {
+            let lag = d.lag.eval(t, values)?;
+            if lag.is_nan() || lag < 0.0 {
+                return Err(EvalError::Domain {
+                    function: "delay",
+                    argument: lag,
+                });
+            }
+            values[d.slot] = if lag == 0.0 {
+                d.value.eval(t, values)?
+            } else {
+                self.scratch.copy_from_slice(values);
+                self.points.state(t - lag, &mut self.scratch[..n]);
+                system.update(t - lag, &mut self.scratch)?;
+                d.value.eval(t - lag, &self.scratch)?
+            };
+        }
+        Ok(())
+    }
+}
+
+impl Points {
+    /// The state at time `t`.
+    fn state(&self, t: f64, out: &mut [f64]) {
+        let last = self.times.len() - 1;
+        if t <= self.times[0] {
+            out.copy_from_slice(&self.states[0]);
+        } else if t >= self.times[last] {
+            let dt = t - self.times[last];
+            for (o, (y, f)) in out
+                .iter_mut()
+                .zip(self.states[last].iter().zip(&self.slopes[last]))
+            {
+                *o = y + dt * f;
+            }
+        } else {
+            let k = self.times.partition_point(|&time| time <= t);
+            let point = |i: usize| (self.times[i], &self.states[i][..], &self.slopes[i][..]);
+            hermite(point(k - 1), point(k), t, out);
+        }
+    }
+}
+
+/// The times after `t`, up to `end`, to which a discontinuity at `t` propagates through the
+/// given (positive) lags.
+pub(crate) fn breakpoints(t: f64, lags: &[f64], end: f64) -> Vec<f64> {
+    let mut all = Vec::new();
+    let mut level = vec![t];
+    for _ in 0..ORDER {
+        let mut next: Vec<f64> = level
+            .iter()
+            .flat_map(|b| lags.iter().map(move |lag| b + lag))
+            .filter(|&time| time <= end)
+            .collect();
+        dedup(&mut next);
+        all.extend_from_slice(&next);
+        if next.is_empty() || all.len() >= MAX_BREAKPOINTS {
+            break;
+        }
+        level = next;
+    }
+    dedup(&mut all);
+    all.truncate(MAX_BREAKPOINTS);
+    all
+}
+
+/// Sort times and merge those that differ by rounding only.
+pub(crate) fn dedup(times: &mut Vec<f64>) {
+    times.sort_by(f64::total_cmp);
+    times.dedup_by(|a, b| (*a - *b).abs() <= 1e-12 * a.abs().max(1.0));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_discontinuities_through_lags() {
+        assert_eq!(
+            breakpoints(0.0, &[1.0, 1.5], 3.2),
+            [1.0, 1.5, 2.0, 2.5, 3.0]
+        );
+        assert_eq!(breakpoints(0.0, &[1.0], 100.0), [1.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+}
@@ -0,0 +1,187 @@
+//! Event semantics shared by the simulators, following SBML Level 3.
+//!
+//! An event is triggered when its trigger changes from false to true. Its delay is evaluated
+//! at that moment, and so are its assignments if it uses values from trigger time; the
+//! assignments are executed once the delay has passed. Events due at the same time execute one
+//! at a time in order of decreasing priority (evaluated just before choosing the next event,
+//! with events without a priority last and ties broken by the order of triggering), and the
+//! triggers are re-examined after every execution, so events can trigger each other. An event
+//! that is not persistent is cancelled if its trigger turns false before it executes.
+
+use super::system::System;
+use super::Error;
+use crate::math::EvalError;
+
+/// Executions at a single time after which the events are taken to trigger each other
+/// endlessly.
+const MAX_CASCADE: usize = 10_000;
+
+#[derive(Debug)]
+struct Pending {
+    event: usize,
+    time: f64,
+    /// The assignment values, if computed at trigger time.
+    values: Option<Vec<f64>>,
+    /// Orders events scheduled for the same time by when they were triggered.
+    sequence: u64,
+}
+
+/// The trigger states and scheduled executions of a system's events.
+#[derive(Debug)]
+pub(crate) struct Events {
+    state: Vec<bool>,
+    pending: Vec<Pending>,
+    sequence: u64,
+}
+
+impl Events {
+    /// The events before the start of a simulation, with the triggers at their initial
+    /// values.
+    pub fn new(system: &System) -> Events {
+        Events {
+            state: system.events.iter().map(|e| e.initial_value).collect(),
+            pending: Vec::new(),
+            sequence: 0,
+        }
+    }
+
+    /// Whether any trigger differs from its last known value.
+    pub fn changed(&self, system: &System, t: f64, values: &[f64]) -> Result<bool, EvalError> {
+        for (e, &before) in system.events.iter().zip(&self.state) {
+            if (e.trigger.eval(t, values)? != 0.0) != before {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The earliest time in `(t0, t1]` at which a trigger changes while the state variables
+    /// keep their values, located by bisection, or `t1` if none does. `probe` is scratch
+    /// space as large as `values`.
+    pub fn first_change(
+        &self,
+        system: &System,
+        t0: f64,
+        t1: f64,
+        values: &[f64],
+        probe: &mut [f64],
+    ) -> Result<f64, EvalError> {
+        if system.events.is_empty() {
+            return Ok(t1);
+        }
+        let mut changed_at = |time: f64| -> Result<bool, EvalError> {
+            probe.copy_from_slice(values);
+            system.update(time, probe)?;
+            self.changed(system, time, probe)
+        };
+        let (mut lo, mut hi) = (t0, t1);
+        if changed_at(hi)? {
+            while hi - lo > 4.0 * f64::EPSILON * hi.abs().max(1.0) {
+                let mid = 0.5 * (lo + hi);
+                if changed_at(mid)? {
+                    hi = mid;
+                } else {
+                    lo = mid;
+                }
+            }
+        }
+        Ok(hi)
+    }
+
+    /// The time of the next scheduled execution, if any.
+    pub fn next_time(&self) -> Option<f64> {
+        self.pending.iter().map(|p| p.time).reduce(f64::min)
+    }
+
+    /// Handle trigger changes at time `t` and execute every event due by then, updating
+    /// `values` (which must be consistent with the assignment rules). Returns whether any
+    /// event was executed.
+    pub fn process(&mut self, system: &System, t: f64, values: &mut [f64]) -> Result<bool, Error> {
+        self.transitions(system, t, values)?;
+        let mut executed = 0;
+        loop {
+            let mut best: Option<(usize, f64)> = None;
+            for (i, p) in self.pending.iter().enumerate() {
+                if p.time > t {
+                    continue;
+                }
+                let priority = match &system.events[p.event].priority {
+                    Some(code) => code.eval(t, values)?,
+                    None => f64::NEG_INFINITY,
+                };
+                let better = match best {
+                    None => true,
+                    Some((j, q)) => {
+                        priority > q || (priority == q && p.sequence < self.pending[j].sequence)
+                    }
+                };
+                if better {
+                    best = Some((i, priority));
+                }
+            }
+            let p = match best {
+                Some((i, _)) => self.pending.remove(i),
+                None => break,
+            };
+            if executed == MAX_CASCADE {
+                return Err(Error::TooManySteps { time: t });
+            }
+            executed += 1;
+            let event = &system.events[p.event];
+            let assigned = match p.values {
+                Some(assigned) => assigned,
+                None => assignment_values(system, p.event, t, values)?,
+            };
+            for ((slot, _), value) in event.assignments.iter().zip(assigned) {
+                values[*slot] = value;
+            }
+            system.update(t, values)?;
+            self.transitions(system, t, values)?;
+        }
+        Ok(executed > 0)
+    }
+
+    /// Schedule newly triggered events and cancel non-persistent ones whose trigger turned
+    /// false.
+    fn transitions(&mut self, system: &System, t: f64, values: &[f64]) -> Result<(), Error> {
+        for (i, e) in system.events.iter().enumerate() {
+            let now = e.trigger.eval(t, values)? != 0.0;
+            if now && !self.state[i] {
+                let delay = match &e.delay {
+                    Some(code) => code.eval(t, values)?.max(0.0),
+                    None => 0.0,
+                };
+                let assigned = if e.from_trigger {
+                    Some(assignment_values(system, i, t, values)?)
+                } else {
+                    None
+                };
+                self.pending.push(Pending {
+                    event: i,
+                    time: t + delay,
+                    values: assigned,
+                    sequence: self.sequence,
+                });
+                self.sequence += 1;
+            } else if !now && self.state[i] && !e.persistent {
+                self.pending.retain(|p| p.event != i);
+            }
+            self.state[i] = now;
+        }
+        Ok(())
+    }
+}
+
+/// Evaluate every assignment of an event before any of them is executed.
+fn assignment_values(
+    system: &System,
+    event: usize,
+    t: f64,
+    values: &[f64],
+) -> Result<Vec<f64>, EvalError> {
+    system.events[event]
+        .assignments
+        .iter()
+        .map(|(_, code)| code.eval(t, values))
+        .collect()
+}
@@ -0,0 +1,260 @@
+//! Hybrid simulation: fast reactions as differential equations, slow ones as discrete events.
+//!
+//! Before every step, reactions are partitioned by propensity: a reaction is fast if its
+//! propensity is at least `Options::fast_propensity` and each species it consumes numbers at
+//! least `FAST_COUNT` molecules, so that its firings are frequent and change the counts little.
+//! Fast reactions change the counts continuously, as do rate rules, and are integrated by the
+//! Rosenbrock method since they are often stiff.
+//!
+//! Slow reactions fire one at a time, with propensities that vary between firings as the fast
+//! reactions proceed: the integral of their total propensity is integrated along with the
+//! state, and the next slow reaction fires when it reaches an exponentially distributed target
+//! (Haseltine & Rawlings, 2002; Salis & Kaznessis, 2005). The partition is revised after every
+//! step, so that reactions move between the subsets as their propensities change.
+//!
+//! Counts that fast reactions changed need not be whole, and a slow reaction cannot fire while
+//! it would take a count below zero: the fraction of a molecule that is left stays.
+
+use super::events::Events;
+use super::network::Network;
+use super::random::Random;
+use super::rosenbrock::Rosenbrock;
+use super::solver::{interpolate, Rhs, Stepper};
+use super::system::System;
+use super::{Error, Options, Recorder};
+use crate::math::EvalError;
+
+/// The fewest molecules of each reactant a fast reaction has.
+const FAST_COUNT: f64 = 100.0;
+
+/// The counts of the species, the rate-rule variables, and the integral of the total slow
+/// propensity, with the fast reactions as differential equations.
+struct HybridRhs<'a> {
+    system: &'a System,
+    network: &'a Network,
+    values: Vec<f64>,
+    fast: Vec<bool>,
+    /// Whether each species takes part in a fast reaction.
+    continuous: Vec<bool>,
+}
+
+impl HybridRhs<'_> {
+    /// Decide which reactions are fast in the state `y` at time `t`. Returns whether that
+    /// changed.
+    fn partition(&mut self, t: f64, y: &[f64], threshold: f64) -> Result<bool, Error> {
+        let species = self.network.species();
+        load(self.system, self.network, t, y, &mut self.values)?;
+        let mut changed = false;
+        for (j, channel) in self.network.channels.iter().enumerate() {
+            let a = self
+                .network
+                .propensity(self.system, j, t, &y[..species], &self.values)?;
+            let fast = a >= threshold
+                && channel
+                    .changes
+                    .iter()
+                    .all(|&(i, change)| change > 0.0 || y[i] >= FAST_COUNT);
+            changed |= fast != self.fast[j];
+            self.fast[j] = fast;
+        }
+        self.continuous.iter_mut().for_each(|c| *c = false);
+        for (channel, _) in self
+            .network
+            .channels
+            .iter()
+            .zip(&self.fast)
+            .filter(|c| *c.1)
+        {
+            for &(i, _) in &channel.changes {
+                self.continuous[i] = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Restore the counts in `y` that only slow reactions change to their values in `before`,
+    /// undoing the rounding errors of integration.
+    fn hold(&self, before: &[f64], y: &mut [f64]) {
+        for (i, &continuous) in self.continuous.iter().enumerate() {
+            if !continuous {
+                y[i] = before[i];
+            }
+        }
+    }
+}
+
+impl Rhs for HybridRhs<'_> {
+    fn eval(&mut self, t: f64, y: &[f64], dy: &mut [f64]) -> Result<(), Error> {
+        let (system, network) = (self.system, self.network);
+        let species = network.species();
+        load(system, network, t, y, &mut self.values)?;
+        for (i, d) in dy.iter_mut().enumerate().take(system.n_state) {
+            *d = if i < species {
+                0.0
+            } else {
+                system.rates[i].eval(t, &self.values)?
+            };
+        }
+        let slow = system.n_state;
+        dy[slow] = 0.0;
+        for (j, channel) in network.channels.iter().enumerate() {
+            let a = network.propensity(system, j, t, &y[..species], &self.values)?;
+            if self.fast[j] {
+                for &(i, change) in &channel.changes {
+                    dy[i] += change * a;
+                }
+            } else {
+                dy[slow] += a;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Set the state variables in `values` from the hybrid state `y` and update every assignment.
+fn load(
+    system: &System,
+    network: &Network,
+    t: f64,
+    y: &[f64],
+    values: &mut [f64],
+) -> Result<(), EvalError> {
+    let (species, n) = (network.species(), system.n_state);
+    values[species..n].copy_from_slice(&y[species..n]);
+    network.write(system, t, &y[..species], values)
+}
+
+/// Simulate from the state in `values` at `options.start` until every output is recorded.
+pub(crate) fn simulate(
+    system: &System,
+    network: &Network,
+    options: &Options,
+    values: &mut [f64],
+    random: &mut Random,
+    out: &mut Recorder,
+) -> Result<(), Error> {
+    let (species, n) = (network.species(), system.n_state);
+    let slow = n;
+    let mut t = options.start;
+    let mut events = Events::new(system);
+    events.process(system, t, values)?;
+    let mut y = network.counts(system, t, values)?;
+    y.extend_from_slice(&values[species..n]);
+    y.push(0.0);
+
+    let mut rhs = HybridRhs {
+        system,
+        network,
+        values: values.to_vec(),
+        fast: vec![false; network.channels.len()],
+        continuous: vec![false; species],
+    };
+    rhs.partition(t, &y, options.fast_propensity)?;
+    let mut stepper = Rosenbrock::new(
+        n + 1,
+        options.relative_tolerance,
+        options.absolute_tolerance,
+        options.max_step,
+    );
+    let mut f = vec![0.0; n + 1];
+    rhs.eval(t, &y, &mut f)?;
+    let mut target = random.exponential();
+    let mut point = y.clone();
+    let mut probe = values.to_vec();
+    let mut steps = 0;
+    while out.next().is_some() {
+        if steps == options.max_steps {
+            return Err(Error::TooManySteps { time: t });
+        }
+        let t_stop = events
+            .next_time()
+            .map_or(options.end, |te| te.min(options.end));
+        let step = stepper.step(&mut rhs, t, &y, &f, t_stop)?;
+        steps += 1;
+
+        // Stop where the next slow reaction fires or a trigger changes, whichever is first.
+        let mut happened = |time: f64| -> Result<bool, Error> {
+            interpolate(t, &y, &f, &step, time, &mut point);
+            rhs.hold(&y, &mut point);
+            if point[slow] >= target {
+                return Ok(true);
+            }
+            load(system, network, time, &point, &mut probe)?;
+            Ok(events.changed(system, time, &probe)?)
+        };
+        let mut end = step.t;
+        if happened(end)? {
+            let mut lo = t;
+            while end - lo > 4.0 * f64::EPSILON * end.abs().max(1.0) {
+                let mid = 0.5 * (lo + end);
+                if happened(mid)? {
+                    end = mid;
+                } else {
+                    lo = mid;
+                }
+            }
+        }
+
+        while let Some(time) = out.next().filter(|&time| time <= end) {
+            interpolate(t, &y, &f, &step, time, &mut point);
+            rhs.hold(&y, &mut point);
+            load(system, network, time, &point, values)?;
+            out.record(values);
+        }
+        let truncated = end < step.t;
+        if truncated {
+            interpolate(t, &y, &f, &step, end, &mut point);
+        } else {
+            point.copy_from_slice(&step.y);
+        }
+        rhs.hold(&y, &mut point);
+        y.copy_from_slice(&point);
+        t = end;
+        load(system, network, t, &y, values)?;
+
+        let fired = y[slow] >= target;
+        if fired {
+            let mut a = Vec::with_capacity(network.channels.len());
+            for (j, &fast) in rhs.fast.iter().enumerate() {
+                let depleting = network.channels[j]
+                    .changes
+                    .iter()
+                    .any(|&(i, change)| y[i] + change < 0.0);
+                a.push(if fast || depleting {
+                    0.0
+                } else {
+                    network.propensity(system, j, t, &y[..species], values)?
+                });
+            }
+            let choice = random.uniform() * a.iter().sum::<f64>();
+            let mut sum = 0.0;
+            if let Some(j) = (0..a.len()).find(|&j| {
+                sum += a[j];
+                sum > choice && a[j] > 0.0
+            }) {
+                for &(i, change) in &network.channels[j].changes {
+                    y[i] += change;
+                }
+            }
+            y[slow] = 0.0;
+            target = random.exponential();
+            load(system, network, t, &y, values)?;
+        }
+        let executed = events.process(system, t, values)?;
+        if executed {
+            let amounts = network.amounts(values);
+            y[..species].copy_from_slice(&amounts);
+            y[species..n].copy_from_slice(&values[species..n]);
+        }
+        let repartitioned = rhs.partition(t, &y, options.fast_propensity)?;
+        if fired || executed || repartitioned {
+            stepper.reset();
+        }
+        if fired || executed || repartitioned || truncated {
+            rhs.eval(t, &y, &mut f)?;
+        } else {
+            f.copy_from_slice(&step.f);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,243 @@
+//! Approximate stochastic simulation: tau-leaping and the chemical Langevin equation.
+//!
+//! Both methods advance by leaps during which the propensities are taken to be constant, and
+//! choose each leap so that no propensity is expected to change by more than a fraction
+//! `Options::leap_tolerance` of itself (the tau selection of Cao, Gillespie & Petzold, 2006).
+//!
+//! Tau-leaping fires each reaction a Poisson-distributed number of times per leap. Reactions
+//! close to exhausting a reactant are critical: they fire at most once per leap, as in exact
+//! simulation, and a leap that would still make a count negative is halved. When leaps become
+//! so short that exact simulation is as fast, a run of exact steps is taken instead.
+//!
+//! The chemical Langevin equation replaces the Poisson numbers by normal ones with the same
+//! mean and variance, turning the counts into a continuous diffusion, which is integrated by
+//! the Euler–Maruyama method. It suits networks in which every reaction fires often.
+//!
+//! Events execute at the ends of leaps; leaps stop where a trigger changes with time alone.
+
+use super::events::Events;
+use super::network::Network;
+use super::random::Random;
+use super::ssa::{Direct, Selector};
+use super::system::System;
+use super::{Error, Options, Recorder};
+
+/// Reactions that can exhaust a reactant in fewer firings are critical.
+const CRITICAL: f64 = 10.0;
+/// Leaps shorter than this many mean waiting times between reactions are not worth taking.
+const MIN_LEAP: f64 = 10.0;
+/// The number of exact steps taken when leaps become too short.
+const EXACT_STEPS: usize = 100;
+
+/// Simulate by tau-leaping from the state in `values` at `options.start`.
+pub(crate) fn tau_leaping(
+    system: &System,
+    network: &Network,
+    options: &Options,
+    values: &mut [f64],
+    random: &mut Random,
+    out: &mut Recorder,
+) -> Result<(), Error> {
+    let mut exact = 0;
+    let mut next = Vec::new();
+    let leap = |random: &mut Random, t: f64, stop: f64, a: &[f64], counts: &mut [f64]| {
+        let total: f64 = a.iter().sum();
+        if total <= 0.0 {
+            return stop;
+        }
+        let critical: Vec<bool> = network
+            .channels
+            .iter()
+            .zip(a)
+            .map(|(channel, &a)| {
+                let exhausts = |&(i, change): &(usize, f64)| -counts[i] / change < CRITICAL;
+                a > 0.0 && channel.changes.iter().filter(|c| c.1 < 0.0).any(exhausts)
+            })
+            .collect();
+        let mut tau = leap_size(network, counts, a, options.leap_tolerance, |j| !critical[j]);
+        if exact == 0 && tau < MIN_LEAP / total {
+            exact = EXACT_STEPS;
+        }
+        if exact > 0 {
+            exact -= 1;
+            let (time, j) = Direct.next(t, a, random);
+            if time >= stop {
+                return stop;
+            }
+            for &(i, change) in &network.channels[j].changes {
+                counts[i] += change;
+            }
+            return time;
+        }
+
+        let critical_total: f64 = a.iter().zip(&critical).filter(|c| *c.1).map(|c| c.0).sum();
+        let critical_wait = if critical_total > 0.0 {
+            random.exponential() / critical_total
+        } else {
+            f64::INFINITY
+        };
+        loop {
+            let step = tau.min(critical_wait).min(stop - t);
+            next.clear();
+            next.extend_from_slice(counts);
+            for (j, channel) in network.channels.iter().enumerate() {
+                if critical[j] || a[j] <= 0.0 {
+                    continue;
+                }
+                let firings = random.poisson(a[j] * step);
+                for &(i, change) in &channel.changes {
+                    next[i] += change * firings;
+                }
+            }
+            if step == critical_wait {
+                let target = random.uniform() * critical_total;
+                let mut sum = 0.0;
+                let chosen = (0..a.len())
+                    .filter(|&j| critical[j])
+                    .find(|&j| {
+                        sum += a[j];
+                        sum > target
+                    })
+                    .or_else(|| (0..a.len()).rev().find(|&j| critical[j]))
+                    .expect("a critical reaction has a positive propensity");
+                for &(i, change) in &network.channels[chosen].changes {
+                    next[i] += change;
+                }
+            }
+            if next.iter().all(|&x| x >= 0.0) {
+                counts.copy_from_slice(&next);
+                return if step == stop - t { stop } else { t + step };
+            }
+            tau = step / 2.0;
+        }
+    };
+    run(system, network, options, values, random, out, true, leap)
+}
+
+/// Simulate the chemical Langevin equation from the state in `values` at `options.start`.
+pub(crate) fn langevin(
+    system: &System,
+    network: &Network,
+    options: &Options,
+    values: &mut [f64],
+    random: &mut Random,
+    out: &mut Recorder,
+) -> Result<(), Error> {
+    let step = |random: &mut Random, t: f64, stop: f64, a: &[f64], counts: &mut [f64]| {
+        let tau = leap_size(network, counts, a, options.leap_tolerance, |_| true).min(stop - t);
+        for (channel, &a) in network.channels.iter().zip(a) {
+            if a <= 0.0 {
+                continue;
+            }
+            let firings = a * tau + (a * tau).sqrt() * random.normal();
+            for &(i, change) in &channel.changes {
+                counts[i] += change * firings;
+            }
+        }
+        for x in counts.iter_mut() {
+            *x = x.max(0.0);
+        }
+        if tau == stop - t {
+            stop
+        } else {
+            t + tau
+        }
+    };
+    run(system, network, options, values, random, out, false, step)
+}
+
+/// The longest leap over which the expected change and the standard deviation of the change
+/// of every reactant count, from the included channels, stay within the bound of the leap
+/// condition.
+fn leap_size<F: Fn(usize) -> bool>(
+    network: &Network,
+    counts: &[f64],
+    a: &[f64],
+    epsilon: f64,
+    include: F,
+) -> f64 {
+    let mut mean = vec![0.0; counts.len()];
+    let mut variance = vec![0.0; counts.len()];
+    for (j, channel) in network.channels.iter().enumerate() {
+        if a[j] <= 0.0 || !include(j) {
+            continue;
+        }
+        for &(i, change) in &channel.changes {
+            mean[i] += change * a[j];
+            variance[i] += change * change * a[j];
+        }
+    }
+    let mut tau = f64::INFINITY;
+    for (i, &x) in counts.iter().enumerate() {
+        let order = network.highest_order(i, x);
+        if order == 0.0 {
+            continue;
+        }
+        let bound = (epsilon * x / order).max(1.0);
+        if mean[i] != 0.0 {
+            tau = tau.min(bound / mean[i].abs());
+        }
+        if variance[i] > 0.0 {
+            tau = tau.min(bound * bound / variance[i]);
+        }
+    }
+    tau
+}
+
+/// Advance by `leap` (which maps the time, the time not to pass, the propensities and the
+/// counts to the time reached, updating the counts) until every output is recorded. Counts are
+/// rounded to whole molecules after events if `discrete`.
+#[allow(clippy::too_many_arguments)]
+fn run<F>(
+    system: &System,
+    network: &Network,
+    options: &Options,
+    values: &mut [f64],
+    random: &mut Random,
+    out: &mut Recorder,
+    discrete: bool,
+    mut leap: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&mut Random, f64, f64, &[f64], &mut [f64]) -> f64,
+{
+    let mut t = options.start;
+    let mut events = Events::new(system);
+    events.process(system, t, values)?;
+    let mut counts = if discrete {
+        network.counts(system, t, values)?
+    } else {
+        network.amounts(values)
+    };
+    let mut a = vec![0.0; network.channels.len()];
+    let mut probe = values.to_vec();
+    let mut steps = 0;
+    while let Some(output) = out.next() {
+        if steps == options.max_steps {
+            return Err(Error::TooManySteps { time: t });
+        }
+        steps += 1;
+        for (k, a) in a.iter_mut().enumerate() {
+            *a = network.propensity(system, k, t, &counts, values)?;
+        }
+        let mut stop = output.min(t + options.max_step);
+        if let Some(time) = events.next_time() {
+            stop = stop.min(time);
+        }
+        stop = events.first_change(system, t, stop, values, &mut probe)?;
+
+        t = leap(random, t, stop, &a, &mut counts);
+        network.write(system, t, &counts, values)?;
+        while out.next().filter(|&time| time <= t).is_some() {
+            out.record(values);
+        }
+        if events.process(system, t, values)? {
+            counts = if discrete {
+                network.counts(system, t, values)?
+            } else {
+                network.amounts(values)
+            };
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,308 @@
+//! Metabolic control analysis: how a steady state responds to changes in reaction rates.
+//!
+//! The elasticity of reaction `j` to species `i` is the partial derivative of the rate of `j`
+//! with respect to the concentration of `i`, found by differentiating the expanded rate law
+//! (or, where that fails, by finite differences). Control coefficients follow from the
+//! elasticities and the stoichiometry (Reder, 1988): with conservation laws removed, the
+//! concentration control coefficients are `C_S = -L (N_R ε L)^-1 N_R` and the flux control
+//! coefficients `C_J = I + ε C_S`, where `N_R` is the reduced stoichiometry matrix and `L` the
+//! link matrix (see `Module::conservation_laws`), both scaled to concentrations by the
+//! compartment sizes.
+//!
+//! Scaled coefficients are relative changes, e.g. `(∂v_j/∂x_i) x_i / v_j`; they are not
+//! finite where a flux or concentration is zero.
+
+use super::{Code, Error, Simulator, SteadyStateOptions};
+use crate::linalg::Lu;
+use crate::model::Rule;
+use crate::structural::reduce;
+use crate::Module;
+
+/// Elasticities and control coefficients at a steady state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlAnalysis {
+    /// The species, which are the state variables.
+    pub species: Vec<String>,
+    /// The reactions with a rate.
+    pub reactions: Vec<String>,
+    /// The steady-state concentration of each species.
+    pub concentrations: Vec<f64>,
+    /// The steady-state rate of each reaction.
+    pub fluxes: Vec<f64>,
+    /// The unscaled elasticities, one row per reaction and one column per species.
+    pub elasticities: Vec<Vec<f64>>,
+    /// The unscaled flux control coefficients, one row per flux and one column per reaction.
+    pub flux_control: Vec<Vec<f64>>,
+    /// The unscaled concentration control coefficients, one row per species and one column per
+    /// reaction.
+    pub concentration_control: Vec<Vec<f64>>,
+}
+
+impl ControlAnalysis {
+    /// The scaled elasticities: `ε_ji x_i / v_j`.
+    pub fn scaled_elasticities(&self) -> Vec<Vec<f64>> {
+        scale(&self.elasticities, &self.fluxes, &self.concentrations)
+    }
+
+    /// The scaled flux control coefficients: `C_jk v_k / v_j`. Each row sums to one.
+    pub fn scaled_flux_control(&self) -> Vec<Vec<f64>> {
+        scale(&self.flux_control, &self.fluxes, &self.fluxes)
+    }
+
+    /// The scaled concentration control coefficients: `C_ik v_k / x_i`. Each row sums to zero.
+    pub fn scaled_concentration_control(&self) -> Vec<Vec<f64>> {
+        scale(
+            &self.concentration_control,
+            &self.concentrations,
+            &self.fluxes,
+        )
+    }
+}
+
+/// Multiply entry `(i, j)` by `columns[j] / rows[i]`.
+fn scale(matrix: &[Vec<f64>], rows: &[f64], columns: &[f64]) -> Vec<Vec<f64>> {
+    matrix
+        .iter()
+        .zip(rows)
+        .map(|(row, r)| row.iter().zip(columns).map(|(x, c)| x * c / r).collect())
+        .collect()
+}
+
+/// Find a steady state of a module and analyze its control; see
+/// `Simulator::control_analysis`.
+pub fn control_analysis(
+    module: &Module,
+    options: &SteadyStateOptions,
+) -> Result<ControlAnalysis, Error> {
+    Simulator::new(module)?.control_analysis(options)
+}
+
+impl Simulator {
+    /// Find a steady state (see `Simulator::steady_state`) and compute its elasticities and
+    /// control coefficients.
+    ///
+    /// Every state variable must be a species, and the steady state must be isolated: the
+    /// Jacobian of the reduced system must not be singular there.
+    pub fn control_analysis(&self, options: &SteadyStateOptions) -> Result<ControlAnalysis, Error> {
+        let system = &self.system;
+        let module = &self.module;
+        let n = system.n_state;
+        let rules = system.names[..n].iter().find(|id| {
+            let rule = module.symbol(id).and_then(|s| s.rule.as_ref());
+            matches!(rule, Some(Rule::Rate(_)))
+        });
+        if let Some(id) = rules {
+            let message = format!("`{}` has a rate rule, which control analysis excludes", id);
+            return Err(Error::InvalidOptions(message));
+        }
+        let steady = self.steady_state(options)?;
+        let mut values = system.initial_values(0.0, &self.overrides)?;
+        values[..n].copy_from_slice(&steady.values);
+        system.update(0.0, &mut values)?;
+
+        let stoichiometry = &module.stoichiometry;
+        let reactions: Vec<&str> = stoichiometry
+            .reactions
+            .iter()
+            .filter(|id| module.reaction(id).is_some_and(|r| r.rate.is_some()))
+            .map(String::as_str)
+            .collect();
+        let species = &system.names[..n];
+        let fluxes: Vec<f64> = reactions.iter().map(|r| values[system.index[*r]]).collect();
+
+        let mut elasticities = Vec::with_capacity(reactions.len());
+        for &id in &reactions {
+            let slot = system.index[id];
+            let rate = module.reaction(id).and_then(|r| r.rate.as_ref());
+            let rate = module.expand(&module.formula(rate.expect("reactions have rates"))?)?;
+            let mut row = Vec::with_capacity(n);
+            for (i, s) in species.iter().enumerate() {
+                let compiled = rate
+                    .derivative(s)
+                    .ok()
+                    .and_then(|d| Code::compile(&d.simplify(), &system.index).ok());
+                row.push(match compiled {
+                    Some(code) => code.eval(0.0, &values)?,
+                    None => {
+                        // Central differences, through every assignment.
+                        let h = f64::EPSILON.cbrt() * values[i].abs().max(1e-5);
+                        let mut shifted = values.clone();
+                        shifted[i] = values[i] + h;
+                        system.update(0.0, &mut shifted)?;
+                        let hi = shifted[slot];
+                        shifted[i] = values[i] - h;
+                        system.update(0.0, &mut shifted)?;
+                        (hi - shifted[slot]) / (2.0 * h)
+                    }
+                });
+            }
+            elasticities.push(row);
+        }
+
+        // The stoichiometry of the state species in concentrations, and its reduction.
+        let rows: Vec<Vec<f64>> = species
+            .iter()
+            .map(|id| {
+                let volume = module
+                    .symbol(id)
+                    .and_then(|s| s.compartment.as_ref())
+                    .map_or(1.0, |c| values[system.index[c]]);
+                let row = stoichiometry.species.iter().position(|s| s == id);
+                reactions
+                    .iter()
+                    .map(|r| {
+                        let column = stoichiometry.reactions.iter().position(|c| c == r);
+                        match (row, column) {
+                            (Some(i), Some(j)) => stoichiometry.matrix[i][j] / volume,
+                            _ => 0.0,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let reduction = reduce(&rows);
+        let rank = reduction.independent.len();
+        let mut link = vec![vec![0.0; rank]; n];
+        for (k, &i) in reduction.independent.iter().enumerate() {
+            link[i][k] = 1.0;
+        }
+        for (&d, coefficients) in reduction.dependent.iter().zip(&reduction.link) {
+            for (entry, c) in link[d].iter_mut().zip(coefficients) {
+                *entry = c.to_f64();
+            }
+        }
+        let reduced: Vec<&Vec<f64>> = reduction.independent.iter().map(|&i| &rows[i]).collect();
+
+        // M = N_R ε L, the Jacobian of the reduced system.
+        let r = reactions.len();
+        let el: Vec<Vec<f64>> = (0..r)
+            .map(|j| {
+                (0..rank)
+                    .map(|k| (0..n).map(|i| elasticities[j][i] * link[i][k]).sum())
+                    .collect()
+            })
+            .collect();
+        let m: Vec<Vec<f64>> = reduced
+            .iter()
+            .map(|row| {
+                (0..rank)
+                    .map(|k| (0..r).map(|j| row[j] * el[j][k]).sum())
+                    .collect()
+            })
+            .collect();
+        let lu = Lu::new(&m).ok_or(Error::Singular)?;
+        // -M^-1 N_R, column by column, then C_S = L (-M^-1 N_R).
+        let mut solved = vec![vec![0.0; r]; rank];
+        for j in 0..r {
+            let mut column: Vec<f64> = reduced.iter().map(|row| -row[j]).collect();
+            lu.solve(&mut column);
+            for (k, x) in column.into_iter().enumerate() {
+                solved[k][j] = x;
+            }
+        }
+        let concentration_control: Vec<Vec<f64>> = link
+            .iter()
+            .map(|l| {
+                (0..r)
+                    .map(|j| (0..rank).map(|k| l[k] * solved[k][j]).sum())
+                    .collect()
+            })
+            .collect();
+        let flux_control = (0..r)
+            .map(|j| {
+                (0..r)
+                    .map(|k| {
+                        let identity = if j == k { 1.0 } else { 0.0 };
+                        let sum: f64 = (0..n)
+                            .map(|i| elasticities[j][i] * concentration_control[i][k])
+                            .sum();
+                        identity + sum
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Ok(ControlAnalysis {
+            species: species.to_vec(),
+            reactions: reactions.iter().map(|r| r.to_string()).collect(),
+            concentrations: steady.values,
+            fluxes,
+            elasticities,
+            flux_control,
+            concentration_control,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn satisfies_the_summation_theorems() {
+        // X0 -> S1 -> S2 -> X1, with S2 catalyzed by an enzyme that cycles between E and EP.
+        let module = Fixture::new()
+            .boundary("X0", 10.0)
+            .species("S1", 1.0)
+            .species("S2", 1.0)
+            .species("E", 1.0)
+            .species("EP", 0.0)
+            .parameter("k1", 1.0)
+            .parameter("k2", 2.0)
+            .parameter("Km", 0.5)
+            .irreversible("J1", &[(1.0, "X0")], &[(1.0, "S1")], "k1*X0/(1 + S1)")
+            .irreversible("J2", &[(1.0, "S1")], &[(1.0, "S2")], "k2*E*S1/(Km + S1)")
+            .irreversible("J3", &[(1.0, "S2")], &[], "S2")
+            .reaction("J4", &[(1.0, "E")], &[(1.0, "EP")], "E - 0.5*EP")
+            .build();
+        let analysis = control_analysis(&module, &SteadyStateOptions::default()).unwrap();
+        assert_eq!(analysis.species, ["S1", "S2", "E", "EP"]);
+        assert_eq!(analysis.reactions, ["J1", "J2", "J3", "J4"]);
+        for row in analysis.scaled_flux_control().iter().take(3) {
+            assert!((row.iter().sum::<f64>() - 1.0).abs() < 1e-9, "{:?}", row);
+        }
+        for row in analysis.scaled_concentration_control() {
+            assert!(row.iter().sum::<f64>().abs() < 1e-9, "{:?}", row);
+        }
+        // dJ1/dS1 = -k1*X0/(1 + S1)^2
+        let s1 = analysis.concentrations[0];
+        let expected = -10.0 / (1.0 + s1).powi(2);
+        assert!((analysis.elasticities[0][0] - expected).abs() < 1e-9);
+
+        // Raising the rate of J1 by a small fraction raises the pathway flux by C_J1 times that.
+        let mut perturbed = module.clone();
+        perturbed.reactions[0].rate = Some("1.0001*k1*X0/(1 + S1)".into());
+        let options = SteadyStateOptions::default();
+        let flux = control_analysis(&perturbed, &options).unwrap().fluxes[2];
+        let response = (flux / analysis.fluxes[2]).ln() / 1.0001f64.ln();
+        assert!((response - analysis.scaled_flux_control()[2][0]).abs() < 1e-3);
+    }
+
+    #[test]
+    fn controls_a_source_and_sink() {
+        let module = Fixture::new()
+            .boundary("X", 2.0)
+            .species("S", 0.0)
+            .irreversible("J1", &[(1.0, "X")], &[(1.0, "S")], "3*X")
+            .irreversible("J2", &[(1.0, "S")], &[], "4*S")
+            .build();
+        let analysis = control_analysis(&module, &SteadyStateOptions::default()).unwrap();
+        assert!((analysis.concentrations[0] - 1.5).abs() < 1e-9);
+        assert_eq!(analysis.elasticities, [[0.0], [4.0]]);
+        let close = |a: &[Vec<f64>], b: &[[f64; 2]]| {
+            a.iter()
+                .zip(b)
+                .all(|(a, b)| a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-9))
+        };
+        assert!(close(
+            &analysis.scaled_flux_control(),
+            &[[1.0, 0.0], [1.0, 0.0]]
+        ));
+        assert!(close(
+            &analysis.scaled_concentration_control(),
+            &[[1.0, -1.0]]
+        ));
+        assert!(close(&analysis.concentration_control, &[[0.25, -0.25]]));
+    }
+}
@@ -0,0 +1,1097 @@
+//! Time-course simulation.
+//!
+//! A `Simulator` compiles a module's reactions, rate rules, and assignment rules into a system
+//! of ordinary differential equations once, and then integrates it as often as needed.
+//! Events are honored with SBML Level 3 semantics: triggers are located between steps, and
+//! integration restarts after every assignment.
+//!
+//! Calls of `delay` with a positive lag make the equations delay differential equations,
+//! which the ODE methods integrate from a constant history before the start.
+//!
+//! The stochastic methods simulate the same reactions as discrete events on molecule counts,
+//! which are the amounts of the species (concentration times compartment size): exactly
+//! (`Method::Direct` and `Method::NextReaction`), or approximately but faster for large
+//! networks (`Method::TauLeaping` and `Method::Langevin`). `Method::Hybrid` combines both
+//! kinds, integrating the fast reactions and the rate rules as ODEs. Results still report
+//! concentrations, so with compartments of size one they are the counts themselves. Set
+//! `Options::seed` to make them reproducible.
+//!
+//! `steady_state` finds where the ODEs come to rest, and whether they stay there;
+//! `control_analysis` finds how that steady state depends on each reaction, and
+//! `continuation` follows it as a parameter varies, locating folds and Hopf bifurcations.
+//! `sensitivities` integrates the derivatives of the state with respect to parameters, and
+//! `Simulator::sobol` and `Simulator::morris` rank parameters over whole ranges.
+//! `Simulator::scan` simulates over grids and random samples of parameter values (see `Scan`),
+//! on many threads with the `rayon` feature.
+//!
+//! Results come as a `TimeCourse`, which can be interpolated between output times and written
+//! as CSV or TSV, or, with the `arrow` and `parquet` features, as Arrow or Parquet.
+//!
+//! ```no_run
+//! use antimony::sim::{Options, Simulator};
+//! use antimony::Document;
+//!
+//! let doc = Document::load_antimony_str("J0: S1 => ; k1*S1; S1 = 10; k1 = 0.5")?;
+//! let mut sim = Simulator::new(doc.main())?;
+//! let options = Options { end: 4.0, points: 5, ..Options::default() };
+//! let slow = sim.simulate(&options)?;
+//! sim.set("k1", 1.0)?;
+//! let fast = sim.simulate(&options)?;
+//! assert!(fast.column("S1").unwrap()[4] < slow.column("S1").unwrap()[4]);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+#[cfg(feature = "arrow")]
+mod arrow;
+mod continuation;
+mod course;
+mod delay;
+mod events;
+mod hybrid;
+mod leaping;
+mod mca;
+mod network;
+mod random;
+mod rk45;
+mod rosenbrock;
+mod scan;
+mod sensitivity;
+mod solver;
+mod ssa;
+mod steady;
+mod system;
+
+use std::error;
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::math::{self, EvalError, Expr};
+use crate::Module;
+
+pub use self::continuation::{
+    continuation, Bifurcation, BifurcationKind, Branch, BranchPoint, ContinuationOptions,
+};
+pub use self::course::TimeCourse;
+use self::delay::History;
+use self::events::Events;
+pub use self::mca::{control_analysis, ControlAnalysis};
+use self::network::Network;
+pub(crate) use self::random::{normal_quantile, Random};
+use self::rk45::DormandPrince;
+use self::rosenbrock::Rosenbrock;
+pub use self::scan::{scan, Distribution, Scan};
+pub use self::sensitivity::{
+    sensitivities, ElementaryEffects, ParameterRange, Sensitivities, SensitivitySeries,
+    SobolIndices,
+};
+use self::solver::{finite_differences, interpolate, Rhs, Stepper};
+use self::ssa::{Direct, NextReaction, Selector};
+pub use self::steady::{steady_state, Eigenvalue, SteadyState, SteadyStateOptions};
+use self::system::{Code, System};
+
+/// Errors from setting up or running a simulation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A formula of the model failed to parse or refers to something undefined.
+    Formula(math::Error),
+    /// A formula failed to evaluate, or initial values depend on each other in a cycle.
+    Eval(EvalError),
+    /// The integrator could not take a step large enough to make progress.
+    StepSize {
+        /// The time at which the integrator gave up.
+        time: f64,
+    },
+    /// The integrator took `Options::max_steps` steps without reaching the end.
+    TooManySteps {
+        /// The time reached.
+        time: f64,
+    },
+    /// A symbol named in the options or passed to `Simulator::set` is not part of the model.
+    UnknownSymbol(String),
+    /// The options are inconsistent, or the method cannot simulate the model; carries a
+    /// description.
+    InvalidOptions(String),
+    /// A reaction rate was negative or not finite in a stochastic simulation, where it is the
+    /// reaction's propensity.
+    Propensity {
+        /// The reaction id.
+        reaction: String,
+        /// The time of the evaluation.
+        time: f64,
+    },
+    /// The Jacobian of the reduced system is singular at the steady state, whose control
+    /// coefficients are therefore undefined.
+    Singular,
+    /// Neither Newton's method nor integration found a steady state.
+    NoSteadyState {
+        /// The smallest largest relative rate of change reached.
+        residual: f64,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Formula(err) => err.fmt(f),
+            Error::Eval(err) => err.fmt(f),
+            Error::StepSize { time } => write!(f, "step size became too small at time {}", time),
+            Error::TooManySteps { time } => write!(f, "too many steps before time {}", time),
+            Error::UnknownSymbol(id) => write!(f, "`{}` is not a symbol of the model", id),
+            Error::InvalidOptions(message) => write!(f, "invalid options: {}", message),
+            Error::Propensity { reaction, time } => write!(
+                f,
+                "the rate of `{}` is not a valid propensity at time {}",
+                reaction, time
+            ),
+            Error::Singular => write!(f, "the Jacobian is singular at the steady state"),
+            Error::NoSteadyState { residual } => write!(
+                f,
+                "found no steady state; the rates of change remained as large as {}",
+                residual
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<math::Error> for Error {
+    fn from(err: math::Error) -> Error {
+        Error::Formula(err)
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(err: EvalError) -> Error {
+        Error::Eval(err)
+    }
+}
+
+/// The simulation method: an ODE integrator, or a stochastic method that counts molecules
+/// (see the module documentation).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Method {
+    /// The explicit Runge–Kutta method of Dormand and Prince, of order 5(4). Fast for
+    /// non-stiff models.
+    Rk45,
+    /// The linearly implicit Rosenbrock method of Shampine and Reichelt, of order 2(3), as in
+    /// MATLAB's `ode23s`. Suited to stiff models, where explicit methods need tiny steps.
+    Rosenbrock,
+    /// Gillespie's direct method, an exact stochastic simulation whose cost per reaction
+    /// grows with the number of reactions.
+    Direct,
+    /// The next reaction method of Gibson and Bruck, an exact stochastic simulation that only
+    /// updates the propensities a reaction affects. Faster than `Direct` for large, sparsely
+    /// coupled networks.
+    NextReaction,
+    /// Adaptive tau-leaping, which fires many reactions at once, approximately. Reactions
+    /// close to exhausting a reactant fire one at a time.
+    TauLeaping,
+    /// The chemical Langevin equation, a stochastic differential equation approximating the
+    /// counts as continuous. Suited to networks in which every reaction fires often.
+    Langevin,
+    /// Fast reactions as ODEs and slow ones as exact stochastic events, partitioned by
+    /// propensity (see `Options::fast_propensity`) and repartitioned as the simulation goes.
+    /// The only stochastic method that also simulates rate rules.
+    Hybrid,
+}
+
+/// What to simulate and how.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// The initial time.
+    pub start: f64,
+    /// The final time.
+    pub end: f64,
+    /// The number of evenly spaced output times, including `start` and `end`.
+    pub points: usize,
+    /// Output times to use instead of the evenly spaced ones, if not empty. They must increase
+    /// and lie between `start` and `end`, e.g., to match the times of measurements.
+    pub times: Vec<f64>,
+    /// The integration method.
+    pub method: Method,
+    /// The relative error tolerance of each step.
+    pub relative_tolerance: f64,
+    /// The absolute error tolerance of each step.
+    pub absolute_tolerance: f64,
+    /// The largest step the integrator may take.
+    pub max_step: f64,
+    /// The number of steps (or, for exact stochastic methods, reaction events) after which
+    /// to give up.
+    pub max_steps: usize,
+    /// Whether implicit methods use the symbolic Jacobian of the model (see
+    /// `Module::jacobian`). Models whose Jacobian cannot be derived, and all models if this is
+    /// `false`, use finite differences instead.
+    pub analytic_jacobian: bool,
+    /// The columns of the result: `time` or symbol ids. If empty, `time`, the state
+    /// variables, and every symbol with an assignment rule.
+    pub selections: Vec<String>,
+    /// The seed of the random numbers of stochastic methods, which makes their results
+    /// reproducible. If `None`, every simulation uses a different seed.
+    pub seed: Option<u64>,
+    /// For `Method::TauLeaping` and `Method::Langevin`, the largest expected relative change
+    /// of a propensity during one leap.
+    pub leap_tolerance: f64,
+    /// For `Method::Hybrid`, the propensity from which a reaction is simulated as an ODE,
+    /// provided that it consumes no species with fewer than 100 molecules.
+    pub fast_propensity: f64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            start: 0.0,
+            end: 10.0,
+            points: 101,
+            times: Vec::new(),
+            method: Method::Rk45,
+            relative_tolerance: 1e-6,
+            absolute_tolerance: 1e-9,
+            max_step: f64::INFINITY,
+            max_steps: 100_000,
+            analytic_jacobian: true,
+            selections: Vec::new(),
+            seed: None,
+            leap_tolerance: 0.03,
+            fast_propensity: 100.0,
+        }
+    }
+}
+
+impl Options {
+    fn check(&self) -> Result<(), Error> {
+        let invalid = |message: &str| Err(Error::InvalidOptions(message.to_owned()));
+        if !(self.start.is_finite() && self.end.is_finite()) || self.end < self.start {
+            return invalid("`end` must not be before `start`");
+        }
+        if !self.times.is_empty() {
+            let within = |t: &f64| *t >= self.start && *t <= self.end;
+            let increasing = self.times.windows(2).all(|w| w[0] < w[1]);
+            if !(increasing && self.times.iter().all(within)) {
+                return invalid("`times` must increase from `start` to `end`");
+            }
+        } else if self.points == 0 || (self.points == 1 && self.end != self.start) {
+            return invalid("`points` must include both `start` and `end`");
+        }
+        if !(self.relative_tolerance > 0.0 && self.absolute_tolerance > 0.0) {
+            return invalid("tolerances must be positive");
+        }
+        if self.max_step.is_nan() || self.max_step <= 0.0 {
+            return invalid("`max_step` must be positive");
+        }
+        if self.leap_tolerance.is_nan() || self.leap_tolerance <= 0.0 {
+            return invalid("`leap_tolerance` must be positive");
+        }
+        if self.fast_propensity.is_nan() {
+            return invalid("`fast_propensity` must be a number");
+        }
+        Ok(())
+    }
+
+    /// The output times.
+    fn times(&self) -> Vec<f64> {
+        if !self.times.is_empty() {
+            return self.times.clone();
+        }
+        if self.points == 1 {
+            return vec![self.start];
+        }
+        let dt = (self.end - self.start) / (self.points - 1) as f64;
+        (0..self.points)
+            .map(|i| {
+                if i + 1 == self.points {
+                    self.end
+                } else {
+                    self.start + i as f64 * dt
+                }
+            })
+            .collect()
+    }
+}
+
+/// A module compiled for simulation, with initial values that can be changed between runs.
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    module: Module,
+    system: System,
+    overrides: Vec<Option<f64>>,
+    /// The compiled symbolic Jacobian, derived on first use, or `None` if it has none.
+    jacobian: OnceLock<Option<Vec<Vec<Code>>>>,
+    /// The reactions compiled for stochastic methods on first use.
+    network: OnceLock<Result<Network, Error>>,
+}
+
+impl Simulator {
+    /// Compile a module.
+    pub fn new(module: &Module) -> Result<Simulator, Error> {
+        let system = System::new(module)?;
+        let overrides = vec![None; system.names.len()];
+        Ok(Simulator {
+            module: module.clone(),
+            system,
+            overrides,
+            jacobian: OnceLock::new(),
+            network: OnceLock::new(),
+        })
+    }
+
+    /// Set the initial value of a symbol (e.g., a parameter or species), replacing its
+    /// initial value or assignment in the model. Initial assignments that depend on the
+    /// symbol follow the new value.
+    pub fn set(&mut self, id: &str, value: f64) -> Result<&mut Self, Error> {
+        let slot = self.slot(id)?;
+        self.overrides[slot] = Some(value);
+        Ok(self)
+    }
+
+    /// Undo every `set`.
+    pub fn reset(&mut self) {
+        self.overrides.iter_mut().for_each(|v| *v = None);
+    }
+
+    /// The value of a symbol at time `t` before any integration, as given by the model and by
+    /// `set`.
+    pub fn initial_value(&self, id: &str, t: f64) -> Result<f64, Error> {
+        let slot = self.slot(id)?;
+        Ok(self.system.initial_values(t, &self.overrides)?[slot])
+    }
+
+    /// The ids of the state variables, in the order integrators see them.
+    pub fn state_variables(&self) -> &[String] {
+        &self.system.names[..self.system.n_state]
+    }
+
+    /// Run a simulation.
+    pub fn simulate(&self, options: &Options) -> Result<TimeCourse, Error> {
+        options.check()?;
+        let columns = self.selections(options)?;
+        let mut out = Recorder {
+            select: columns
+                .iter()
+                .map(|c| self.system.index.get(c.as_str()).copied())
+                .collect(),
+            times: options.times(),
+            rows: Vec::new(),
+        };
+        let mut values = self.system.initial_values(options.start, &self.overrides)?;
+        if out.next() == Some(options.start) {
+            out.record(&values);
+        }
+        match options.method {
+            Method::Rk45 | Method::Rosenbrock => self.integrate(options, values, &mut out)?,
+            method => {
+                let system = &self.system;
+                if !system.delays.is_empty() {
+                    let message = "`delay` needs an ODE method".to_owned();
+                    return Err(Error::InvalidOptions(message));
+                }
+                let network = self.network()?;
+                if method != Method::Hybrid {
+                    network.discrete(system)?;
+                }
+                let mut random = options.seed.map_or_else(Random::from_entropy, Random::new);
+                let (values, random, out) = (&mut values, &mut random, &mut out);
+                match method {
+                    Method::TauLeaping => {
+                        leaping::tau_leaping(system, network, options, values, random, out)?
+                    }
+                    Method::Langevin => {
+                        leaping::langevin(system, network, options, values, random, out)?
+                    }
+                    Method::Hybrid => {
+                        hybrid::simulate(system, network, options, values, random, out)?
+                    }
+                    _ => {
+                        let mut selector: Box<dyn Selector> = match method {
+                            Method::NextReaction => {
+                                Box::new(NextReaction::new(network.channels.len()))
+                            }
+                            _ => Box::new(Direct),
+                        };
+                        let selector = &mut *selector;
+                        ssa::simulate(system, network, selector, options, values, random, out)?
+                    }
+                }
+            }
+        }
+        Ok(TimeCourse::new(columns, out.rows).name(&self.module))
+    }
+
+    /// Integrate the ODEs from the values at `options.start`.
+    fn integrate(
+        &self,
+        options: &Options,
+        mut values: Vec<f64>,
+        out: &mut Recorder,
+    ) -> Result<(), Error> {
+        let system = &self.system;
+        let n = system.n_state;
+        let jacobian = match options.method {
+            Method::Rosenbrock if options.analytic_jacobian => self.jacobian(),
+            _ => None,
+        };
+        // Delays are followed from their lags at the start.
+        let mut lags = Vec::with_capacity(system.delays.len());
+        for d in &system.delays {
+            let lag = d.lag.eval(options.start, &values)?;
+            if lag > 0.0 {
+                lags.push(lag);
+            }
+        }
+        let max_step = lags.iter().fold(options.max_step, |m, &lag| m.min(lag));
+        let mut stepper: Box<dyn Stepper> = match options.method {
+            Method::Rk45 => Box::new(DormandPrince::new(
+                n,
+                options.relative_tolerance,
+                options.absolute_tolerance,
+                max_step,
+            )),
+            _ => Box::new(Rosenbrock::new(
+                n,
+                options.relative_tolerance,
+                options.absolute_tolerance,
+                max_step,
+            )),
+        };
+        let mut events = Events::new(system);
+        let mut t = options.start;
+        events.process(system, t, &mut values)?;
+        let mut rhs = SystemRhs {
+            system,
+            values: values.clone(),
+            jacobian,
+            history: if system.delays.is_empty() {
+                None
+            } else {
+                Some(History::new(t, &values[..n], values.len()))
+            },
+        };
+        let mut breakpoints = delay::breakpoints(t, &lags, options.end);
+        let mut y = values[..n].to_vec();
+        let mut f = vec![0.0; n];
+        rhs.eval(t, &y, &mut f)?;
+        rhs.record(t, &y, &f);
+        let mut probe = values.clone();
+        let mut steps = 0;
+        while out.next().is_some() {
+            if steps == options.max_steps {
+                return Err(Error::TooManySteps { time: t });
+            }
+            let breakpoint = breakpoints.iter().copied().find(|&b| b > t);
+            let t_stop = [events.next_time(), breakpoint]
+                .iter()
+                .flatten()
+                .fold(options.end, |stop, &time| stop.min(time));
+            let step = stepper.step(&mut rhs, t, &y, &f, t_stop)?;
+            steps += 1;
+
+            // Stop the step where the first trigger changes, located by bisection.
+            let mut changed_at = |time: f64| -> Result<bool, Error> {
+                probe.copy_from_slice(&values);
+                interpolate(t, &y, &f, &step, time, &mut probe[..n]);
+                rhs.update(time, &mut probe)?;
+                Ok(events.changed(system, time, &probe)?)
+            };
+            let mut end = step.t;
+            if changed_at(end)? {
+                let mut lo = t;
+                while end - lo > 4.0 * f64::EPSILON * end.abs().max(1.0) {
+                    let mid = 0.5 * (lo + end);
+                    if changed_at(mid)? {
+                        end = mid;
+                    } else {
+                        lo = mid;
+                    }
+                }
+            }
+
+            while let Some(time) = out.next().filter(|&time| time <= end) {
+                interpolate(t, &y, &f, &step, time, &mut values[..n]);
+                rhs.update(time, &mut values)?;
+                out.record(&values);
+            }
+            interpolate(t, &y, &f, &step, end, &mut values[..n]);
+            t = end;
+            rhs.update(t, &mut values)?;
+            let executed = events.process(system, t, &mut values)?;
+            y.copy_from_slice(&values[..n]);
+            rhs.values.copy_from_slice(&values);
+            let restart = executed || breakpoint == Some(end);
+            if restart {
+                stepper.reset();
+            }
+            if restart || end < step.t {
+                rhs.eval(t, &y, &mut f)?;
+            } else {
+                y.copy_from_slice(&step.y);
+                f.copy_from_slice(&step.f);
+            }
+            rhs.record(t, &y, &f);
+            if executed && !lags.is_empty() {
+                breakpoints.extend(delay::breakpoints(t, &lags, options.end));
+                delay::dedup(&mut breakpoints);
+            }
+        }
+        Ok(())
+    }
+
+    fn jacobian(&self) -> Option<&[Vec<Code>]> {
+        self.jacobian
+            .get_or_init(|| {
+                let jacobian = self.module.jacobian().ok()?;
+                let index = &self.system.index;
+                let compile = |row: &Vec<Expr>| -> Option<Vec<Code>> {
+                    row.iter().map(|e| Code::compile(e, index).ok()).collect()
+                };
+                jacobian.entries.iter().map(compile).collect()
+            })
+            .as_deref()
+    }
+
+    fn network(&self) -> Result<&Network, Error> {
+        self.network
+            .get_or_init(|| Network::new(&self.module, &self.system))
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    fn slot(&self, id: &str) -> Result<usize, Error> {
+        self.system
+            .index
+            .get(id)
+            .copied()
+            .ok_or_else(|| Error::UnknownSymbol(id.to_owned()))
+    }
+
+    fn selections(&self, options: &Options) -> Result<Vec<String>, Error> {
+        if options.selections.is_empty() {
+            let system = &self.system;
+            let mut columns = vec!["time".to_owned()];
+            columns.extend_from_slice(&system.names[..system.n_state]);
+            for (slot, _) in &system.assignments {
+                let id = &system.names[*slot];
+                // Reaction rates are available, but only on request.
+                if !system.is_reaction(*slot) {
+                    columns.push(id.clone());
+                }
+            }
+            return Ok(columns);
+        }
+        for c in &options.selections {
+            if c != "time" {
+                self.slot(c)?;
+            }
+        }
+        Ok(options.selections.clone())
+    }
+}
+
+/// Simulate a module once; see `Simulator` to run several simulations of the same module.
+pub fn simulate(module: &Module, options: &Options) -> Result<TimeCourse, Error> {
+    Simulator::new(module)?.simulate(options)
+}
+
+/// The rows of a time course, recorded as a simulation passes the output times.
+struct Recorder {
+    /// The slot of each column, or `None` for time.
+    select: Vec<Option<usize>>,
+    times: Vec<f64>,
+    rows: Vec<Vec<f64>>,
+}
+
+impl Recorder {
+    /// The next output time, if any remain.
+    fn next(&self) -> Option<f64> {
+        self.times.get(self.rows.len()).copied()
+    }
+
+    /// Record the values at the next output time.
+    fn record(&mut self, values: &[f64]) {
+        let t = self.next().expect("an output time remains");
+        let row = self.select.iter().map(|slot| slot.map_or(t, |s| values[s]));
+        self.rows.push(row.collect());
+    }
+}
+
+/// The right-hand side of a compiled system, with the values of the slots outside the state.
+struct SystemRhs<'a> {
+    system: &'a System,
+    values: Vec<f64>,
+    jacobian: Option<&'a [Vec<Code>]>,
+    /// The past states, if the system has delays.
+    history: Option<History>,
+}
+
+impl SystemRhs<'_> {
+    /// Fill in the delayed values at time `t` and update the assignments.
+    fn update(&mut self, t: f64, values: &mut [f64]) -> Result<(), EvalError> {
+        if let Some(history) = &mut self.history {
+            history.fill(self.system, t, values)?;
+        }
+        self.system.update(t, values)
+    }
+
+    /// Add an accepted step to the history, if one is kept.
+    fn record(&mut self, t: f64, y: &[f64], f: &[f64]) {
+        if let Some(history) = &mut self.history {
+            history.push(t, y, f);
+        }
+    }
+}
+
+impl Rhs for SystemRhs<'_> {
+    fn eval(&mut self, t: f64, y: &[f64], dy: &mut [f64]) -> Result<(), Error> {
+        self.values[..y.len()].copy_from_slice(y);
+        if let Some(history) = &mut self.history {
+            history.fill(self.system, t, &mut self.values)?;
+        }
+        Ok(self.system.derivatives(t, &mut self.values, dy)?)
+    }
+
+    fn jacobian(
+        &mut self,
+        t: f64,
+        y: &[f64],
+        f: &[f64],
+        jac: &mut [Vec<f64>],
+    ) -> Result<(), Error> {
+        let codes = match self.jacobian {
+            Some(codes) => codes,
+            None => return finite_differences(self, t, y, f, jac),
+        };
+        self.values[..y.len()].copy_from_slice(y);
+        for (row, codes) in jac.iter_mut().zip(codes) {
+            for (entry, code) in row.iter_mut().zip(codes) {
+                *entry = code.eval(t, &self.values)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Assignment, Event};
+    use crate::testing::Fixture;
+
+    #[test]
+    fn integrates_reactions_and_rules() {
+        let module = Fixture::new()
+            .compartment("cell", 2.0)
+            .species("S1", 10.0)
+            .within("S1", "cell")
+            .species("S2", 0.0)
+            .within("S2", "cell")
+            .parameter("k1", 0.5)
+            .reaction("J0", &[(1.0, "S1")], &[(1.0, "S2")], "cell*k1*S1")
+            .assignment("total", "S1 + S2")
+            .rate_rule("x", 1.0, "-x")
+            .build();
+        let result = simulate(
+            &module,
+            &Options {
+                end: 2.0,
+                points: 3,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.columns, ["time", "S1", "S2", "x", "total"]);
+        let last = &result.rows[2];
+        assert_eq!(last[0], 2.0);
+        assert!((last[1] - 10.0 * (-1.0f64).exp()).abs() < 1e-5);
+        assert!((last[3] - (-2.0f64).exp()).abs() < 1e-6);
+        assert!((last[4] - 10.0).abs() < 1e-6);
+
+        // Output times after the start come from the simulation, not from the start.
+        let options = Options {
+            end: 2.0,
+            times: vec![1.0, 2.0],
+            selections: vec!["time".into(), "S1".into()],
+            ..Options::default()
+        };
+        let result = simulate(&module, &options).unwrap();
+        assert_eq!(result.times(), Some(vec![1.0, 2.0]));
+        assert!((result.rows[0][1] - 10.0 * (-0.5f64).exp()).abs() < 1e-5);
+        let module = Fixture::new()
+            .species("S1", 100.0)
+            .irreversible("J0", &[(1.0, "S1")], &[], "S1")
+            .build();
+        for method in [Method::Direct, Method::TauLeaping, Method::Hybrid] {
+            let options = Options {
+                method,
+                seed: Some(1),
+                ..options.clone()
+            };
+            let result = simulate(&module, &options).unwrap();
+            assert_eq!(result.times(), Some(vec![1.0, 2.0]));
+            assert!(result.rows[0][1] < 60.0, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn rejects_recursive_functions() {
+        let recursive = |fixture: Fixture| {
+            fixture
+                .species("S", 1.0)
+                .irreversible("J0", &[(1.0, "S")], &[], "f(S)")
+                .build()
+        };
+        let module = recursive(Fixture::new().function("f", &["x"], "f(x)"));
+        assert_eq!(
+            Simulator::new(&module).err(),
+            Some(Error::Formula(crate::math::Error::Recursion("f".into())))
+        );
+        let module = recursive(Fixture::new().function("f", &["x"], "2*g(x)").function(
+            "g",
+            &["x"],
+            "f(x) + 1",
+        ));
+        assert!(Simulator::new(&module).is_err());
+    }
+
+    #[test]
+    fn overrides_initial_values() {
+        let module = Fixture::new()
+            .species("S1", 0.0)
+            .parameter("k", 2.0)
+            .initial("S0", "3*k")
+            .irreversible("J0", &[], &[(1.0, "S1")], "S0")
+            .build();
+        let mut sim = Simulator::new(&module).unwrap();
+        sim.set("k", 1.0).unwrap();
+        let options = Options {
+            end: 1.0,
+            points: 2,
+            selections: vec!["S1".into(), "J0".into()],
+            ..Options::default()
+        };
+        let result = sim.simulate(&options).unwrap();
+        assert_eq!(result.rows[1], [3.0, 3.0]);
+        assert_eq!(
+            sim.set("nope", 1.0).err(),
+            Some(Error::UnknownSymbol("nope".into()))
+        );
+    }
+
+    #[test]
+    fn solves_stiff_models_with_either_jacobian() {
+        let module = Fixture::new()
+            .species("A", 1.0)
+            .species("B", 0.0)
+            .species("C", 0.0)
+            .irreversible("J1", &[(1.0, "A")], &[(1.0, "B")], "0.04*A")
+            .irreversible("J2", &[(2.0, "B")], &[(1.0, "B"), (1.0, "C")], "3e7*B^2")
+            .irreversible(
+                "J3",
+                &[(1.0, "B"), (1.0, "C")],
+                &[(1.0, "A"), (1.0, "C")],
+                "1e4*B*C",
+            )
+            .build();
+        let sim = Simulator::new(&module).unwrap();
+        let mut options = Options {
+            end: 40.0,
+            points: 2,
+            method: Method::Rosenbrock,
+            absolute_tolerance: 1e-10,
+            max_steps: 1000,
+            ..Options::default()
+        };
+        let analytic = sim.simulate(&options).unwrap();
+        options.analytic_jacobian = false;
+        let numeric = sim.simulate(&options).unwrap();
+        for result in &[analytic, numeric] {
+            assert!((result.rows[1][1] - 0.7158).abs() < 1e-3);
+            assert!((result.rows[1][3] - 0.2842).abs() < 1e-3);
+        }
+    }
+
+    fn event(id: &str, trigger: &str, delay: Option<&str>, assignments: &[(&str, &str)]) -> Event {
+        Event {
+            id: id.into(),
+            name: None,
+            trigger: trigger.into(),
+            delay: delay.map(Into::into),
+            priority: None,
+            persistent: true,
+            initial_value: false,
+            from_trigger: true,
+            assignments: assignments
+                .iter()
+                .map(|&(variable, formula)| Assignment {
+                    variable: variable.into(),
+                    formula: formula.into(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn locates_triggers_between_steps() {
+        let module = Fixture::new()
+            .species("S", 10.0)
+            .parameter("k", 1.0)
+            .parameter("fired", 0.0)
+            .irreversible("J0", &[(1.0, "S")], &[], "k*S")
+            .event(event(
+                "E0",
+                "S < 5",
+                None,
+                &[("S", "10"), ("fired", "time")],
+            ))
+            .build();
+        let options = Options {
+            end: 1.0,
+            points: 2,
+            selections: vec!["S".into(), "fired".into()],
+            ..Options::default()
+        };
+        let result = simulate(&module, &options).unwrap();
+        let t = 2f64.ln();
+        assert!((result.rows[1][1] - t).abs() < 1e-5);
+        assert!((result.rows[1][0] - 10.0 * (t - 1.0).exp()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn orders_delays_priorities_and_persistence() {
+        let mut transient = event("E0", "time > 1 && time < 1.5", Some("1"), &[("a", "1")]);
+        let persistent = Event {
+            id: "E1".into(),
+            ..transient.clone()
+        };
+        transient.persistent = false;
+        let mut low = event("E2", "time > 3", None, &[("b", "1")]);
+        low.priority = Some("1".into());
+        let mut high = event("E3", "time > 3", None, &[("b", "2")]);
+        high.priority = Some("2".into());
+        let mut execution = event("E4", "time > 4", Some("1"), &[("c", "time")]);
+        execution.from_trigger = false;
+        let module = Fixture::new()
+            .parameter("a", 0.0)
+            .parameter("b", 0.0)
+            .parameter("c", 0.0)
+            .event(transient)
+            .event(Event {
+                assignments: vec![Assignment {
+                    variable: "a".into(),
+                    formula: "a + 2".into(),
+                }],
+                ..persistent
+            })
+            .event(low)
+            .event(high)
+            .event(execution)
+            .build();
+        let options = Options {
+            end: 6.0,
+            points: 7,
+            selections: vec!["time".into(), "a".into(), "b".into(), "c".into()],
+            ..Options::default()
+        };
+        let result = simulate(&module, &options).unwrap();
+        assert_eq!(result.rows[2], [2.0, 0.0, 0.0, 0.0]);
+        assert_eq!(result.rows[3][1], 2.0);
+        assert_eq!(result.rows[4][2], 1.0);
+        assert!((result.rows[6][3] - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn samples_stochastic_trajectories() {
+        let module = Fixture::new()
+            .species("S", 0.0)
+            .parameter("k", 10.0)
+            .irreversible("make", &[], &[(1.0, "S")], "k")
+            .irreversible("decay", &[(1.0, "S")], &[], "S")
+            .event(event("stop", "time > 100", None, &[("k", "0")]))
+            .build();
+        let sim = Simulator::new(&module).unwrap();
+        for &method in &[Method::Direct, Method::NextReaction] {
+            let options = Options {
+                end: 200.0,
+                points: 201,
+                method,
+                selections: vec!["S".into()],
+                seed: Some(1),
+                ..Options::default()
+            };
+            let result = sim.simulate(&options).unwrap();
+            assert_eq!(result, sim.simulate(&options).unwrap());
+            let s = result.column("S").unwrap();
+            assert!(s.iter().all(|s| s.fract() == 0.0));
+            let mean = s[20..=100].iter().sum::<f64>() / 81.0;
+            assert!((mean - 10.0).abs() < 1.5, "{:?}: {}", method, mean);
+            assert_eq!(s[200], 0.0);
+        }
+    }
+
+    #[test]
+    fn counts_molecules_in_compartments() {
+        let module = Fixture::new()
+            .compartment("cell", 2.0)
+            .species("A", 1.5)
+            .within("A", "cell")
+            .species("B", 0.0)
+            .within("B", "cell")
+            .irreversible("J0", &[(2.0, "A")], &[(1.0, "B")], "cell*A^2")
+            .build();
+        for &method in &[Method::NextReaction, Method::TauLeaping] {
+            let options = Options {
+                end: 100.0,
+                points: 2,
+                method,
+                seed: Some(3),
+                ..Options::default()
+            };
+            let result = simulate(&module, &options).unwrap();
+            // Three molecules of A dimerize once, and the one left cannot react.
+            assert_eq!(result.rows[1], [100.0, 0.5, 0.5]);
+        }
+    }
+
+    #[test]
+    fn leaps_approximately() {
+        let module = Fixture::new()
+            .species("S", 0.0)
+            .parameter("k", 1000.0)
+            .irreversible("make", &[], &[(1.0, "S")], "k")
+            .irreversible("decay", &[(1.0, "S")], &[], "S")
+            .build();
+        for &method in &[Method::TauLeaping, Method::Langevin] {
+            let options = Options {
+                end: 100.0,
+                points: 101,
+                method,
+                selections: vec!["S".into()],
+                seed: Some(5),
+                ..Options::default()
+            };
+            let s = simulate(&module, &options).unwrap().column("S").unwrap();
+            let mean = s[10..].iter().sum::<f64>() / 91.0;
+            assert!((mean - 1000.0).abs() < 20.0, "{:?}: {}", method, mean);
+            let variance = s[10..].iter().map(|s| (s - mean).powi(2)).sum::<f64>() / 90.0;
+            assert!(
+                (variance / 1000.0 - 1.0).abs() < 0.5,
+                "{:?}: {}",
+                method,
+                variance
+            );
+        }
+    }
+
+    #[test]
+    fn partitions_fast_and_slow_reactions() {
+        let module = Fixture::new()
+            .species("M", 1000.0)
+            .species("G", 0.0)
+            .parameter("k", 1000.0)
+            .irreversible("make", &[], &[(1.0, "M")], "k")
+            .irreversible("decay", &[(1.0, "M")], &[], "M")
+            .irreversible("express", &[], &[(1.0, "G")], "0.1")
+            .rate_rule("x", 1.0, "-x")
+            .build();
+        let options = Options {
+            end: 100.0,
+            points: 11,
+            method: Method::Hybrid,
+            selections: vec!["M".into(), "G".into(), "x".into()],
+            seed: Some(2),
+            ..Options::default()
+        };
+        let result = simulate(&module, &options).unwrap();
+        assert_eq!(result, simulate(&module, &options).unwrap());
+        for row in &result.rows {
+            assert!((row[0] - 1000.0).abs() < 1e-3);
+            assert_eq!(row[1].fract(), 0.0);
+        }
+        assert!(result.rows[10][1] > 0.0 && result.rows[10][1] < 30.0);
+        assert!((result.rows[1][2] - (-10.0f64).exp()).abs() < 1e-6);
+
+        let options = Options {
+            method: Method::Direct,
+            ..options
+        };
+        assert!(matches!(
+            simulate(&module, &options),
+            Err(Error::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn conserves_moieties_in_hybrid_simulations() {
+        let module = Fixture::new()
+            .species("M", 1000.0)
+            .species("E", 2.0)
+            .species("C", 0.0)
+            .parameter("k", 1000.0)
+            .irreversible("make", &[], &[(1.0, "M")], "k")
+            .irreversible("decay", &[(1.0, "M")], &[], "M")
+            .irreversible(
+                "bind",
+                &[(1.0, "E"), (1.0, "M")],
+                &[(1.0, "C")],
+                "0.001*E*M",
+            )
+            .irreversible("release", &[(1.0, "C")], &[(1.0, "E")], "C")
+            .build();
+        let options = Options {
+            end: 50.0,
+            points: 51,
+            method: Method::Hybrid,
+            selections: vec!["E".into(), "C".into()],
+            seed: Some(5),
+            ..Options::default()
+        };
+        let result = simulate(&module, &options).unwrap();
+        for row in &result.rows {
+            assert_eq!(row[0] + row[1], 2.0);
+            assert!(row[0] >= 0.0 && row[0].fract() == 0.0);
+        }
+        assert!(result.rows.iter().any(|row| row[1] > 0.0));
+
+        // Once the decay turns slow, the fraction of a molecule it integrated cannot fire.
+        let module = Fixture::new()
+            .species("M", 1000.0)
+            .irreversible("decay", &[(1.0, "M")], &[], "10*M")
+            .build();
+        let options = Options {
+            end: 5.0,
+            points: 6,
+            selections: vec!["M".into()],
+            ..options
+        };
+        let result = simulate(&module, &options).unwrap();
+        let last = result.rows[5][0];
+        assert!(last > 0.0 && last < 1.0, "{}", last);
+        assert!(result.rows.iter().all(|row| row[0] >= 0.0));
+    }
+
+    #[test]
+    fn integrates_delay_differential_equations() {
+        let module = Fixture::new()
+            .parameter("tau", 1.0)
+            .rate_rule("y", 1.0, "-delay(y, tau)")
+            .build();
+        let mut options = Options {
+            end: 3.0,
+            points: 4,
+            selections: vec!["y".into()],
+            ..Options::default()
+        };
+        for &method in &[Method::Rk45, Method::Rosenbrock] {
+            options.method = method;
+            let y = simulate(&module, &options).unwrap().column("y").unwrap();
+            // The solution is a different polynomial on each interval between breakpoints.
+            let expected = [1.0, 0.0, -0.5, -1.0 / 6.0];
+            for (y, expected) in y.iter().zip(&expected) {
+                assert!((y - expected).abs() < 1e-4, "{:?}: {:?}", method, y);
+            }
+        }
+        options.method = Method::Direct;
+        assert!(matches!(
+            simulate(&module, &options),
+            Err(Error::InvalidOptions(_))
+        ));
+    }
+}
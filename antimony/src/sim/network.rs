@@ -0,0 +1,329 @@
+//! Reactions as the channels of stochastic simulation.
+//!
+//! Stochastic methods count molecules: the amount of each species is its concentration times
+//! the size of its compartment (species without a compartment count as concentrations), and a
+//! reaction fires one event at a time, changing the counts by its stoichiometry. A reaction's
+//! propensity is its rate, which is an amount per time; for mass-action rates, powers of
+//! reactant counts are replaced by the number of distinct combinations of molecules, so that
+//! `k*A^2` with `A = 1` cannot fire.
+//!
+//! Between firings the counts are kept alongside the slot values of the `System`, where species
+//! hold concentrations as in deterministic simulation.
+
+use std::collections::HashMap;
+
+use super::system::System;
+use super::Error;
+use crate::math::{BinaryOp, EvalError, Expr};
+use crate::model::Rule;
+use crate::Module;
+
+/// A reaction, compiled for stochastic simulation.
+#[derive(Debug, Clone)]
+pub(crate) struct Channel {
+    /// The slot holding the reaction's rate.
+    pub slot: usize,
+    /// The state variables the reaction changes, with the change in count.
+    pub changes: Vec<(usize, f64)>,
+    /// For mass-action rates, the reactants with a stoichiometry of two or more.
+    combinations: Vec<(usize, u32)>,
+    /// The assignments (as indices into `System::assignments`) that read, directly or not,
+    /// a count the reaction changes, in evaluation order.
+    updates: Vec<usize>,
+    /// The channels whose propensity changes when the reaction fires.
+    pub dependents: Vec<usize>,
+}
+
+/// How a state variable takes part in a reaction as a reactant.
+#[derive(Debug, Clone, Copy)]
+struct Reactant {
+    /// The order of the reaction: the sum of its reactant stoichiometries.
+    order: f64,
+    /// The stoichiometry of the state variable.
+    stoichiometry: f64,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Network {
+    pub channels: Vec<Channel>,
+    /// The slot of the compartment of each species among the state variables, if any.
+    compartments: Vec<Option<usize>>,
+    /// The reactions each state variable is a reactant of.
+    reactants: Vec<Vec<Reactant>>,
+}
+
+impl Network {
+    pub fn new(module: &Module, system: &System) -> Result<Network, Error> {
+        // The species come first among the state variables, followed by rate rules.
+        let mut compartments = Vec::with_capacity(system.n_state);
+        for id in &system.names[..system.n_state] {
+            let symbol = module.symbol(id).expect("state variables are symbols");
+            if let Some(Rule::Rate(_)) = symbol.rule {
+                break;
+            }
+            compartments.push(symbol.compartment.as_ref().map(|c| system.index[c]));
+        }
+        let n = compartments.len();
+        let state: HashMap<&str, usize> = system.names[..n]
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.as_str(), i))
+            .collect();
+
+        // The state variables that each assignment reads, following other assignments.
+        let position: HashMap<usize, usize> = system
+            .assignments
+            .iter()
+            .enumerate()
+            .map(|(a, (slot, _))| (*slot, a))
+            .collect();
+        let mut reads: Vec<Vec<bool>> = Vec::with_capacity(system.assignments.len());
+        for (_, code) in &system.assignments {
+            let mut read = vec![false; n];
+            let mut slots = Vec::new();
+            code.visit_slots(&mut |slot| slots.push(slot));
+            for slot in slots {
+                if slot < n {
+                    read[slot] = true;
+                } else if let Some(&b) = position.get(&slot) {
+                    for (r, &s) in read.iter_mut().zip(&reads[b]) {
+                        *r |= s;
+                    }
+                }
+            }
+            reads.push(read);
+        }
+
+        let stoichiometry = &module.stoichiometry;
+        let mut channels = Vec::new();
+        let mut reactants = vec![Vec::new(); n];
+        for (column, id) in stoichiometry.reactions.iter().enumerate() {
+            let reaction = match module.reaction(id) {
+                Some(r) => r,
+                None => continue,
+            };
+            let rate = match &reaction.rate {
+                Some(rate) => rate,
+                None => continue,
+            };
+            let changes: Vec<(usize, f64)> = stoichiometry
+                .species
+                .iter()
+                .zip(&stoichiometry.matrix)
+                .filter_map(|(s, row)| Some((*state.get(s.as_str())?, row[column])))
+                .filter(|&(_, change)| change != 0.0)
+                .collect();
+            if changes.is_empty() {
+                continue;
+            }
+            let mut consumed: HashMap<usize, f64> = HashMap::new();
+            for p in &reaction.reactants {
+                if let Some(&i) = state.get(p.species.as_str()) {
+                    *consumed.entry(i).or_default() += p.stoichiometry;
+                }
+            }
+            let order = reaction.reactants.iter().map(|p| p.stoichiometry).sum();
+            for (&i, &stoichiometry) in &consumed {
+                reactants[i].push(Reactant {
+                    order,
+                    stoichiometry,
+                });
+            }
+            let mut orders = HashMap::new();
+            let rate = module.expand(&module.formula(rate)?)?.simplify();
+            let combinations = if mass_action(&rate, &state, &mut orders) {
+                if consumed == orders {
+                    let mut combinations: Vec<(usize, u32)> = consumed
+                        .into_iter()
+                        .filter(|&(_, s)| s >= 2.0 && s.fract() == 0.0)
+                        .map(|(i, s)| (i, s as u32))
+                        .collect();
+                    combinations.sort_unstable();
+                    combinations
+                } else {
+                    Vec::new()
+                }
+            } else {
+                Vec::new()
+            };
+            let updates = reads
+                .iter()
+                .enumerate()
+                .filter(|(_, read)| changes.iter().any(|&(i, _)| read[i]))
+                .map(|(a, _)| a)
+                .collect();
+            channels.push(Channel {
+                slot: system.index[id],
+                changes,
+                combinations,
+                updates,
+                dependents: Vec::new(),
+            });
+        }
+        for j in 0..channels.len() {
+            let dependents = (0..channels.len())
+                .filter(|&k| {
+                    let rate = position[&channels[k].slot];
+                    let changed = |i: usize| channels[j].changes.iter().any(|&(c, _)| c == i);
+                    channels[j].updates.contains(&rate)
+                        || channels[k].combinations.iter().any(|&(i, _)| changed(i))
+                })
+                .collect();
+            channels[j].dependents = dependents;
+        }
+        Ok(Network {
+            channels,
+            compartments,
+            reactants,
+        })
+    }
+
+    /// The number of species among the state variables. The rest have rate rules.
+    pub fn species(&self) -> usize {
+        self.compartments.len()
+    }
+
+    /// Fail unless every state variable is a species, as purely discrete methods require.
+    pub fn discrete(&self, system: &System) -> Result<(), Error> {
+        match system.names[..system.n_state].get(self.species()) {
+            Some(id) => Err(Error::InvalidOptions(format!(
+                "`{}` has a rate rule, which needs the hybrid method",
+                id
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn size(&self, i: usize, values: &[f64]) -> f64 {
+        self.compartments[i].map_or(1.0, |c| values[c])
+    }
+
+    /// Round the amounts of the species in `values` to whole molecules, returning the
+    /// counts and updating `values` to match.
+    pub fn counts(&self, system: &System, t: f64, values: &mut [f64]) -> Result<Vec<f64>, Error> {
+        let counts: Vec<f64> = self
+            .amounts(values)
+            .into_iter()
+            .map(|x| x.round().max(0.0))
+            .collect();
+        self.write(system, t, &counts, values)?;
+        Ok(counts)
+    }
+
+    /// The amounts of the species in `values`, as continuous counts.
+    pub fn amounts(&self, values: &[f64]) -> Vec<f64> {
+        (0..self.compartments.len())
+            .map(|i| values[i] * self.size(i, values))
+            .collect()
+    }
+
+    /// Set the species in `values` to the given counts and update every assignment.
+    pub fn write(
+        &self,
+        system: &System,
+        t: f64,
+        counts: &[f64],
+        values: &mut [f64],
+    ) -> Result<(), EvalError> {
+        for (i, &count) in counts.iter().enumerate() {
+            values[i] = count / self.size(i, values);
+        }
+        system.update(t, values)
+    }
+
+    /// The highest order of reaction (HOR) in which a state variable with the given count is
+    /// a reactant, as used by tau selection: how much a relative change in the count can
+    /// change a propensity at most (Cao, Gillespie & Petzold, 2006). Zero if it is no
+    /// reactant.
+    pub fn highest_order(&self, i: usize, count: f64) -> f64 {
+        let x = count.max(3.0);
+        self.reactants[i]
+            .iter()
+            .map(|r| {
+                if r.stoichiometry < 2.0 {
+                    r.order
+                } else if r.stoichiometry < 3.0 {
+                    r.order / 2.0 * (2.0 + 1.0 / (x - 1.0))
+                } else {
+                    r.order / 3.0 * (3.0 + 1.0 / (x - 1.0) + 2.0 / (x - 2.0))
+                }
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// The propensity of a channel, given slot values consistent with `counts`.
+    pub fn propensity(
+        &self,
+        system: &System,
+        j: usize,
+        t: f64,
+        counts: &[f64],
+        values: &[f64],
+    ) -> Result<f64, Error> {
+        let channel = &self.channels[j];
+        let rate = values[channel.slot];
+        if rate.is_nan() || rate < 0.0 || rate == f64::INFINITY {
+            return Err(Error::Propensity {
+                reaction: system.names[channel.slot].clone(),
+                time: t,
+            });
+        }
+        let mut a = rate;
+        for &(i, order) in &channel.combinations {
+            let n = counts[i];
+            if n < f64::from(order) {
+                return Ok(0.0);
+            }
+            for m in 1..order {
+                a *= (n - f64::from(m)) / n;
+            }
+        }
+        Ok(a)
+    }
+
+    /// Fire a channel at time `t`, recomputing the assignments that depend on the counts it
+    /// changes (but not those that depend on time alone).
+    pub fn fire(
+        &self,
+        system: &System,
+        j: usize,
+        t: f64,
+        counts: &mut [f64],
+        values: &mut [f64],
+    ) -> Result<(), EvalError> {
+        let channel = &self.channels[j];
+        for &(i, change) in &channel.changes {
+            counts[i] += change;
+            values[i] = counts[i] / self.size(i, values);
+        }
+        for &a in &channel.updates {
+            let (slot, code) = &system.assignments[a];
+            values[*slot] = code.eval(t, values)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a rate law is of mass-action form in the state variables: a product of their
+/// powers, and of factors that do not depend on them. Collects the powers into `orders`.
+fn mass_action(e: &Expr, state: &HashMap<&str, usize>, orders: &mut HashMap<usize, f64>) -> bool {
+    let independent = |e: &Expr| e.symbols().iter().all(|s| !state.contains_key(s));
+    match e {
+        Expr::Binary(BinaryOp::Mul, a, b) => {
+            mass_action(a, state, orders) && mass_action(b, state, orders)
+        }
+        Expr::Binary(BinaryOp::Div, a, b) => mass_action(a, state, orders) && independent(b),
+        Expr::Symbol(id) if state.contains_key(id.as_str()) => {
+            *orders.entry(state[id.as_str()]).or_default() += 1.0;
+            true
+        }
+        Expr::Binary(BinaryOp::Pow, base, exponent) => match (&**base, &**exponent) {
+            (Expr::Symbol(id), Expr::Number(k)) if state.contains_key(id.as_str()) => {
+                *orders.entry(state[id.as_str()]).or_default() += k;
+                true
+            }
+            _ => independent(e),
+        },
+        _ => independent(e),
+    }
+}
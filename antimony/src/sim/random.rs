@@ -0,0 +1,199 @@
+//! The random numbers of the stochastic methods.
+//!
+//! The generator is xoshiro256** (Blackman and Vigna), seeded through SplitMix64 so that any
+//! 64-bit seed gives a well-mixed state. It is implemented here, rather than taken from a
+//! crate, so that a seed reproduces the same trajectory across versions of this crate's
+//! dependencies.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Random {
+    state: [u64; 4],
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        let mut x = seed;
+        let mut splitmix = || {
+            x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        Random {
+            state: [splitmix(), splitmix(), splitmix(), splitmix()],
+        }
+    }
+
+    /// A generator with a seed that differs from run to run.
+    pub fn from_entropy() -> Random {
+        Random::new(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// A uniform sample from the open interval (0, 1).
+    pub fn uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+
+    /// A sample from the exponential distribution with rate 1.
+    pub fn exponential(&mut self) -> f64 {
+        -self.uniform().ln()
+    }
+
+    /// A sample from the standard normal distribution (Box–Muller).
+    pub fn normal(&mut self) -> f64 {
+        let (u, v) = (self.uniform(), self.uniform());
+        (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+    }
+
+    /// A sample from the Poisson distribution with the given mean: by inversion for small
+    /// means, and by Hörmann's transformed rejection (PTRS) otherwise.
+    pub fn poisson(&mut self, mean: f64) -> f64 {
+        if mean <= 0.0 {
+            return 0.0;
+        }
+        if mean < 10.0 {
+            let u = self.uniform();
+            let (mut k, mut p) = (0.0, (-mean).exp());
+            let mut sum = p;
+            while u > sum && p > 0.0 {
+                k += 1.0;
+                p *= mean / k;
+                sum += p;
+            }
+            return k;
+        }
+        let root = mean.sqrt();
+        let b = 0.931 + 2.53 * root;
+        let a = -0.059 + 0.02483 * b;
+        let alpha = 1.1239 + 1.1328 / (b - 3.4);
+        let vr = 0.9277 - 3.6224 / (b - 2.0);
+        loop {
+            let u = self.uniform() - 0.5;
+            let v = self.uniform();
+            let us = 0.5 - u.abs();
+            let k = ((2.0 * a / us + b) * u + mean + 0.43).floor();
+            if us >= 0.07 && v <= vr {
+                return k;
+            }
+            if k < 0.0 || (us < 0.013 && v > us) {
+                continue;
+            }
+            let accept = -mean + k * mean.ln() - ln_factorial(k);
+            if v.ln() + alpha.ln() - (a / (us * us) + b).ln() <= accept {
+                return k;
+            }
+        }
+    }
+}
+
+/// The `p` quantile of the standard normal distribution (Acklam's rational approximation,
+/// with a relative error below 1.2e-9).
+pub(crate) fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-3,
+        3.224671290700398e-1,
+        2.445134137142996,
+        3.754408661907416,
+    ];
+    let polynomial = |c: &[f64], x: f64| c.iter().fold(0.0, |sum, c| sum * x + c);
+    let tail = |q: f64| {
+        let q = (-2.0 * q.ln()).sqrt();
+        polynomial(&C, q) / (polynomial(&D, q) * q + 1.0)
+    };
+    if p < 0.02425 {
+        tail(p)
+    } else if p > 1.0 - 0.02425 {
+        -tail(1.0 - p)
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        polynomial(&A, r) * q / (polynomial(&B, r) * r + 1.0)
+    }
+}
+
+/// `ln(k!)`, exactly for small `k` and by Stirling's series otherwise.
+fn ln_factorial(k: f64) -> f64 {
+    if k < 10.0 {
+        return (2..=k as u32).map(|i| f64::from(i).ln()).sum();
+    }
+    let x = k + 1.0;
+    let x2 = x * x;
+    (x - 0.5) * x.ln() - x
+        + 0.5 * (2.0 * std::f64::consts::PI).ln()
+        + (1.0 / 12.0 - (1.0 / 360.0 - 1.0 / (1260.0 * x2)) / x2) / x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproduces_and_fills_the_unit_interval() {
+        let (mut a, mut b) = (Random::new(7), Random::new(7));
+        let samples: Vec<f64> = (0..10_000).map(|_| a.uniform()).collect();
+        assert!(samples
+            .iter()
+            .all(|&u| u > 0.0 && u < 1.0 && u == b.uniform()));
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        assert!((mean - 0.5).abs() < 0.01);
+        assert_ne!(Random::new(8).next_u64(), Random::new(7).next_u64());
+    }
+
+    #[test]
+    fn samples_poisson_moments() {
+        let mut random = Random::new(11);
+        for &mean in &[0.5, 4.0, 25.0, 1e4] {
+            let samples: Vec<f64> = (0..20_000).map(|_| random.poisson(mean)).collect();
+            let m = samples.iter().sum::<f64>() / samples.len() as f64;
+            let var = samples.iter().map(|k| (k - m).powi(2)).sum::<f64>() / samples.len() as f64;
+            assert!((m - mean).abs() < 0.05 * mean.max(1.0), "{}: {}", mean, m);
+            assert!(
+                (var - mean).abs() < 0.1 * mean.max(1.0),
+                "{}: {}",
+                mean,
+                var
+            );
+        }
+        assert!((ln_factorial(12.0) - 479_001_600f64.ln()).abs() < 1e-10);
+    }
+}
@@ -0,0 +1,163 @@
+//! The explicit Runge–Kutta method of Dormand and Prince, of order 5(4).
+
+use super::solver::{initial_step, norm, Rhs, Step, Stepper};
+use super::Error;
+
+const C: [f64; 7] = [0.0, 1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+
+const A: [[f64; 6]; 7] = [
+    [0.0; 6],
+    [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [
+        19372.0 / 6561.0,
+        -25360.0 / 2187.0,
+        64448.0 / 6561.0,
+        -212.0 / 729.0,
+        0.0,
+        0.0,
+    ],
+    [
+        9017.0 / 3168.0,
+        -355.0 / 33.0,
+        46732.0 / 5247.0,
+        49.0 / 176.0,
+        -5103.0 / 18656.0,
+        0.0,
+    ],
+    // The last stage evaluates the solution itself (first same as last).
+    [
+        35.0 / 384.0,
+        0.0,
+        500.0 / 1113.0,
+        125.0 / 192.0,
+        -2187.0 / 6784.0,
+        11.0 / 84.0,
+    ],
+];
+
+/// The difference between the fifth- and fourth-order weights.
+const E: [f64; 7] = [
+    71.0 / 57600.0,
+    0.0,
+    -71.0 / 16695.0,
+    71.0 / 1920.0,
+    -17253.0 / 339200.0,
+    22.0 / 525.0,
+    -1.0 / 40.0,
+];
+
+/// Dormand–Prince with the usual step-size controller.
+pub(crate) struct DormandPrince {
+    rtol: f64,
+    atol: f64,
+    max_step: f64,
+    h: Option<f64>,
+    k: Vec<Vec<f64>>,
+}
+
+impl DormandPrince {
+    pub fn new(n: usize, rtol: f64, atol: f64, max_step: f64) -> DormandPrince {
+        DormandPrince {
+            rtol,
+            atol,
+            max_step,
+            h: None,
+            k: vec![vec![0.0; n]; 7],
+        }
+    }
+}
+
+impl Stepper for DormandPrince {
+    fn step(
+        &mut self,
+        rhs: &mut dyn Rhs,
+        t: f64,
+        y: &[f64],
+        f: &[f64],
+        t_stop: f64,
+    ) -> Result<Step, Error> {
+        let n = y.len();
+        let mut h = match self.h {
+            Some(h) => h,
+            None => initial_step(rhs, t, y, f, 5, self.rtol, self.atol)?,
+        };
+        let mut stage = vec![0.0; n];
+        let mut scale = vec![0.0; n];
+        let mut err = vec![0.0; n];
+        loop {
+            h = h.min(self.max_step).min(t_stop - t);
+            if h <= 16.0 * f64::EPSILON * t.abs().max(1.0) {
+                return Err(Error::StepSize { time: t });
+            }
+            self.k[0].copy_from_slice(f);
+            for s in 1..7 {
+                for i in 0..n {
+                    let sum: f64 = (0..s).map(|j| A[s][j] * self.k[j][i]).sum();
+                    stage[i] = y[i] + h * sum;
+                }
+                rhs.eval(t + C[s] * h, &stage, &mut self.k[s])?;
+            }
+            // `stage` now holds the fifth-order solution and `k[6]` its derivative.
+            for i in 0..n {
+                scale[i] = self.atol + self.rtol * y[i].abs().max(stage[i].abs());
+                err[i] = h * (0..7).map(|j| E[j] * self.k[j][i]).sum::<f64>();
+            }
+            let error = norm(&err, &scale);
+            if !error.is_finite() {
+                h *= 0.2;
+                continue;
+            }
+            let factor = if error == 0.0 {
+                10.0
+            } else {
+                (0.9 * error.powf(-0.2)).clamp(0.2, 10.0)
+            };
+            if error <= 1.0 {
+                self.h = Some(h * factor);
+                return Ok(Step {
+                    t: if h == t_stop - t { t_stop } else { t + h },
+                    y: stage,
+                    f: self.k[6].clone(),
+                });
+            }
+            h *= factor.min(1.0);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.h = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Decay;
+
+    impl Rhs for Decay {
+        fn eval(&mut self, _: f64, y: &[f64], dy: &mut [f64]) -> Result<(), Error> {
+            dy[0] = -y[0];
+            dy[1] = y[0] - 0.5 * y[1];
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn integrates_linear_system() {
+        let mut stepper = DormandPrince::new(2, 1e-10, 1e-12, f64::INFINITY);
+        let (mut t, mut y, mut f) = (0.0, vec![1.0, 0.0], vec![-1.0, 1.0]);
+        while t < 2.0 {
+            let step = stepper.step(&mut Decay, t, &y, &f, 2.0).unwrap();
+            t = step.t;
+            y = step.y;
+            f = step.f;
+        }
+        assert_eq!(t, 2.0);
+        let exact = [(-2f64).exp(), 2.0 * ((-1f64).exp() - (-2f64).exp())];
+        assert!((y[0] - exact[0]).abs() < 1e-9);
+        assert!((y[1] - exact[1]).abs() < 1e-9);
+    }
+}
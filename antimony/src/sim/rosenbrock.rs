@@ -0,0 +1,160 @@
+//! A linearly implicit (Rosenbrock) method for stiff systems.
+//!
+//! This is the L-stable method of order 2(3) by Shampine and Reichelt, as in MATLAB's
+//! `ode23s`. Each step solves three linear systems with the matrix `I - h*d*J`, which is
+//! factored once per step attempt, so no Newton iteration is needed.
+
+use super::solver::{initial_step, norm, Rhs, Step, Stepper};
+use super::Error;
+use crate::linalg::Lu;
+
+pub(crate) struct Rosenbrock {
+    rtol: f64,
+    atol: f64,
+    max_step: f64,
+    h: Option<f64>,
+    jac: Vec<Vec<f64>>,
+}
+
+impl Rosenbrock {
+    pub fn new(n: usize, rtol: f64, atol: f64, max_step: f64) -> Rosenbrock {
+        Rosenbrock {
+            rtol,
+            atol,
+            max_step,
+            h: None,
+            jac: vec![vec![0.0; n]; n],
+        }
+    }
+}
+
+impl Stepper for Rosenbrock {
+    fn step(
+        &mut self,
+        rhs: &mut dyn Rhs,
+        t: f64,
+        y: &[f64],
+        f0: &[f64],
+        t_stop: f64,
+    ) -> Result<Step, Error> {
+        let n = y.len();
+        let d = 1.0 / (2.0 + 2f64.sqrt());
+        let e32 = 6.0 + 2f64.sqrt();
+
+        let mut h = match self.h {
+            Some(h) => h,
+            None => initial_step(rhs, t, y, f0, 2, self.rtol, self.atol)?,
+        };
+        rhs.jacobian(t, y, f0, &mut self.jac)?;
+        // The explicit time dependence, df/dt, by forward differences.
+        let dt = f64::EPSILON.sqrt() * t.abs().max(1.0);
+        let mut ft = vec![0.0; n];
+        rhs.eval(t + dt, y, &mut ft)?;
+        let ft: Vec<f64> = ft.iter().zip(f0).map(|(a, b)| (a - b) / dt).collect();
+
+        let mut w = vec![vec![0.0; n]; n];
+        let mut stage = vec![0.0; n];
+        let (mut f1, mut f2) = (vec![0.0; n], vec![0.0; n]);
+        loop {
+            h = h.min(self.max_step).min(t_stop - t);
+            if h <= 16.0 * f64::EPSILON * t.abs().max(1.0) {
+                return Err(Error::StepSize { time: t });
+            }
+            for (i, (row, jac)) in w.iter_mut().zip(&self.jac).enumerate() {
+                for (j, (w, jac)) in row.iter_mut().zip(jac).enumerate() {
+                    *w = if i == j { 1.0 } else { 0.0 } - h * d * jac;
+                }
+            }
+            let lu = match Lu::new(&w) {
+                Some(lu) => lu,
+                None => {
+                    h *= 0.5;
+                    continue;
+                }
+            };
+            let mut k1: Vec<f64> = (0..n).map(|i| f0[i] + h * d * ft[i]).collect();
+            lu.solve(&mut k1);
+            for i in 0..n {
+                stage[i] = y[i] + 0.5 * h * k1[i];
+            }
+            rhs.eval(t + 0.5 * h, &stage, &mut f1)?;
+            let mut k2: Vec<f64> = (0..n).map(|i| f1[i] - k1[i]).collect();
+            lu.solve(&mut k2);
+            for i in 0..n {
+                k2[i] += k1[i];
+                stage[i] = y[i] + h * k2[i];
+            }
+            rhs.eval(t + h, &stage, &mut f2)?;
+            let mut k3: Vec<f64> = (0..n)
+                .map(|i| f2[i] - e32 * (k2[i] - f1[i]) - 2.0 * (k1[i] - f0[i]) + h * d * ft[i])
+                .collect();
+            lu.solve(&mut k3);
+
+            let scale: Vec<f64> = (0..n)
+                .map(|i| self.atol + self.rtol * y[i].abs().max(stage[i].abs()))
+                .collect();
+            let err: Vec<f64> = (0..n)
+                .map(|i| h / 6.0 * (k1[i] - 2.0 * k2[i] + k3[i]))
+                .collect();
+            let error = norm(&err, &scale);
+            if !error.is_finite() {
+                h *= 0.25;
+                continue;
+            }
+            let factor = if error == 0.0 {
+                5.0
+            } else {
+                (0.8 * error.powf(-1.0 / 3.0)).clamp(0.1, 5.0)
+            };
+            if error <= 1.0 {
+                self.h = Some(h * factor);
+                return Ok(Step {
+                    t: if h == t_stop - t { t_stop } else { t + h },
+                    y: stage,
+                    f: f2,
+                });
+            }
+            h *= factor.min(1.0);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.h = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Robertson's chemical kinetics problem, a standard stiff test.
+    struct Robertson;
+
+    impl Rhs for Robertson {
+        fn eval(&mut self, _: f64, y: &[f64], dy: &mut [f64]) -> Result<(), Error> {
+            dy[0] = -0.04 * y[0] + 1e4 * y[1] * y[2];
+            dy[2] = 3e7 * y[1] * y[1];
+            dy[1] = -dy[0] - dy[2];
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn integrates_stiff_system() {
+        let mut stepper = Rosenbrock::new(3, 1e-6, 1e-10, f64::INFINITY);
+        let mut y = vec![1.0, 0.0, 0.0];
+        let mut f = vec![0.0; 3];
+        Robertson.eval(0.0, &y, &mut f).unwrap();
+        let (mut t, mut steps) = (0.0, 0);
+        while t < 40.0 {
+            let step = stepper.step(&mut Robertson, t, &y, &f, 40.0).unwrap();
+            t = step.t;
+            y = step.y;
+            f = step.f;
+            steps += 1;
+        }
+        assert!(steps < 1000, "{} steps", steps);
+        assert!((y[0] - 0.7158).abs() < 1e-3, "{:?}", y);
+        assert!((y[2] - 0.2842).abs() < 1e-3, "{:?}", y);
+    }
+}
@@ -0,0 +1,377 @@
+//! Parameter scans: one simulation per point of a grid or sample of parameter values.
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use super::{normal_quantile, Error, Options, Random, Simulator, TimeCourse};
+use crate::Module;
+
+/// How a parameter is distributed in a random design.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Distribution {
+    /// Evenly between two values.
+    Uniform { lower: f64, upper: f64 },
+    /// Evenly in the logarithm between two positive values, as suits rate constants known
+    /// to within orders of magnitude.
+    LogUniform { lower: f64, upper: f64 },
+    /// Normally, with the given mean and standard deviation.
+    Normal { mean: f64, deviation: f64 },
+    /// With a logarithm that is normally distributed with the given mean and standard
+    /// deviation.
+    LogNormal { mean: f64, deviation: f64 },
+}
+
+impl Distribution {
+    /// The `p` quantile.
+    pub fn quantile(&self, p: f64) -> f64 {
+        match *self {
+            Distribution::Uniform { lower, upper } => lower + p * (upper - lower),
+            Distribution::LogUniform { lower, upper } => {
+                (lower.ln() + p * (upper.ln() - lower.ln())).exp()
+            }
+            Distribution::Normal { mean, deviation } => mean + deviation * normal_quantile(p),
+            Distribution::LogNormal { mean, deviation } => {
+                (mean + deviation * normal_quantile(p)).exp()
+            }
+        }
+    }
+
+    fn check(&self, id: &str) -> Result<(), Error> {
+        let valid = match *self {
+            Distribution::Uniform { lower, upper } => {
+                lower.is_finite() && upper.is_finite() && lower <= upper
+            }
+            Distribution::LogUniform { lower, upper } => {
+                lower > 0.0 && upper.is_finite() && lower <= upper
+            }
+            Distribution::Normal { mean, deviation }
+            | Distribution::LogNormal { mean, deviation } => {
+                mean.is_finite() && deviation.is_finite() && deviation >= 0.0
+            }
+        };
+        if valid {
+            Ok(())
+        } else {
+            let message = format!("the distribution of `{}` is invalid", id);
+            Err(Error::InvalidOptions(message))
+        }
+    }
+}
+
+/// Points at which to simulate a model: a value for each of some parameters per point.
+///
+/// A scan runs on copies of a `Simulator`, which was compiled from a `Module` already copied
+/// out of libAntimony, so the simulations never touch the library's global state and can run
+/// on many threads at once. With the `rayon` feature they do.
+///
+/// ```no_run
+/// use antimony::sim::{Distribution, Options, Scan, Simulator};
+/// use antimony::Document;
+///
+/// let doc = Document::load_antimony_str("J0: S1 => ; k1*S1; S1 = 10; k1 = 0.5")?;
+/// let sim = Simulator::new(doc.main())?;
+/// let options = Options { end: 4.0, points: 5, ..Options::default() };
+/// // A 5 × 3 grid of rate constants and initial values.
+/// let grid = Scan::linear("k1", 0.1, 1.0, 5).product(&Scan::values("S1", &[1.0, 5.0, 10.0]));
+/// let table = sim.scan(&grid, &options)?;
+/// assert_eq!(table.rows.len(), 15 * 5);
+/// // 100 samples of a rate constant known to within an order of magnitude.
+/// let k1 = Distribution::LogUniform { lower: 0.1, upper: 1.0 };
+/// let sample = Scan::latin_hypercube(&[("k1", k1)], 100, 1)?;
+/// let table = sim.scan(&sample, &options)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scan {
+    /// The symbols varied, each any symbol that `Simulator::set` accepts.
+    pub parameters: Vec<String>,
+    /// The points, with one value per parameter each.
+    pub points: Vec<Vec<f64>>,
+}
+
+impl Scan {
+    /// One parameter at the given values.
+    pub fn values(id: &str, values: &[f64]) -> Scan {
+        Scan {
+            parameters: vec![id.to_owned()],
+            points: values.iter().map(|&v| vec![v]).collect(),
+        }
+    }
+
+    /// One parameter at `count` evenly spaced values from `lower` to `upper`.
+    pub fn linear(id: &str, lower: f64, upper: f64, count: usize) -> Scan {
+        Scan::values(id, &spaced(lower, upper, count))
+    }
+
+    /// One parameter at `count` values from `lower` to `upper`, both positive, evenly spaced
+    /// in the logarithm.
+    pub fn logarithmic(id: &str, lower: f64, upper: f64, count: usize) -> Scan {
+        let values: Vec<f64> = spaced(lower.ln(), upper.ln(), count)
+            .iter()
+            .map(|v| v.exp())
+            .collect();
+        Scan::values(id, &values)
+    }
+
+    /// `count` repetitions of the model as it is, varying nothing: an ensemble of stochastic
+    /// simulations.
+    pub fn ensemble(count: usize) -> Scan {
+        Scan {
+            parameters: Vec::new(),
+            points: vec![Vec::new(); count],
+        }
+    }
+
+    /// The grid of every point of this scan combined with every point of `other`, with the
+    /// points of `other` varying fastest. Combining two one-parameter scans gives a 2-D grid.
+    pub fn product(&self, other: &Scan) -> Scan {
+        let mut parameters = self.parameters.clone();
+        parameters.extend_from_slice(&other.parameters);
+        let points = self
+            .points
+            .iter()
+            .flat_map(|a| other.points.iter().map(move |b| [&a[..], b].concat()))
+            .collect();
+        Scan { parameters, points }
+    }
+
+    /// A Latin hypercube sample of `samples` points: the range of each parameter is divided
+    /// into `samples` intervals of equal probability, each of which holds the value of
+    /// exactly one point, and the intervals are paired at random across parameters. The
+    /// sample covers every parameter evenly with far fewer points than a grid.
+    pub fn latin_hypercube(
+        distributions: &[(&str, Distribution)],
+        samples: usize,
+        seed: u64,
+    ) -> Result<Scan, Error> {
+        let mut random = Random::new(seed);
+        Scan::sample(distributions, samples, || {
+            let mut strata: Vec<usize> = (0..samples).collect();
+            for i in (1..samples).rev() {
+                let j = ((random.uniform() * (i + 1) as f64) as usize).min(i);
+                strata.swap(i, j);
+            }
+            strata
+                .iter()
+                .map(|&k| (k as f64 + random.uniform()) / samples as f64)
+                .collect()
+        })
+    }
+
+    /// A simple random sample of `samples` points, with the parameters independent.
+    pub fn random(
+        distributions: &[(&str, Distribution)],
+        samples: usize,
+        seed: u64,
+    ) -> Result<Scan, Error> {
+        let mut random = Random::new(seed);
+        Scan::sample(distributions, samples, || {
+            (0..samples).map(|_| random.uniform()).collect()
+        })
+    }
+
+    /// A sample from probabilities given for each parameter, in turn, by `probabilities`.
+    fn sample<F>(
+        distributions: &[(&str, Distribution)],
+        samples: usize,
+        mut probabilities: F,
+    ) -> Result<Scan, Error>
+    where
+        F: FnMut() -> Vec<f64>,
+    {
+        let mut points = vec![Vec::with_capacity(distributions.len()); samples];
+        for (id, distribution) in distributions {
+            distribution.check(id)?;
+            for (point, p) in points.iter_mut().zip(probabilities()) {
+                point.push(distribution.quantile(p));
+            }
+        }
+        Ok(Scan {
+            parameters: distributions
+                .iter()
+                .map(|(id, _)| (*id).to_owned())
+                .collect(),
+            points,
+        })
+    }
+}
+
+/// `count` evenly spaced values from `lower` to `upper`.
+fn spaced(lower: f64, upper: f64, count: usize) -> Vec<f64> {
+    if count == 1 {
+        return vec![lower];
+    }
+    let step = (upper - lower) / count.saturating_sub(1) as f64;
+    (0..count)
+        .map(|i| {
+            if i + 1 == count {
+                upper
+            } else {
+                lower + i as f64 * step
+            }
+        })
+        .collect()
+}
+
+impl Simulator {
+    /// Simulate at every point of a scan, from the initial values given by `set` and by the
+    /// point, and collect the results into one table: a column `point` with the index of the
+    /// point, a column `<id> (initial)` for each parameter of the scan, and the selections of
+    /// `options`, with one row per point and output time.
+    ///
+    /// With `Options::seed`, point `i` uses the seed plus `i`, so that stochastic simulations
+    /// differ between points but the whole table is reproducible. The first point that
+    /// cannot be simulated fails the scan.
+    pub fn scan(&self, scan: &Scan, options: &Options) -> Result<TimeCourse, Error> {
+        options.check()?;
+        for id in &scan.parameters {
+            self.slot(id)?;
+        }
+        if scan.points.iter().any(|p| p.len() != scan.parameters.len()) {
+            let message = "every point must have a value for each parameter".to_owned();
+            return Err(Error::InvalidOptions(message));
+        }
+        let selections = self.selections(options)?;
+        let run = |simulator: &mut Simulator, (i, point): (usize, &Vec<f64>)| {
+            for (id, &value) in scan.parameters.iter().zip(point) {
+                simulator.set(id, value)?;
+            }
+            let options = Options {
+                seed: options.seed.map(|seed| seed.wrapping_add(i as u64)),
+                ..options.clone()
+            };
+            simulator.simulate(&options)
+        };
+        #[cfg(feature = "rayon")]
+        let results: Vec<Result<TimeCourse, Error>> = scan
+            .points
+            .par_iter()
+            .enumerate()
+            .map_init(|| self.clone(), run)
+            .collect();
+        #[cfg(not(feature = "rayon"))]
+        let results: Vec<Result<TimeCourse, Error>> = {
+            let mut simulator = self.clone();
+            scan.points
+                .iter()
+                .enumerate()
+                .map(|point| run(&mut simulator, point))
+                .collect()
+        };
+
+        let mut columns = vec!["point".to_owned()];
+        columns.extend(scan.parameters.iter().map(|id| format!("{} (initial)", id)));
+        columns.extend(selections);
+        let mut rows = Vec::new();
+        for (i, (point, result)) in scan.points.iter().zip(results).enumerate() {
+            for row in result?.rows {
+                let mut values = Vec::with_capacity(columns.len());
+                values.push(i as f64);
+                values.extend_from_slice(point);
+                values.extend(row);
+                rows.push(values);
+            }
+        }
+        let mut table = TimeCourse::new(columns, rows).name(&self.module);
+        for (name, id) in table.names[1..].iter_mut().zip(&scan.parameters) {
+            let symbol = self.module.symbol(id).and_then(|s| s.name.as_ref());
+            if let Some(display) = symbol.filter(|d| !d.is_empty()) {
+                *name = format!("{} (initial)", display);
+            }
+        }
+        Ok(table)
+    }
+}
+
+/// Run a scan of a module; see `Simulator::scan`.
+pub fn scan(module: &Module, scan: &Scan, options: &Options) -> Result<TimeCourse, Error> {
+    Simulator::new(module)?.scan(scan, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Method;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn scans_a_grid() {
+        let module = Fixture::new()
+            .species("S", 1.0)
+            .parameter("k", 1.0)
+            .irreversible("J0", &[(1.0, "S")], &[], "k*S")
+            .build();
+        let grid = Scan::linear("k", 0.5, 1.0, 2).product(&Scan::values("S", &[1.0, 2.0, 4.0]));
+        assert_eq!(grid.points[1], [0.5, 2.0]);
+        let options = Options {
+            end: 2.0,
+            points: 3,
+            relative_tolerance: 1e-9,
+            absolute_tolerance: 1e-12,
+            ..Options::default()
+        };
+        let table = scan(&module, &grid, &options).unwrap();
+        assert_eq!(
+            table.columns,
+            ["point", "k (initial)", "S (initial)", "time", "S"]
+        );
+        assert_eq!(table.rows.len(), 6 * 3);
+        for row in &table.rows {
+            let exact = row[2] * (-row[1] * row[3]).exp();
+            assert!((row[4] - exact).abs() < 1e-6, "{:?}", row);
+        }
+        assert_eq!(table.rows[17][0], 5.0);
+
+        // Stochastic simulations differ between points, and are reproducible with a seed.
+        let options = Options {
+            method: Method::Direct,
+            selections: vec!["S".into()],
+            seed: Some(3),
+            ..options
+        };
+        let mut sim = Simulator::new(&module).unwrap();
+        sim.set("S", 100.0).unwrap();
+        let first = sim.scan(&Scan::ensemble(4), &options).unwrap();
+        assert_eq!(first.columns, ["point", "S"]);
+        assert_eq!(first, sim.scan(&Scan::ensemble(4), &options).unwrap());
+        let ends: Vec<f64> = first.rows.chunks(3).map(|rows| rows[2][1]).collect();
+        assert!(ends.iter().any(|&s| s != ends[0]), "{:?}", ends);
+        assert_eq!(
+            sim.scan(&Scan::values("nothing", &[1.0]), &options),
+            Err(Error::UnknownSymbol("nothing".into()))
+        );
+    }
+
+    #[test]
+    fn samples_latin_hypercubes() {
+        let k = Distribution::LogUniform {
+            lower: 0.01,
+            upper: 100.0,
+        };
+        let x = Distribution::Normal {
+            mean: 1.0,
+            deviation: 0.5,
+        };
+        let sample = Scan::latin_hypercube(&[("k", k), ("x", x)], 20, 7).unwrap();
+        assert_eq!(sample.parameters, ["k", "x"]);
+        // Each of the 20 intervals of equal probability holds exactly one value.
+        let mut strata: Vec<usize> = sample
+            .points
+            .iter()
+            .map(|p| ((p[0].log10() + 2.0) / 4.0 * 20.0) as usize)
+            .collect();
+        strata.sort_unstable();
+        assert_eq!(strata, (0..20).collect::<Vec<_>>());
+        let mean = sample.points.iter().map(|p| p[1]).sum::<f64>() / 20.0;
+        assert!((mean - 1.0).abs() < 0.05, "{}", mean);
+        assert_eq!(
+            sample,
+            Scan::latin_hypercube(&[("k", k), ("x", x)], 20, 7).unwrap()
+        );
+        let invalid = Distribution::LogUniform {
+            lower: 0.0,
+            upper: 1.0,
+        };
+        assert!(Scan::random(&[("k", invalid)], 5, 1).is_err());
+    }
+}
@@ -0,0 +1,599 @@
+//! Parameter sensitivity analysis, local and global.
+//!
+//! Local sensitivities `s = ∂x/∂p` of the state variables to a parameter `p` obey the forward
+//! sensitivity equations `ds/dt = J s + ∂f/∂p`, which are integrated alongside the model's own
+//! equations, from the derivatives of the initial values. `J` is the Jacobian of the model and
+//! `∂f/∂p` is found by differentiating the expanded rate equations, or, where that fails, by
+//! finite differences.
+//!
+//! Global methods sample parameters over ranges instead, and simulate the model at every
+//! sample: Sobol indices apportion the variance of each output to the parameters (Saltelli et
+//! al., 2010), and the Morris method screens them by the spread of their elementary effects
+//! (Campolongo et al., 2007). Both are computed at every output time, for every selected
+//! column, and are not a number where the output does not vary.
+
+use super::rk45::DormandPrince;
+use super::rosenbrock::Rosenbrock;
+use super::solver::{interpolate, Rhs, Stepper};
+use super::{Code, Error, Method, Options, Random, Simulator, SystemRhs, TimeCourse};
+use crate::math::Expr;
+use crate::{Module, SymbolKind};
+
+/// Sensitivities over time: one value per output time, output, and parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensitivitySeries {
+    /// The output times.
+    pub times: Vec<f64>,
+    /// The outputs: state variables, or the selected columns of a simulation.
+    pub outputs: Vec<String>,
+    /// The parameters.
+    pub parameters: Vec<String>,
+    /// The values, indexed by time, output, and parameter in turn.
+    pub values: Vec<Vec<Vec<f64>>>,
+}
+
+impl SensitivitySeries {
+    /// The values for the given output and parameter over time, if both are known.
+    pub fn get(&self, output: &str, parameter: &str) -> Option<Vec<f64>> {
+        let i = self.outputs.iter().position(|o| o == output)?;
+        let k = self.parameters.iter().position(|p| p == parameter)?;
+        Some(self.values.iter().map(|at| at[i][k]).collect())
+    }
+}
+
+/// The solution of the forward sensitivity equations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sensitivities {
+    /// The state variables over time.
+    pub values: TimeCourse,
+    /// The unscaled sensitivities `∂x_i/∂p_k` of the state variables.
+    pub sensitivities: SensitivitySeries,
+}
+
+/// A parameter varied uniformly between two values by the global methods.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterRange {
+    /// The symbol id, which may be any symbol that `Simulator::set` accepts.
+    pub id: String,
+    /// The lowest value.
+    pub lower: f64,
+    /// The highest value.
+    pub upper: f64,
+}
+
+impl ParameterRange {
+    pub fn new(id: &str, lower: f64, upper: f64) -> ParameterRange {
+        ParameterRange {
+            id: id.to_owned(),
+            lower,
+            upper,
+        }
+    }
+}
+
+/// Variance-based sensitivity indices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SobolIndices {
+    /// The fraction of the variance of each output due to each parameter alone.
+    pub first_order: SensitivitySeries,
+    /// The fraction of the variance of each output due to each parameter, including its
+    /// interactions with the others.
+    pub total: SensitivitySeries,
+}
+
+/// Statistics of the elementary effects of the Morris method, in output units per parameter
+/// range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementaryEffects {
+    /// The mean effect `μ`.
+    pub mean: SensitivitySeries,
+    /// The mean absolute effect `μ*`, which ranks the parameters by influence.
+    pub mean_absolute: SensitivitySeries,
+    /// The standard deviation `σ` of the effects, which is large for parameters that act
+    /// nonlinearly or through interactions.
+    pub deviation: SensitivitySeries,
+}
+
+/// Compile a module and integrate its forward sensitivities; see
+/// `Simulator::sensitivities`.
+pub fn sensitivities(
+    module: &Module,
+    parameters: &[&str],
+    options: &Options,
+) -> Result<Sensitivities, Error> {
+    Simulator::new(module)?.sensitivities(parameters, options)
+}
+
+impl Simulator {
+    /// Integrate the model with the sensitivities of its state variables to the given
+    /// parameters, which must be constant formulas (`SymbolKind::FormulaConstant`).
+    ///
+    /// Only the ODE methods apply, and the model must have no events or delays, across which
+    /// sensitivities jump. The error control covers the sensitivities as well as the state.
+    pub fn sensitivities(
+        &self,
+        parameters: &[&str],
+        options: &Options,
+    ) -> Result<Sensitivities, Error> {
+        options.check()?;
+        let system = &self.system;
+        let invalid = |message: String| Err(Error::InvalidOptions(message));
+        if !matches!(options.method, Method::Rk45 | Method::Rosenbrock) {
+            return invalid("sensitivities need an ODE method".to_owned());
+        }
+        if !system.events.is_empty() || !system.delays.is_empty() {
+            return invalid("sensitivities exclude models with events or delays".to_owned());
+        }
+        let mut slots = Vec::with_capacity(parameters.len());
+        for &id in parameters {
+            let symbol = self.module.symbol(id);
+            let symbol = symbol.ok_or_else(|| Error::UnknownSymbol(id.to_owned()))?;
+            if symbol.kind != SymbolKind::FormulaConstant {
+                return invalid(format!("`{}` is not a constant parameter", id));
+            }
+            slots.push(self.slot(id)?);
+        }
+        let (n, p) = (system.n_state, slots.len());
+
+        // The state and its derivatives with respect to each parameter at the start.
+        let values = system.initial_values(options.start, &self.overrides)?;
+        let mut y = vec![0.0; n * (1 + p)];
+        y[..n].copy_from_slice(&values[..n]);
+        for (k, &slot) in slots.iter().enumerate() {
+            let h = f64::EPSILON.cbrt() * values[slot].abs().max(1e-5);
+            let mut overrides = self.overrides.clone();
+            overrides[slot] = Some(values[slot] + h);
+            let hi = system.initial_values(options.start, &overrides)?;
+            overrides[slot] = Some(values[slot] - h);
+            let lo = system.initial_values(options.start, &overrides)?;
+            for (i, s) in y[n * (k + 1)..n * (k + 2)].iter_mut().enumerate() {
+                *s = (hi[i] - lo[i]) / (2.0 * h);
+            }
+        }
+
+        let equations = self.module.rate_equations()?;
+        let expanded: Vec<Option<Expr>> = equations
+            .iter()
+            .map(|(_, rhs)| self.module.expand(rhs).ok())
+            .collect();
+        let derivatives = parameters
+            .iter()
+            .map(|&id| {
+                let compile = |e: &Option<Expr>| {
+                    let d = e.as_ref()?.derivative(id).ok()?;
+                    Code::compile(&d.simplify(), &system.index).ok()
+                };
+                expanded.iter().map(compile).collect()
+            })
+            .collect();
+        let mut rhs = SensitivityRhs {
+            inner: SystemRhs {
+                system,
+                values,
+                jacobian: if options.analytic_jacobian {
+                    self.jacobian()
+                } else {
+                    None
+                },
+                history: None,
+            },
+            slots,
+            derivatives,
+            f: vec![0.0; n],
+            jac: vec![vec![0.0; n]; n],
+        };
+
+        let dim = y.len();
+        let mut stepper: Box<dyn Stepper> = match options.method {
+            Method::Rk45 => Box::new(DormandPrince::new(
+                dim,
+                options.relative_tolerance,
+                options.absolute_tolerance,
+                options.max_step,
+            )),
+            _ => Box::new(Rosenbrock::new(
+                dim,
+                options.relative_tolerance,
+                options.absolute_tolerance,
+                options.max_step,
+            )),
+        };
+        let times = options.times();
+        let mut rows = Vec::with_capacity(times.len());
+        if times[0] == options.start {
+            rows.push(y.clone());
+        }
+        let mut t = options.start;
+        let mut f = vec![0.0; dim];
+        rhs.eval(t, &y, &mut f)?;
+        let mut steps = 0;
+        while rows.len() < times.len() {
+            if steps == options.max_steps {
+                return Err(Error::TooManySteps { time: t });
+            }
+            let step = stepper.step(&mut rhs, t, &y, &f, options.end)?;
+            steps += 1;
+            while let Some(&time) = times.get(rows.len()).filter(|&&time| time <= step.t) {
+                let mut row = vec![0.0; dim];
+                interpolate(t, &y, &f, &step, time, &mut row);
+                rows.push(row);
+            }
+            t = step.t;
+            y = step.y;
+            f = step.f;
+        }
+
+        let variables = system.names[..n].to_vec();
+        let mut columns = vec!["time".to_owned()];
+        columns.extend(variables.iter().cloned());
+        Ok(Sensitivities {
+            values: TimeCourse::new(
+                columns,
+                rows.iter()
+                    .zip(&times)
+                    .map(|(row, &t)| std::iter::once(t).chain(row[..n].iter().copied()).collect())
+                    .collect(),
+            )
+            .name(&self.module),
+            sensitivities: SensitivitySeries {
+                values: rows
+                    .iter()
+                    .map(|row| {
+                        (0..n)
+                            .map(|i| (0..p).map(|k| row[n * (k + 1) + i]).collect())
+                            .collect()
+                    })
+                    .collect(),
+                times,
+                outputs: variables,
+                parameters: parameters.iter().map(|&id| id.to_owned()).collect(),
+            },
+        })
+    }
+
+    /// Estimate the first-order and total Sobol indices of the selected columns (see
+    /// `Options::selections`) from `samples` Monte Carlo samples of the parameters, which
+    /// takes `samples * (parameters + 2)` simulations. The estimates converge slowly; a few
+    /// thousand samples give about two digits.
+    pub fn sobol(
+        &self,
+        ranges: &[ParameterRange],
+        samples: usize,
+        options: &Options,
+    ) -> Result<SobolIndices, Error> {
+        if samples < 2 {
+            return Err(Error::InvalidOptions(
+                "`samples` must be at least 2".to_owned(),
+            ));
+        }
+        let mut global = Global::new(self, ranges, options)?;
+        let p = ranges.len();
+        let mut random = options.seed.map_or_else(Random::from_entropy, Random::new);
+        let mut a = Vec::with_capacity(samples);
+        let mut b = Vec::with_capacity(samples);
+        let mut ab = vec![Vec::with_capacity(samples); p];
+        for _ in 0..samples {
+            let x: Vec<f64> = (0..p).map(|_| random.uniform()).collect();
+            let z: Vec<f64> = (0..p).map(|_| random.uniform()).collect();
+            a.push(global.run(&x)?);
+            b.push(global.run(&z)?);
+            // The first sample with the parameter of the second.
+            for (k, runs) in ab.iter_mut().enumerate() {
+                let mut mixed = x.clone();
+                mixed[k] = z[k];
+                runs.push(global.run(&mixed)?);
+            }
+        }
+
+        let m = samples as f64;
+        let [first_order, total] = global.indices(|t, i| {
+            let fa: Vec<f64> = a.iter().map(|run| run[t][i]).collect();
+            let fb: Vec<f64> = b.iter().map(|run| run[t][i]).collect();
+            let mean = (fa.iter().sum::<f64>() + fb.iter().sum::<f64>()) / (2.0 * m);
+            let variance = fa
+                .iter()
+                .chain(&fb)
+                .map(|f| (f - mean).powi(2))
+                .sum::<f64>()
+                / (2.0 * m - 1.0);
+            ab.iter()
+                .map(|runs| {
+                    if variance == 0.0 {
+                        return [f64::NAN; 2];
+                    }
+                    let (mut first, mut total) = (0.0, 0.0);
+                    for ((fa, fb), run) in fa.iter().zip(&fb).zip(runs) {
+                        let fab = run[t][i];
+                        first += (fb - mean) * (fab - fa);
+                        total += (fa - fab).powi(2);
+                    }
+                    [first / m / variance, total / (2.0 * m) / variance]
+                })
+                .collect()
+        });
+        Ok(SobolIndices { first_order, total })
+    }
+
+    /// Compute the elementary effects of the parameters on the selected columns (see
+    /// `Options::selections`) along `trajectories` random one-at-a-time paths through a grid
+    /// of `levels` values per parameter, which takes `trajectories * (parameters + 1)`
+    /// simulations. Four levels and ten to fifty trajectories are usual.
+    pub fn morris(
+        &self,
+        ranges: &[ParameterRange],
+        trajectories: usize,
+        levels: usize,
+        options: &Options,
+    ) -> Result<ElementaryEffects, Error> {
+        if trajectories == 0 || levels < 2 {
+            let message = "Morris needs a trajectory and at least 2 levels".to_owned();
+            return Err(Error::InvalidOptions(message));
+        }
+        let mut global = Global::new(self, ranges, options)?;
+        let p = ranges.len();
+        let mut random = options.seed.map_or_else(Random::from_entropy, Random::new);
+        let pick = |random: &mut Random, count: usize| {
+            ((random.uniform() * count as f64) as usize).min(count - 1)
+        };
+        let delta = levels as f64 / (2.0 * (levels - 1) as f64);
+        // The elementary effects of each parameter, one per trajectory.
+        let mut effects: Vec<Vec<Vec<Vec<f64>>>> = vec![Vec::with_capacity(trajectories); p];
+        for _ in 0..trajectories {
+            let mut x: Vec<f64> = (0..p)
+                .map(|_| pick(&mut random, levels) as f64 / (levels - 1) as f64)
+                .collect();
+            let mut order: Vec<usize> = (0..p).collect();
+            for i in (1..p).rev() {
+                order.swap(i, pick(&mut random, i + 1));
+            }
+            let mut before = global.run(&x)?;
+            for k in order {
+                let step = if x[k] + delta <= 1.0 + 1e-12 {
+                    delta
+                } else {
+                    -delta
+                };
+                x[k] = (x[k] + step).clamp(0.0, 1.0);
+                let after = global.run(&x)?;
+                let effect = after
+                    .iter()
+                    .zip(&before)
+                    .map(|(a, b)| a.iter().zip(b).map(|(a, b)| (a - b) / step).collect())
+                    .collect();
+                effects[k].push(effect);
+                before = after;
+            }
+        }
+
+        let r = trajectories as f64;
+        let [mean, mean_absolute, deviation] = global.indices(|t, i| {
+            effects
+                .iter()
+                .map(|runs| {
+                    let mean = runs.iter().map(|e| e[t][i]).sum::<f64>() / r;
+                    let absolute = runs.iter().map(|e| e[t][i].abs()).sum::<f64>() / r;
+                    let squares = runs.iter().map(|e| (e[t][i] - mean).powi(2)).sum::<f64>();
+                    [mean, absolute, (squares / (r - 1.0).max(1.0)).sqrt()]
+                })
+                .collect()
+        });
+        Ok(ElementaryEffects {
+            mean,
+            mean_absolute,
+            deviation,
+        })
+    }
+}
+
+/// The forward sensitivity equations: the state followed by the sensitivities to each
+/// parameter in turn.
+struct SensitivityRhs<'a> {
+    inner: SystemRhs<'a>,
+    /// The slot of each parameter.
+    slots: Vec<usize>,
+    /// For each parameter, the compiled `∂f_i/∂p`, or `None` to use finite differences.
+    derivatives: Vec<Vec<Option<Code>>>,
+    f: Vec<f64>,
+    jac: Vec<Vec<f64>>,
+}
+
+impl Rhs for SensitivityRhs<'_> {
+    fn eval(&mut self, t: f64, y: &[f64], dy: &mut [f64]) -> Result<(), Error> {
+        let n = self.f.len();
+        let state = &y[..n];
+        self.inner.eval(t, state, &mut self.f)?;
+        dy[..n].copy_from_slice(&self.f);
+        // `∂f/∂p` while the slots hold the current state, then `J s` on top.
+        let mut shifted = Vec::new();
+        let mut df = vec![0.0; n];
+        for (k, (&slot, codes)) in self.slots.iter().zip(&self.derivatives).enumerate() {
+            let out = &mut dy[n * (k + 1)..n * (k + 2)];
+            if codes.iter().all(Option::is_some) {
+                for (d, code) in out.iter_mut().zip(codes.iter().flatten()) {
+                    *d = code.eval(t, &self.inner.values)?;
+                }
+                continue;
+            }
+            shifted.clone_from(&self.inner.values);
+            let h = f64::EPSILON.sqrt() * shifted[slot].abs().max(1e-5);
+            shifted[slot] += h;
+            self.inner.system.derivatives(t, &mut shifted, &mut df)?;
+            for ((d, hi), f) in out.iter_mut().zip(&df).zip(&self.f) {
+                *d = (hi - f) / h;
+            }
+        }
+        self.inner.jacobian(t, state, &self.f, &mut self.jac)?;
+        for k in 0..self.slots.len() {
+            let s = &y[n * (k + 1)..n * (k + 2)];
+            let out = &mut dy[n * (k + 1)..n * (k + 2)];
+            for (d, row) in out.iter_mut().zip(&self.jac) {
+                *d += row.iter().zip(s).map(|(j, s)| j * s).sum::<f64>();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Simulations at points of the unit cube, mapped onto the parameter ranges.
+struct Global<'a> {
+    simulator: Simulator,
+    ranges: &'a [ParameterRange],
+    options: &'a Options,
+    /// The columns of the results, other than time.
+    columns: Vec<usize>,
+    labels: Vec<String>,
+    times: Vec<f64>,
+}
+
+impl<'a> Global<'a> {
+    fn new(
+        simulator: &Simulator,
+        ranges: &'a [ParameterRange],
+        options: &'a Options,
+    ) -> Result<Global<'a>, Error> {
+        options.check()?;
+        for range in ranges {
+            simulator.slot(&range.id)?;
+            if !(range.lower.is_finite() && range.upper.is_finite()) || range.upper < range.lower {
+                let message = format!("the range of `{}` is empty or not finite", range.id);
+                return Err(Error::InvalidOptions(message));
+            }
+        }
+        let selections = simulator.selections(options)?;
+        let columns: Vec<usize> = (0..selections.len())
+            .filter(|&j| selections[j] != "time")
+            .collect();
+        Ok(Global {
+            simulator: simulator.clone(),
+            ranges,
+            options,
+            labels: columns.iter().map(|&j| selections[j].clone()).collect(),
+            columns,
+            times: options.times(),
+        })
+    }
+
+    /// Simulate at a point of the unit cube, giving the outputs by time and column.
+    fn run(&mut self, x: &[f64]) -> Result<Vec<Vec<f64>>, Error> {
+        for (range, &u) in self.ranges.iter().zip(x) {
+            let value = range.lower + u * (range.upper - range.lower);
+            self.simulator.set(&range.id, value)?;
+        }
+        let course = self.simulator.simulate(self.options)?;
+        Ok(course
+            .rows
+            .iter()
+            .map(|row| self.columns.iter().map(|&j| row[j]).collect())
+            .collect())
+    }
+
+    /// Series of indices, from a function of the time and output index giving `N` values
+    /// per parameter, one for each series.
+    fn indices<const N: usize, F>(&self, values: F) -> [SensitivitySeries; N]
+    where
+        F: Fn(usize, usize) -> Vec<[f64; N]>,
+    {
+        let mut series: [SensitivitySeries; N] = std::array::from_fn(|_| SensitivitySeries {
+            times: self.times.clone(),
+            outputs: self.labels.clone(),
+            parameters: self.ranges.iter().map(|r| r.id.clone()).collect(),
+            values: Vec::with_capacity(self.times.len()),
+        });
+        for t in 0..self.times.len() {
+            series.iter_mut().for_each(|s| s.values.push(Vec::new()));
+            for i in 0..self.labels.len() {
+                let at = values(t, i);
+                for (n, s) in series.iter_mut().enumerate() {
+                    let row = at.iter().map(|v| v[n]).collect();
+                    s.values[t].push(row);
+                }
+            }
+        }
+        series
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    fn decay() -> Module {
+        let mut module = Fixture::new()
+            .species("S", 0.0)
+            .parameter("k", 0.5)
+            .parameter("unused", 1.0)
+            .irreversible("J0", &[(1.0, "S")], &[], "k*S")
+            .rate_rule("x", 10.0, "-k*x")
+            .build();
+        module.symbols[0].initial = Some("4*k".into());
+        module
+    }
+
+    #[test]
+    fn integrates_forward_sensitivities() {
+        let module = decay();
+        let options = Options {
+            end: 2.0,
+            points: 3,
+            relative_tolerance: 1e-9,
+            absolute_tolerance: 1e-12,
+            ..Options::default()
+        };
+        for &method in &[Method::Rk45, Method::Rosenbrock] {
+            let options = Options {
+                method,
+                ..options.clone()
+            };
+            let result = sensitivities(&module, &["k", "unused"], &options).unwrap();
+            // x = 10 exp(-k t), so ∂x/∂k = -10 t exp(-k t).
+            let dk = result.sensitivities.get("x", "k").unwrap();
+            for (t, d) in [0.0f64, 1.0, 2.0].iter().zip(&dk) {
+                let exact = -10.0 * t * (-0.5 * t).exp();
+                assert!((d - exact).abs() < 1e-5, "{:?}: {} vs {}", method, d, exact);
+            }
+            // S = 4k exp(-k t) depends on k through its initial value too.
+            let ds = result.sensitivities.get("S", "k").unwrap();
+            for (t, d) in [0.0f64, 1.0, 2.0].iter().zip(&ds) {
+                let exact = 4.0 * (-0.5 * t).exp() * (1.0 - 0.5 * t);
+                assert!((d - exact).abs() < 1e-5, "{:?}: {} vs {}", method, d, exact);
+            }
+            assert_eq!(result.sensitivities.get("x", "unused"), Some(vec![0.0; 3]));
+            assert_eq!(result.values.columns, ["time", "S", "x"]);
+        }
+        assert_eq!(
+            sensitivities(&module, &["S"], &Options::default()).err(),
+            Some(Error::InvalidOptions(
+                "`S` is not a constant parameter".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn ranks_parameters_globally() {
+        let module = decay();
+        let sim = Simulator::new(&module).unwrap();
+        let options = Options {
+            end: 2.0,
+            points: 2,
+            selections: vec!["time".into(), "x".into()],
+            seed: Some(7),
+            ..Options::default()
+        };
+        let ranges = [
+            ParameterRange::new("k", 0.1, 1.0),
+            ParameterRange::new("unused", 0.0, 1.0),
+        ];
+        let sobol = sim.sobol(&ranges, 300, &options).unwrap();
+        let first = sobol.first_order.get("x", "k").unwrap();
+        assert!(first[0].is_nan());
+        assert!((first[1] - 1.0).abs() < 0.15, "{}", first[1]);
+        assert_eq!(sobol.total.get("x", "unused").unwrap()[1], 0.0);
+
+        let morris = sim.morris(&ranges, 10, 4, &options).unwrap();
+        // x(2) = 10 exp(-2k) falls by about 5.2 over the range of k.
+        let mu = morris.mean.get("x", "k").unwrap()[1];
+        assert!(mu < -3.0 && mu > -8.0, "{}", mu);
+        assert_eq!(morris.mean_absolute.get("x", "unused").unwrap()[1], 0.0);
+    }
+}
@@ -0,0 +1,138 @@
+//! The interface between the simulation driver and the ODE integrators.
+//!
+//! Integrators know nothing about models: they advance a state vector `y` of a system
+//! `dy/dt = f(t, y)` one adaptive step at a time, and the driver interpolates between steps to
+//! produce output at the requested times.
+
+use super::Error;
+
+/// The right-hand side of an ODE system.
+pub(crate) trait Rhs {
+    /// Compute `dy = f(t, y)`.
+    fn eval(&mut self, t: f64, y: &[f64], dy: &mut [f64]) -> Result<(), Error>;
+
+    /// Compute the Jacobian `jac[i][j] = df_i/dy_j` at `(t, y)`, where `f = f(t, y)`.
+    fn jacobian(
+        &mut self,
+        t: f64,
+        y: &[f64],
+        f: &[f64],
+        jac: &mut [Vec<f64>],
+    ) -> Result<(), Error> {
+        finite_differences(self, t, y, f, jac)
+    }
+}
+
+/// The Jacobian of `rhs` by forward differences.
+pub(crate) fn finite_differences<R: Rhs + ?Sized>(
+    rhs: &mut R,
+    t: f64,
+    y: &[f64],
+    f: &[f64],
+    jac: &mut [Vec<f64>],
+) -> Result<(), Error> {
+    let mut shifted = y.to_vec();
+    let mut df = vec![0.0; y.len()];
+    for j in 0..y.len() {
+        let delta = f64::EPSILON.sqrt() * y[j].abs().max(1e-5);
+        shifted[j] = y[j] + delta;
+        rhs.eval(t, &shifted, &mut df)?;
+        shifted[j] = y[j];
+        for i in 0..y.len() {
+            jac[i][j] = (df[i] - f[i]) / delta;
+        }
+    }
+    Ok(())
+}
+
+/// An accepted step: the new time, state, and derivative.
+#[derive(Debug, Clone)]
+pub(crate) struct Step {
+    pub t: f64,
+    pub y: Vec<f64>,
+    pub f: Vec<f64>,
+}
+
+/// An adaptive one-step (or multistep) integrator.
+pub(crate) trait Stepper {
+    /// Advance from `t`, where the state is `y` and its derivative `f`, by one accepted step
+    /// that does not pass `t_stop`.
+    fn step(
+        &mut self,
+        rhs: &mut dyn Rhs,
+        t: f64,
+        y: &[f64],
+        f: &[f64],
+        t_stop: f64,
+    ) -> Result<Step, Error>;
+
+    /// Forget the step size learned from previous steps, e.g., after the state jumped.
+    fn reset(&mut self);
+}
+
+/// The weighted root-mean-square norm used for error control.
+pub(crate) fn norm(v: &[f64], scale: &[f64]) -> f64 {
+    if v.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = v.iter().zip(scale).map(|(x, s)| (x / s).powi(2)).sum();
+    (sum / v.len() as f64).sqrt()
+}
+
+/// A starting step size for a method of the given order (Hairer, Nørsett & Wanner, II.4).
+pub(crate) fn initial_step(
+    rhs: &mut dyn Rhs,
+    t: f64,
+    y: &[f64],
+    f: &[f64],
+    order: i32,
+    rtol: f64,
+    atol: f64,
+) -> Result<f64, Error> {
+    let scale: Vec<f64> = y.iter().map(|y| atol + rtol * y.abs()).collect();
+    let (d0, d1) = (norm(y, &scale), norm(f, &scale));
+    let h0 = if d0 < 1e-5 || d1 < 1e-5 {
+        1e-6
+    } else {
+        0.01 * d0 / d1
+    };
+    let y1: Vec<f64> = y.iter().zip(f).map(|(y, f)| y + h0 * f).collect();
+    let mut f1 = vec![0.0; y.len()];
+    rhs.eval(t + h0, &y1, &mut f1)?;
+    let df: Vec<f64> = f1.iter().zip(f).map(|(a, b)| a - b).collect();
+    let d2 = norm(&df, &scale) / h0;
+    let h1 = if d1.max(d2) <= 1e-15 {
+        (h0 * 1e-3).max(1e-6)
+    } else {
+        (0.01 / d1.max(d2)).powf(1.0 / f64::from(order + 1))
+    };
+    Ok((100.0 * h0).min(h1))
+}
+
+/// The cubic Hermite interpolant between two steps, evaluated at `t`.
+pub(crate) fn interpolate(t0: f64, y0: &[f64], f0: &[f64], step: &Step, t: f64, out: &mut [f64]) {
+    hermite((t0, y0, f0), (step.t, &step.y, &step.f), t, out);
+}
+
+/// The cubic Hermite interpolant of two points `(t, y, dy/dt)`, evaluated at `t`.
+pub(crate) fn hermite(
+    (t0, y0, f0): (f64, &[f64], &[f64]),
+    (t1, y1, f1): (f64, &[f64], &[f64]),
+    t: f64,
+    out: &mut [f64],
+) {
+    let h = t1 - t0;
+    if h == 0.0 {
+        out.copy_from_slice(y1);
+        return;
+    }
+    let s = (t - t0) / h;
+    let (s2, s3) = (s * s, s * s * s);
+    let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+    let h10 = s3 - 2.0 * s2 + s;
+    let h01 = -2.0 * s3 + 3.0 * s2;
+    let h11 = s3 - s2;
+    for i in 0..out.len() {
+        out[i] = h00 * y0[i] + h10 * h * f0[i] + h01 * y1[i] + h11 * h * f1[i];
+    }
+}
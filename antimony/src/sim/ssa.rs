@@ -0,0 +1,262 @@
+//! Exact stochastic simulation (Gillespie's algorithm).
+//!
+//! Both methods sample the same process: the next reaction to fire and its time, given the
+//! propensities of the channels of a `Network`. The direct method draws both anew after every
+//! firing, at a cost linear in the number of channels. The next reaction method of Gibson and
+//! Bruck keeps a putative firing time per channel in an indexed priority queue and, when a
+//! firing changes some propensities, rescales only the times of the affected channels.
+//!
+//! Propensities are taken to be constant between firings. Events are located in the gaps
+//! between firings, where only time changes, and rescheduling after an event is exact because
+//! waiting times are memoryless.
+
+use super::events::Events;
+use super::network::Network;
+use super::random::Random;
+use super::system::System;
+use super::{Error, Options, Recorder};
+
+/// Chooses the next channel to fire and when.
+pub(crate) trait Selector {
+    /// The time of the next firing and its channel, given the propensities `a` at time `t`.
+    /// The time is infinite if nothing can fire.
+    fn next(&mut self, t: f64, a: &[f64], random: &mut Random) -> (f64, usize);
+
+    /// Account for propensities that changed at time `t`: `changed` holds the channels
+    /// affected, with their previous propensities, including `fired` if a channel just fired.
+    fn update(
+        &mut self,
+        t: f64,
+        fired: Option<usize>,
+        changed: &[(usize, f64)],
+        a: &[f64],
+        random: &mut Random,
+    );
+}
+
+/// Gillespie's direct method.
+pub(crate) struct Direct;
+
+impl Selector for Direct {
+    fn next(&mut self, t: f64, a: &[f64], random: &mut Random) -> (f64, usize) {
+        let total: f64 = a.iter().sum();
+        if total <= 0.0 {
+            return (f64::INFINITY, 0);
+        }
+        let time = t + random.exponential() / total;
+        let target = random.uniform() * total;
+        let mut sum = 0.0;
+        let mut chosen = 0;
+        for (j, &a) in a.iter().enumerate() {
+            if a > 0.0 {
+                chosen = j;
+                sum += a;
+                if sum > target {
+                    break;
+                }
+            }
+        }
+        (time, chosen)
+    }
+
+    fn update(&mut self, _: f64, _: Option<usize>, _: &[(usize, f64)], _: &[f64], _: &mut Random) {}
+}
+
+/// The next reaction method of Gibson and Bruck.
+pub(crate) struct NextReaction {
+    queue: Queue,
+}
+
+impl NextReaction {
+    pub fn new(channels: usize) -> NextReaction {
+        NextReaction {
+            queue: Queue::new(channels),
+        }
+    }
+}
+
+impl Selector for NextReaction {
+    fn next(&mut self, _: f64, _: &[f64], _: &mut Random) -> (f64, usize) {
+        self.queue.first()
+    }
+
+    fn update(
+        &mut self,
+        t: f64,
+        fired: Option<usize>,
+        changed: &[(usize, f64)],
+        a: &[f64],
+        random: &mut Random,
+    ) {
+        for &(k, before) in changed {
+            let time = self.queue.times[k];
+            let time = if a[k] <= 0.0 {
+                f64::INFINITY
+            } else if fired == Some(k) || before <= 0.0 || time == f64::INFINITY {
+                t + random.exponential() / a[k]
+            } else {
+                t + before / a[k] * (time - t)
+            };
+            self.queue.set(k, time);
+        }
+    }
+}
+
+/// A binary min-heap of channel firing times that can change the time of any channel.
+struct Queue {
+    times: Vec<f64>,
+    heap: Vec<usize>,
+    /// The position of each channel in `heap`.
+    position: Vec<usize>,
+}
+
+impl Queue {
+    fn new(channels: usize) -> Queue {
+        Queue {
+            times: vec![f64::INFINITY; channels],
+            heap: (0..channels).collect(),
+            position: (0..channels).collect(),
+        }
+    }
+
+    fn first(&self) -> (f64, usize) {
+        match self.heap.first() {
+            Some(&k) => (self.times[k], k),
+            None => (f64::INFINITY, 0),
+        }
+    }
+
+    fn set(&mut self, k: usize, time: f64) {
+        self.times[k] = time;
+        let mut i = self.position[k];
+        while i > 0 && self.key(i) < self.key((i - 1) / 2) {
+            self.swap(i, (i - 1) / 2);
+            i = (i - 1) / 2;
+        }
+        loop {
+            let smallest = [2 * i + 1, 2 * i + 2]
+                .iter()
+                .copied()
+                .filter(|&c| c < self.heap.len())
+                .fold(i, |s, c| if self.key(c) < self.key(s) { c } else { s });
+            if smallest == i {
+                break;
+            }
+            self.swap(i, smallest);
+            i = smallest;
+        }
+    }
+
+    fn key(&self, i: usize) -> f64 {
+        self.times[self.heap[i]]
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.heap.swap(i, j);
+        self.position[self.heap[i]] = i;
+        self.position[self.heap[j]] = j;
+    }
+}
+
+/// Simulate from the state in `values` at `options.start` until every output is recorded.
+pub(crate) fn simulate(
+    system: &System,
+    network: &Network,
+    selector: &mut dyn Selector,
+    options: &Options,
+    values: &mut [f64],
+    random: &mut Random,
+    out: &mut Recorder,
+) -> Result<(), Error> {
+    let mut t = options.start;
+    let mut events = Events::new(system);
+    events.process(system, t, values)?;
+    let mut counts = network.counts(system, t, values)?;
+    let mut a = vec![0.0; network.channels.len()];
+    let mut changed = Vec::with_capacity(a.len());
+    refresh(system, network, t, &counts, values, &mut a, &mut changed)?;
+    selector.update(t, None, &changed, &a, random);
+
+    let mut probe = values.to_vec();
+    let mut steps = 0;
+    while out.next().is_some() {
+        let (fire_at, j) = selector.next(t, &a, random);
+        let mut until = fire_at.min(options.end);
+        if let Some(time) = events.next_time() {
+            until = until.min(time);
+        }
+        // Only time changes until then; stop where the first trigger changes.
+        until = events.first_change(system, t, until, values, &mut probe)?;
+
+        while let Some(time) = out.next().filter(|&time| time <= until) {
+            system.update(time, values)?;
+            out.record(values);
+        }
+        t = until;
+        if t == fire_at {
+            if steps == options.max_steps {
+                return Err(Error::TooManySteps { time: t });
+            }
+            steps += 1;
+            network.fire(system, j, t, &mut counts, values)?;
+            changed.clear();
+            let channel = &network.channels[j];
+            if !channel.dependents.contains(&j) {
+                changed.push((j, a[j]));
+            }
+            for &k in &channel.dependents {
+                changed.push((k, a[k]));
+                a[k] = network.propensity(system, k, t, &counts, values)?;
+            }
+            selector.update(t, Some(j), &changed, &a, random);
+        }
+        if !system.events.is_empty() {
+            system.update(t, values)?;
+            if events.process(system, t, values)? {
+                counts = network.counts(system, t, values)?;
+                refresh(system, network, t, &counts, values, &mut a, &mut changed)?;
+                selector.update(t, None, &changed, &a, random);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Recompute every propensity, noting the previous ones in `changed`.
+fn refresh(
+    system: &System,
+    network: &Network,
+    t: f64,
+    counts: &[f64],
+    values: &[f64],
+    a: &mut [f64],
+    changed: &mut Vec<(usize, f64)>,
+) -> Result<(), Error> {
+    changed.clear();
+    for (k, a) in a.iter_mut().enumerate() {
+        changed.push((k, *a));
+        *a = network.propensity(system, k, t, counts, values)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_orders_changing_times() {
+        let mut queue = Queue::new(4);
+        for (k, &time) in [3.0, 1.0, 4.0, 2.0].iter().enumerate() {
+            queue.set(k, time);
+        }
+        assert_eq!(queue.first(), (1.0, 1));
+        queue.set(1, 5.0);
+        assert_eq!(queue.first(), (2.0, 3));
+        queue.set(2, 0.5);
+        assert_eq!(queue.first(), (0.5, 2));
+        queue.set(2, f64::INFINITY);
+        queue.set(3, f64::INFINITY);
+        assert_eq!(queue.first(), (3.0, 0));
+    }
+}
@@ -0,0 +1,486 @@
+//! Steady states: the values at which every state variable stops changing.
+//!
+//! Conserved moieties (sums of species amounts that no reaction changes, such as the total of
+//! an enzyme) make the Jacobian of the full system singular and leave a continuum of steady
+//! states. The solver therefore works on the reduced system: each conservation law determines
+//! one dependent species from the others, keeping the totals of the starting point, and Newton's
+//! method solves for the independent species and the rate-rule variables alone.
+//!
+//! Newton steps are damped by backtracking until the norm of the rates decreases and no
+//! concentration becomes negative. When Newton's method fails to converge from the initial
+//! values, the model is integrated over ever longer times, and Newton's method retried from
+//! where the integration ends. The stability of the steady state found is that of the reduced
+//! system, given by the eigenvalues of its Jacobian.
+
+use super::solver::Rhs;
+use super::{Error, Method, Options, Recorder, Simulator, SystemRhs};
+use crate::linalg::{eigenvalues, Lu};
+use crate::model::Rule;
+use crate::structural::reduce;
+use crate::Module;
+
+/// How to look for a steady state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SteadyStateOptions {
+    /// The largest rate of change accepted at a steady state, relative to the value of the
+    /// variable if that is larger than one.
+    pub tolerance: f64,
+    /// The number of Newton iterations after which to give up on one starting point.
+    pub max_iterations: usize,
+    /// The longest time to integrate for when Newton's method fails; the model is integrated
+    /// for 10, 100, and so on up to this time, with Newton's method tried after each.
+    pub max_time: f64,
+    /// Whether steady states may have negative concentrations.
+    pub allow_negative: bool,
+    /// Whether to use the symbolic Jacobian of the model; see `Options::analytic_jacobian`.
+    pub analytic_jacobian: bool,
+}
+
+impl Default for SteadyStateOptions {
+    fn default() -> SteadyStateOptions {
+        SteadyStateOptions {
+            tolerance: 1e-9,
+            max_iterations: 100,
+            max_time: 1e6,
+            allow_negative: false,
+            analytic_jacobian: true,
+        }
+    }
+}
+
+/// A steady state and its stability.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SteadyState {
+    /// The state variables (see `Simulator::state_variables`).
+    pub variables: Vec<String>,
+    /// The steady value of each state variable.
+    pub values: Vec<f64>,
+    /// The largest relative rate of change remaining, at most `SteadyStateOptions::tolerance`.
+    pub residual: f64,
+    /// The eigenvalues of the Jacobian of the reduced system at the steady state.
+    pub eigenvalues: Vec<Eigenvalue>,
+    /// How long the model was integrated before Newton's method converged, zero if it
+    /// converged from the initial values.
+    pub integrated: f64,
+}
+
+impl SteadyState {
+    /// The steady value of a state variable, if it is one.
+    pub fn value(&self, id: &str) -> Option<f64> {
+        let i = self.variables.iter().position(|v| v == id)?;
+        Some(self.values[i])
+    }
+
+    /// Whether small perturbations decay: every eigenvalue has a negative real part.
+    pub fn is_stable(&self) -> bool {
+        self.eigenvalues.iter().all(|e| e.re < 0.0)
+    }
+}
+
+/// A complex eigenvalue.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Eigenvalue {
+    /// The real part.
+    pub re: f64,
+    /// The imaginary part.
+    pub im: f64,
+}
+
+/// Find a steady state of a module, starting from its initial values; see
+/// `Simulator::steady_state` to change them.
+pub fn steady_state(module: &Module, options: &SteadyStateOptions) -> Result<SteadyState, Error> {
+    Simulator::new(module)?.steady_state(options)
+}
+
+impl Simulator {
+    /// Find a steady state, starting from the initial values of the model and of `set`.
+    pub fn steady_state(&self, options: &SteadyStateOptions) -> Result<SteadyState, Error> {
+        let invalid = |message: &str| Err(Error::InvalidOptions(message.to_owned()));
+        if options.tolerance.is_nan() || options.tolerance <= 0.0 {
+            return invalid("`tolerance` must be positive");
+        }
+        if options.max_time.is_nan() || options.max_time <= 0.0 {
+            return invalid("`max_time` must be positive");
+        }
+        if !self.system.delays.is_empty() {
+            return invalid("steady states of models with `delay` are not supported");
+        }
+        let system = &self.system;
+        let jacobian = if options.analytic_jacobian {
+            self.jacobian()
+        } else {
+            None
+        };
+        let mut values = system.initial_values(0.0, &self.overrides)?;
+        let mut reduced = Reduced::new(
+            &self.module,
+            SystemRhs {
+                system,
+                values: values.clone(),
+                jacobian,
+                history: None,
+            },
+        );
+        let mut best = match reduced.newton(&values, options) {
+            Ok(found) => return reduced.finish(found, 0.0),
+            Err(residual) => residual,
+        };
+
+        let mut integrated = 0.0;
+        let mut duration = 10.0f64;
+        loop {
+            let end = duration.min(options.max_time);
+            let integration = Options {
+                end,
+                points: 2,
+                method: Method::Rosenbrock,
+                analytic_jacobian: options.analytic_jacobian,
+                ..Options::default()
+            };
+            let mut out = Recorder {
+                select: (0..values.len()).map(Some).collect(),
+                times: vec![0.0, end],
+                rows: Vec::with_capacity(2),
+            };
+            out.record(&values);
+            self.integrate(&integration, values.clone(), &mut out)?;
+            values = out.rows.pop().expect("the end is recorded");
+            integrated += end;
+            match reduced.newton(&values, options) {
+                Ok(found) => return reduced.finish(found, integrated),
+                Err(residual) => best = best.min(residual),
+            }
+            if end == options.max_time {
+                return Err(Error::NoSteadyState { residual: best });
+            }
+            duration *= 10.0;
+        }
+    }
+}
+
+/// The state variables of a system with the dependent species of its conservation laws
+/// removed.
+pub(super) struct Reduced<'a> {
+    pub full: SystemRhs<'a>,
+    /// The number of species among the state variables, which come first.
+    species: usize,
+    /// The state variables solved for: the independent species and the rate-rule variables.
+    pub independent: Vec<usize>,
+    /// The conservation laws, one per dependent species.
+    moieties: Vec<Moiety>,
+    /// The full state, derivative, and Jacobian.
+    pub y: Vec<f64>,
+    f: Vec<f64>,
+    jac: Vec<Vec<f64>>,
+}
+
+/// A conservation law solved for its dependent species: `y[species] = total + Σ c y[j]` over
+/// the `terms` `(j, c)`, in concentrations.
+struct Moiety {
+    species: usize,
+    total: f64,
+    terms: Vec<(usize, f64)>,
+    /// The conserved amount is `Σ w y[j]` over the `weights` `(j, w)`.
+    weights: Vec<(usize, f64)>,
+    /// The size of the compartment of the dependent species.
+    volume: f64,
+}
+
+/// A steady state of the reduced system: its full state, the reduced state, and the residual.
+struct Found {
+    y: Vec<f64>,
+    x: Vec<f64>,
+    residual: f64,
+}
+
+impl<'a> Reduced<'a> {
+    pub fn new(module: &Module, full: SystemRhs<'a>) -> Reduced<'a> {
+        let system = full.system;
+        let n = system.n_state;
+        // The species come first among the state variables, followed by rate rules.
+        let species = system.names[..n]
+            .iter()
+            .take_while(|id| {
+                let rule = module.symbol(id).and_then(|s| s.rule.as_ref());
+                !matches!(rule, Some(Rule::Rate(_)))
+            })
+            .count();
+        let stoichiometry = &module.stoichiometry;
+        let rows: Vec<Vec<f64>> = system.names[..species]
+            .iter()
+            .map(|id| {
+                let row = stoichiometry.species.iter().position(|s| s == id);
+                row.map_or_else(
+                    || vec![0.0; stoichiometry.reactions.len()],
+                    |row| stoichiometry.matrix[row].clone(),
+                )
+            })
+            .collect();
+        // Conservation laws hold for amounts, with compartments taken to be constant.
+        let volumes: Vec<f64> = system.names[..species]
+            .iter()
+            .map(|id| {
+                let compartment = module.symbol(id).and_then(|s| s.compartment.as_ref());
+                compartment.map_or(1.0, |c| full.values[system.index[c]])
+            })
+            .collect();
+
+        let reduction = reduce(&rows);
+        let laws: Vec<Vec<(usize, f64)>> = reduction
+            .dependent
+            .iter()
+            .zip(&reduction.link)
+            .map(|(&d, link)| {
+                let mut law = vec![(d, 1.0)];
+                for (&p, c) in reduction.independent.iter().zip(link) {
+                    if !c.is_zero() {
+                        law.push((p, -c.to_f64()));
+                    }
+                }
+                law
+            })
+            .collect();
+        let dependent = reduction.dependent;
+        let moieties = dependent
+            .iter()
+            .zip(&laws)
+            .map(|(&d, law)| Moiety {
+                species: d,
+                total: 0.0,
+                terms: law
+                    .iter()
+                    .filter(|&&(j, _)| j != d)
+                    .map(|&(j, c)| (j, -c * volumes[j] / volumes[d]))
+                    .collect(),
+                weights: law.iter().map(|&(j, c)| (j, c * volumes[j])).collect(),
+                volume: volumes[d],
+            })
+            .collect();
+        let independent = (0..n).filter(|i| !dependent.contains(i)).collect();
+        Reduced {
+            full,
+            species,
+            independent,
+            moieties,
+            y: vec![0.0; n],
+            f: vec![0.0; n],
+            jac: vec![vec![0.0; n]; n],
+        }
+    }
+
+    /// Set the full state from the reduced state `x`.
+    pub fn expand(&mut self, x: &[f64]) {
+        for (&i, &x) in self.independent.iter().zip(x) {
+            self.y[i] = x;
+        }
+        for m in &self.moieties {
+            let sum: f64 = m.terms.iter().map(|&(j, c)| c * self.y[j]).sum();
+            self.y[m.species] = m.total + sum;
+        }
+    }
+
+    /// The largest rate of change, relative to the value of the variable if that is larger
+    /// than one.
+    pub fn residual(&self, x: &[f64], dx: &[f64]) -> f64 {
+        x.iter()
+            .zip(dx)
+            .fold(0.0, |r, (x, dx)| r.max(dx.abs() / x.abs().max(1.0)))
+    }
+
+    /// Take the values of every symbol, and the conserved totals, from `values`.
+    pub fn conserve(&mut self, values: &[f64]) {
+        self.full.values.copy_from_slice(values);
+        for m in &mut self.moieties {
+            let amount: f64 = m.weights.iter().map(|&(j, w)| w * values[j]).sum();
+            m.total = amount / m.volume;
+        }
+    }
+
+    /// Solve for a steady state by damped Newton iteration from the state in `values`, with
+    /// the conserved totals of that state. Returns the smallest residual reached on failure.
+    fn newton(&mut self, values: &[f64], options: &SteadyStateOptions) -> Result<Found, f64> {
+        self.conserve(values);
+        let mut x: Vec<f64> = self.independent.iter().map(|&i| values[i]).collect();
+        let mut dx = vec![0.0; x.len()];
+        if self.eval(0.0, &x, &mut dx).is_err() {
+            return Err(f64::INFINITY);
+        }
+        let mut residual = self.residual(&x, &dx);
+        let mut best = residual;
+        let mut step = vec![0.0; x.len()];
+        let mut trial = x.clone();
+        let mut trial_dx = dx.clone();
+        for iteration in 0..=options.max_iterations {
+            if residual <= options.tolerance {
+                self.expand(&x);
+                return Ok(Found {
+                    y: self.y.clone(),
+                    x,
+                    residual,
+                });
+            }
+            if iteration == options.max_iterations {
+                break;
+            }
+            let mut jac = vec![vec![0.0; x.len()]; x.len()];
+            if self.jacobian(0.0, &x, &dx, &mut jac).is_err() {
+                return Err(best);
+            }
+            let lu = match Lu::new(&jac) {
+                Some(lu) => lu,
+                None => return Err(best),
+            };
+            step.iter_mut().zip(&dx).for_each(|(s, dx)| *s = -dx);
+            lu.solve(&mut step);
+
+            // Halve the step until the rates decrease and the concentrations stay valid.
+            let norm = |dx: &[f64]| dx.iter().map(|d| d * d).sum::<f64>().sqrt();
+            let before = norm(&dx);
+            let mut lambda = 1.0;
+            loop {
+                if lambda < 1e-10 {
+                    return Err(best);
+                }
+                for ((t, x), s) in trial.iter_mut().zip(&x).zip(&step) {
+                    *t = x + lambda * s;
+                }
+                self.expand(&trial);
+                let valid = options.allow_negative
+                    || self.y[..self.species]
+                        .iter()
+                        .all(|&c| c >= -options.tolerance);
+                if valid
+                    && self.eval(0.0, &trial, &mut trial_dx).is_ok()
+                    && norm(&trial_dx) <= (1.0 - 1e-4 * lambda) * before
+                {
+                    break;
+                }
+                lambda /= 2.0;
+            }
+            x.copy_from_slice(&trial);
+            dx.copy_from_slice(&trial_dx);
+            residual = self.residual(&x, &dx);
+            best = best.min(residual);
+        }
+        Err(best)
+    }
+
+    /// Describe a steady state, with the eigenvalues of the reduced Jacobian there.
+    fn finish(&mut self, found: Found, integrated: f64) -> Result<SteadyState, Error> {
+        let m = found.x.len();
+        let mut dx = vec![0.0; m];
+        self.eval(0.0, &found.x, &mut dx)?;
+        let mut jac = vec![vec![0.0; m]; m];
+        self.jacobian(0.0, &found.x, &dx, &mut jac)?;
+        let eigenvalues = eigenvalues(&jac)
+            .ok_or(Error::NoSteadyState {
+                residual: found.residual,
+            })?
+            .into_iter()
+            .map(|(re, im)| Eigenvalue { re, im })
+            .collect();
+        let system = self.full.system;
+        Ok(SteadyState {
+            variables: system.names[..system.n_state].to_vec(),
+            values: found.y,
+            residual: found.residual,
+            eigenvalues,
+            integrated,
+        })
+    }
+}
+
+impl Rhs for Reduced<'_> {
+    fn eval(&mut self, t: f64, x: &[f64], dx: &mut [f64]) -> Result<(), Error> {
+        self.expand(x);
+        self.full.eval(t, &self.y, &mut self.f)?;
+        for (d, &i) in dx.iter_mut().zip(&self.independent) {
+            *d = self.f[i];
+        }
+        Ok(())
+    }
+
+    /// The Jacobian of the full system, by the chain rule through the conservation laws.
+    fn jacobian(
+        &mut self,
+        t: f64,
+        x: &[f64],
+        _: &[f64],
+        jac: &mut [Vec<f64>],
+    ) -> Result<(), Error> {
+        self.expand(x);
+        self.full.eval(t, &self.y, &mut self.f)?;
+        self.full.jacobian(t, &self.y, &self.f, &mut self.jac)?;
+        let full = &self.jac;
+        for (row, &i) in jac.iter_mut().zip(&self.independent) {
+            for (entry, &j) in row.iter_mut().zip(&self.independent) {
+                *entry = full[i][j];
+            }
+            for m in &self.moieties {
+                for &(j, c) in &m.terms {
+                    let k = self.independent.iter().position(|&k| k == j);
+                    if let Some(k) = k {
+                        row[k] += full[i][m.species] * c;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn solves_the_reduced_system() {
+        let module = Fixture::new()
+            .compartment("cell", 2.0)
+            .species("A", 1.0)
+            .within("A", "cell")
+            .species("B", 0.0)
+            .parameter("k1", 1.0)
+            .parameter("k2", 2.0)
+            .reaction("J", &[(1.0, "A")], &[(1.0, "B")], "cell*k1*A - k2*B")
+            .build();
+        let found = steady_state(&module, &SteadyStateOptions::default()).unwrap();
+        // 2 A + B is conserved, and the reduced system is dA/dt = 2 - 3 A.
+        assert!((found.value("A").unwrap() - 2.0 / 3.0).abs() < 1e-9);
+        assert!((found.value("B").unwrap() - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(found.integrated, 0.0);
+        assert_eq!(found.eigenvalues.len(), 1);
+        assert!((found.eigenvalues[0].re + 3.0).abs() < 1e-6);
+        assert!(found.is_stable());
+
+        let module = Fixture::new().rate_rule("x", 1.5, "x - 1").build();
+        let found = steady_state(&module, &SteadyStateOptions::default()).unwrap();
+        assert!((found.value("x").unwrap() - 1.0).abs() < 1e-9);
+        assert!(!found.is_stable());
+    }
+
+    #[test]
+    fn integrates_where_newton_fails() {
+        // The Jacobian is singular at the start, where A = 0.
+        let module = Fixture::new()
+            .species("A", 0.0)
+            .irreversible("J0", &[], &[(1.0, "A")], "1")
+            .irreversible("J1", &[(1.0, "A")], &[], "A^2")
+            .build();
+        let found = steady_state(&module, &SteadyStateOptions::default()).unwrap();
+        assert!((found.value("A").unwrap() - 1.0).abs() < 1e-9);
+        assert!(found.integrated > 0.0);
+        assert!(found.is_stable());
+
+        let module = Fixture::new().rate_rule("x", 0.0, "1").build();
+        let options = SteadyStateOptions {
+            max_time: 100.0,
+            ..SteadyStateOptions::default()
+        };
+        assert!(matches!(
+            steady_state(&module, &options),
+            Err(Error::NoSteadyState { .. })
+        ));
+    }
+}
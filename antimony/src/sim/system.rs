@@ -0,0 +1,426 @@
+//! Modules compiled for fast repeated evaluation.
+//!
+//! Every symbol of the module gets a slot in a flat vector of values. The state variables (see
+//! `Module::rate_equations`) come first, so that the integrators can work on a prefix of that
+//! vector, followed by every other symbol and every reaction. Formulas are compiled into `Code`,
+//! in which symbols are slot indices and user-defined functions have been inlined.
+
+use std::collections::HashMap;
+
+use super::Error;
+use crate::math::eval::{binary, builtin, truth};
+use crate::math::{BinaryOp, Builtin, EvalError, Expr, Function, UnaryOp};
+use crate::model::Rule;
+use crate::Module;
+
+/// A compiled formula.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Code {
+    Number(f64),
+    Time,
+    Slot(usize),
+    Unary(UnaryOp, Box<Code>),
+    Binary(BinaryOp, Box<Code>, Box<Code>),
+    Call(Builtin, Vec<Code>),
+    Piecewise(Vec<(Code, Code)>, Box<Code>),
+}
+
+impl Code {
+    /// Compile an expression whose user-defined functions have been inlined.
+    pub fn compile(e: &Expr, index: &HashMap<String, usize>) -> Result<Code, Error> {
+        let compile = |e: &Expr| Code::compile(e, index);
+        Ok(match e {
+            Expr::Number(n) => Code::Number(*n),
+            Expr::Boolean(b) => Code::Number(truth(*b)),
+            Expr::Constant(c) => Code::Number(c.value()),
+            Expr::Time => Code::Time,
+            Expr::Symbol(id) => match index.get(id) {
+                Some(&slot) => Code::Slot(slot),
+                None => return Err(Error::Eval(EvalError::UndefinedSymbol(id.clone()))),
+            },
+            Expr::Unary(op, a) => Code::Unary(*op, Box::new(compile(a)?)),
+            Expr::Binary(op, a, b) => {
+                Code::Binary(*op, Box::new(compile(a)?), Box::new(compile(b)?))
+            }
+            Expr::Call(Function::Builtin(b), args) if !b.accepts(args.len()) => {
+                return Err(Error::Eval(EvalError::Arity {
+                    function: b.name().to_owned(),
+                    found: args.len(),
+                }))
+            }
+            Expr::Call(Function::Builtin(b), args) => {
+                Code::Call(*b, args.iter().map(compile).collect::<Result<_, _>>()?)
+            }
+            Expr::Call(Function::User(id), _) => {
+                return Err(Error::Eval(EvalError::UndefinedFunction(id.clone())))
+            }
+            Expr::Piecewise(pieces, otherwise) => Code::Piecewise(
+                pieces
+                    .iter()
+                    .map(|(value, condition)| Ok((compile(value)?, compile(condition)?)))
+                    .collect::<Result<_, Error>>()?,
+                Box::new(compile(otherwise)?),
+            ),
+        })
+    }
+
+    /// Evaluate at time `t` with the given slot values.
+    pub fn eval(&self, t: f64, v: &[f64]) -> Result<f64, EvalError> {
+        Ok(match self {
+            Code::Number(n) => *n,
+            Code::Time => t,
+            Code::Slot(i) => v[*i],
+            Code::Unary(UnaryOp::Neg, a) => -a.eval(t, v)?,
+            Code::Unary(UnaryOp::Not, a) => truth(a.eval(t, v)? == 0.0),
+            Code::Binary(BinaryOp::And, a, b) => {
+                truth(a.eval(t, v)? != 0.0 && b.eval(t, v)? != 0.0)
+            }
+            Code::Binary(BinaryOp::Or, a, b) => truth(a.eval(t, v)? != 0.0 || b.eval(t, v)? != 0.0),
+            Code::Binary(op, a, b) => binary(*op, a.eval(t, v)?, b.eval(t, v)?)?,
+            // Delays left in compiled code are those of initial values, where the history is
+            // constant; see `System::delays` for the others.
+            Code::Call(Builtin::Delay, args) => args[0].eval(t, v)?,
+            Code::Call(b, args) => match args.len() {
+                1 => builtin(*b, &[args[0].eval(t, v)?])?,
+                2 => builtin(*b, &[args[0].eval(t, v)?, args[1].eval(t, v)?])?,
+                _ => {
+                    let args = args
+                        .iter()
+                        .map(|a| a.eval(t, v))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    builtin(*b, &args)?
+                }
+            },
+            Code::Piecewise(pieces, otherwise) => {
+                for (value, condition) in pieces {
+                    if condition.eval(t, v)? != 0.0 {
+                        return value.eval(t, v);
+                    }
+                }
+                otherwise.eval(t, v)?
+            }
+        })
+    }
+
+    /// Move every call of `delay` whose lag is not zero out of the code, inner calls first, and
+    /// read its value from a new slot instead.
+    fn extract_delays(&mut self, names: &mut Vec<String>, delays: &mut Vec<DelayCode>) {
+        match self {
+            Code::Call(Builtin::Delay, args) if args[1] != Code::Number(0.0) => {
+                for a in args.iter_mut() {
+                    a.extract_delays(names, delays);
+                }
+                let slot = names.len();
+                names.push(format!("delay #{}", delays.len() + 1));
+                let lag = args.pop().expect("delay has two arguments");
+                let value = args.pop().expect("delay has two arguments");
+                delays.push(DelayCode { slot, value, lag });
+                *self = Code::Slot(slot);
+            }
+            Code::Unary(_, a) => a.extract_delays(names, delays),
+            Code::Binary(_, a, b) => {
+                a.extract_delays(names, delays);
+                b.extract_delays(names, delays);
+            }
+            Code::Call(_, args) => args
+                .iter_mut()
+                .for_each(|a| a.extract_delays(names, delays)),
+            Code::Piecewise(pieces, otherwise) => {
+                for (value, condition) in pieces {
+                    value.extract_delays(names, delays);
+                    condition.extract_delays(names, delays);
+                }
+                otherwise.extract_delays(names, delays);
+            }
+            Code::Number(_) | Code::Time | Code::Slot(_) => {}
+        }
+    }
+
+    /// Call `f` with every slot the code reads.
+    pub fn visit_slots<F: FnMut(usize)>(&self, f: &mut F) {
+        match self {
+            Code::Slot(i) => f(*i),
+            Code::Unary(_, a) => a.visit_slots(f),
+            Code::Binary(_, a, b) => {
+                a.visit_slots(f);
+                b.visit_slots(f);
+            }
+            Code::Call(_, args) => args.iter().for_each(|a| a.visit_slots(f)),
+            Code::Piecewise(pieces, otherwise) => {
+                for (value, condition) in pieces {
+                    value.visit_slots(f);
+                    condition.visit_slots(f);
+                }
+                otherwise.visit_slots(f);
+            }
+            Code::Number(_) | Code::Time => {}
+        }
+    }
+}
+
+/// An event with compiled formulas; see `model::Event`.
+#[derive(Debug, Clone)]
+pub(crate) struct EventCode {
+    pub trigger: Code,
+    pub delay: Option<Code>,
+    pub priority: Option<Code>,
+    pub persistent: bool,
+    pub initial_value: bool,
+    pub from_trigger: bool,
+    pub assignments: Vec<(usize, Code)>,
+}
+
+/// A call of `delay` with a lag that is not known to be zero. Its value, `value` at `lag`
+/// before the current time, depends on the history of the simulation, which the integrator
+/// keeps and stores into `slot`.
+#[derive(Debug, Clone)]
+pub(crate) struct DelayCode {
+    pub slot: usize,
+    pub value: Code,
+    pub lag: Code,
+}
+
+/// A module compiled into slots and code.
+#[derive(Debug, Clone)]
+pub(crate) struct System {
+    /// The symbol in each slot.
+    pub names: Vec<String>,
+    pub index: HashMap<String, usize>,
+    /// The number of state variables, which occupy the first slots.
+    pub n_state: usize,
+    /// The time derivative of each state variable.
+    pub rates: Vec<Code>,
+    /// Assignment rules and reaction rates, in dependency order.
+    pub assignments: Vec<(usize, Code)>,
+    /// The formulas giving initial values, in dependency order.
+    initial: Vec<(usize, Code)>,
+    /// Slots that formulas read but that nothing gives a value.
+    undefined: Vec<usize>,
+    /// Whether each slot holds the rate of a reaction.
+    reaction: Vec<bool>,
+    pub events: Vec<EventCode>,
+    /// The delayed values, each in a slot after those of the symbols, inner ones first.
+    pub delays: Vec<DelayCode>,
+}
+
+impl System {
+    pub fn new(module: &Module) -> Result<System, Error> {
+        let equations = module.rate_equations()?;
+        let mut names: Vec<String> = equations.iter().map(|(id, _)| id.clone()).collect();
+        let n_state = names.len();
+        let mut index: HashMap<String, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.clone(), i))
+            .collect();
+        let ids = module.symbols.iter().map(|s| &s.id);
+        for id in ids.chain(module.reactions.iter().map(|r| &r.id)) {
+            if !index.contains_key(id) {
+                index.insert(id.clone(), names.len());
+                names.push(id.clone());
+            }
+        }
+        let compile = |e: &Expr| Code::compile(&e.inline(module)?, &index);
+
+        let mut rates = equations
+            .iter()
+            .map(|(_, rhs)| compile(rhs))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut assignments = Vec::new();
+        let mut initial = Vec::new();
+        for (slot, id) in names.iter().enumerate() {
+            let rule = match module.symbol(id).and_then(|s| s.rule.as_ref()) {
+                Some(Rule::Assignment(f)) => Some(f),
+                _ => None,
+            };
+            let rate = module.reaction(id).and_then(|r| r.rate.as_ref());
+            if let Some(formula) = rule.or(rate) {
+                assignments.push((slot, compile(&module.formula(formula)?)?));
+            }
+            let start = rule
+                .or_else(|| module.symbol(id)?.initial.as_ref())
+                .or(rate);
+            if let Some(formula) = start {
+                initial.push((slot, compile(&module.formula(formula)?)?));
+            }
+        }
+        let initial = sorted(initial, &names)?;
+
+        let mut events = Vec::with_capacity(module.events.len());
+        for e in &module.events {
+            let optional = |formula: &Option<String>| -> Result<Option<Code>, Error> {
+                match formula {
+                    Some(f) => Ok(Some(compile(&module.formula(f)?)?)),
+                    None => Ok(None),
+                }
+            };
+            let mut targets = Vec::with_capacity(e.assignments.len());
+            for a in &e.assignments {
+                let slot = match index.get(&a.variable) {
+                    Some(&slot) => slot,
+                    None => return Err(Error::UnknownSymbol(a.variable.clone())),
+                };
+                targets.push((slot, compile(&module.formula(&a.formula)?)?));
+            }
+            events.push(EventCode {
+                trigger: compile(&module.formula(&e.trigger)?)?,
+                delay: optional(&e.delay)?,
+                priority: optional(&e.priority)?,
+                persistent: e.persistent,
+                initial_value: e.initial_value,
+                from_trigger: e.from_trigger,
+                assignments: targets,
+            });
+        }
+
+        let mut delays = Vec::new();
+        let symbols = names.len();
+        let mut codes: Vec<&mut Code> = rates.iter_mut().collect();
+        codes.extend(assignments.iter_mut().map(|(_, c)| c));
+        for e in &mut events {
+            codes.push(&mut e.trigger);
+            codes.extend(e.delay.iter_mut().chain(&mut e.priority));
+            codes.extend(e.assignments.iter_mut().map(|(_, c)| c));
+        }
+        for code in codes {
+            code.extract_delays(&mut names, &mut delays);
+        }
+        let assignments = sorted(assignments, &names)?;
+
+        let reaction = names
+            .iter()
+            .enumerate()
+            .map(|(slot, id)| slot < symbols && module.reaction(id).is_some())
+            .collect();
+        let mut defined = vec![false; names.len()];
+        for (slot, _) in &initial {
+            defined[*slot] = true;
+        }
+        for d in &delays {
+            defined[d.slot] = true;
+        }
+        let mut undefined = Vec::new();
+        let mut codes: Vec<&Code> = rates.iter().collect();
+        codes.extend(assignments.iter().chain(&initial).map(|(_, c)| c));
+        for e in &events {
+            codes.push(&e.trigger);
+            codes.extend(e.delay.iter().chain(&e.priority));
+            codes.extend(e.assignments.iter().map(|(_, c)| c));
+        }
+        codes.extend(delays.iter().flat_map(|d| vec![&d.value, &d.lag]));
+        for code in codes {
+            code.visit_slots(&mut |slot| {
+                if !defined[slot] && !undefined.contains(&slot) {
+                    undefined.push(slot);
+                }
+            });
+        }
+        Ok(System {
+            names,
+            index,
+            n_state,
+            rates,
+            assignments,
+            initial,
+            undefined,
+            reaction,
+            events,
+            delays,
+        })
+    }
+
+    pub fn is_reaction(&self, slot: usize) -> bool {
+        self.reaction[slot]
+    }
+
+    /// The value of every slot at time `t`, with `overrides` taking the place of the
+    /// module's initial values.
+    pub fn initial_values(&self, t: f64, overrides: &[Option<f64>]) -> Result<Vec<f64>, Error> {
+        if let Some(&slot) = self.undefined.iter().find(|&&s| overrides[s].is_none()) {
+            let id = self.names[slot].clone();
+            return Err(Error::Eval(EvalError::UndefinedSymbol(id)));
+        }
+        let mut values: Vec<f64> = overrides.iter().map(|v| v.unwrap_or(f64::NAN)).collect();
+        for (slot, code) in &self.initial {
+            if overrides[*slot].is_none() {
+                values[*slot] = code.eval(t, &values)?;
+            }
+        }
+        self.update(t, &mut values)?;
+        if !self.delays.is_empty() {
+            // The history before the start is constant.
+            for d in &self.delays {
+                values[d.slot] = d.value.eval(t, &values)?;
+            }
+            self.update(t, &mut values)?;
+        }
+        Ok(values)
+    }
+
+    /// Recompute the slots governed by assignment rules and the reaction rates.
+    pub fn update(&self, t: f64, values: &mut [f64]) -> Result<(), EvalError> {
+        for (slot, code) in &self.assignments {
+            values[*slot] = code.eval(t, values)?;
+        }
+        Ok(())
+    }
+
+    /// The time derivatives of the state variables, given the values of every slot.
+    pub fn derivatives(&self, t: f64, values: &mut [f64], dy: &mut [f64]) -> Result<(), EvalError> {
+        self.update(t, values)?;
+        for (d, code) in dy.iter_mut().zip(&self.rates) {
+            *d = code.eval(t, values)?;
+        }
+        Ok(())
+    }
+}
+
+/// Order formulas so that each comes after the formulas of the slots it reads.
+fn sorted(formulas: Vec<(usize, Code)>, names: &[String]) -> Result<Vec<(usize, Code)>, Error> {
+    let mut position = vec![None; names.len()];
+    for (i, (slot, _)) in formulas.iter().enumerate() {
+        position[*slot] = Some(i);
+    }
+    // 0: unvisited, 1: in progress, 2: done.
+    let mut state = vec![0u8; formulas.len()];
+    let mut order = Vec::with_capacity(formulas.len());
+    fn visit(
+        i: usize,
+        formulas: &[(usize, Code)],
+        position: &[Option<usize>],
+        state: &mut [u8],
+        order: &mut Vec<usize>,
+    ) -> Result<(), usize> {
+        match state[i] {
+            2 => return Ok(()),
+            1 => return Err(i),
+            _ => {}
+        }
+        state[i] = 1;
+        let mut deps = Vec::new();
+        formulas[i]
+            .1
+            .visit_slots(&mut |slot| deps.extend(position[slot]));
+        for j in deps {
+            visit(j, formulas, position, state, order)?;
+        }
+        state[i] = 2;
+        order.push(i);
+        Ok(())
+    }
+    for i in 0..formulas.len() {
+        if let Err(j) = visit(i, &formulas, &position, &mut state, &mut order) {
+            let cycle = (0..formulas.len())
+                .filter(|&k| state[k] == 1 || k == j)
+                .map(|k| names[formulas[k].0].clone())
+                .collect();
+            return Err(Error::Eval(EvalError::Cycle(cycle)));
+        }
+    }
+    let mut formulas: Vec<Option<(usize, Code)>> = formulas.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .map(|i| formulas[i].take().expect("each formula is ordered once"))
+        .collect())
+}
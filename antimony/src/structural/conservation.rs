@@ -0,0 +1,233 @@
+//! Conserved moieties: the combinations of species that no reaction changes.
+
+use std::collections::BTreeMap;
+
+use super::rational::{integers, Rational};
+use crate::model::Stoichiometry;
+use crate::Module;
+
+/// The conservation laws of a reaction network, and the reduction they allow.
+///
+/// A conservation law is a vector `g` with `g N = 0` for the stoichiometry matrix `N`, so
+/// that `Σ g_i x_i` stays constant over amounts `x`; the laws form a basis of the left null
+/// space of `N`. Each law determines one dependent species from the independent ones, whose
+/// rows of `N` form the reduced stoichiometry matrix `N_R`, with `N = L N_R` for the link
+/// matrix `L`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConservationLaws {
+    /// The conserved moieties, one per dependent species.
+    pub moieties: Vec<Moiety>,
+    /// The independent species, in the order of the stoichiometry matrix.
+    pub independent: Vec<String>,
+    /// The dependent species, in the order of the stoichiometry matrix.
+    pub dependent: Vec<String>,
+    /// The rows of the independent species of the stoichiometry matrix.
+    pub reduced: Stoichiometry,
+    /// The link matrix.
+    pub link: Link,
+}
+
+/// A conserved sum of species, such as the total of an enzyme in its free and bound forms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Moiety {
+    /// The integer coefficient of each species in the sum, for the species with a nonzero
+    /// coefficient. The coefficients have no common divisor.
+    pub coefficients: BTreeMap<String, i64>,
+    /// The species that the law determines in the reduced system.
+    pub dependent: String,
+}
+
+/// The link matrix `L` of `N = L N_R`, with one row per species of `N` and one column per
+/// independent species. The rows of the independent species are rows of the identity matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    /// The row labels: every species of the stoichiometry matrix.
+    pub species: Vec<String>,
+    /// The column labels: the independent species.
+    pub independent: Vec<String>,
+    /// The entries, row-major.
+    pub matrix: Vec<Vec<f64>>,
+}
+
+impl Link {
+    /// The entry for the given species and independent species, if both are known.
+    pub fn get(&self, species: &str, independent: &str) -> Option<f64> {
+        let i = self.species.iter().position(|s| s == species)?;
+        let j = self.independent.iter().position(|s| s == independent)?;
+        Some(self.matrix[i][j])
+    }
+}
+
+/// The dependencies among the rows of a matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Reduction {
+    /// The rows that are not combinations of earlier ones.
+    pub independent: Vec<usize>,
+    /// The other rows.
+    pub dependent: Vec<usize>,
+    /// For each dependent row, its coefficients on the independent rows.
+    pub link: Vec<Vec<Rational>>,
+}
+
+/// Find the dependencies among `rows` in exact arithmetic, preferring earlier rows as the
+/// independent ones. Entries are read as the simplest fractions within rounding error.
+pub(crate) fn reduce(rows: &[Vec<f64>]) -> Reduction {
+    let m = rows.len();
+    let columns = rows.first().map_or(0, Vec::len);
+    // Bring the transpose to reduced row echelon form: the pivot columns are the
+    // independent rows, and the other columns hold the coefficients of the dependent ones.
+    let mut a: Vec<Vec<Rational>> = (0..columns)
+        .map(|j| rows.iter().map(|row| Rational::from_f64(row[j])).collect())
+        .collect();
+    let mut independent = Vec::new();
+    let mut dependent = Vec::new();
+    for i in 0..m {
+        let r = independent.len();
+        let p = match (r..columns).find(|&p| !a[p][i].is_zero()) {
+            Some(p) => p,
+            None => {
+                dependent.push(i);
+                continue;
+            }
+        };
+        a.swap(r, p);
+        let pivot = a[r][i];
+        a[r].iter_mut().for_each(|x| *x = *x / pivot);
+        let pivot_row = a[r].clone();
+        for (k, row) in a.iter_mut().enumerate() {
+            let factor = row[i];
+            if k != r && !factor.is_zero() {
+                for (x, &p) in row.iter_mut().zip(&pivot_row) {
+                    *x = *x - factor * p;
+                }
+            }
+        }
+        independent.push(i);
+    }
+    let link = dependent
+        .iter()
+        .map(|&d| (0..independent.len()).map(|r| a[r][d]).collect())
+        .collect();
+    Reduction {
+        independent,
+        dependent,
+        link,
+    }
+}
+
+impl Module {
+    /// The conservation laws of the module's stoichiometry matrix, computed exactly.
+    ///
+    /// # Panics
+    ///
+    /// If the exact arithmetic overflows 128-bit integers, which takes stoichiometric
+    /// coefficients far larger or less regular than those of chemical reactions.
+    pub fn conservation_laws(&self) -> ConservationLaws {
+        let n = &self.stoichiometry;
+        let reduction = reduce(&n.matrix);
+        let label = |rows: &[usize]| -> Vec<String> {
+            rows.iter().map(|&i| n.species[i].clone()).collect()
+        };
+        let moieties = reduction
+            .dependent
+            .iter()
+            .zip(&reduction.link)
+            .map(|(&d, link)| {
+                // The law is x_d - Σ link_p x_p.
+                let mut law = vec![Rational::ZERO; n.species.len()];
+                law[d] = Rational::ONE;
+                for (&p, &c) in reduction.independent.iter().zip(link) {
+                    law[p] = -c;
+                }
+                let coefficients = integers(&law)
+                    .into_iter()
+                    .zip(&n.species)
+                    .filter(|&(c, _)| c != 0)
+                    .map(|(c, s)| (s.clone(), c as i64))
+                    .collect();
+                Moiety {
+                    coefficients,
+                    dependent: n.species[d].clone(),
+                }
+            })
+            .collect();
+
+        let mut matrix = vec![vec![0.0; reduction.independent.len()]; n.species.len()];
+        for (column, &i) in reduction.independent.iter().enumerate() {
+            matrix[i][column] = 1.0;
+        }
+        for (&d, link) in reduction.dependent.iter().zip(&reduction.link) {
+            for (entry, c) in matrix[d].iter_mut().zip(link) {
+                *entry = c.to_f64();
+            }
+        }
+        ConservationLaws {
+            moieties,
+            independent: label(&reduction.independent),
+            dependent: label(&reduction.dependent),
+            reduced: Stoichiometry {
+                species: label(&reduction.independent),
+                reactions: n.reactions.clone(),
+                matrix: reduction
+                    .independent
+                    .iter()
+                    .map(|&i| n.matrix[i].clone())
+                    .collect(),
+            },
+            link: Link {
+                species: n.species.clone(),
+                independent: label(&reduction.independent),
+                matrix,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::Fixture;
+
+    #[test]
+    fn finds_enzyme_and_substrate_totals() {
+        let module = Fixture::new()
+            .species("S", 10.0)
+            .species("E", 1.0)
+            .species("ES", 0.0)
+            .species("P", 0.0)
+            .reaction("J1", &[(1.0, "S"), (1.0, "E")], &[(1.0, "ES")], "k1*S*E")
+            .irreversible("J2", &[(1.0, "ES")], &[(1.0, "E"), (1.0, "P")], "k2*ES")
+            .build();
+        let laws = module.conservation_laws();
+        assert_eq!(laws.independent, ["S", "E"]);
+        assert_eq!(laws.dependent, ["ES", "P"]);
+        let sums: Vec<Vec<(&str, i64)>> = laws
+            .moieties
+            .iter()
+            .map(|m| {
+                m.coefficients
+                    .iter()
+                    .map(|(s, &c)| (s.as_str(), c))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(sums[0], [("E", 1), ("ES", 1)]);
+        assert_eq!(sums[1], [("E", -1), ("P", 1), ("S", 1)]);
+        assert_eq!(laws.reduced.matrix, [[-1.0, 0.0], [-1.0, 1.0]]);
+        // N = L N_R: ES changes as -E, and P as E - S.
+        assert_eq!(laws.link.get("ES", "E"), Some(-1.0));
+        assert_eq!(laws.link.get("P", "S"), Some(-1.0));
+        assert_eq!(laws.link.get("P", "E"), Some(1.0));
+        assert_eq!(laws.link.get("S", "S"), Some(1.0));
+
+        let module = Fixture::new()
+            .species("A", 1.0)
+            .species("B", 0.0)
+            .reaction("J", &[(2.0, "A")], &[(1.0, "B")], "k*A^2")
+            .build();
+        let laws = module.conservation_laws();
+        let coefficients: Vec<i64> = laws.moieties[0].coefficients.values().copied().collect();
+        assert_eq!(coefficients, [1, 2]);
+        assert_eq!(laws.moieties[0].dependent, "B");
+        assert_eq!(laws.link.get("B", "A"), Some(-0.5));
+    }
+}
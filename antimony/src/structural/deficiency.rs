@@ -0,0 +1,309 @@
+//! Chemical reaction network theory: the graph of complexes and its deficiency.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::conservation::reduce;
+use crate::model::Participant;
+use crate::Module;
+
+/// The complex graph of a reaction network, with the invariants of chemical reaction network
+/// theory (Feinberg, 1987).
+///
+/// The complexes are the two sides of the reactions, and each reaction is an edge from its
+/// reactant complex to its product complex. With `n` complexes, `ℓ` linkage classes (the
+/// connected components of the graph) and a stoichiometric subspace of dimension `s`, the
+/// deficiency `n - ℓ - s` is never negative. Boundary species are left out of the complexes,
+/// as they are in the stoichiometry matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReactionNetwork {
+    /// The species of the complexes.
+    pub species: Vec<String>,
+    /// The complexes, in order of first appearance.
+    pub complexes: Vec<Complex>,
+    /// The reactions, in declaration order.
+    pub reactions: Vec<String>,
+    /// The reactant and product complex of each reaction.
+    pub edges: Vec<(usize, usize)>,
+    /// Whether each reaction is reversible, so that its edge also runs backwards.
+    pub reversible: Vec<bool>,
+    /// The linkage classes, as sets of complexes.
+    pub linkage_classes: Vec<Vec<usize>>,
+    /// The strong linkage classes: the sets of complexes that each reach one another.
+    pub strong_linkage_classes: Vec<Vec<usize>>,
+    /// Whether each strong linkage class is terminal, with no reaction leading out of it.
+    pub terminal: Vec<bool>,
+    /// The dimension of the stoichiometric subspace, the rank of the stoichiometry matrix.
+    pub rank: usize,
+    /// The deficiency of the network.
+    pub deficiency: usize,
+    /// The deficiency of each linkage class taken as a network of its own.
+    pub linkage_deficiencies: Vec<usize>,
+}
+
+/// A complex: a side of a reaction, with the stoichiometry of each of its species. The zero
+/// complex of a source or sink has no species.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Complex {
+    /// The species with their stoichiometries.
+    pub coefficients: BTreeMap<String, f64>,
+}
+
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.coefficients.is_empty() {
+            return f.write_str("0");
+        }
+        for (i, (species, &c)) in self.coefficients.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" + ")?;
+            }
+            if c != 1.0 {
+                write!(f, "{} ", c)?;
+            }
+            f.write_str(species)?;
+        }
+        Ok(())
+    }
+}
+
+impl ReactionNetwork {
+    /// Whether every reaction is reversible.
+    pub fn is_reversible(&self) -> bool {
+        self.reversible.iter().all(|&r| r)
+    }
+
+    /// Whether every reaction lies on a cycle of reactions, so that each linkage class is a
+    /// single strong linkage class.
+    pub fn is_weakly_reversible(&self) -> bool {
+        self.linkage_classes.len() == self.strong_linkage_classes.len()
+    }
+
+    /// Whether mass-action kinetics on the network is complex balanced for every choice of
+    /// rate constants, which holds exactly when the network is weakly reversible with
+    /// deficiency zero. By the deficiency zero theorem, every compatibility class then has
+    /// one positive steady state, which is locally asymptotically stable, and there are no
+    /// sustained oscillations.
+    pub fn is_complex_balanced(&self) -> bool {
+        self.deficiency == 0 && self.is_weakly_reversible()
+    }
+
+    /// Whether the deficiency one theorem applies: no linkage class has a deficiency above
+    /// one, their deficiencies add up to that of the network, and each linkage class has one
+    /// terminal strong linkage class. Mass-action kinetics then has at most one positive
+    /// steady state in each compatibility class, whatever the rate constants, and exactly one
+    /// if the network is also weakly reversible.
+    pub fn deficiency_one_applies(&self) -> bool {
+        let terminal = |class: &Vec<usize>| {
+            self.strong_linkage_classes
+                .iter()
+                .zip(&self.terminal)
+                .filter(|&(strong, &t)| t && class.contains(&strong[0]))
+                .count()
+        };
+        self.linkage_deficiencies.iter().all(|&d| d <= 1)
+            && self.linkage_deficiencies.iter().sum::<usize>() == self.deficiency
+            && self
+                .linkage_classes
+                .iter()
+                .all(|class| terminal(class) == 1)
+    }
+
+    /// Whether the reaction fluxes (net, in the order of `reactions`) balance at every
+    /// complex, so that the flow into each complex equals the flow out of it, within
+    /// `tolerance` relative to the largest flux. Fluxes at a steady state of a network that is
+    /// complex balanced pass this test.
+    pub fn complex_balanced_at(&self, fluxes: &[f64], tolerance: f64) -> bool {
+        let mut balance = vec![0.0; self.complexes.len()];
+        for (&(from, to), &v) in self.edges.iter().zip(fluxes) {
+            balance[from] -= v;
+            balance[to] += v;
+        }
+        let scale = fluxes.iter().fold(1.0f64, |s, v| s.max(v.abs()));
+        balance.iter().all(|b| b.abs() <= tolerance * scale)
+    }
+}
+
+impl Module {
+    /// The complex graph of the module's reactions, with its linkage classes and deficiency.
+    pub fn reaction_network(&self) -> ReactionNetwork {
+        let species: Vec<String> = self
+            .stoichiometry
+            .species
+            .iter()
+            .filter(|id| self.symbol(id).is_none_or(|s| !s.is_constant()))
+            .cloned()
+            .collect();
+        let mut complexes: Vec<Complex> = Vec::new();
+        let mut find = |side: &[Participant]| {
+            let mut complex = Complex::default();
+            for p in side.iter().filter(|p| species.contains(&p.species)) {
+                *complex.coefficients.entry(p.species.clone()).or_insert(0.0) += p.stoichiometry;
+            }
+            complex.coefficients.retain(|_, c| *c != 0.0);
+            match complexes.iter().position(|c| *c == complex) {
+                Some(k) => k,
+                None => {
+                    complexes.push(complex);
+                    complexes.len() - 1
+                }
+            }
+        };
+        let edges: Vec<(usize, usize)> = self
+            .reactions
+            .iter()
+            .map(|r| (find(&r.reactants), find(&r.products)))
+            .collect();
+        let reversible: Vec<bool> = self.reactions.iter().map(|r| r.is_reversible()).collect();
+        let n = complexes.len();
+
+        // Which complexes each complex reaches along the reactions.
+        let mut successors = vec![Vec::new(); n];
+        for (&(from, to), &r) in edges.iter().zip(&reversible) {
+            successors[from].push(to);
+            if r {
+                successors[to].push(from);
+            }
+        }
+        let reach: Vec<Vec<bool>> = (0..n)
+            .map(|start| {
+                let mut seen = vec![false; n];
+                let mut stack = vec![start];
+                seen[start] = true;
+                while let Some(k) = stack.pop() {
+                    for &next in &successors[k] {
+                        if !seen[next] {
+                            seen[next] = true;
+                            stack.push(next);
+                        }
+                    }
+                }
+                seen
+            })
+            .collect();
+        let classes = |related: &dyn Fn(usize, usize) -> bool| {
+            let mut classes: Vec<Vec<usize>> = Vec::new();
+            for k in 0..n {
+                match classes.iter_mut().find(|c| related(c[0], k)) {
+                    Some(class) => class.push(k),
+                    None => classes.push(vec![k]),
+                }
+            }
+            classes
+        };
+        let strong_linkage_classes = classes(&|a, b| reach[a][b] && reach[b][a]);
+        let terminal = strong_linkage_classes
+            .iter()
+            .map(|class| (0..n).all(|k| !reach[class[0]][k] || class.contains(&k)))
+            .collect();
+        // Each reaction joins the linkage classes of its two complexes.
+        let mut linkage_classes: Vec<Vec<usize>> = Vec::new();
+        for &(from, to) in &edges {
+            let joined: Vec<usize> = (0..linkage_classes.len())
+                .filter(|&c| linkage_classes[c].contains(&from) || linkage_classes[c].contains(&to))
+                .collect();
+            let mut class = vec![from, to];
+            for &c in joined.iter().rev() {
+                class.extend(linkage_classes.remove(c));
+            }
+            class.sort_unstable();
+            class.dedup();
+            linkage_classes.push(class);
+        }
+        linkage_classes.sort();
+
+        // The rank of the reaction vectors, over the network and over each linkage class.
+        let rank = |reactions: &[usize]| {
+            let rows: Vec<Vec<f64>> = species
+                .iter()
+                .map(|s| {
+                    reactions
+                        .iter()
+                        .map(|&j| {
+                            let (from, to) = edges[j];
+                            let c = |k: usize| complexes[k].coefficients.get(s).copied();
+                            c(to).unwrap_or(0.0) - c(from).unwrap_or(0.0)
+                        })
+                        .collect()
+                })
+                .collect();
+            reduce(&rows).independent.len()
+        };
+        let all: Vec<usize> = (0..edges.len()).collect();
+        let s = rank(&all);
+        let linkage_deficiencies = linkage_classes
+            .iter()
+            .map(|class| {
+                let reactions: Vec<usize> = all
+                    .iter()
+                    .copied()
+                    .filter(|&j| class.contains(&edges[j].0))
+                    .collect();
+                class.len() - 1 - rank(&reactions)
+            })
+            .collect();
+        ReactionNetwork {
+            species,
+            deficiency: n - linkage_classes.len() - s,
+            complexes,
+            reactions: self.reactions.iter().map(|r| r.id.clone()).collect(),
+            edges,
+            reversible,
+            linkage_classes,
+            strong_linkage_classes,
+            terminal,
+            rank: s,
+            linkage_deficiencies,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::Fixture;
+
+    #[test]
+    fn weakly_reversible_deficiency_zero() {
+        let module = Fixture::new()
+            .species("A", 1.0)
+            .species("B", 0.0)
+            .species("C", 0.0)
+            .species("D", 0.0)
+            .boundary("E", 1.0)
+            .reaction("J1", &[(1.0, "A")], &[(1.0, "B")], "A - B")
+            .irreversible("J2", &[(1.0, "B")], &[(1.0, "C")], "B")
+            .irreversible("J3", &[(1.0, "C"), (1.0, "E")], &[(1.0, "A")], "C")
+            .reaction("J4", &[(2.0, "A")], &[(1.0, "D")], "A^2 - D")
+            .build();
+        let network = module.reaction_network();
+        assert_eq!(network.complexes.len(), 5);
+        assert_eq!(network.complexes[3].to_string(), "2 A");
+        assert_eq!(network.linkage_classes, [vec![0, 1, 2], vec![3, 4]]);
+        assert_eq!(network.rank, 3);
+        assert_eq!(network.deficiency, 0);
+        assert!(network.is_weakly_reversible() && !network.is_reversible());
+        assert!(network.is_complex_balanced() && network.deficiency_one_applies());
+        assert!(network.complex_balanced_at(&[1.0, 1.0, 1.0, 0.0], 1e-12));
+        assert!(!network.complex_balanced_at(&[1.0, 0.0, 0.0, 0.0], 1e-12));
+    }
+
+    #[test]
+    fn finds_deficiency_one() {
+        // A <-> 2A, A + B <-> C <-> B: the classes have deficiency zero, the network one.
+        let module = Fixture::new()
+            .species("A", 1.0)
+            .species("B", 1.0)
+            .species("C", 0.0)
+            .reaction("J1", &[(1.0, "A")], &[(2.0, "A")], "A")
+            .reaction("J2", &[(1.0, "A"), (1.0, "B")], &[(1.0, "C")], "A*B")
+            .reaction("J3", &[(1.0, "C")], &[(1.0, "B")], "C")
+            .build();
+        let network = module.reaction_network();
+        assert_eq!(network.linkage_classes, [vec![0, 1], vec![2, 3, 4]]);
+        assert_eq!(network.rank, 2);
+        assert_eq!(network.deficiency, 1);
+        assert_eq!(network.linkage_deficiencies, [0, 0]);
+        assert!(network.is_weakly_reversible() && !network.is_complex_balanced());
+        assert!(!network.deficiency_one_applies());
+    }
+}
@@ -0,0 +1,21 @@
+//! Structural analysis of reaction networks: properties of the stoichiometry matrix that hold
+//! whatever the rate laws.
+//!
+//! `Module::conservation_laws` finds the conserved moieties of a network, such as the total
+//! of an enzyme, along with the reduced stoichiometry and link matrices that remove them;
+//! `Module::elementary_modes` enumerates the elementary flux modes, the minimal pathways the
+//! network can run at steady state; and `Module::reaction_network` finds the deficiency and
+//! linkage classes of chemical reaction network theory, which rule out multistability for
+//! some networks whatever their rate constants.
+//! The analyses work in exact rational arithmetic, so that a coefficient is zero exactly when
+//! it should be.
+
+mod conservation;
+mod deficiency;
+mod modes;
+mod rational;
+
+pub(crate) use self::conservation::reduce;
+pub use self::conservation::{ConservationLaws, Link, Moiety};
+pub use self::deficiency::{Complex, ReactionNetwork};
+pub use self::modes::ElementaryModes;
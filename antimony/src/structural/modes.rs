@@ -0,0 +1,227 @@
+//! Elementary flux modes: the minimal sets of reactions that can operate at steady state.
+
+use std::collections::BTreeSet;
+
+use super::rational::{gcd, integers, Rational};
+use crate::Module;
+
+/// The elementary flux modes of a reaction network.
+///
+/// A flux mode is a vector of fluxes `v` with `N v = 0` in which every irreversible reaction
+/// runs forward; it is elementary if no other mode uses a proper subset of its reactions. Every
+/// steady-state flux distribution is a nonnegative combination of elementary modes. When all
+/// reactions are irreversible, the elementary modes are the extreme rays of the flux cone, that
+/// is, its extreme pathways.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementaryModes {
+    /// The reactions, in the order of the stoichiometry matrix.
+    pub reactions: Vec<String>,
+    /// The modes, one row each, as the smallest integer fluxes in their proportions. A mode
+    /// of reversible reactions only may also run backwards; it is listed once, with its first
+    /// nonzero flux positive.
+    pub modes: Vec<Vec<i64>>,
+}
+
+impl ElementaryModes {
+    /// The reactions that mode `k` uses.
+    pub fn support(&self, k: usize) -> Vec<&str> {
+        self.reactions
+            .iter()
+            .zip(&self.modes[k])
+            .filter(|&(_, &v)| v != 0)
+            .map(|(r, _)| r.as_str())
+            .collect()
+    }
+}
+
+/// A ray of the cone during the double description method: its fluxes, the constraints it
+/// leaves unsatisfied, and its support as a bit set.
+#[derive(Debug, Clone)]
+struct Ray {
+    fluxes: Vec<i128>,
+    residuals: Vec<i128>,
+    support: Vec<u64>,
+}
+
+impl Ray {
+    fn new(fluxes: Vec<i128>, rows: &[Vec<i128>]) -> Ray {
+        let residuals = rows
+            .iter()
+            .map(|row| row.iter().zip(&fluxes).map(|(a, v)| a * v).sum())
+            .collect();
+        let mut support = vec![0u64; fluxes.len().div_ceil(64)];
+        for (j, &v) in fluxes.iter().enumerate() {
+            if v != 0 {
+                support[j / 64] |= 1 << (j % 64);
+            }
+        }
+        Ray {
+            fluxes,
+            residuals,
+            support,
+        }
+    }
+}
+
+impl Module {
+    /// The elementary flux modes of the module's stoichiometry matrix, with reactions
+    /// irreversible as declared (see `Reaction::is_reversible`).
+    ///
+    /// Each reversible reaction is split into a forward and a backward reaction, so that every
+    /// flux is nonnegative, and the extreme rays of the resulting cone are found by the double
+    /// description method (Motzkin et al., 1953), in exact integer arithmetic; rays that run a
+    /// reaction both ways are dropped (Gagneur and Klamt, 2004). The number of modes can grow
+    /// exponentially with the size of the network.
+    ///
+    /// # Panics
+    ///
+    /// If the exact arithmetic overflows 128-bit integers.
+    pub fn elementary_modes(&self) -> ElementaryModes {
+        let n = &self.stoichiometry;
+        let q = n.reactions.len();
+        let reversible: Vec<bool> = n
+            .reactions
+            .iter()
+            .map(|id| self.reaction(id).is_none_or(|r| r.is_reversible()))
+            .collect();
+        // The columns of the split network: each reaction, then the reverse of each
+        // reversible one.
+        let backward: Vec<usize> = (0..q).filter(|&j| reversible[j]).collect();
+        let rows: Vec<Vec<i128>> = n
+            .matrix
+            .iter()
+            .map(|row| {
+                let mut split: Vec<Rational> = row.iter().map(|&x| Rational::from_f64(x)).collect();
+                split.extend(backward.iter().map(|&j| -Rational::from_f64(row[j])));
+                integers(&split)
+            })
+            .filter(|row| row.iter().any(|&x| x != 0))
+            .collect();
+        let columns = q + backward.len();
+
+        let mut rays: Vec<Ray> = (0..columns)
+            .map(|k| {
+                let mut fluxes = vec![0; columns];
+                fluxes[k] = 1;
+                Ray::new(fluxes, &rows)
+            })
+            .collect();
+        let mut remaining: Vec<usize> = (0..rows.len()).collect();
+        while !remaining.is_empty() {
+            // Impose the constraint that produces the fewest candidate rays first.
+            let count = |i: usize| {
+                let positive = rays.iter().filter(|r| r.residuals[i] > 0).count();
+                let negative = rays.iter().filter(|r| r.residuals[i] < 0).count();
+                positive * negative
+            };
+            let (position, &i) = remaining
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, &i)| count(i))
+                .expect("constraints remain");
+            remaining.swap_remove(position);
+
+            let mut next: Vec<Ray> = Vec::new();
+            for (a, p) in rays.iter().enumerate().filter(|r| r.1.residuals[i] > 0) {
+                for (b, m) in rays.iter().enumerate().filter(|r| r.1.residuals[i] < 0) {
+                    let union: Vec<u64> = p
+                        .support
+                        .iter()
+                        .zip(&m.support)
+                        .map(|(x, y)| x | y)
+                        .collect();
+                    // Adjacent rays combine into an extreme ray of the new cone; they are
+                    // adjacent if no other ray has its support within theirs.
+                    let adjacent = rays.iter().enumerate().all(|(c, r)| {
+                        c == a || c == b || r.support.iter().zip(&union).any(|(s, u)| s & !u != 0)
+                    });
+                    if !adjacent {
+                        continue;
+                    }
+                    let (x, y) = (-m.residuals[i], p.residuals[i]);
+                    let fluxes: Vec<i128> = p
+                        .fluxes
+                        .iter()
+                        .zip(&m.fluxes)
+                        .map(|(u, v)| {
+                            let sum = u.checked_mul(x).zip(v.checked_mul(y));
+                            sum.and_then(|(u, v)| u.checked_add(v))
+                                .expect("integer arithmetic overflowed")
+                        })
+                        .collect();
+                    let g = fluxes.iter().fold(0, |g, &v| gcd(g, v)).max(1);
+                    next.push(Ray::new(fluxes.into_iter().map(|v| v / g).collect(), &rows));
+                }
+            }
+            rays.retain(|r| r.residuals[i] == 0);
+            rays.extend(next);
+        }
+
+        // Net fluxes, without the cycles of a reaction and its reverse.
+        let mut modes = BTreeSet::new();
+        for ray in rays {
+            let mut net: Vec<i128> = ray.fluxes[..q].to_vec();
+            for (k, &j) in backward.iter().enumerate() {
+                net[j] -= ray.fluxes[q + k];
+            }
+            if net.iter().all(|&v| v == 0) {
+                continue;
+            }
+            let g = net.iter().fold(0, |g, &v| gcd(g, v)).max(1);
+            let mut mode: Vec<i64> = net.iter().map(|&v| (v / g) as i64).collect();
+            let backwards = mode.iter().find(|&&v| v != 0).is_some_and(|&v| v < 0);
+            let all_reversible = mode.iter().zip(&reversible).all(|(&v, &r)| v == 0 || r);
+            if backwards && all_reversible {
+                mode.iter_mut().for_each(|v| *v = -*v);
+            }
+            modes.insert(mode);
+        }
+        let mut modes: Vec<Vec<i64>> = modes.into_iter().collect();
+        modes.sort_by_key(|m| m.iter().filter(|&&v| v != 0).count());
+        ElementaryModes {
+            reactions: n.reactions.clone(),
+            modes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::Fixture;
+
+    #[test]
+    fn enumerates_pathways_and_cycles() {
+        let module = Fixture::new()
+            .species("A", 0.0)
+            .species("B", 0.0)
+            .species("C", 0.0)
+            .species("D", 0.0)
+            .irreversible("uptake", &[], &[(1.0, "A")], "1")
+            .irreversible("direct", &[(1.0, "A")], &[(1.0, "B")], "1")
+            .irreversible("detour", &[(1.0, "A")], &[(1.0, "C")], "1")
+            .reaction("convert", &[(1.0, "C")], &[(1.0, "B"), (1.0, "D")], "1")
+            .irreversible("secrete", &[(1.0, "D")], &[], "1")
+            .irreversible("growth", &[(2.0, "B")], &[], "1")
+            .build();
+        let efms = module.elementary_modes();
+        assert_eq!(efms.modes, [[2, 2, 0, 0, 0, 1], [2, 0, 2, 2, 2, 1]]);
+        assert_eq!(efms.support(0), ["uptake", "direct", "growth"]);
+
+        // A reversible cycle is one mode; an irreversible step stops the reverse pathway.
+        let module = Fixture::new()
+            .species("A", 0.0)
+            .species("B", 0.0)
+            .species("C", 0.0)
+            .reaction("J1", &[(1.0, "A")], &[(1.0, "B")], "1")
+            .reaction("J2", &[(1.0, "B")], &[(1.0, "C")], "1")
+            .reaction("J3", &[(1.0, "C")], &[(1.0, "A")], "1")
+            .irreversible("in", &[], &[(1.0, "A")], "1")
+            .reaction("out", &[(1.0, "C")], &[], "1")
+            .build();
+        let efms = module.elementary_modes();
+        assert_eq!(
+            efms.modes,
+            [[0, 0, -1, 1, 1], [1, 1, 1, 0, 0], [1, 1, 0, 1, 1]]
+        );
+    }
+}
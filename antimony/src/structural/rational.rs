@@ -0,0 +1,198 @@
+//! Exact rational arithmetic for the structural analyses.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// A fraction in lowest terms with a positive denominator.
+///
+/// Stoichiometric coefficients are small, and so are the numbers that elimination produces
+/// from them, so 128-bit integers suffice; arithmetic panics if they overflow.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Rational {
+    numerator: i128,
+    denominator: i128,
+}
+
+impl Rational {
+    pub const ZERO: Rational = Rational {
+        numerator: 0,
+        denominator: 1,
+    };
+    pub const ONE: Rational = Rational {
+        numerator: 1,
+        denominator: 1,
+    };
+
+    pub fn new(numerator: i128, denominator: i128) -> Rational {
+        assert!(denominator != 0, "zero denominator");
+        let g = gcd(numerator, denominator).max(1) * denominator.signum();
+        Rational {
+            numerator: numerator / g,
+            denominator: denominator / g,
+        }
+    }
+
+    pub fn integer(n: i128) -> Rational {
+        Rational::new(n, 1)
+    }
+
+    /// The simplest fraction within rounding error of `x`, so that a coefficient of `0.1`
+    /// reads as one tenth rather than as the binary fraction stored.
+    pub fn from_f64(x: f64) -> Rational {
+        assert!(x.is_finite(), "stoichiometric coefficients are finite");
+        if x.fract() == 0.0 && x.abs() < 1e18 {
+            return Rational::integer(x as i128);
+        }
+        // The convergents of the continued fraction of x.
+        let (mut h, mut h1) = (1i128, 0i128);
+        let (mut k, mut k1) = (0i128, 1i128);
+        let mut rest = x;
+        for _ in 0..40 {
+            let a = rest.floor();
+            if a.abs() > 1e15 {
+                break;
+            }
+            let a = a as i128;
+            (h, h1) = (a * h + h1, h);
+            (k, k1) = (a * k + k1, k);
+            if (x - h as f64 / k as f64).abs() <= 1e-12 * x.abs().max(1.0) {
+                break;
+            }
+            rest = 1.0 / (rest - rest.floor());
+        }
+        Rational::new(h, k)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.numerator == 0
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+}
+
+/// The greatest common divisor of the magnitudes, zero if both are zero.
+pub(crate) fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn checked(value: Option<i128>) -> i128 {
+    value.expect("rational arithmetic overflowed")
+}
+
+impl Add for Rational {
+    type Output = Rational;
+
+    fn add(self, other: Rational) -> Rational {
+        let g = gcd(self.denominator, other.denominator);
+        let (a, b) = (self.denominator / g, other.denominator / g);
+        let numerator = checked(
+            checked(self.numerator.checked_mul(b))
+                .checked_add(checked(other.numerator.checked_mul(a))),
+        );
+        Rational::new(numerator, checked(self.denominator.checked_mul(b)))
+    }
+}
+
+impl Sub for Rational {
+    type Output = Rational;
+
+    fn sub(self, other: Rational) -> Rational {
+        self + -other
+    }
+}
+
+impl Neg for Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational {
+            numerator: -self.numerator,
+            denominator: self.denominator,
+        }
+    }
+}
+
+impl Mul for Rational {
+    type Output = Rational;
+
+    fn mul(self, other: Rational) -> Rational {
+        // Cancel crosswise first to keep the products small.
+        let g = gcd(self.numerator, other.denominator).max(1);
+        let h = gcd(other.numerator, self.denominator).max(1);
+        Rational::new(
+            checked((self.numerator / g).checked_mul(other.numerator / h)),
+            checked((self.denominator / h).checked_mul(other.denominator / g)),
+        )
+    }
+}
+
+impl Div for Rational {
+    type Output = Rational;
+
+    fn div(self, other: Rational) -> Rational {
+        assert!(!other.is_zero(), "division by zero");
+        self * Rational::new(other.denominator, other.numerator)
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        (*self - *other).numerator.cmp(&0)
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.denominator == 1 {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+/// Scale a vector of fractions to the smallest integers with the same ratios.
+pub(crate) fn integers(v: &[Rational]) -> Vec<i128> {
+    let lcm = v.iter().fold(1i128, |l, x| {
+        checked(l.checked_mul(x.denominator / gcd(l, x.denominator)))
+    });
+    let scaled: Vec<i128> = v
+        .iter()
+        .map(|x| checked(x.numerator.checked_mul(lcm / x.denominator)))
+        .collect();
+    let g = scaled.iter().fold(0, |g, &x| gcd(g, x)).max(1);
+    scaled.into_iter().map(|x| x / g).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_in_lowest_terms() {
+        let half = Rational::new(2, -4);
+        assert_eq!(half, Rational::new(-1, 2));
+        assert_eq!(half * Rational::integer(-2), Rational::ONE);
+        assert_eq!(half + Rational::new(1, 3), Rational::new(-1, 6));
+        assert_eq!((half / Rational::new(3, 4)).to_string(), "-2/3");
+        assert!(half < Rational::ZERO);
+        assert_eq!(Rational::from_f64(0.1), Rational::new(1, 10));
+        assert_eq!(Rational::from_f64(-2.5), Rational::new(-5, 2));
+        assert_eq!(Rational::from_f64(1.0 / 3.0), Rational::new(1, 3));
+        let v = [Rational::new(1, 2), Rational::new(-3, 4), Rational::ZERO];
+        assert_eq!(integers(&v), [2, -3, 0]);
+    }
+}
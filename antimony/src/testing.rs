@@ -0,0 +1,191 @@
+//! Modules assembled without libAntimony, for unit tests.
+
+#![allow(dead_code)]
+
+use crate::model::{Event, Function, Participant, Reaction, Rule, Stoichiometry, Symbol};
+use crate::{Interaction, Module, SymbolKind};
+
+/// Assembles a `Module` the way extraction would, including its stoichiometry matrix.
+pub struct Fixture {
+    module: Module,
+}
+
+impl Fixture {
+    pub fn new() -> Fixture {
+        Fixture {
+            module: Module {
+                name: "m".into(),
+                ..Module::default()
+            },
+        }
+    }
+
+    fn symbol(mut self, id: &str, kind: SymbolKind, initial: Option<String>) -> Fixture {
+        self.module.symbols.push(Symbol {
+            id: id.into(),
+            name: None,
+            kind,
+            compartment: None,
+            initial,
+            rule: None,
+        });
+        self
+    }
+
+    pub fn species(self, id: &str, initial: f64) -> Fixture {
+        self.symbol(id, SymbolKind::SpeciesVariable, Some(initial.to_string()))
+    }
+
+    pub fn boundary(self, id: &str, initial: f64) -> Fixture {
+        self.symbol(id, SymbolKind::SpeciesConstant, Some(initial.to_string()))
+    }
+
+    /// Place an already declared species in a compartment.
+    pub fn within(mut self, species: &str, compartment: &str) -> Fixture {
+        let s = self.find(species);
+        s.compartment = Some(compartment.into());
+        self
+    }
+
+    pub fn compartment(self, id: &str, size: f64) -> Fixture {
+        self.symbol(id, SymbolKind::CompartmentConstant, Some(size.to_string()))
+    }
+
+    pub fn parameter(self, id: &str, value: f64) -> Fixture {
+        self.symbol(id, SymbolKind::FormulaConstant, Some(value.to_string()))
+    }
+
+    /// A parameter whose initial value is a formula.
+    pub fn initial(self, id: &str, formula: &str) -> Fixture {
+        self.symbol(id, SymbolKind::FormulaConstant, Some(formula.into()))
+    }
+
+    pub fn assignment(self, id: &str, formula: &str) -> Fixture {
+        self.symbol(id, SymbolKind::FormulaVariable, None)
+            .rule(id, Rule::Assignment(formula.into()))
+    }
+
+    pub fn rate_rule(self, id: &str, initial: f64, formula: &str) -> Fixture {
+        self.symbol(id, SymbolKind::FormulaVariable, Some(initial.to_string()))
+            .rule(id, Rule::Rate(formula.into()))
+    }
+
+    fn rule(mut self, id: &str, rule: Rule) -> Fixture {
+        self.find(id).rule = Some(rule);
+        self
+    }
+
+    pub fn reaction(
+        self,
+        id: &str,
+        reactants: &[(f64, &str)],
+        products: &[(f64, &str)],
+        rate: &str,
+    ) -> Fixture {
+        self.push_reaction(id, Interaction::Becomes, reactants, products, rate)
+    }
+
+    pub fn irreversible(
+        self,
+        id: &str,
+        reactants: &[(f64, &str)],
+        products: &[(f64, &str)],
+        rate: &str,
+    ) -> Fixture {
+        self.push_reaction(id, Interaction::Transforms, reactants, products, rate)
+    }
+
+    pub fn event(mut self, event: Event) -> Fixture {
+        self = self.symbol(&event.id, SymbolKind::Event, None);
+        self.module.events.push(event);
+        self
+    }
+
+    pub fn function(mut self, id: &str, parameters: &[&str], body: &str) -> Fixture {
+        self.module.functions.push(Function {
+            id: id.into(),
+            parameters: parameters.iter().map(|&p| p.into()).collect(),
+            body: body.into(),
+        });
+        self
+    }
+
+    pub fn build(mut self) -> Module {
+        let module = &mut self.module;
+        let species: Vec<String> = module
+            .symbols
+            .iter()
+            .filter(|s| s.kind == SymbolKind::SpeciesVariable)
+            .map(|s| s.id.clone())
+            .filter(|id| {
+                module.reactions.iter().any(|r| {
+                    r.reactants
+                        .iter()
+                        .chain(&r.products)
+                        .any(|p| p.species == *id)
+                })
+            })
+            .collect();
+        let reactions: Vec<String> = module.reactions.iter().map(|r| r.id.clone()).collect();
+        let matrix = species
+            .iter()
+            .map(|s| {
+                module
+                    .reactions
+                    .iter()
+                    .map(|r| {
+                        let side = |ps: &[Participant]| -> f64 {
+                            ps.iter()
+                                .filter(|p| p.species == *s)
+                                .map(|p| p.stoichiometry)
+                                .sum()
+                        };
+                        side(&r.products) - side(&r.reactants)
+                    })
+                    .collect()
+            })
+            .collect();
+        module.stoichiometry = Stoichiometry {
+            species,
+            reactions,
+            matrix,
+        };
+        self.module
+    }
+
+    fn find(&mut self, id: &str) -> &mut Symbol {
+        self.module
+            .symbols
+            .iter_mut()
+            .find(|s| s.id == id)
+            .expect("declared before use")
+    }
+
+    fn push_reaction(
+        mut self,
+        id: &str,
+        kind: Interaction,
+        reactants: &[(f64, &str)],
+        products: &[(f64, &str)],
+        rate: &str,
+    ) -> Fixture {
+        let participants = |side: &[(f64, &str)]| -> Vec<Participant> {
+            side.iter()
+                .map(|&(stoichiometry, species)| Participant {
+                    species: species.into(),
+                    stoichiometry,
+                })
+                .collect()
+        };
+        self = self.symbol(id, SymbolKind::Reaction, None);
+        self.module.reactions.push(Reaction {
+            id: id.into(),
+            name: None,
+            kind,
+            reactants: participants(reactants),
+            products: participants(products),
+            rate: Some(rate.into()),
+        });
+        self
+    }
+}
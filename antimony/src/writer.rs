@@ -0,0 +1,452 @@
+//! Rendering owned modules back into Antimony text.
+//!
+//! Modules are flattened, so symbols of submodules appear under dotted names. When a module is
+//! written as part of a `Document`, dotted values are only written out when they differ from the
+//! definition of the submodule (i.e., when the parent overrides them); written on its own, every
+//! dotted value is repeated, which is redundant but equivalent.
+
+use std::fmt::Write;
+
+use crate::model::*;
+use crate::{Interaction, SymbolKind};
+
+pub(crate) fn document(doc: &Document) -> String {
+    let mut out = String::new();
+    write_functions(&mut out, doc.modules.iter().flat_map(|m| &m.functions));
+    for module in &doc.modules {
+        let main = doc.modules.len() > 1 && module.name == doc.main;
+        write_module(&mut out, module, main, Some(doc));
+        out.push('\n');
+    }
+    out
+}
+
+pub(crate) fn module(module: &Module) -> String {
+    let mut out = String::new();
+    write_functions(&mut out, &module.functions);
+    write_module(&mut out, module, false, None);
+    out
+}
+
+/// Every module of a document, none of them marked as the main one.
+pub(crate) fn definitions(doc: &Document) -> String {
+    let mut out = String::new();
+    write_functions(&mut out, doc.modules.iter().flat_map(|m| &m.functions));
+    for module in &doc.modules {
+        write_module(&mut out, module, false, Some(doc));
+        out.push('\n');
+    }
+    out
+}
+
+/// Write each distinct function definition once.
+fn write_functions<'a, I>(out: &mut String, functions: I)
+where
+    I: IntoIterator<Item = &'a Function>,
+{
+    let mut written: Vec<&str> = Vec::new();
+    for f in functions {
+        if written.contains(&f.id.as_str()) {
+            continue;
+        }
+        written.push(&f.id);
+        let _ = writeln!(out, "function {}({})", f.id, f.parameters.join(", "));
+        let _ = writeln!(out, "  {};", f.body);
+        out.push_str("end\n\n");
+    }
+}
+
+fn write_module(out: &mut String, m: &Module, main: bool, doc: Option<&Document>) {
+    let star = if main { "*" } else { "" };
+    let _ = writeln!(out, "model {}{}({})", star, m.name, m.interface.join(", "));
+
+    let local = |id: &str| !id.contains('.');
+
+    let submodules: Vec<_> = m.submodules.iter().filter(|s| local(&s.id)).collect();
+    if !submodules.is_empty() {
+        out.push_str("  // Sub-modules:\n");
+        for sub in submodules {
+            match &sub.module {
+                Some(name) => {
+                    let _ = writeln!(out, "  {}: {}();", sub.id, name);
+                }
+                None => {
+                    let _ = writeln!(out, "  // {}: unknown module", sub.id);
+                }
+            }
+        }
+        out.push('\n');
+    }
+
+    if !m.replacements.is_empty() {
+        out.push_str("  // Replacements:\n");
+        for r in &m.replacements {
+            let _ = writeln!(out, "  {} is {};", r.former, r.replacement);
+        }
+        out.push('\n');
+    }
+
+    let compartments: Vec<_> = m.compartments().filter(|s| local(&s.id)).collect();
+    let species: Vec<_> = m.species().filter(|s| local(&s.id)).collect();
+    if !compartments.is_empty() || !species.is_empty() {
+        out.push_str("  // Compartments and Species:\n");
+        for c in compartments {
+            let _ = writeln!(out, "  compartment {}{};", c.id, within(c));
+        }
+        for s in species {
+            let boundary = if s.kind == SymbolKind::SpeciesConstant {
+                "$"
+            } else {
+                ""
+            };
+            let _ = writeln!(out, "  species {}{}{};", boundary, s.id, within(s));
+        }
+        out.push('\n');
+    }
+
+    let reactions: Vec<_> = m.reactions.iter().filter(|r| local(&r.id)).collect();
+    if !reactions.is_empty() {
+        out.push_str("  // Reactions:\n");
+        for r in reactions {
+            let arrow = match r.kind {
+                Interaction::Transforms => "=>",
+                _ => "->",
+            };
+            let _ = write!(
+                out,
+                "  {}: {} {} {};",
+                r.id,
+                side(&r.reactants),
+                arrow,
+                side(&r.products)
+            );
+            if let Some(rate) = &r.rate {
+                let _ = write!(out, " {};", rate);
+            }
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+
+    let interactions: Vec<_> = m.interactions.iter().filter(|i| local(&i.id)).collect();
+    if !interactions.is_empty() {
+        out.push_str("  // Interactions:\n");
+        for i in interactions {
+            let divider = match i.kind {
+                Interaction::Activates => "-o",
+                Interaction::Inhibits => "-|",
+                _ => "-(",
+            };
+            let _ = writeln!(
+                out,
+                "  {}: {} {} {};",
+                i.id,
+                i.interactors.join(", "),
+                divider,
+                i.interactees.join(", ")
+            );
+        }
+        out.push('\n');
+    }
+
+    let events: Vec<_> = m.events.iter().filter(|e| local(&e.id)).collect();
+    if !events.is_empty() {
+        out.push_str("  // Events:\n");
+        for e in events {
+            let _ = writeln!(out, "  {}", event(e));
+        }
+        out.push('\n');
+    }
+
+    if !m.strands.is_empty() {
+        out.push_str("  // DNA strands:\n");
+        for s in &m.strands {
+            let up = if s.open_upstream { "--" } else { "" };
+            let down = if s.open_downstream { "--" } else { "" };
+            let _ = writeln!(out, "  {}{}{};", up, s.parts.join("--"), down);
+        }
+        out.push('\n');
+    }
+
+    let values: Vec<_> = m
+        .symbols
+        .iter()
+        .filter(|s| has_value(s))
+        .filter(|s| local(&s.id) || overrides(doc, m, s))
+        .collect();
+    let assignments: Vec<_> = values
+        .iter()
+        .filter_map(|s| match &s.rule {
+            Some(Rule::Assignment(f)) => Some((&s.id, f)),
+            _ => None,
+        })
+        .collect();
+    if !assignments.is_empty() {
+        out.push_str("  // Assignment Rules:\n");
+        for (id, formula) in assignments {
+            let _ = writeln!(out, "  {} := {};", id, formula);
+        }
+        out.push('\n');
+    }
+    let rates: Vec<_> = values
+        .iter()
+        .filter_map(|s| match &s.rule {
+            Some(Rule::Rate(f)) => Some((&s.id, f)),
+            _ => None,
+        })
+        .collect();
+    if !rates.is_empty() {
+        out.push_str("  // Rate Rules:\n");
+        for (id, formula) in rates {
+            let _ = writeln!(out, "  {}' = {};", id, formula);
+        }
+        out.push('\n');
+    }
+    let initials: Vec<_> = values
+        .iter()
+        .filter_map(|s| s.initial.as_ref().map(|f| (&s.id, f)))
+        .collect();
+    if !initials.is_empty() {
+        out.push_str("  // Initial values:\n");
+        for (id, formula) in initials {
+            let _ = writeln!(out, "  {} = {};", id, formula);
+        }
+        out.push('\n');
+    }
+
+    let constness = |constant: bool| -> Vec<&str> {
+        m.symbols
+            .iter()
+            .filter(|s| local(&s.id) && (s.is_formula() || s.is_compartment()))
+            .filter(|s| s.is_constant() == constant)
+            .map(|s| s.id.as_str())
+            .collect()
+    };
+    let (constants, variables) = (constness(true), constness(false));
+    if !constants.is_empty() || !variables.is_empty() {
+        out.push_str("  // Variability:\n");
+        if !constants.is_empty() {
+            let _ = writeln!(out, "  const {};", constants.join(", "));
+        }
+        if !variables.is_empty() {
+            let _ = writeln!(out, "  var {};", variables.join(", "));
+        }
+        out.push('\n');
+    }
+
+    let names: Vec<_> = m
+        .symbols
+        .iter()
+        .filter(|s| local(&s.id))
+        .filter_map(|s| s.name.as_ref().filter(|n| **n != s.id).map(|n| (&s.id, n)))
+        .collect();
+    if !names.is_empty() {
+        out.push_str("  // Display Names:\n");
+        for (id, name) in names {
+            let _ = writeln!(out, "  {} is {:?};", id, name);
+        }
+        out.push('\n');
+    }
+
+    if out.ends_with("\n\n") {
+        out.pop();
+    }
+    out.push_str("end\n");
+}
+
+fn within(s: &Symbol) -> String {
+    match &s.compartment {
+        Some(c) => format!(" in {}", c),
+        None => String::new(),
+    }
+}
+
+/// An event definition, e.g., `E0: at 2 after (time > 5), persistent = false: S1 = 0;`.
+pub(crate) fn event(e: &Event) -> String {
+    let mut out = format!("{}: at ", e.id);
+    if let Some(delay) = &e.delay {
+        let _ = write!(out, "{} after ", delay);
+    }
+    let _ = write!(out, "({})", e.trigger);
+    if let Some(priority) = &e.priority {
+        let _ = write!(out, ", priority = {}", priority);
+    }
+    if !e.initial_value {
+        out.push_str(", t0 = false");
+    }
+    // libAntimony's and SBML's defaults for persistence differ, so always spell it out.
+    let _ = write!(out, ", persistent = {}", e.persistent);
+    if !e.from_trigger {
+        out.push_str(", fromTrigger = false");
+    }
+    let assignments: Vec<_> = e
+        .assignments
+        .iter()
+        .map(|a| format!("{} = {}", a.variable, a.formula))
+        .collect();
+    let _ = write!(out, ": {};", assignments.join(", "));
+    out
+}
+
+/// One side of a reaction, e.g., `2 S1 + S2`.
+pub(crate) fn side(participants: &[Participant]) -> String {
+    let terms: Vec<_> = participants
+        .iter()
+        .map(|p| {
+            if p.stoichiometry == 1.0 {
+                p.species.clone()
+            } else {
+                format!("{} {}", p.stoichiometry, p.species)
+            }
+        })
+        .collect();
+    terms.join(" + ")
+}
+
+/// Whether a symbol carries a value to write: an initial value or a rule.
+fn has_value(s: &Symbol) -> bool {
+    let writable = !matches!(
+        s.kind,
+        SymbolKind::Reaction
+            | SymbolKind::Gene
+            | SymbolKind::Event
+            | SymbolKind::Interaction
+            | SymbolKind::Module
+            | SymbolKind::StrandExpanded
+            | SymbolKind::StrandModular
+    );
+    writable && (s.initial.is_some() || s.rule.is_some())
+}
+
+/// Whether a dotted symbol differs from its value in the definition of its submodule.
+fn overrides(doc: Option<&Document>, m: &Module, s: &Symbol) -> bool {
+    let doc = match doc {
+        Some(doc) => doc,
+        None => return true,
+    };
+    match definition(doc, m, &s.id) {
+        Some(def) => def.initial != s.initial || def.rule != s.rule,
+        None => true,
+    }
+}
+
+/// The symbol a dotted name refers to in the definition of its submodule.
+fn definition<'a>(doc: &'a Document, m: &Module, id: &str) -> Option<&'a Symbol> {
+    let dot = id.find('.')?;
+    let sub = m.submodules.iter().find(|s| s.id == id[..dot])?;
+    let inner = doc.module(sub.module.as_ref()?)?;
+    let rest = &id[dot + 1..];
+    match inner.symbol(rest) {
+        Some(symbol) if !rest.contains('.') => Some(symbol),
+        _ => definition(doc, inner, rest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(id: &str, kind: SymbolKind, initial: &str) -> Symbol {
+        Symbol {
+            id: id.into(),
+            name: None,
+            kind,
+            compartment: None,
+            initial: Some(initial.into()),
+            rule: None,
+        }
+    }
+
+    #[test]
+    fn writes_reactions_and_values() {
+        let module = Module {
+            name: "M".into(),
+            symbols: vec![
+                symbol("S1", SymbolKind::SpeciesVariable, "10"),
+                symbol("S2", SymbolKind::SpeciesConstant, "0"),
+                symbol("k1", SymbolKind::FormulaConstant, "0.1"),
+            ],
+            reactions: vec![Reaction {
+                id: "J0".into(),
+                name: None,
+                kind: Interaction::Transforms,
+                reactants: vec![Participant {
+                    species: "S1".into(),
+                    stoichiometry: 2.0,
+                }],
+                products: vec![Participant {
+                    species: "S2".into(),
+                    stoichiometry: 1.0,
+                }],
+                rate: Some("k1*S1".into()),
+            }],
+            ..Module::default()
+        };
+        let text = module.to_antimony();
+        assert!(text.starts_with("model M()\n"));
+        assert!(text.contains("  species $S2;\n"));
+        assert!(text.contains("  J0: 2 S1 => S2; k1*S1;\n"));
+        assert!(text.contains("  k1 = 0.1;\n"));
+        assert!(text.contains("  const k1;\n"));
+        assert!(text.ends_with("end\n"));
+    }
+
+    #[test]
+    fn skips_values_inherited_from_submodules() {
+        let inner = Module {
+            name: "Inner".into(),
+            symbols: vec![symbol("k", SymbolKind::FormulaConstant, "1")],
+            ..Module::default()
+        };
+        let outer = Module {
+            name: "Outer".into(),
+            symbols: vec![
+                symbol("A.k", SymbolKind::FormulaConstant, "1"),
+                symbol("B.k", SymbolKind::FormulaConstant, "2"),
+            ],
+            submodules: vec![
+                Submodule {
+                    id: "A".into(),
+                    module: Some("Inner".into()),
+                },
+                Submodule {
+                    id: "B".into(),
+                    module: Some("Inner".into()),
+                },
+            ],
+            ..Module::default()
+        };
+        let doc = Document {
+            main: "Outer".into(),
+            modules: vec![inner, outer],
+        };
+        let text = doc.to_antimony();
+        assert!(text.contains("model *Outer()"));
+        assert!(text.contains("  A: Inner();\n"));
+        assert!(!text.contains("A.k = 1"));
+        assert!(text.contains("  B.k = 2;\n"));
+    }
+
+    #[test]
+    fn writes_replacements_without_local_submodules() {
+        // The replaced symbol belongs to a submodule of a submodule, so no submodule is
+        // declared here, but the replacement must still be written to survive a reload.
+        let module = Module {
+            name: "Outer".into(),
+            symbols: vec![symbol("y", SymbolKind::FormulaConstant, "3")],
+            submodules: vec![Submodule {
+                id: "A.B".into(),
+                module: Some("Inner".into()),
+            }],
+            replacements: vec![Replacement {
+                former: "A.B.x".into(),
+                replacement: "y".into(),
+            }],
+            ..Module::default()
+        };
+        let text = module.to_antimony();
+        assert!(!text.contains("// Sub-modules:"));
+        assert!(text.contains("  // Replacements:\n  A.B.x is y;\n"));
+        assert!(text.contains("  y = 3;\n"));
+    }
+}